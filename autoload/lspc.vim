@@ -1,6 +1,18 @@
 let s:config = {
       \ 'auto_start': v:true,
+      \ 'diagnostic_signs': {
+      \   'error': 'E',
+      \   'warning': 'W',
+      \   'information': 'I',
+      \   'hint': 'H',
+      \   },
+      \ 'snippet_expander': '',
+      \ 'rpc_channel_capacity': 16,
       \ }
+" Completion trigger characters of the started servers, by lang id
+let s:completion_triggers = {}
+" On type formatting trigger characters of the started servers, by lang id
+let s:on_type_format_triggers = {}
 
 function! lspc#output(log)
   " if !exists('s:output_buffer') || !nvim_buf_is_loaded(s:output_buffer)
@@ -32,6 +44,7 @@ function! lspc#init()
   let l:binpath = s:root . '/target/debug/neovim_lspc'
 
   call setenv('RUST_BACKTRACE', '1')
+  call setenv('LSPC_RPC_CAPACITY', s:config['rpc_channel_capacity'])
   let s:job_id = jobstart([l:binpath], {
         \ 'rpc': v:true,
         \ 'on_stderr': function('s:echo_handler'),
@@ -42,27 +55,122 @@ function! lspc#init()
   endif
 endfunction
 
+" Gutter sign of the diagnostics of `severity`, at most 2 characters
+function! lspc#diagnostic_sign(severity) abort
+  return get(s:config['diagnostic_signs'], a:severity, 'E')
+endfunction
+
+" Function expanding the snippets of committed completion items, empty to
+" keep their text without placeholders
+function! lspc#snippet_expander() abort
+  return s:config['snippet_expander']
+endfunction
+
 function! lspc#started() abort
   return exists('s:job_id')
 endfunction
 
+" Servers are shut down by lspc before it exits, it's stopped if that takes
+" too long. lspc shuts them all down together, within two seconds however
+" many are running.
 function! lspc#destroy()
-  call jobstop(s:job_id)
+  call rpcnotify(s:job_id, 'exit')
+  if jobwait([s:job_id], 3000)[0] == -1
+    call jobstop(s:job_id)
+  endif
   unlet! s:job_id
 endfunction
 
+" A language is configured with one server config or a list of them, e.g. a
+" linter along the language's server
+function! s:server_configs(lang_id) abort
+  let l:config = s:config[a:lang_id]
+  return type(l:config) == v:t_list ? l:config : [l:config]
+endfunction
+
 function! lspc#start_lang_server()
   if exists('b:current_syntax')
     let l:lang_id = b:current_syntax
     if has_key(s:config, l:lang_id) && !lspc#lang_server_started(l:lang_id)
-      let l:config = s:config[l:lang_id]
       let l:cur_path = lspc#buffer#filename()
       call add(s:lang_servers, l:lang_id)
-      call rpcnotify(s:job_id, 'start_lang_server', l:lang_id, l:config, l:cur_path)
+      for l:config in s:server_configs(l:lang_id)
+        call rpcnotify(s:job_id, 'start_lang_server', l:lang_id, l:config, l:cur_path)
+      endfor
     endif
   endif
 endfunction
 
+" Start indexing a project before any of its files is opened,
+" e.g. `autocmd DirChanged * call lspc#prewarm_server('rust', getcwd())`
+function! lspc#prewarm_server(lang_id, root_path)
+  if has_key(s:config, a:lang_id) && !lspc#lang_server_started(a:lang_id)
+    call add(s:lang_servers, a:lang_id)
+    for l:config in s:server_configs(a:lang_id)
+      call rpcnotify(s:job_id, 'prewarm_server', a:lang_id, l:config, a:root_path)
+    endfor
+  endif
+endfunction
+
+" Only the given settings are changed, the others are kept
+function! lspc#update_server_settings(lang_id, settings)
+  call rpcnotify(s:job_id, 'update_server_settings', a:lang_id, a:settings, v:false)
+endfunction
+
+" All the settings are replaced
+function! lspc#update_config(lang_id, settings)
+  call rpcnotify(s:job_id, 'update_server_settings', a:lang_id, a:settings, v:true)
+endfunction
+
+" One of 'off', 'messages' or 'verbose'
+function! lspc#set_trace(lang_id, value)
+  call rpcnotify(s:job_id, 'set_trace', a:lang_id, a:value)
+endfunction
+
+function! lspc#add_workspace_folder(lang_id, path)
+  call rpcnotify(s:job_id, 'add_workspace_folder', a:lang_id, fnamemodify(a:path, ':p:h'))
+endfunction
+
+function! lspc#remove_workspace_folder(lang_id, path)
+  call rpcnotify(s:job_id, 'remove_workspace_folder', a:lang_id, fnamemodify(a:path, ':p:h'))
+endfunction
+
+function! lspc#progress()
+  return lspc#command#progress()
+endfunction
+
+function! lspc#on_server_ready(ready)
+  let s:completion_triggers[a:ready.lang_id] = a:ready.completion_trigger_characters
+  let s:on_type_format_triggers[a:ready.lang_id] = a:ready.on_type_formatting_trigger_characters
+endfunction
+
+function! lspc#stop_lang_server(lang_id)
+  let l:index = index(s:lang_servers, a:lang_id)
+  if l:index >= 0
+    call remove(s:lang_servers, l:index)
+    call rpcnotify(s:job_id, 'stop_lang_server', a:lang_id)
+  endif
+endfunction
+
+" Servers are only notified of the files matching their registered patterns
+function! lspc#did_create_files(paths)
+  call rpcnotify(s:job_id, 'did_create_files', map(copy(a:paths), 'fnamemodify(v:val, ":p")'))
+endfunction
+
+function! lspc#did_rename_file(old_path, new_path)
+  call rpcnotify(s:job_id, 'did_rename_file', fnamemodify(a:old_path, ':p'), fnamemodify(a:new_path, ':p'))
+endfunction
+
+function! lspc#did_delete_files(paths)
+  call rpcnotify(s:job_id, 'did_delete_files', map(copy(a:paths), 'fnamemodify(v:val, ":p")'))
+endfunction
+
+" Changes made outside of the editor, as [path, type] with type 1 for
+" created, 2 for changed and 3 for deleted
+function! lspc#files_changed(changes)
+  call rpcnotify(s:job_id, 'files_changed', map(copy(a:changes), '[fnamemodify(v:val[0], ":p"), v:val[1]]'))
+endfunction
+
 function! lspc#lang_server_started(lang_id)
   return index(s:lang_servers, a:lang_id) >= 0
 endfunction
@@ -74,6 +182,13 @@ function! lspc#hover()
   call rpcnotify(s:job_id, 'hover', l:buf_id, l:cur_path, l:position)
 endfunction
 
+" Hover of several positions at once, e.g. one per cursor
+function! lspc#multi_hover(positions)
+  let l:buf_id = bufnr()
+  let l:cur_path = lspc#buffer#filename()
+  call rpcnotify(s:job_id, 'multi_hover', l:buf_id, l:cur_path, a:positions)
+endfunction
+
 function! lspc#reference()
   let l:buf_id = bufnr()
   let l:cur_path = lspc#buffer#filename()
@@ -87,7 +202,8 @@ function! lspc#track_all_buffers()
   let l:listed_buffers = filter(l:all_buffers, 'buflisted(v:val)')
   for l:buf_id in listed_buffers
     let l:buf_path = expand('#' . buf_id . ':p')
-    call rpcnotify(s:job_id, 'did_open', l:buf_id, l:buf_path)
+    let l:language_id = getbufvar(l:buf_id, '&filetype')
+    call rpcnotify(s:job_id, 'did_open', l:buf_id, l:buf_path, l:language_id)
   endfor
 endfunction
 
@@ -97,14 +213,61 @@ function! lspc#did_open()
   if s:config['auto_start']
     call lspc#start_lang_server()
   endif
-  call rpcnotify(s:job_id, 'did_open', l:buf_id, l:cur_path)
+  call rpcnotify(s:job_id, 'did_open', l:buf_id, l:cur_path, &filetype)
 endfunction
 
-function! lspc#goto_definition()
+" Blocks the write until the server edits are applied, for at most a second.
+" lspc drops edits arriving later than WILL_SAVE_TIMEOUT_MS, keep it shorter
+function! lspc#will_save()
+  let l:buf_id = bufnr()
+  let l:cur_path = lspc#buffer#filename()
+  let l:lines = lspc#buffer#text()
+  call rpcnotify(s:job_id, 'will_save', l:buf_id, l:cur_path, 1)
+  call setbufvar(l:buf_id, 'lspc_will_save_pending', v:true)
+  call rpcnotify(s:job_id, 'will_save_wait_until', l:buf_id, l:cur_path, 1, l:lines)
+  call wait(1000, {-> !getbufvar(l:buf_id, 'lspc_will_save_pending', v:false)})
+  call setbufvar(l:buf_id, 'lspc_will_save_pending', v:false)
+endfunction
+
+function! lspc#will_save_done(buf_id)
+  call setbufvar(a:buf_id, 'lspc_will_save_pending', v:false)
+endfunction
+
+function! lspc#did_save()
+  let l:buf_id = bufnr()
+  let l:cur_path = lspc#buffer#filename()
+  call rpcnotify(s:job_id, 'did_save', l:buf_id, l:cur_path)
+endfunction
+
+" Optional argument: 'edit' (default), 'split', 'vsplit' or 'tab'
+function! lspc#goto_definition(...)
   let l:buf_id = bufnr()
   let l:cur_path = lspc#buffer#filename()
   let l:position = lspc#buffer#position()
-  call rpcnotify(s:job_id, 'goto_definition', l:buf_id, l:cur_path, l:position)
+  let l:target = get(a:000, 0, 'edit')
+  call rpcnotify(s:job_id, 'goto_definition', l:buf_id, l:cur_path, l:position, l:target)
+endfunction
+
+" Declaration and definition differ e.g. for C/C++ headers
+function! lspc#goto_declaration()
+  let l:buf_id = bufnr()
+  let l:cur_path = lspc#buffer#filename()
+  let l:position = lspc#buffer#position()
+  call rpcnotify(s:job_id, 'declaration', l:buf_id, l:cur_path, l:position)
+endfunction
+
+function! lspc#goto_type_definition()
+  let l:buf_id = bufnr()
+  let l:cur_path = lspc#buffer#filename()
+  let l:position = lspc#buffer#position()
+  call rpcnotify(s:job_id, 'type_definition', l:buf_id, l:cur_path, l:position)
+endfunction
+
+function! lspc#goto_implementation()
+  let l:buf_id = bufnr()
+  let l:cur_path = lspc#buffer#filename()
+  let l:position = lspc#buffer#position()
+  call rpcnotify(s:job_id, 'implementation', l:buf_id, l:cur_path, l:position)
 endfunction
 
 function! lspc#inlay_hints()
@@ -113,6 +276,15 @@ function! lspc#inlay_hints()
   call rpcnotify(s:job_id, 'inlay_hints', l:buf_id, l:cur_path)
 endfunction
 
+" Jump to e.g. the definition of the type shown by the inlay hint of the
+" current line
+function! lspc#inlay_hint_goto()
+  let l:buf_id = bufnr()
+  let l:cur_path = lspc#buffer#filename()
+  let l:position = lspc#buffer#position()
+  call rpcnotify(s:job_id, 'inlay_hint_goto', l:buf_id, l:cur_path, l:position)
+endfunction
+
 function! lspc#format_doc()
   let l:buf_id = bufnr()
   let l:cur_path = lspc#buffer#filename()
@@ -120,6 +292,267 @@ function! lspc#format_doc()
   call rpcnotify(s:job_id, 'format_doc', l:buf_id, l:cur_path, l:lines)
 endfunction
 
+" Format the lines of the visual selection,
+" e.g. `xnoremap <silent> = :<C-u>call lspc#format_range()<CR>`
+function! lspc#format_range()
+  let l:buf_id = bufnr()
+  let l:cur_path = lspc#buffer#filename()
+  let l:lines = lspc#buffer#text()
+  let l:range = {
+        \ 'start': {'line': lspc#buffer#range_start_line(), 'character': 0},
+        \ 'end': {'line': lspc#buffer#range_end_line(), 'character': 0},
+        \ }
+  call rpcnotify(s:job_id, 'format_range', l:buf_id, l:cur_path, l:lines, l:range)
+endfunction
+
+" Let the server format the document after typing one of its trigger
+" characters, e.g. `}` or `;`
+function! lspc#on_type_format_on_trigger()
+  let l:lang_id = get(b:, 'current_syntax', &filetype)
+  let l:char = getline('.')[col('.') - 2]
+  if col('.') < 2 || index(get(s:on_type_format_triggers, l:lang_id, []), l:char) < 0
+    return
+  endif
+  let l:buf_id = bufnr()
+  let l:cur_path = lspc#buffer#filename()
+  let l:lines = lspc#buffer#text()
+  let l:position = lspc#buffer#position()
+  call rpcnotify(s:job_id, 'on_type_format', l:buf_id, l:cur_path, l:lines, l:position, l:char)
+endfunction
+
+function! lspc#completion()
+  let l:buf_id = bufnr()
+  let l:cur_path = lspc#buffer#filename()
+  let l:position = lspc#buffer#position()
+  call rpcnotify(s:job_id, 'completion', l:buf_id, l:cur_path, l:position)
+  return ''
+endfunction
+
+" Request completion right after typing one of the server's trigger characters
+function! lspc#completion_on_trigger()
+  if col('.') < 2
+    return
+  endif
+  let l:lang_id = get(b:, 'current_syntax', &filetype)
+  let l:line = getline('.')[:col('.') - 2]
+  for l:trigger in get(s:completion_triggers, l:lang_id, [])
+    if l:line[-len(l:trigger):] ==# l:trigger
+      call lspc#completion()
+      return
+    endif
+  endfor
+endfunction
+
+function! s:completion_user_data(item)
+  let l:user_data = get(a:item, 'user_data', '')
+  if type(l:user_data) != v:t_string
+    return ''
+  endif
+  return l:user_data
+endfunction
+
+function! lspc#resolve_completion()
+  let l:user_data = s:completion_user_data(get(v:event, 'completed_item', {}))
+  if l:user_data ==# ''
+    return
+  endif
+  let l:buf_id = bufnr()
+  let l:cur_path = lspc#buffer#filename()
+  call rpcnotify(s:job_id, 'resolve_completion', l:buf_id, l:cur_path, l:user_data)
+endfunction
+
+function! lspc#completion_done()
+  call lspc#command#close_completion_info()
+  let l:user_data = s:completion_user_data(v:completed_item)
+  if l:user_data ==# ''
+    return
+  endif
+  let l:buf_id = bufnr()
+  let l:cur_path = lspc#buffer#filename()
+  let l:lines = lspc#buffer#text()
+  call rpcnotify(s:job_id, 'completion_done', l:buf_id, l:cur_path, l:lines, l:user_data)
+endfunction
+
+function! lspc#signature_help()
+  let l:buf_id = bufnr()
+  let l:cur_path = lspc#buffer#filename()
+  let l:position = lspc#buffer#position()
+  call rpcnotify(s:job_id, 'signature_help', l:buf_id, l:cur_path, l:position)
+endfunction
+
+" Request signature help right after typing an argument list delimiter
+function! lspc#signature_help_on_trigger()
+  let l:char = getline('.')[col('.') - 2]
+  if l:char ==# '(' || l:char ==# ','
+    call lspc#signature_help()
+  endif
+endfunction
+
+function! lspc#toggle_diagnostics()
+  let l:buf_id = bufnr()
+  let l:cur_path = lspc#buffer#filename()
+  let l:enabled = get(b:, 'lspc_diagnostics_enabled', v:true) ? v:false : v:true
+  let b:lspc_diagnostics_enabled = l:enabled
+  call rpcnotify(s:job_id, 'toggle_diagnostics', l:buf_id, l:cur_path, l:enabled)
+endfunction
+
+function! lspc#diagnostics_to_loclist()
+  let l:buf_id = bufnr()
+  let l:cur_path = lspc#buffer#filename()
+  call rpcnotify(s:job_id, 'diagnostics_to_loclist', l:buf_id, l:cur_path)
+endfunction
+
+" Turn features on or off for the current buffer only,
+" e.g. `call lspc#set_buffer_features({'inlay_hints': v:false})`
+function! lspc#set_buffer_features(features)
+  let l:buf_id = bufnr()
+  let l:cur_path = lspc#buffer#filename()
+  call rpcnotify(s:job_id, 'set_buffer_features', l:buf_id, l:cur_path, a:features)
+endfunction
+
+" Without a name given, lspc asks for it once the server agreed to rename
+function! lspc#rename(...)
+  let l:new_name = a:0 > 0 && a:1 !=# '' ? a:1 : v:null
+  let l:buf_id = bufnr()
+  let l:cur_path = lspc#buffer#filename()
+  let l:position = lspc#buffer#position()
+  call rpcnotify(s:job_id, 'rename', l:buf_id, l:cur_path, l:position, l:new_name)
+endfunction
+
+function! lspc#code_action()
+  let l:buf_id = bufnr()
+  let l:cur_path = lspc#buffer#filename()
+  let l:position = lspc#buffer#position()
+  let l:range = {'start': l:position, 'end': l:position}
+  call rpcnotify(s:job_id, 'code_action', l:buf_id, l:cur_path, l:range)
+endfunction
+
+" Expand the visual selection to the enclosing syntax node,
+" e.g. `xnoremap <silent> v :<C-u>call lspc#expand_selection()<CR>`
+function! lspc#expand_selection()
+  if lspc#command#selection_is_current()
+    call lspc#command#step_selection(1)
+    return
+  endif
+  let l:buf_id = bufnr()
+  let l:cur_path = lspc#buffer#filename()
+  let l:position = lspc#buffer#position()
+  call rpcnotify(s:job_id, 'selection_range', l:buf_id, l:cur_path, [l:position])
+endfunction
+
+function! lspc#shrink_selection()
+  if lspc#command#selection_is_current()
+    call lspc#command#step_selection(-1)
+  endif
+endfunction
+
+function! lspc#document_highlight()
+  let l:buf_id = bufnr()
+  let l:cur_path = lspc#buffer#filename()
+  let l:position = lspc#buffer#position()
+  call rpcnotify(s:job_id, 'document_highlight', l:buf_id, l:cur_path, l:position)
+endfunction
+
+function! lspc#document_symbols()
+  let l:buf_id = bufnr()
+  let l:cur_path = lspc#buffer#filename()
+  call rpcnotify(s:job_id, 'document_symbols', l:buf_id, l:cur_path)
+endfunction
+
+function! lspc#document_links()
+  let l:buf_id = bufnr()
+  let l:cur_path = lspc#buffer#filename()
+  call rpcnotify(s:job_id, 'document_links', l:buf_id, l:cur_path)
+endfunction
+
+" Open the target of the link under the cursor, links come from the last
+" `lspc#document_links()`
+function! lspc#goto_document_link()
+  let l:buf_id = bufnr()
+  let l:cur_path = lspc#buffer#filename()
+  let l:position = lspc#buffer#position()
+  call rpcnotify(s:job_id, 'goto_document_link', l:buf_id, l:cur_path, l:position)
+endfunction
+
+function! lspc#document_color()
+  let l:buf_id = bufnr()
+  let l:cur_path = lspc#buffer#filename()
+  call rpcnotify(s:job_id, 'document_color', l:buf_id, l:cur_path)
+endfunction
+
+" Pick another representation of the color under the cursor, e.g. `rgb()`
+" instead of a hex code. Colors come from the last `lspc#document_color()`
+function! lspc#color_presentation()
+  let l:buf_id = bufnr()
+  let l:cur_path = lspc#buffer#filename()
+  let l:position = lspc#buffer#position()
+  let l:lines = lspc#buffer#text()
+  call rpcnotify(s:job_id, 'color_presentation', l:buf_id, l:cur_path, l:position, l:lines)
+endfunction
+
+function! lspc#code_lens()
+  let l:buf_id = bufnr()
+  let l:cur_path = lspc#buffer#filename()
+  call rpcnotify(s:job_id, 'code_lens', l:buf_id, l:cur_path)
+endfunction
+
+" For servers publishing no diagnostics by themselves
+function! lspc#request_diagnostics()
+  let l:buf_id = bufnr()
+  let l:cur_path = lspc#buffer#filename()
+  call rpcnotify(s:job_id, 'request_diagnostics', l:buf_id, l:cur_path)
+endfunction
+
+function! lspc#semantic_tokens()
+  let l:buf_id = bufnr()
+  let l:cur_path = lspc#buffer#filename()
+  call rpcnotify(s:job_id, 'semantic_tokens', l:buf_id, l:cur_path)
+endfunction
+
+" Calls are listed for the item under cursor when the hierarchy was prepared
+function! lspc#prepare_call_hierarchy()
+  let l:buf_id = bufnr()
+  let l:cur_path = lspc#buffer#filename()
+  let l:position = lspc#buffer#position()
+  call rpcnotify(s:job_id, 'prepare_call_hierarchy', l:buf_id, l:cur_path, l:position)
+endfunction
+
+function! lspc#incoming_calls()
+  let l:buf_id = bufnr()
+  let l:cur_path = lspc#buffer#filename()
+  call rpcnotify(s:job_id, 'incoming_calls', l:buf_id, l:cur_path)
+endfunction
+
+function! lspc#outgoing_calls()
+  let l:buf_id = bufnr()
+  let l:cur_path = lspc#buffer#filename()
+  call rpcnotify(s:job_id, 'outgoing_calls', l:buf_id, l:cur_path)
+endfunction
+
+function! lspc#goto_workspace_symbol(...)
+  let l:query = a:0 > 0 ? a:1 : input('Symbol: ', expand('<cword>'))
+  let l:buf_id = bufnr()
+  let l:cur_path = lspc#buffer#filename()
+  call rpcnotify(s:job_id, 'goto_workspace_symbol', l:buf_id, l:cur_path, l:query)
+endfunction
+
+function! lspc#workspace_symbol(...)
+  let l:query = a:0 > 0 ? a:1 : input('Symbol: ', expand('<cword>'))
+  call rpcnotify(s:job_id, 'workspace_symbol', l:query)
+endfunction
+
+" Server notifications lspc doesn't handle, forwarded when the server config
+" has 'forward_notifications' set. Handle them with `autocmd User LspcNotification`
+" reading `g:lspc_notification`.
+function! lspc#on_notification(method, params)
+  let g:lspc_notification = {'method': a:method, 'params': a:params}
+  doautocmd <nomodeline> User LspcNotification
+endfunction
+
+function! lspc#status()
+  call rpcnotify(s:job_id, 'status')
+endfunction
+
 function! lspc#hello_from_the_other_side()
   call rpcnotify(s:job_id, 'hello')
 endfunction
@@ -15,7 +15,7 @@ function! lspc#buffer#text(...) abort
     let l:buf = get(a:000, 0, '')
 
     let l:lines = getbufline(l:buf, 1, '$')
-    if len(l:lines) > 0 && l:lines[-1] !=# '' && &fixendofline
+    if len(l:lines) > 0 && l:lines[-1] !=# '' && getbufvar(l:buf, '&fixendofline')
         let l:lines += ['']
     endif
     return l:lines
@@ -25,6 +25,7 @@ function! lspc#buffer#line() abort
     return line('.') - 1
 endfunction
 
+" Byte column, lspc converts it to the position encoding of the server
 function! lspc#buffer#character() abort
     return col('.') - 1
 endfunction
@@ -126,17 +126,310 @@ function! lspc#command#open_hover_preview(bufname, lines, filetype) abort
     endif
 endfunction
 
+" The text of the referencing line is shown when the file can be read, the
+" window is only opened when there's more than one reference
 function! lspc#command#open_reference_preview(references) abort
   let references = a:references
   for reference in references
     let buf_id = bufnr(reference.filename . '$')
-    if buf_id >= 0
-      let content = getbufline(buf_id, reference.lnum)[0]
-    else
-      let content = readfile(reference.filename)[reference.lnum - 1]
+    if buf_id >= 0 && bufloaded(buf_id)
+      let reference.text = get(getbufline(buf_id, reference.lnum), 0, '')
+    elseif filereadable(reference.filename)
+      let lines = readfile(reference.filename, '', reference.lnum)
+      let reference.text = len(lines) == reference.lnum ? lines[-1] : ''
     endif
-    let reference.text = content
   endfor
   call setqflist([], 'r', {'title' : 'Lspc references view', 'items': references})
+  if len(references) > 1
+    exec 'copen'
+  endif
+endfunction
+
+" Outline of the current buffer, entries without filename belong to it
+function! lspc#command#show_document_symbols(symbols) abort
+  let l:buf_id = bufnr()
+  let l:items = []
+  for symbol in a:symbols
+    if !has_key(symbol, 'filename')
+      let symbol.bufnr = l:buf_id
+    endif
+    call add(l:items, symbol)
+  endfor
+  call setloclist(0, [], 'r', {'title' : 'Lspc document symbols', 'items': l:items})
+  exec 'lopen'
+  " Nested symbols are folded under their parent, from the indentation of
+  " their text. An entry followed by its children starts their fold.
+  let l:depths = map(copy(l:items), {_, item -> len(matchstr(item.text, '^ *')) / 2})
+  let w:lspc_symbol_fold_levels = []
+  for l:index in range(len(l:depths))
+    let l:depth = l:depths[l:index]
+    if get(l:depths, l:index + 1, 0) > l:depth
+      call add(w:lspc_symbol_fold_levels, '>' . (l:depth + 1))
+    else
+      call add(w:lspc_symbol_fold_levels, l:depth)
+    endif
+  endfor
+  setlocal foldmethod=expr foldlevel=99
+  setlocal foldexpr=get(w:lspc_symbol_fold_levels,v:lnum-1,0)
+endfunction
+
+" Walked with `:lnext` / `:lprevious`
+function! lspc#command#show_diagnostics_list(diagnostics) abort
+  call setloclist(0, [], 'r', {'title' : 'Lspc diagnostics', 'items': a:diagnostics})
+  exec 'lopen'
+endfunction
+
+function! lspc#command#show_workspace_symbols(symbols) abort
+  call setqflist([], 'r', {'title' : 'Lspc workspace symbols', 'items': a:symbols})
   exec 'copen'
 endfunction
+
+function! lspc#command#show_call_hierarchy(title, calls) abort
+  call setqflist([], 'r', {'title' : a:title, 'items': a:calls})
+  exec 'copen'
+endfunction
+
+function! lspc#command#show_completions(items) abort
+  if mode() !=# 'i'
+    return
+  endif
+  let line = getline('.')[: col('.') - 2]
+  let start = match(line, '\k*$') + 1
+  call complete(start, a:items)
+
+  " Move the selection onto the first preselected item
+  let l:index = 0
+  for item in a:items
+    if get(item, 'preselect', v:false)
+      let l:moves = &completeopt =~# 'noselect' ? l:index + 1 : l:index
+      if l:moves > 0
+        call feedkeys(repeat("\<C-n>", l:moves), 'n')
+      endif
+      break
+    endif
+    let l:index += 1
+  endfor
+endfunction
+
+" Expand the snippet of the committed completion item in place of the `word`
+" inserted for it, unless the text before the cursor has changed since
+function! lspc#command#expand_snippet(buf_id, word, snippet) abort
+  let l:expander = lspc#snippet_expander()
+  if empty(l:expander) || a:buf_id != bufnr() || mode() !=# 'i'
+    return
+  endif
+  let l:line = getline('.')
+  let l:col = col('.') - 1
+  let l:start = l:col - len(a:word)
+  if l:start < 0 || strpart(l:line, l:start, len(a:word)) !=# a:word
+    return
+  endif
+
+  call setline('.', strpart(l:line, 0, l:start) . strpart(l:line, l:col))
+  call cursor(line('.'), l:start + 1)
+  call call(l:expander, [a:snippet])
+endfunction
+
+" Show documentation of the selected completion item next to the popup menu
+function! lspc#command#show_completion_info(lines) abort
+  call lspc#command#close_completion_info()
+  if empty(a:lines) || !pumvisible() || !s:FLOAT_WINDOW_AVAILABLE || !exists('*pum_getpos')
+    return
+  endif
+
+  let pum = pum_getpos()
+  let width = max(map(copy(a:lines), 'strdisplaywidth(v:val)'))
+  let col = float2nr(pum.col + pum.width + (pum.scrollbar ? 1 : 0))
+  if col + width > &columns
+    let width = &columns - col
+  endif
+  if width <= 0
+    return
+  endif
+
+  let bufnr = nvim_create_buf(v:false, v:true)
+  call nvim_buf_set_lines(bufnr, 0, -1, v:true, a:lines)
+  let s:completion_info_win = nvim_open_win(bufnr, v:false, {
+  \   'relative': 'editor',
+  \   'row': float2nr(pum.row),
+  \   'col': col,
+  \   'width': width,
+  \   'height': len(a:lines),
+  \   'focusable': v:false,
+  \ })
+endfunction
+
+function! lspc#command#close_completion_info() abort
+  if exists('s:completion_info_win') && nvim_win_is_valid(s:completion_info_win)
+    call nvim_win_close(s:completion_info_win, v:true)
+  endif
+  unlet! s:completion_info_win
+endfunction
+
+" Show the active signature above the cursor, highlighting the active
+" parameter given as a [start, end) byte range
+function! lspc#command#show_signature_help(label, highlight) abort
+  call lspc#command#close_signature_help()
+  if !s:FLOAT_WINDOW_AVAILABLE
+    echo a:label
+    return
+  endif
+
+  let width = min([strdisplaywidth(a:label), &columns])
+  let row = winline() > 1 ? -1 : 1
+  let bufnr = nvim_create_buf(v:false, v:true)
+  call nvim_buf_set_lines(bufnr, 0, -1, v:true, [a:label])
+  if a:highlight isnot v:null
+    call nvim_buf_add_highlight(bufnr, -1, 'LspcActiveParameter', 0, a:highlight[0], a:highlight[1])
+  endif
+  let s:signature_help_win = nvim_open_win(bufnr, v:false, {
+  \   'relative': 'cursor',
+  \   'row': row,
+  \   'col': 0,
+  \   'width': width,
+  \   'height': 1,
+  \   'focusable': v:false,
+  \ })
+
+  augroup plugin-lspc-close-signature-help
+    autocmd!
+    autocmd InsertLeave,BufLeave * call lspc#command#close_signature_help()
+  augroup END
+endfunction
+
+function! lspc#command#close_signature_help() abort
+  if exists('s:signature_help_win') && nvim_win_is_valid(s:signature_help_win)
+    call nvim_win_close(s:signature_help_win, v:true)
+  endif
+  unlet! s:signature_help_win
+  silent! autocmd! plugin-lspc-close-signature-help
+endfunction
+
+function! lspc#command#show_diagnostics(filename, diagnostics) abort
+  let bufnr = bufnr(a:filename . '$')
+  if bufnr < 0
+    return
+  endif
+  if !exists('s:diagnostics_ns')
+    let s:diagnostics_ns = nvim_create_namespace('lspc_diagnostics')
+  endif
+
+  call nvim_buf_clear_namespace(bufnr, s:diagnostics_ns, 0, -1)
+  for diagnostic in a:diagnostics
+    call nvim_buf_set_virtual_text(bufnr, s:diagnostics_ns, diagnostic.line,
+    \   [[diagnostic.message, diagnostic.hl_group]], {})
+    call nvim_buf_set_extmark(bufnr, s:diagnostics_ns, diagnostic.line, 0, {
+    \   'sign_text': lspc#diagnostic_sign(diagnostic.severity),
+    \   'sign_hl_group': diagnostic.hl_group,
+    \ })
+  endfor
+endfunction
+
+" Progress reported by the servers, by token. `lspc#progress()` gives the
+" statusline text, e.g. `set statusline+=%{lspc#progress()}`
+let s:progress = {}
+
+function! lspc#command#show_progress(token, status) abort
+  if a:status is v:null
+    silent! call remove(s:progress, a:token)
+  else
+    let s:progress[a:token] = a:status
+  endif
+  redrawstatus!
+endfunction
+
+function! lspc#command#progress() abort
+  return join(values(s:progress), ' | ')
+endfunction
+
+" Lines of a file, taken from its buffer when loaded, otherwise from disk
+function! lspc#command#file_lines(filename) abort
+  let bufnr = bufnr(a:filename . '$')
+  if bufnr >= 0 && bufloaded(bufnr)
+    return getbufline(bufnr, 1, '$')
+  endif
+  return readfile(a:filename)
+endfunction
+
+" Line `lnum` (0-based) of a file, read from disk when it's not loaded in a buffer
+function! lspc#command#file_line(filename, lnum) abort
+  let bufnr = bufnr(a:filename . '$')
+  if bufnr >= 0 && bufloaded(bufnr)
+    return get(getbufline(bufnr, a:lnum + 1), 0, '')
+  endif
+  return get(readfile(a:filename, '', a:lnum + 1), a:lnum, '')
+endfunction
+
+" Replace the content of a file. Files that are not loaded in a buffer are
+" written directly so the edits are not lost
+function! lspc#command#set_file_lines(filename, lines) abort
+  let bufnr = bufnr(a:filename . '$')
+  if bufnr >= 0 && bufloaded(bufnr)
+    call nvim_buf_set_lines(bufnr, 0, -1, v:false, a:lines)
+  else
+    call writefile(a:lines, a:filename)
+  endif
+endfunction
+
+" Let the user pick one of `items`, returns its index or -1 when cancelled
+function! lspc#command#choose(prompt, items) abort
+  let choices = [a:prompt]
+  for index in range(len(a:items))
+    call add(choices, printf('%d. %s', index + 1, a:items[index]))
+  endfor
+
+  let choice = inputlist(choices)
+  if choice < 1 || choice > len(a:items)
+    return -1
+  endif
+  return choice - 1
+endfunction
+
+" Chains of ranges, innermost first, one per requested position. Only the
+" first chain is used since there is a single visual selection.
+function! lspc#command#set_selection_ranges(buf_id, chains) abort
+  " The cursor left the buffer while the server answered
+  if bufnr() != a:buf_id
+    return
+  endif
+  let b:lspc_selection = {
+        \ 'ranges': get(a:chains, 0, []),
+        \ 'index': 0,
+        \ 'changedtick': b:changedtick,
+        \ }
+  call lspc#command#select_range()
+endfunction
+
+function! lspc#command#select_range() abort
+  let l:selection = get(b:, 'lspc_selection', {})
+  if empty(get(l:selection, 'ranges', []))
+    return
+  endif
+  let l:range = l:selection.ranges[l:selection.index]
+  let l:end_line = l:range.end.line + 1
+  let l:end_col = l:range.end.character
+  " Exclusive end at line start, select until the end of previous line
+  if l:end_col == 0 && l:end_line > l:range.start.line + 1
+    let l:end_line -= 1
+    let l:end_col = max([len(getline(l:end_line)), 1])
+  endif
+  call setpos("'<", [0, l:range.start.line + 1, l:range.start.character + 1, 0])
+  call setpos("'>", [0, l:end_line, l:end_col, 0])
+  let l:selection.marks = [getpos("'<"), getpos("'>")]
+  normal! gv
+endfunction
+
+" Whether the visual selection is still the one made by lspc
+function! lspc#command#selection_is_current() abort
+  let l:selection = get(b:, 'lspc_selection', {})
+  return !empty(l:selection)
+        \ && l:selection.changedtick == b:changedtick
+        \ && get(l:selection, 'marks', []) == [getpos("'<"), getpos("'>")]
+endfunction
+
+function! lspc#command#step_selection(step) abort
+  let l:selection = b:lspc_selection
+  let l:index = l:selection.index + a:step
+  let l:selection.index = max([0, min([l:index, len(l:selection.ranges) - 1])])
+  call lspc#command#select_range()
+endfunction
@@ -1,5 +1,40 @@
 " Commands
 command! -nargs=0 LspcStart call lspc#init()
+command! -nargs=0 LspcStatus call lspc#status()
+
+highlight default link LspcActiveParameter Underlined
+highlight default link LspcDiagnosticError ErrorMsg
+highlight default link LspcDiagnosticWarning WarningMsg
+highlight default link LspcDiagnosticInformation Comment
+highlight default link LspcDiagnosticHint Comment
+highlight default link LspcHighlightText Visual
+highlight default link LspcHighlightRead Visual
+highlight default link LspcHighlightWrite Search
+highlight default link LspcCodeLens Comment
+highlight default link LspcInlayHint Comment
+highlight default link LspcDocumentLink Underlined
+highlight default link LspcSemanticNamespace Include
+highlight default link LspcSemanticType Type
+highlight default link LspcSemanticClass Type
+highlight default link LspcSemanticEnum Type
+highlight default link LspcSemanticInterface Type
+highlight default link LspcSemanticStruct Structure
+highlight default link LspcSemanticTypeParameter Type
+highlight default link LspcSemanticParameter Identifier
+highlight default link LspcSemanticVariable Identifier
+highlight default link LspcSemanticProperty Identifier
+highlight default link LspcSemanticEnumMember Constant
+highlight default link LspcSemanticEvent Identifier
+highlight default link LspcSemanticFunction Function
+highlight default link LspcSemanticMethod Function
+highlight default link LspcSemanticMacro Macro
+highlight default link LspcSemanticKeyword Keyword
+highlight default link LspcSemanticModifier StorageClass
+highlight default link LspcSemanticComment Comment
+highlight default link LspcSemanticString String
+highlight default link LspcSemanticNumber Number
+highlight default link LspcSemanticRegexp String
+highlight default link LspcSemanticOperator Operator
 
 augroup lspc
   autocmd!
@@ -7,5 +42,13 @@ augroup lspc
     autocmd VimEnter         * call lspc#init()
   endif
   autocmd BufNewFile,BufRead * call lspc#did_open()
+  autocmd BufWritePre        * call lspc#will_save()
+  autocmd BufWritePost       * call lspc#did_save()
+  autocmd CompleteChanged    * call lspc#resolve_completion()
+  autocmd CompleteDone       * call lspc#completion_done()
+  autocmd TextChangedI       * call lspc#signature_help_on_trigger()
+  autocmd TextChangedI       * call lspc#completion_on_trigger()
+  autocmd TextChangedI       * call lspc#on_type_format_on_trigger()
+  autocmd CursorHold         * call lspc#document_highlight()
   autocmd VimLeave           * call lspc#destroy()
 augroup END
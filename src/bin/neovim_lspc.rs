@@ -1,7 +1,7 @@
 use std::io::{self, Stdin, StdinLock, Stdout, StdoutLock};
 
 use lspc::neovim::{Neovim, NvimMessage};
-use lspc::rpc::Client;
+use lspc::rpc::{self, Client};
 use lspc::Lspc;
 use std::error::Error;
 
@@ -28,7 +28,12 @@ fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
     log_dir.push("lspc_log.txt");
     simple_logging::log_to_file(log_dir, log::LevelFilter::Debug).expect("Can not open log file");
 
-    let nvim_rpc = Client::<NvimMessage>::new(stdinlock, stdoutlock);
+    // Set from the `rpc_channel_capacity` config by the plugin
+    let capacity = std::env::var("LSPC_RPC_CAPACITY")
+        .ok()
+        .and_then(|capacity| capacity.parse().ok())
+        .unwrap_or(rpc::DEFAULT_CAPACITY);
+    let nvim_rpc = Client::<NvimMessage>::with_capacity(capacity, stdinlock, stdoutlock);
     let neovim = Neovim::new(nvim_rpc);
     let lspc = Lspc::new(neovim);
 
@@ -1,73 +1,375 @@
+mod glob;
 pub mod handler;
 // Custom LSP types
 pub mod msg;
+mod quirks;
+#[cfg(test)]
+pub mod testing;
 mod tracking_file;
 pub mod types;
 
+pub use self::tracking_file::LineIndex;
+
 use std::{
-    collections::HashMap,
+    cell::RefCell,
+    collections::{BTreeMap, HashMap, HashSet},
     io,
     path::{Path, PathBuf},
+    rc::Rc,
     time::{Duration, Instant},
 };
 
 use crossbeam::channel::{tick, Receiver, Select};
 use lsp_types::{
-    self as lsp, notification as noti,
+    self as lsp,
+    notification::{self as noti, Notification as _},
     request::{
-        Formatting, GotoDefinition, GotoDefinitionResponse, HoverRequest, Initialize, References,
+        ApplyWorkspaceEdit, CodeActionRequest, CodeLensRequest, CodeLensResolve,
+        ColorPresentationRequest, DocumentColor, DocumentHighlightRequest, DocumentSymbolRequest,
+        Formatting, GotoDeclaration, GotoDefinition, GotoDefinitionResponse, GotoImplementation,
+        GotoTypeDefinition, HoverRequest, OnTypeFormatting, PrepareRenameRequest, RangeFormatting,
+        References, RegisterCapability, Rename, ShowMessageRequest, SignatureHelpRequest,
+        UnregisterCapability, WillSaveWaitUntil, WorkspaceConfiguration, WorkspaceFoldersRequest,
     },
-    DocumentFormattingParams, FormattingOptions, Hover, Location, Position, ShowMessageParams,
-    TextDocumentIdentifier, TextEdit,
+    CodeActionOrCommand, CodeLens, ColorInformation, ColorPresentation, Diagnostic,
+    DiagnosticSeverity, DocumentFormattingParams, DocumentHighlight, DocumentSymbol,
+    DocumentSymbolResponse, FormattingOptions, Hover, Location, LocationLink, MessageActionItem,
+    Position, Range, ShowMessageParams, ShowMessageRequestParams, SignatureHelp, SymbolInformation,
+    TextDocumentIdentifier, TextDocumentSaveReason, TextEdit, WorkspaceEdit,
 };
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use url::Url;
 
 use self::{
     handler::{LangServerHandler, LangSettings},
-    msg::{LspMessage, RawNotification, RawRequest, RawResponse},
+    msg::{ErrorCode, LspMessage, RawNotification, RawRequest, RawResponse},
     tracking_file::TrackingFile,
-    types::{InlayHint, InlayHints, InlayHintsParams},
+    types::{
+        CallHierarchyCallsParams, CallHierarchyIncomingCalls, CallHierarchyItem,
+        CallHierarchyOutgoingCalls, CallHierarchyPrepare, CompletionItem, CompletionRequest,
+        CompletionResponse, CreateFilesParams, DeleteFilesParams, DidCreateFiles, DidDeleteFiles,
+        DidRenameFiles, DocumentDiagnosticParams, DocumentDiagnosticReport,
+        DocumentDiagnosticRequest, DocumentLink, DocumentLinkRequest, DocumentLinkResolve,
+        FileCreate, FileDelete, FileRename, Initialize, InitializeParams, InlayHint,
+        InlayHintLabel, InlayHintParams, InlayHintRequest, InlayHints, InlayHintsParams, InlayKind,
+        LogTrace, PositionEncoding, Progress, ProgressToken, PublishDiagnostics,
+        PublishDiagnosticsParams, RenameFilesParams, ResolveCompletionItem, SelectionRange,
+        SelectionRangeParams, SelectionRangeRequest, SemanticTokens, SemanticTokensDeltaParams,
+        SemanticTokensEdit, SemanticTokensFullDeltaRequest, SemanticTokensFullDeltaResult,
+        SemanticTokensFullRequest, SemanticTokensLegend, SemanticTokensParams, StandardInlayHint,
+        TraceValue, WorkDoneProgressCreate, WorkspaceSymbol, WorkspaceSymbolLocation,
+        WorkspaceSymbolRequest, WorkspaceSymbolResolve,
+    },
 };
 
 pub const SYNC_DELAY_MS: u64 = 500;
 pub const TIMER_TICK_MS: u64 = 100;
+pub const SHUTDOWN_TIMEOUT_MS: u64 = 1000;
+// Servers slower than this to answer `initialize` are considered hung
+pub const INITIALIZE_TIMEOUT_MS: u64 = 10_000;
+// Edits to apply before saving are dropped past this. The editor waits a
+// second for them (`lspc#will_save`), the margin leaves time to apply them
+// before the file is written.
+pub const WILL_SAVE_TIMEOUT_MS: u64 = 800;
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct LsConfig {
+    // Not needed to connect to an already running server
+    #[serde(default)]
     pub command: Vec<String>,
     pub root_markers: Vec<String>,
     #[serde(default)]
     pub indentation: u64,
     #[serde(default)]
     pub indentation_with_space: bool,
+    // Number of rapid changes after which syncing waits for the burst to settle
+    #[serde(default = "default_sync_burst_threshold")]
+    pub sync_burst_threshold: usize,
+    // Changes are sent this long after the first pending one
+    #[serde(default = "default_sync_delay_ms")]
+    pub sync_delay_ms: u64,
+    // How often pending syncs and timeouts are checked, the smallest of the
+    // started servers is used
+    #[serde(default = "default_timer_tick_ms")]
+    pub timer_tick_ms: u64,
+    // Forward unhandled server notifications to the editor
+    #[serde(default)]
+    pub forward_notifications: bool,
+    #[serde(default)]
+    pub hover_mode: HoverMode,
+    // Format the document before it's written
+    #[serde(default)]
+    pub format_on_save: bool,
+    #[serde(default)]
+    pub min_diagnostic_severity: DiagnosticLevel,
+    // Requests unanswered for this long are cancelled
+    #[serde(default = "default_request_timeout_ms")]
+    pub request_timeout_ms: u64,
+    #[serde(default)]
+    pub transport: Transport,
+    // Start the server again with the same config when it exits unexpectedly
+    #[serde(default)]
+    pub restart_on_crash: bool,
+    // Server settings, sent at initialize and asked for with
+    // `workspace/configuration`
+    #[serde(default)]
+    pub settings: serde_json::Value,
+    // Folders handled by the server besides the root found with
+    // `root_markers`
+    #[serde(default)]
+    pub workspace_folders: Vec<String>,
+    // Sent with `initialize`, the settings are sent if missing
+    #[serde(default)]
+    pub initialization_options: serde_json::Value,
+    // Environment variables set for the spawned server
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    // Working directory of the spawned server, the root if missing
+    #[serde(default)]
+    pub cwd: Option<String>,
+    // Traces sent by the server are written to the log
+    #[serde(default)]
+    pub trace: TraceValue,
+    // Without a root marker the server isn't started, instead of using the
+    // file's directory as root
+    #[serde(default)]
+    pub require_root_marker: bool,
+}
+
+fn default_sync_burst_threshold() -> usize {
+    10
+}
+
+fn default_sync_delay_ms() -> u64 {
+    SYNC_DELAY_MS
+}
+
+fn default_timer_tick_ms() -> u64 {
+    TIMER_TICK_MS
+}
+
+fn default_request_timeout_ms() -> u64 {
+    60_000
+}
+
+// How a hover is shown: in a preview window, or its first line echoed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HoverMode {
+    Preview,
+    Echo,
+}
+
+impl Default for HoverMode {
+    fn default() -> Self {
+        HoverMode::Preview
+    }
+}
+
+// Window a location is opened in: the current one, a new split or a new tab
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GotoTarget {
+    Edit,
+    Split,
+    Vsplit,
+    Tab,
+}
+
+impl Default for GotoTarget {
+    fn default() -> Self {
+        GotoTarget::Edit
+    }
+}
+
+// How lspc talks to the server: through the stdio of the spawned `command`,
+// or by connecting to a server listening on a TCP port or a Unix domain
+// socket (a named pipe on Windows)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Transport {
+    Stdio,
+    Tcp { host: String, port: u16 },
+    Socket { path: String },
+}
+
+impl Default for Transport {
+    fn default() -> Self {
+        Transport::Stdio
+    }
+}
+
+// Least severe diagnostics shown, e.g. `warning` hides information and hints
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiagnosticLevel {
+    Error,
+    Warning,
+    Information,
+    Hint,
+}
+
+impl DiagnosticLevel {
+    // Diagnostics without severity are errors
+    pub fn includes(self, severity: Option<DiagnosticSeverity>) -> bool {
+        let level = match severity {
+            Some(DiagnosticSeverity::Warning) => DiagnosticLevel::Warning,
+            Some(DiagnosticSeverity::Information) => DiagnosticLevel::Information,
+            Some(DiagnosticSeverity::Hint) => DiagnosticLevel::Hint,
+            Some(DiagnosticSeverity::Error) | None => DiagnosticLevel::Error,
+        };
+
+        level as u8 <= self as u8
+    }
+}
+
+impl Default for DiagnosticLevel {
+    fn default() -> Self {
+        DiagnosticLevel::Hint
+    }
 }
 
+// Features that can be turned off per buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Feature {
+    Hover,
+    Completion,
+    SignatureHelp,
+    InlayHints,
+    DocumentHighlight,
+    CodeLens,
+}
+
+// Sent to the editor once a server is initialized
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ServerReady {
+    pub lang_id: String,
+    pub completion_trigger_characters: Vec<String>,
+    pub on_type_formatting_trigger_characters: Vec<String>,
+}
+
+// Absolute position of a semantic token with the names of its type and modifiers
+#[derive(Debug, Clone, PartialEq)]
+pub struct SemanticToken {
+    pub line: u64,
+    pub start: u64,
+    pub length: u64,
+    pub token_type: String,
+    pub token_modifiers: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CallDirection {
+    Incoming,
+    Outgoing,
+}
+
+// `B` is the editor's buffer handle, given for the events whose results are
+// shown in the buffer that sent them
 #[derive(Debug, PartialEq)]
-pub enum Event {
+pub enum Event<B: BufferId> {
     Hello,
+    // Report the started servers and the number of files they track
+    Status,
+    // The editor is quitting, servers are shut down before lspc exits
+    Exit,
     StartServer {
         lang_id: String,
         config: LsConfig,
         cur_path: String,
     },
+    StopServer {
+        lang_id: String,
+    },
+    // Start a server for a project before any of its files is opened
+    PrewarmServer {
+        lang_id: String,
+        config: LsConfig,
+        root_path: String,
+    },
     Hover {
+        buf_id: B,
         text_document: TextDocumentIdentifier,
         position: Position,
     },
+    // Ids are the ones of the server handling `text_document`
+    CancelRequest {
+        text_document: TextDocumentIdentifier,
+        id: u64,
+    },
+    MultiHover {
+        text_document: TextDocumentIdentifier,
+        positions: Vec<Position>,
+    },
     GotoDefinition {
         text_document: TextDocumentIdentifier,
         position: Position,
+        target: GotoTarget,
+    },
+    GotoDeclaration {
+        text_document: TextDocumentIdentifier,
+        position: Position,
+    },
+    GotoImplementation {
+        text_document: TextDocumentIdentifier,
+        position: Position,
+    },
+    GotoTypeDefinition {
+        text_document: TextDocumentIdentifier,
+        position: Position,
+    },
+    UpdateServerSettings {
+        lang_id: String,
+        settings: serde_json::Value,
+        // Replace the current settings instead of merging the given ones into them
+        replace: bool,
+    },
+    SetTrace {
+        lang_id: String,
+        value: TraceValue,
+    },
+    AddWorkspaceFolder {
+        lang_id: String,
+        path: String,
+    },
+    RemoveWorkspaceFolder {
+        lang_id: String,
+        path: String,
     },
     InlayHints {
+        buf_id: B,
+        text_document: TextDocumentIdentifier,
+    },
+    // Jump to what the inlay hint nearest to `position` points to
+    InlayHintGoto {
         text_document: TextDocumentIdentifier,
+        position: Position,
     },
     FormatDoc {
+        buf_id: B,
+        text_document_lines: Vec<String>,
+        text_document: TextDocumentIdentifier,
+    },
+    FormatRange {
+        buf_id: B,
         text_document_lines: Vec<String>,
         text_document: TextDocumentIdentifier,
+        range: Range,
+    },
+    // `ch` was just typed at `position`
+    OnTypeFormat {
+        buf_id: B,
+        text_document_lines: Vec<String>,
+        text_document: TextDocumentIdentifier,
+        position: Position,
+        ch: String,
     },
     DidOpen {
+        buf_id: B,
         text_document: TextDocumentIdentifier,
+        language_id: String,
     },
     DidChange {
         text_document: TextDocumentIdentifier,
@@ -77,11 +379,143 @@ pub enum Event {
     DidClose {
         text_document: TextDocumentIdentifier,
     },
+    WillSave {
+        text_document: TextDocumentIdentifier,
+        reason: TextDocumentSaveReason,
+    },
+    WillSaveWaitUntil {
+        buf_id: B,
+        text_document_lines: Vec<String>,
+        text_document: TextDocumentIdentifier,
+        reason: TextDocumentSaveReason,
+    },
+    DidSave {
+        text_document: TextDocumentIdentifier,
+    },
     References {
         text_document: TextDocumentIdentifier,
         position: Position,
         include_declaration: bool,
     },
+    Completion {
+        text_document: TextDocumentIdentifier,
+        position: Position,
+    },
+    ResolveCompletion {
+        text_document: TextDocumentIdentifier,
+        item: CompletionItem,
+    },
+    CompletionDone {
+        buf_id: B,
+        text_document_lines: Vec<String>,
+        text_document: TextDocumentIdentifier,
+        item: CompletionItem,
+    },
+    SignatureHelp {
+        text_document: TextDocumentIdentifier,
+        position: Position,
+    },
+    // Diagnostics are hidden in the buffer until they're enabled again
+    ToggleDiagnostics {
+        buf_id: B,
+        text_document: TextDocumentIdentifier,
+        enabled: bool,
+    },
+    DiagnosticsToLoclist {
+        text_document: TextDocumentIdentifier,
+    },
+    // Only the given features are changed, the others are kept
+    SetBufferFeatures {
+        text_document: TextDocumentIdentifier,
+        features: HashMap<Feature, bool>,
+    },
+    // The user is asked for the new name when it's not given, once the
+    // server agreed to rename the symbol
+    Rename {
+        text_document: TextDocumentIdentifier,
+        position: Position,
+        new_name: Option<String>,
+    },
+    CodeAction {
+        text_document: TextDocumentIdentifier,
+        range: Range,
+        diagnostics: Vec<Diagnostic>,
+    },
+    GotoWorkspaceSymbol {
+        text_document: TextDocumentIdentifier,
+        query: String,
+    },
+    WorkspaceSymbols {
+        query: String,
+    },
+    DocumentSymbols {
+        text_document: TextDocumentIdentifier,
+    },
+    CodeLens {
+        buf_id: B,
+        text_document: TextDocumentIdentifier,
+    },
+    SemanticTokens {
+        buf_id: B,
+        text_document: TextDocumentIdentifier,
+    },
+    RequestDiagnostics {
+        text_document: TextDocumentIdentifier,
+    },
+    DocumentLinks {
+        buf_id: B,
+        text_document: TextDocumentIdentifier,
+    },
+    DocumentColor {
+        buf_id: B,
+        text_document: TextDocumentIdentifier,
+    },
+    // Replace the color under the cursor by another representation of it
+    ColorPresentation {
+        buf_id: B,
+        text_document_lines: Vec<String>,
+        text_document: TextDocumentIdentifier,
+        position: Position,
+    },
+    GotoDocumentLink {
+        text_document: TextDocumentIdentifier,
+        position: Position,
+    },
+    DocumentHighlight {
+        buf_id: B,
+        text_document: TextDocumentIdentifier,
+        position: Position,
+    },
+    SelectionRange {
+        buf_id: B,
+        text_document: TextDocumentIdentifier,
+        positions: Vec<Position>,
+    },
+    // Calls are then asked for the prepared item of the document
+    PrepareCallHierarchy {
+        text_document: TextDocumentIdentifier,
+        position: Position,
+    },
+    CallHierarchyIncomingCalls {
+        text_document: TextDocumentIdentifier,
+    },
+    CallHierarchyOutgoingCalls {
+        text_document: TextDocumentIdentifier,
+    },
+    DidCreateFiles {
+        uris: Vec<Url>,
+    },
+    DidRenameFiles {
+        // (old, new)
+        renames: Vec<(Url, Url)>,
+    },
+    DidDeleteFiles {
+        uris: Vec<Url>,
+    },
+    // Changed outside the editor
+    FilesChanged {
+        changes: Vec<lsp::FileEvent>,
+    },
 }
 
 #[derive(Debug)]
@@ -104,6 +538,10 @@ impl From<EditorError> for LspcError {
 #[derive(Debug)]
 pub enum LangServerError {
     Process(io::Error),
+    // Failed to connect to the server at the address
+    Connection(String, io::Error),
+    // Nothing at the socket path yet, the server may still be starting
+    SocketNotFound(String),
     ServerDisconnected,
     InvalidRequest(String),
     InvalidNotification(String),
@@ -162,50 +600,204 @@ pub trait BufferId: Eq + std::fmt::Debug + std::hash::Hash + Copy + 'static {}
 pub trait Editor: 'static {
     type BufferId: BufferId;
 
-    fn events(&self) -> Receiver<Event>;
+    fn events(&self) -> Receiver<Event<Self::BufferId>>;
     fn capabilities(&self) -> lsp_types::ClientCapabilities;
     fn say_hello(&self) -> Result<(), EditorError>;
+    fn server_ready(&mut self, ready: &ServerReady) -> Result<(), EditorError>;
     fn message(&mut self, msg: &str) -> Result<(), EditorError>;
+    // Only shown when `buf_id` is still the focused buffer
     fn show_hover(
         &mut self,
+        buf_id: Self::BufferId,
         text_document: &TextDocumentIdentifier,
         hover: &Hover,
+        mode: HoverMode,
+    ) -> Result<(), EditorError>;
+    // Hovers of several positions, in the requested order
+    fn show_hovers(
+        &mut self,
+        text_document: &TextDocumentIdentifier,
+        hovers: &[(Position, Hover)],
     ) -> Result<(), EditorError>;
     fn inline_hints(
         &mut self,
+        buf_id: Self::BufferId,
         text_document: &TextDocumentIdentifier,
         hints: &Vec<InlayHint>,
     ) -> Result<(), EditorError>;
     fn show_message(&mut self, show_message_params: &ShowMessageParams) -> Result<(), EditorError>;
+    // Status of the progress reported with `token`, None clears it
+    fn show_progress(
+        &mut self,
+        token: &ProgressToken,
+        status: Option<&str>,
+    ) -> Result<(), EditorError>;
+    // Let the user pick one of the actions, None when cancelled
+    fn show_message_request(
+        &mut self,
+        params: &ShowMessageRequestParams,
+    ) -> Result<Option<MessageActionItem>, EditorError>;
+    fn forward_notification(
+        &mut self,
+        method: &str,
+        params: &serde_json::Value,
+    ) -> Result<(), EditorError>;
+    // Positions of the listed references, symbols, calls and diagnostics are
+    // converted to byte columns
     fn show_references(&mut self, locations: &Vec<Location>) -> Result<(), EditorError>;
-    fn goto(&mut self, location: &Location) -> Result<(), EditorError>;
-    fn apply_edits(&self, lines: &Vec<String>, edits: &Vec<TextEdit>) -> Result<(), EditorError>;
-    fn track_all_buffers(&self) -> Result<(), EditorError>;
-    fn watch_file_events(
+    fn show_completions(&mut self, items: &Vec<CompletionItem>) -> Result<(), EditorError>;
+    fn show_completion_item(&mut self, item: &CompletionItem) -> Result<(), EditorError>;
+    // Replaces the text inserted for the committed completion item by the
+    // expansion of `snippet`
+    fn expand_snippet(&mut self, buf_id: Self::BufferId, snippet: &str) -> Result<(), EditorError>;
+    fn show_signature_help(&mut self, help: &SignatureHelp) -> Result<(), EditorError>;
+    // Servers publish diagnostics by document, with no event of the editor
+    // telling its buffer, the editor finds the buffer showing it
+    fn show_diagnostics(
+        &mut self,
+        text_document: &TextDocumentIdentifier,
+        diagnostics: &Vec<Diagnostic>,
+    ) -> Result<(), EditorError>;
+    // Let the user pick one of the actions, returns the index of the chosen one
+    fn show_code_actions(
+        &mut self,
+        actions: &[CodeActionOrCommand],
+    ) -> Result<Option<usize>, EditorError>;
+    // One chain per requested position, in the requested order. Only selected
+    // when `buf_id` is still the focused buffer.
+    fn set_selection_ranges(
+        &mut self,
+        buf_id: Self::BufferId,
+        ranges: &[SelectionRange],
+    ) -> Result<(), EditorError>;
+    // Replaces the highlights of the previous request
+    fn highlight_references(
+        &mut self,
+        buf_id: Self::BufferId,
+        highlights: &[DocumentHighlight],
+    ) -> Result<(), EditorError>;
+    fn show_document_symbols(
+        &mut self,
+        symbols: &DocumentSymbolResponse,
+    ) -> Result<(), EditorError>;
+    // Diagnostics of the document in a list to jump through, by position
+    fn list_diagnostics(
         &mut self,
         text_document: &TextDocumentIdentifier,
+        diagnostics: &[Diagnostic],
+    ) -> Result<(), EditorError>;
+    // Callers or callees of `item`, each with the location to jump to:
+    // the call site for callers, the definition for callees
+    fn show_call_hierarchy(
+        &mut self,
+        direction: CallDirection,
+        item: &CallHierarchyItem,
+        calls: &[(CallHierarchyItem, Location)],
+    ) -> Result<(), EditorError>;
+    fn show_workspace_symbols(&mut self, symbols: &[SymbolInformation]) -> Result<(), EditorError>;
+    // Replaces the links previously shown in the buffer
+    fn show_document_links(
+        &mut self,
+        buf_id: Self::BufferId,
+        links: &[DocumentLink],
+    ) -> Result<(), EditorError>;
+    // Replaces the colors previously shown in the buffer
+    fn show_document_colors(
+        &mut self,
+        buf_id: Self::BufferId,
+        colors: &[ColorInformation],
+    ) -> Result<(), EditorError>;
+    // Let the user pick one of the presentations, returns the index of the chosen one
+    fn choose_color_presentation(
+        &mut self,
+        presentations: &[ColorPresentation],
+    ) -> Result<Option<usize>, EditorError>;
+    // Replaces the code lenses previously shown in the buffer
+    fn show_code_lens(
+        &mut self,
+        buf_id: Self::BufferId,
+        lenses: &[CodeLens],
+    ) -> Result<(), EditorError>;
+    // Replaces the semantic highlighting of the buffer
+    fn apply_semantic_tokens(
+        &mut self,
+        buf_id: Self::BufferId,
+        tokens: &[SemanticToken],
+    ) -> Result<(), EditorError>;
+    // Let the user pick one of the symbols, returns the index of the chosen one
+    fn choose_workspace_symbol(
+        &mut self,
+        symbols: &[WorkspaceSymbol],
+    ) -> Result<Option<usize>, EditorError>;
+    // Text typed by the user, starting from `default`. None when it's
+    // cancelled or empty.
+    fn input(&mut self, prompt: &str, default: &str) -> Result<Option<String>, EditorError>;
+    // Text of the 0-based `line` of the document, without its line ending
+    fn document_line(&self, uri: &Url, line: u64) -> Result<String, EditorError>;
+    // Current lines of the buffer, like the ones sent along the events
+    fn buffer_lines(&self, buf_id: Self::BufferId) -> Result<Vec<String>, EditorError>;
+    // Characters of the positions are counted in `encoding` code units
+    fn goto(
+        &mut self,
+        location: &Location,
+        encoding: PositionEncoding,
+        target: GotoTarget,
     ) -> Result<(), EditorError>;
+    fn apply_edits(
+        &self,
+        buf_id: Self::BufferId,
+        lines: &Vec<String>,
+        edits: &Vec<TextEdit>,
+        encoding: PositionEncoding,
+    ) -> Result<(), EditorError>;
+    fn apply_workspace_edit(
+        &mut self,
+        edit: &WorkspaceEdit,
+        encoding: PositionEncoding,
+    ) -> Result<(), EditorError>;
+    // The buffer can be written, the edits to apply before saving are done
+    fn will_save_done(&mut self, buf_id: Self::BufferId) -> Result<(), EditorError>;
+    fn track_all_buffers(&self) -> Result<(), EditorError>;
+    fn watch_file_events(&mut self, buf_id: Self::BufferId) -> Result<(), EditorError>;
 }
 
 pub struct Lspc<E: Editor> {
     editor: E,
     lsp_handlers: Vec<LangServerHandler<E>>,
-    tracking_files: HashMap<Url, TrackingFile>,
+    // One per server the file is open in
+    tracking_files: HashMap<Url, Vec<TrackingFile>>,
+    // Last published or pulled diagnostics of each server by handler id,
+    // kept even when not rendered
+    diagnostics: Rc<RefCell<HashMap<Url, BTreeMap<u64, Vec<Diagnostic>>>>>,
+    // Buffers whose diagnostics are hidden, with the document they show
+    suppressed_diagnostics: Rc<RefCell<HashMap<E::BufferId, Url>>>,
+    disabled_features: HashMap<Url, HashSet<Feature>>,
+    // Last received links, shared with the request callbacks
+    document_links: Rc<RefCell<HashMap<Url, Vec<DocumentLink>>>>,
+    document_colors: Rc<RefCell<HashMap<Url, Vec<ColorInformation>>>>,
+    // Positions of the rendered inlay hints that have a label location
+    inlay_hint_locations: Rc<RefCell<HashMap<Url, Vec<(Position, Location)>>>>,
+    // Last prepared call hierarchy item of each document
+    call_hierarchy_items: Rc<RefCell<HashMap<Url, CallHierarchyItem>>>,
+    // Configs of the started servers by handler id, to restart them
+    server_configs: HashMap<u64, LsConfig>,
     next_handler_id: u64,
 }
 
 #[derive(Debug)]
-enum SelectedMsg {
-    Editor(Event),
+enum SelectedMsg<B: BufferId> {
+    Editor(Event<B>),
     Lsp(usize, LspMessage),
+    // The connection to the server was closed
+    LspExited(usize),
     TimerTick,
 }
 
 fn select<E: Editor>(
-    event_receiver: &Receiver<Event>,
+    event_receiver: &Receiver<Event<E::BufferId>>,
     timer_tick: &Receiver<Instant>,
     handlers: &Vec<LangServerHandler<E>>,
-) -> SelectedMsg {
+) -> SelectedMsg<E::BufferId> {
     let mut sel = Select::new();
 
     sel.recv(event_receiver);
@@ -225,26 +817,32 @@ fn select<E: Editor>(
             oper.recv(timer_tick).unwrap();
             SelectedMsg::TimerTick
         }
-        i => {
-            let lsp_msg = oper.recv(handlers[i - 2].receiver()).unwrap();
-
-            SelectedMsg::Lsp(i - 2, lsp_msg)
-        }
+        i => match oper.recv(handlers[i - 2].receiver()) {
+            Ok(lsp_msg) => SelectedMsg::Lsp(i - 2, lsp_msg),
+            Err(_) => SelectedMsg::LspExited(i - 2),
+        },
     }
 }
 
-fn find_root_path<'a>(mut cur_path: &'a Path, root_marker: &Vec<String>) -> Option<&'a Path> {
-    if cur_path.is_file() {
-        cur_path = cur_path.parent()?;
-    }
-    loop {
-        if root_marker
-            .iter()
-            .any(|marker| cur_path.join(marker).exists())
-        {
-            return Some(cur_path);
-        }
-        cur_path = cur_path.parent()?;
+// Closest ancestor containing a root marker, the file's directory when there
+// is none unless a marker is required
+fn find_root_path<'a>(
+    cur_path: &'a Path,
+    root_marker: &Vec<String>,
+    require_root_marker: bool,
+) -> Option<&'a Path> {
+    let cur_dir = if cur_path.is_dir() {
+        cur_path
+    } else {
+        cur_path.parent()?
+    };
+    let marked = cur_dir
+        .ancestors()
+        .find(|path| root_marker.iter().any(|marker| path.join(marker).exists()));
+    if require_root_marker {
+        marked
+    } else {
+        marked.or(Some(cur_dir))
     }
 }
 
@@ -252,387 +850,5708 @@ fn to_file_url(s: &str) -> Option<Url> {
     Url::from_file_path(s).ok()
 }
 
-// Get the handler of a file by checking
-// if that handler's root is ancestor of `file_path`
-fn handler_of<'a, E>(
-    handlers: &'a mut Vec<LangServerHandler<E>>,
-    file_path: &str,
-) -> Option<&'a mut LangServerHandler<E>>
+// Resolved items only need to carry the lazily computed fields,
+// keep everything else from the item that is already displayed.
+fn merge_resolved_completion(mut item: CompletionItem, resolved: CompletionItem) -> CompletionItem {
+    if resolved.base.documentation.is_some() {
+        item.base.documentation = resolved.base.documentation;
+    }
+    if resolved.base.additional_text_edits.is_some() {
+        item.base.additional_text_edits = resolved.base.additional_text_edits;
+    }
+    if item.base.detail.is_none() {
+        item.base.detail = resolved.base.detail;
+    }
+    if item.label_details.is_none() {
+        item.label_details = resolved.label_details;
+    }
+    item
+}
+
+// Text to expand of a snippet item, None for plain text items
+pub fn completion_snippet(item: &CompletionItem) -> Option<&str> {
+    if item.base.insert_text_format != Some(lsp::InsertTextFormat::Snippet) {
+        return None;
+    }
+
+    let snippet = match (&item.base.text_edit, &item.base.insert_text) {
+        (Some(edit), _) => &edit.new_text,
+        (None, Some(insert_text)) => insert_text,
+        (None, None) => &item.base.label,
+    };
+    Some(snippet)
+}
+
+fn supports_snippets(capabilities: &lsp::ClientCapabilities) -> bool {
+    capabilities
+        .text_document
+        .as_ref()
+        .and_then(|text_document| text_document.completion.as_ref())
+        .and_then(|completion| completion.completion_item.as_ref())
+        .and_then(|completion_item| completion_item.snippet_support)
+        .unwrap_or(false)
+}
+
+// Get the handlers of a file by checking if their root is ancestor of the
+// file. All the servers of its language get it, it falls back to the first
+// server including it when none is of its language.
+fn handlers_of<'a, E>(
+    handlers: &'a [LangServerHandler<E>],
+    uri: &Url,
+    language_id: &str,
+) -> Vec<&'a LangServerHandler<E>>
 where
     E: Editor,
 {
-    handlers
-        .iter_mut()
-        .find(|handler| handler.include_file(file_path))
-}
+    let including = handlers
+        .iter()
+        .filter(|handler| handler.include_file(uri))
+        .collect::<Vec<_>>();
+    let same_language = including
+        .iter()
+        .filter(|handler| handler.lang_id == language_id)
+        .cloned()
+        .collect::<Vec<_>>();
 
-impl<E: Editor> Lspc<E> {
-    fn handler_for_file(
-        &mut self,
-        uri: &Url,
-    ) -> Option<(&mut LangServerHandler<E>, &mut TrackingFile, &mut E)> {
-        let tracking_file = self.tracking_files.get_mut(uri)?;
-        let handler = self
-            .lsp_handlers
-            .iter_mut()
-            .find(|handler| handler.id == tracking_file.handler_id)?;
-        Some((handler, tracking_file, &mut self.editor))
+    if same_language.is_empty() {
+        including.into_iter().take(1).collect()
+    } else {
+        same_language
     }
+}
 
-    fn handle_editor_event(&mut self, event: Event) -> Result<(), LspcError> {
-        match event {
-            Event::Hello => {
-                self.editor.say_hello().map_err(|e| LspcError::Editor(e))?;
+// Client capabilities handled by lspc itself that lsp-types can't express
+fn extend_capabilities(capabilities: lsp::ClientCapabilities) -> serde_json::Value {
+    let mut capabilities = serde_json::to_value(capabilities).unwrap_or_default();
+    merge_json(
+        &mut capabilities,
+        json!({
+            "general": { "positionEncodings": ["utf-8", "utf-32", "utf-16"] },
+            "window": { "workDoneProgress": true },
+            "workspace": {
+                "configuration": true,
+                "workspaceFolders": true,
+                "symbol": {
+                    "resolveSupport": { "properties": ["location.range"] }
+                },
+                "didChangeWatchedFiles": { "dynamicRegistration": true },
+                "fileOperations": {
+                    "dynamicRegistration": true,
+                    "didCreate": true,
+                    "didRename": true,
+                    "didDelete": true
+                }
+            },
+            "textDocument": {
+                "selectionRange": { "dynamicRegistration": false },
+                "formatting": { "dynamicRegistration": true },
+                "rangeFormatting": { "dynamicRegistration": true },
+                "publishDiagnostics": { "versionSupport": true },
+                "documentLink": { "dynamicRegistration": false },
+                "inlayHint": { "dynamicRegistration": false },
+                "colorProvider": { "dynamicRegistration": false },
+                "callHierarchy": { "dynamicRegistration": false },
+                "diagnostic": { "dynamicRegistration": false, "relatedDocumentSupport": false },
+                "completion": {
+                    "completionItem": { "labelDetailsSupport": true }
+                },
+                "semanticTokens": {
+                    "dynamicRegistration": false,
+                    "requests": { "full": { "delta": true } },
+                    "tokenTypes": [
+                        "namespace", "type", "class", "enum", "interface", "struct",
+                        "typeParameter", "parameter", "variable", "property", "enumMember",
+                        "event", "function", "method", "macro", "keyword", "modifier",
+                        "comment", "string", "number", "regexp", "operator"
+                    ],
+                    "tokenModifiers": [
+                        "declaration", "definition", "readonly", "static", "deprecated",
+                        "abstract", "async", "modification", "documentation", "defaultLibrary"
+                    ],
+                    "formats": ["relative"]
+                }
             }
-            Event::StartServer {
-                lang_id,
-                config,
-                cur_path,
-            } => {
-                let capabilities = self.editor.capabilities();
-                let lang_settings = LangSettings {
-                    indentation: config.indentation,
-                    indentation_with_space: config.indentation_with_space,
-                };
-
-                let cur_path = PathBuf::from(cur_path);
-                let root = find_root_path(&cur_path, &config.root_markers)
-                    .map(|path| path.to_str())
-                    .ok_or_else(|| LspcError::Editor(EditorError::RootPathNotFound))?
-                    .ok_or_else(|| LspcError::Editor(EditorError::RootPathNotFound))?;
-
-                let root_url =
-                    to_file_url(&root).ok_or(LspcError::Editor(EditorError::RootPathNotFound))?;
-
-                self.next_handler_id += 1;
-                let mut lsp_handler = LangServerHandler::new(
-                    self.next_handler_id,
-                    lang_id,
-                    &config.command[0],
-                    lang_settings,
-                    &config.command[1..],
-                    root.to_owned(),
-                )
-                .map_err(|e| LspcError::LangServer(e))?;
-
-                let init_params = lsp_types::InitializeParams {
-                    process_id: Some(std::process::id() as u64),
-                    root_path: Some(root.into()),
-                    root_uri: Some(root_url),
-                    initialization_options: None,
-                    capabilities,
-                    trace: None,
-                    workspace_folders: None,
-                };
-                lsp_handler.lsp_request::<Initialize>(
-                    &init_params,
-                    Box::new(|editor: &mut E, handler, response| {
-                        handler.initialize_response(response)?;
+        }),
+    );
 
-                        editor.message("LangServer initialized")?;
-                        editor.track_all_buffers()?;
-                        Ok(())
-                    }),
-                )?;
+    capabilities
+}
 
-                self.lsp_handlers.push(lsp_handler);
+fn merge_json(base: &mut serde_json::Value, ext: serde_json::Value) {
+    match (base, ext) {
+        (serde_json::Value::Object(base), serde_json::Value::Object(ext)) => {
+            for (key, value) in ext {
+                merge_json(base.entry(key).or_insert(serde_json::Value::Null), value);
             }
-            Event::Hover {
-                text_document,
-                position,
-            } => {
-                let (handler, _, _) =
-                    self.handler_for_file(&text_document.uri).ok_or_else(|| {
-                        log::info!("Nontracking file: {:?}", text_document);
-                        MainLoopError::IgnoredMessage
-                    })?;
-                let text_document_clone = text_document.clone();
-                let params = lsp_types::TextDocumentPositionParams {
-                    text_document,
-                    position,
-                };
-                handler.lsp_request::<HoverRequest>(
-                    &params,
-                    Box::new(move |editor: &mut E, _handler, response| {
-                        if let Some(hover) = response {
-                            editor.show_hover(&text_document_clone, &hover)?;
+        }
+        (base, ext) => *base = ext,
+    }
+}
+
+// Jump straight to a single location, let the user choose when there are many
+fn goto_locations<E: Editor>(
+    editor: &mut E,
+    encoding: PositionEncoding,
+    target: GotoTarget,
+    response: Option<GotoDefinitionResponse>,
+) -> Result<(), EditorError> {
+    let locations = match response {
+        Some(GotoDefinitionResponse::Scalar(location)) => vec![location],
+        Some(GotoDefinitionResponse::Array(array)) => array,
+        Some(GotoDefinitionResponse::Link(links)) => links.iter().map(location_from_link).collect(),
+        None => Vec::new(),
+    };
+    if locations.len() == 1 {
+        editor.goto(&locations[0], encoding, target)?;
+    } else if !locations.is_empty() {
+        let locations = EditorColumns::new(&*editor, encoding).locations(&locations)?;
+        editor.show_references(&locations)?;
+    }
+
+    Ok(())
+}
+
+// Cursor goes to the start of the target's identifier (`target_selection_range`),
+// not the whole definition (`target_range`)
+fn location_from_link(link: &LocationLink) -> Location {
+    let start = link.target_selection_range.start;
+
+    Location::new(link.target_uri.clone(), Range::new(start, start))
+}
+
+// Collects responses of requests sent together, keeping the order
+// the requests were sent in regardless of the order responses arrive
+struct BatchResponses<T> {
+    responses: Vec<Option<T>>,
+    remaining: usize,
+}
+
+impl<T> BatchResponses<T> {
+    fn new(len: usize) -> Self {
+        BatchResponses {
+            responses: (0..len).map(|_| None).collect(),
+            remaining: len,
+        }
+    }
+
+    // Store the response of the `index`th request, returns all the
+    // responses once the last one arrives
+    fn insert(&mut self, index: usize, response: T) -> Option<Vec<T>> {
+        if self.responses[index].replace(response).is_none() {
+            self.remaining -= 1;
+        }
+
+        if self.remaining == 0 {
+            Some(self.responses.drain(..).flatten().collect())
+        } else {
+            None
+        }
+    }
+}
+
+// Shown once every server answered, or failed to
+fn show_workspace_symbols<E: Editor>(
+    editor: &mut E,
+    responses: Option<Vec<Vec<SymbolInformation>>>,
+) -> Result<(), LspcError> {
+    let symbols = match responses {
+        Some(responses) => responses.into_iter().flatten().collect::<Vec<_>>(),
+        None => return Ok(()),
+    };
+    if symbols.is_empty() {
+        editor.message("No symbols found")?;
+    } else {
+        editor.show_workspace_symbols(&symbols)?;
+    }
+
+    Ok(())
+}
+
+// Shown once every position is answered, or failed to be
+fn show_hovers<E: Editor>(
+    editor: &mut E,
+    text_document: &TextDocumentIdentifier,
+    responses: Option<Vec<Option<(Position, Hover)>>>,
+) -> Result<(), LspcError> {
+    let hovers = match responses {
+        Some(responses) => responses.into_iter().flatten().collect::<Vec<_>>(),
+        None => return Ok(()),
+    };
+    if !hovers.is_empty() {
+        editor.show_hovers(text_document, &hovers)?;
+    }
+
+    Ok(())
+}
+
+fn to_symbol_information(symbol: WorkspaceSymbol) -> SymbolInformation {
+    let location = match symbol.location {
+        WorkspaceSymbolLocation::Location(location) => location,
+        WorkspaceSymbolLocation::Uri { uri } => {
+            Location::new(uri, Range::new(Position::new(0, 0), Position::new(0, 0)))
+        }
+    };
+
+    SymbolInformation {
+        name: symbol.name,
+        kind: symbol.kind,
+        deprecated: None,
+        location,
+        container_name: symbol.container_name,
+    }
+}
+
+// Lazy symbols only carry an uri, resolve their range before jumping
+fn goto_workspace_symbol<E: Editor>(
+    editor: &mut E,
+    handler: &mut LangServerHandler<E>,
+    symbol: &WorkspaceSymbol,
+) -> Result<(), LspcError> {
+    match symbol.location {
+        WorkspaceSymbolLocation::Location(ref location) => {
+            editor.goto(location, handler.position_encoding(), GotoTarget::Edit)?
+        }
+        WorkspaceSymbolLocation::Uri { .. } => {
+            handler.lsp_request::<WorkspaceSymbolResolve>(
+                symbol,
+                Box::new(|editor: &mut E, handler, resolved| {
+                    let location = match resolved.location {
+                        WorkspaceSymbolLocation::Location(location) => location,
+                        // Still unresolved, jump to the file
+                        WorkspaceSymbolLocation::Uri { uri } => {
+                            Location::new(uri, Range::new(Position::new(0, 0), Position::new(0, 0)))
                         }
-                        Ok(())
-                    }),
-                )?;
+                    };
+                    editor.goto(&location, handler.position_encoding(), GotoTarget::Edit)?;
+
+                    Ok(())
+                }),
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+// Lenses without a command are resolved first, all of them are shown together
+fn show_code_lenses<E: Editor>(
+    editor: &mut E,
+    handler: &mut LangServerHandler<E>,
+    buf_id: E::BufferId,
+    lenses: Vec<CodeLens>,
+) -> Result<(), LspcError> {
+    if lenses.iter().all(|lens| lens.command.is_some()) || !handler.can_resolve_code_lens() {
+        editor.show_code_lens(buf_id, &lenses)?;
+        return Ok(());
+    }
+
+    let batch = Rc::new(RefCell::new(BatchResponses::new(lenses.len())));
+    for (index, lens) in lenses.into_iter().enumerate() {
+        if lens.command.is_some() {
+            batch.borrow_mut().insert(index, lens);
+            continue;
+        }
+        let batch = Rc::clone(&batch);
+        let failed_batch = Rc::clone(&batch);
+        let unresolved = lens.clone();
+        handler.lsp_request_or_else::<CodeLensResolve>(
+            &lens,
+            Box::new(move |editor: &mut E, _handler, resolved| {
+                if let Some(lenses) = batch.borrow_mut().insert(index, resolved) {
+                    editor.show_code_lens(buf_id, &lenses)?;
+                }
+
+                Ok(())
+            }),
+            // Kept unresolved, the other lenses are still shown
+            Box::new(move |editor: &mut E, _handler| {
+                if let Some(lenses) = failed_batch.borrow_mut().insert(index, unresolved) {
+                    editor.show_code_lens(buf_id, &lenses)?;
+                }
+
+                Ok(())
+            }),
+        )?;
+    }
+
+    Ok(())
+}
+
+// Each token is encoded as 5 integers: line delta, start delta (relative to the
+// previous token's start when on the same line), length, type index and
+// modifiers bitset. Tokens of types missing from the legend are skipped.
+fn decode_semantic_tokens(legend: &SemanticTokensLegend, data: &[u64]) -> Vec<SemanticToken> {
+    let mut tokens = Vec::new();
+    let (mut line, mut start) = (0, 0);
+    for encoded in data.chunks_exact(5) {
+        if encoded[0] > 0 {
+            line += encoded[0];
+            start = encoded[1];
+        } else {
+            start += encoded[1];
+        }
+        let token_type = match legend.token_types.get(encoded[3] as usize) {
+            Some(token_type) => token_type.clone(),
+            None => continue,
+        };
+        let token_modifiers = legend
+            .token_modifiers
+            .iter()
+            .enumerate()
+            .filter(|(bit, _)| *bit < 64 && encoded[4] & (1 << bit) != 0)
+            .map(|(_, modifier)| modifier.clone())
+            .collect();
+        tokens.push(SemanticToken {
+            line,
+            start,
+            length: encoded[2],
+            token_type,
+            token_modifiers,
+        });
+    }
+
+    tokens
+}
+
+// Edits index the previous array, they're applied from the last one so the
+// earlier indices stay valid
+fn apply_semantic_tokens_edits(data: &mut Vec<u64>, mut edits: Vec<SemanticTokensEdit>) {
+    edits.sort_by_key(|edit| edit.start);
+    for edit in edits.into_iter().rev() {
+        let start = edit.start.min(data.len());
+        let end = (start + edit.delete_count).min(data.len());
+        data.splice(start..end, edit.data.unwrap_or_default());
+    }
+}
+
+// The tokens are cached for the next delta
+fn show_semantic_tokens<E: Editor>(
+    editor: &mut E,
+    handler: &LangServerHandler<E>,
+    legend: &SemanticTokensLegend,
+    buf_id: E::BufferId,
+    uri: &Url,
+    cache: &RefCell<Option<SemanticTokens>>,
+    tokens: SemanticTokens,
+) -> Result<(), LspcError> {
+    let decoded = decode_semantic_tokens(legend, &tokens.data);
+    let decoded =
+        EditorColumns::new(&*editor, handler.position_encoding()).semantic_tokens(uri, decoded)?;
+    editor.apply_semantic_tokens(buf_id, &decoded)?;
+    *cache.borrow_mut() = Some(tokens);
+
+    Ok(())
+}
+
+// Only files can be opened, the fragment (e.g. `#L12`) is ignored
+fn goto_document_link<E: Editor>(editor: &mut E, target: &Url) -> Result<(), EditorError> {
+    if target.scheme() != "file" {
+        return editor.message(&format!("Can't open {}", target));
+    }
+    let mut uri = target.clone();
+    uri.set_fragment(None);
+
+    // The start of the file is the same in every encoding
+    editor.goto(
+        &Location::new(uri, Range::new(Position::new(0, 0), Position::new(0, 0))),
+        PositionEncoding::default(),
+        GotoTarget::Edit,
+    )
+}
+
+// Edits replacing the color at `range` by `presentation`
+fn color_presentation_edits(range: Range, presentation: &ColorPresentation) -> Vec<TextEdit> {
+    let mut edits = vec![presentation
+        .text_edit
+        .clone()
+        .unwrap_or_else(|| TextEdit::new(range, presentation.label.clone()))];
+    if let Some(ref additional_edits) = presentation.additional_text_edits {
+        edits.extend(additional_edits.iter().cloned());
+    }
+
+    edits
+}
+
+fn diagnostics_suppressed<B: BufferId>(suppressed: &HashMap<B, Url>, uri: &Url) -> bool {
+    suppressed
+        .values()
+        .any(|suppressed_uri| suppressed_uri == uri)
+}
+
+// Diagnostics of suppressed documents are kept but not shown
+// Diagnostics of `handler_id` replace its previous ones, the ones of the
+// other servers of the document are shown along them
+fn store_diagnostics<E: Editor>(
+    editor: &mut E,
+    all_diagnostics: &RefCell<HashMap<Url, BTreeMap<u64, Vec<Diagnostic>>>>,
+    suppressed_diagnostics: &RefCell<HashMap<E::BufferId, Url>>,
+    handler_id: u64,
+    text_document: TextDocumentIdentifier,
+    diagnostics: Vec<Diagnostic>,
+) -> Result<(), LspcError> {
+    let mut all_diagnostics = all_diagnostics.borrow_mut();
+    let file_diagnostics = all_diagnostics
+        .entry(text_document.uri.clone())
+        .or_insert_with(BTreeMap::new);
+    file_diagnostics.insert(handler_id, diagnostics);
+    if !diagnostics_suppressed(&suppressed_diagnostics.borrow(), &text_document.uri) {
+        editor.show_diagnostics(&text_document, &merge_diagnostics(file_diagnostics))?;
+    }
+
+    Ok(())
+}
+
+fn merge_diagnostics(diagnostics: &BTreeMap<u64, Vec<Diagnostic>>) -> Vec<Diagnostic> {
+    diagnostics.values().flatten().cloned().collect()
+}
+
+// `text` is only sent when the server asks for it, nothing is sent if it
+// doesn't want to know about saves
+fn notify_did_save<E: Editor>(
+    handler: &mut LangServerHandler<E>,
+    text_document: TextDocumentIdentifier,
+    text: Option<String>,
+) -> Result<(), LspcError> {
+    let text = match handler.save_include_text() {
+        Some(true) => {
+            if text.is_none() {
+                log::warn!(
+                    "Text of {} is not tracked, saved without it",
+                    text_document.uri
+                );
             }
-            Event::GotoDefinition {
-                text_document,
-                position,
-            } => {
-                let (handler, _, _) =
-                    self.handler_for_file(&text_document.uri).ok_or_else(|| {
-                        log::info!("Nontracking file: {:?}", text_document);
-                        MainLoopError::IgnoredMessage
-                    })?;
-                let params = lsp_types::TextDocumentPositionParams {
-                    text_document,
-                    position,
+            text
+        }
+        Some(false) => None,
+        None => return Ok(()),
+    };
+    handler.lsp_notify::<noti::DidSaveTextDocument>(&lsp::DidSaveTextDocumentParams {
+        text_document,
+        text,
+    })?;
+
+    Ok(())
+}
+
+// Servers may format more than asked, only keep the edits inside `range`
+fn edits_within(range: &Range, edits: Vec<TextEdit>) -> Vec<TextEdit> {
+    edits
+        .into_iter()
+        .filter(|edit| {
+            range_contains(range, edit.range.start) && range_contains(range, edit.range.end)
+        })
+        .collect()
+}
+
+fn range_contains(range: &Range, position: Position) -> bool {
+    let pos = |position: &Position| (position.line, position.character);
+
+    pos(&range.start) <= pos(&position) && pos(&position) <= pos(&range.end)
+}
+
+// Rendered like rust-analyzer's hints, the parts of the label are joined
+fn to_inlay_hint(hint: StandardInlayHint) -> InlayHint {
+    let label = match hint.label {
+        InlayHintLabel::String(label) => label,
+        InlayHintLabel::Parts(parts) => parts.into_iter().map(|part| part.value).collect(),
+    };
+    let kind = match hint.kind {
+        Some(2) => InlayKind::ParameterHint,
+        _ => InlayKind::TypeHint,
+    };
+
+    InlayHint {
+        range: Range::new(hint.position, hint.position),
+        kind,
+        label,
+    }
+}
+
+// Location of the first label part that has one
+fn inlay_hint_location(hint: &StandardInlayHint) -> Option<&Location> {
+    match hint.label {
+        InlayHintLabel::String(_) => None,
+        InlayHintLabel::Parts(ref parts) => parts.iter().find_map(|part| part.location.as_ref()),
+    }
+}
+
+// Hints are rendered at the end of their line, pick the one of the cursor's
+// line that is closest to the cursor
+fn inlay_hint_location_at(
+    locations: &[(Position, Location)],
+    position: Position,
+) -> Option<&Location> {
+    locations
+        .iter()
+        .filter(|(hint_position, _)| hint_position.line == position.line)
+        .min_by_key(|(hint_position, _)| {
+            (hint_position.character as i64 - position.character as i64).abs()
+        })
+        .map(|(_, location)| location)
+}
+
+fn ranges_overlap(a: &Range, b: &Range) -> bool {
+    let start = |range: &Range| (range.start.line, range.start.character);
+    let end = |range: &Range| (range.end.line, range.end.character);
+
+    start(a) <= end(b) && start(b) <= end(a)
+}
+
+// Columns of the editor are byte indexes, servers count the characters of
+// positions in their encoding
+fn server_position<E: Editor>(
+    editor: &E,
+    uri: &Url,
+    position: Position,
+    encoding: PositionEncoding,
+) -> Result<Position, EditorError> {
+    if encoding == PositionEncoding::Utf8 {
+        return Ok(position);
+    }
+    let line = editor.document_line(uri, position.line)?;
+    let character = encoding.character(&line, position.character as usize);
+
+    Ok(Position::new(position.line, character as u64))
+}
+
+// Converts positions of a server to the byte columns of the editor, for the
+// lists the editor shows. Each line is only fetched once.
+struct EditorColumns<'a, E: Editor> {
+    editor: &'a E,
+    encoding: PositionEncoding,
+    lines: HashMap<(Url, u64), String>,
+}
+
+impl<'a, E: Editor> EditorColumns<'a, E> {
+    fn new(editor: &'a E, encoding: PositionEncoding) -> Self {
+        EditorColumns {
+            editor,
+            encoding,
+            lines: HashMap::new(),
+        }
+    }
+
+    fn position(&mut self, uri: &Url, position: Position) -> Result<Position, EditorError> {
+        if self.encoding == PositionEncoding::Utf8 {
+            return Ok(position);
+        }
+        let key = (uri.clone(), position.line);
+        if !self.lines.contains_key(&key) {
+            let line = self.editor.document_line(uri, position.line)?;
+            self.lines.insert(key.clone(), line);
+        }
+        let byte_offset = self
+            .encoding
+            .byte_offset(&self.lines[&key], position.character as usize);
+
+        Ok(Position::new(position.line, byte_offset as u64))
+    }
+
+    fn range(&mut self, uri: &Url, range: Range) -> Result<Range, EditorError> {
+        Ok(Range::new(
+            self.position(uri, range.start)?,
+            self.position(uri, range.end)?,
+        ))
+    }
+
+    // Each range is converted, the rest is kept
+    fn ranged<T: Clone>(
+        &mut self,
+        uri: &Url,
+        items: &[T],
+        range_of: impl Fn(&mut T) -> &mut Range,
+    ) -> Result<Vec<T>, EditorError> {
+        items
+            .iter()
+            .map(|item| {
+                let mut item = item.clone();
+                let range = self.range(uri, *range_of(&mut item))?;
+                *range_of(&mut item) = range;
+                Ok(item)
+            })
+            .collect()
+    }
+
+    fn semantic_tokens(
+        &mut self,
+        uri: &Url,
+        tokens: Vec<SemanticToken>,
+    ) -> Result<Vec<SemanticToken>, EditorError> {
+        tokens
+            .into_iter()
+            .map(|mut token| {
+                let start = self.position(uri, Position::new(token.line, token.start))?;
+                let end =
+                    self.position(uri, Position::new(token.line, token.start + token.length))?;
+                token.start = start.character;
+                token.length = end.character - start.character;
+                Ok(token)
+            })
+            .collect()
+    }
+
+    fn location(&mut self, location: &Location) -> Result<Location, EditorError> {
+        let range = self.range(&location.uri, location.range)?;
+        Ok(Location::new(location.uri.clone(), range))
+    }
+
+    fn locations(&mut self, locations: &[Location]) -> Result<Vec<Location>, EditorError> {
+        locations
+            .iter()
+            .map(|location| self.location(location))
+            .collect()
+    }
+
+    fn document_symbols(
+        &mut self,
+        uri: &Url,
+        symbols: DocumentSymbolResponse,
+    ) -> Result<DocumentSymbolResponse, EditorError> {
+        Ok(match symbols {
+            DocumentSymbolResponse::Flat(symbols) => DocumentSymbolResponse::Flat(
+                symbols
+                    .into_iter()
+                    .map(|mut symbol| {
+                        symbol.location = self.location(&symbol.location)?;
+                        Ok(symbol)
+                    })
+                    .collect::<Result<_, EditorError>>()?,
+            ),
+            DocumentSymbolResponse::Nested(symbols) => {
+                DocumentSymbolResponse::Nested(self.nested_symbols(uri, symbols)?)
+            }
+        })
+    }
+
+    fn nested_symbols(
+        &mut self,
+        uri: &Url,
+        symbols: Vec<DocumentSymbol>,
+    ) -> Result<Vec<DocumentSymbol>, EditorError> {
+        symbols
+            .into_iter()
+            .map(|mut symbol| {
+                symbol.range = self.range(uri, symbol.range)?;
+                symbol.selection_range = self.range(uri, symbol.selection_range)?;
+                symbol.children = match symbol.children.take() {
+                    Some(children) => Some(self.nested_symbols(uri, children)?),
+                    None => None,
                 };
-                handler.lsp_request::<GotoDefinition>(
-                    &params,
-                    Box::new(move |editor: &mut E, _handler, response| {
-                        if let Some(definition) = response {
-                            match definition {
-                                GotoDefinitionResponse::Scalar(location) => {
-                                    editor.goto(&location)?;
-                                }
-                                GotoDefinitionResponse::Array(array) => {
-                                    if array.len() == 1 {
-                                        editor.goto(&array[0])?;
-                                    }
-                                }
-                                _ => {
-                                    // FIXME: support Array & Link
-                                }
-                            }
-                        }
+                Ok(symbol)
+            })
+            .collect()
+    }
+}
 
-                        Ok(())
-                    }),
-                )?;
+// Identifier around the byte column `character` of `line`
+fn word_at(line: &str, character: usize) -> &str {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    let mut character = character.min(line.len());
+    while !line.is_char_boundary(character) {
+        character -= 1;
+    }
+    let start = line[..character]
+        .char_indices()
+        .rev()
+        .take_while(|(_, c)| is_word(*c))
+        .last()
+        .map_or(character, |(start, _)| start);
+    let end = line[character..]
+        .find(|c| !is_word(c))
+        .map_or(line.len(), |end| character + end);
+
+    &line[start..end]
+}
+
+// Text of the single line `range` of the document, in `encoding` code units
+fn range_text<E: Editor>(
+    editor: &E,
+    uri: &Url,
+    range: Range,
+    encoding: PositionEncoding,
+) -> Result<String, EditorError> {
+    if range.start.line != range.end.line {
+        return Ok(String::new());
+    }
+    let line = editor.document_line(uri, range.start.line)?;
+    let start = encoding.byte_offset(&line, range.start.character as usize);
+    let end = encoding.byte_offset(&line, range.end.character as usize);
+
+    Ok(line.get(start..end).unwrap_or_default().to_owned())
+}
+
+// Rename to `new_name`, or to the name the user types from `default`
+fn request_rename<E: Editor>(
+    editor: &mut E,
+    handler: &mut LangServerHandler<E>,
+    text_document_position: lsp::TextDocumentPositionParams,
+    new_name: Option<String>,
+    default: &str,
+) -> Result<(), LspcError> {
+    let new_name = match new_name {
+        Some(new_name) => new_name,
+        None => match editor.input("Rename to: ", default)? {
+            Some(new_name) => new_name,
+            None => return Ok(()),
+        },
+    };
+    let params = lsp::RenameParams {
+        text_document_position,
+        new_name,
+    };
+
+    handler.lsp_request::<Rename>(
+        &params,
+        Box::new(|editor: &mut E, handler, response| {
+            if let Some(edit) = response {
+                editor.apply_workspace_edit(&edit, handler.position_encoding())?;
             }
-            Event::InlayHints { text_document } => {
-                let (handler, _, _) =
-                    self.handler_for_file(&text_document.uri).ok_or_else(|| {
-                        log::info!("Nontracking file: {:?}", text_document);
-                        MainLoopError::IgnoredMessage
-                    })?;
-                let text_document_clone = text_document.clone();
-                let params = InlayHintsParams { text_document };
-                handler.lsp_request::<InlayHints>(
-                    &params,
-                    Box::new(move |editor: &mut E, _handler, response| {
-                        editor.inline_hints(&text_document_clone, &response)?;
 
-                        Ok(())
-                    }),
-                )?;
-            }
-            Event::FormatDoc {
-                text_document_lines,
-                text_document,
-            } => {
-                let (handler, _, _) =
-                    self.handler_for_file(&text_document.uri).ok_or_else(|| {
-                        log::info!("Nontracking file: {:?}", text_document);
-                        MainLoopError::IgnoredMessage
-                    })?;
-                let options = FormattingOptions {
-                    tab_size: handler.lang_settings.indentation,
-                    insert_spaces: handler.lang_settings.indentation_with_space,
-                    properties: HashMap::new(),
-                };
-                let params = DocumentFormattingParams {
-                    text_document,
-                    options,
-                };
-                handler.lsp_request::<Formatting>(
-                    &params,
-                    Box::new(move |editor: &mut E, _handler, response| {
-                        if let Some(edits) = response {
-                            editor.apply_edits(&text_document_lines, &edits)?;
-                        }
+            Ok(())
+        }),
+    )?;
+
+    Ok(())
+}
+
+impl<E: Editor> Lspc<E> {
+    // The first started of the servers the file is open in
+    fn handler_for_file(
+        &mut self,
+        uri: &Url,
+    ) -> Option<(&mut LangServerHandler<E>, &mut TrackingFile, &mut E)> {
+        let tracking_files = self.tracking_files.get_mut(uri)?;
+        let handler = self.lsp_handlers.iter_mut().find(|handler| {
+            tracking_files
+                .iter()
+                .any(|tracking_file| tracking_file.handler_id == handler.id)
+        })?;
+        let tracking_file = tracking_files
+            .iter_mut()
+            .find(|tracking_file| tracking_file.handler_id == handler.id)?;
+        Some((handler, tracking_file, &mut self.editor))
+    }
+
+    // The first started of the servers the file is open in that supports
+    // `method`, the first started one when none does
+    fn handler_for_request(
+        &mut self,
+        uri: &Url,
+        method: &str,
+    ) -> Option<(&mut LangServerHandler<E>, &mut TrackingFile, &mut E)> {
+        let tracking_files = self.tracking_files.get_mut(uri)?;
+        let mut handlers = self
+            .lsp_handlers
+            .iter_mut()
+            .filter(|handler| {
+                tracking_files
+                    .iter()
+                    .any(|tracking_file| tracking_file.handler_id == handler.id)
+            })
+            .collect::<Vec<_>>();
+        if handlers.is_empty() {
+            return None;
+        }
+        let index = handlers
+            .iter()
+            .position(|handler| handler.supports(method))
+            .unwrap_or(0);
+        let handler = handlers.swap_remove(index);
+        let tracking_file = tracking_files
+            .iter_mut()
+            .find(|tracking_file| tracking_file.handler_id == handler.id)?;
+        Some((handler, tracking_file, &mut self.editor))
+    }
+
+    // All the servers the file is open in, in the order they were started
+    fn handlers_for_file(
+        &mut self,
+        uri: &Url,
+    ) -> Vec<(&mut LangServerHandler<E>, &mut TrackingFile)> {
+        let mut tracking_files = match self.tracking_files.get_mut(uri) {
+            Some(tracking_files) => tracking_files.iter_mut().collect::<Vec<_>>(),
+            None => return Vec::new(),
+        };
+
+        self.lsp_handlers
+            .iter_mut()
+            .filter_map(|handler| {
+                let index = tracking_files
+                    .iter()
+                    .position(|tracking_file| tracking_file.handler_id == handler.id)?;
+                Some((handler, tracking_files.swap_remove(index)))
+            })
+            .collect()
+    }
+
+    // Cursor `position` of the editor, in the encoding of the server that
+    // handles `method` for the file
+    fn cursor_position(
+        &mut self,
+        uri: &Url,
+        method: &str,
+        position: Position,
+    ) -> Result<Position, EditorError> {
+        let encoding = self
+            .handler_for_request(uri, method)
+            .map(|(handler, _, _)| handler.position_encoding())
+            .unwrap_or_default();
+
+        server_position(&self.editor, uri, position, encoding)
+    }
+
+    // The diagnostics published by the server are withdrawn as well, the
+    // documents are shown with the ones of their other servers
+    fn untrack_files_of(&mut self, handler_id: u64) -> Result<(), LspcError> {
+        self.tracking_files.retain(|_, tracking_files| {
+            tracking_files.retain(|tracking_file| tracking_file.handler_id != handler_id);
+            !tracking_files.is_empty()
+        });
+
+        let mut affected = Vec::new();
+        self.diagnostics
+            .borrow_mut()
+            .retain(|uri, file_diagnostics| {
+                if file_diagnostics.remove(&handler_id).is_some() {
+                    affected.push((uri.clone(), merge_diagnostics(file_diagnostics)));
+                }
+                !file_diagnostics.is_empty()
+            });
+        for (uri, diagnostics) in affected {
+            if !diagnostics_suppressed(&self.suppressed_diagnostics.borrow(), &uri) {
+                self.editor
+                    .show_diagnostics(&TextDocumentIdentifier::new(uri), &diagnostics)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Changes waiting for the sync timer are sent right away, so requests
+    // about the file see its latest text
+    fn flush_file(&mut self, uri: &Url) -> Result<(), LspcError> {
+        for (handler, tracking_file) in self.handlers_for_file(uri) {
+            if tracking_file.scheduled_sync_at.is_some() {
+                if let Some(params) = tracking_file.fetch_pending_changes() {
+                    handler.lsp_notify::<noti::DidChangeTextDocument>(&params)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn start_server(
+        &mut self,
+        lang_id: String,
+        config: LsConfig,
+        root: &str,
+    ) -> Result<(), LspcError> {
+        let lang_settings = LangSettings {
+            indentation: config.indentation,
+            indentation_with_space: config.indentation_with_space,
+            sync_burst_threshold: config.sync_burst_threshold,
+            sync_delay_ms: config.sync_delay_ms,
+            forward_notifications: config.forward_notifications,
+            hover_mode: config.hover_mode,
+            format_on_save: config.format_on_save,
+            min_diagnostic_severity: config.min_diagnostic_severity,
+            request_timeout_ms: config.request_timeout_ms,
+            trace: config.trace,
+        };
+
+        self.next_handler_id += 1;
+        let mut lsp_handler = LangServerHandler::new(
+            self.next_handler_id,
+            lang_id,
+            &config,
+            lang_settings,
+            root.to_owned(),
+        )
+        .map_err(|e| LspcError::LangServer(e))?;
+        lsp_handler.set_settings(config.settings.clone());
+        lsp_handler.set_initialization_options(config.initialization_options.clone());
+        for folder in &config.workspace_folders {
+            lsp_handler.add_workspace_folder(folder.clone())?;
+        }
+
+        self.initialize_server(lsp_handler, root)?;
+        self.server_configs.insert(self.next_handler_id, config);
+
+        Ok(())
+    }
+
+    // Servers crashing before being initialized aren't restarted, they would
+    // likely crash again
+    fn handle_server_exit(&mut self, index: usize) -> Result<(), LspcError> {
+        let handler = self.lsp_handlers.remove(index);
+        self.untrack_files_of(handler.id)?;
+        let config = self.server_configs.remove(&handler.id);
+        self.editor
+            .message(&format!("{} server exited unexpectedly", handler.lang_id))?;
+
+        match config {
+            Some(config) if config.restart_on_crash && !handler.initializing() => {
+                log::info!("Restarting {} server", handler.lang_id);
+                // Tracked files are opened again once it's initialized
+                self.start_server(handler.lang_id.clone(), config, handler.root_path())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Files under `root` opened from now on are handled by `lsp_handler`
+    fn initialize_server(
+        &mut self,
+        mut lsp_handler: LangServerHandler<E>,
+        root: &str,
+    ) -> Result<(), LspcError> {
+        let capabilities = extend_capabilities(self.editor.capabilities());
+        let root_url = to_file_url(root).ok_or(LspcError::Editor(EditorError::RootPathNotFound))?;
+
+        let init_params = InitializeParams {
+            process_id: Some(std::process::id() as u64),
+            root_path: Some(root.into()),
+            root_uri: Some(root_url),
+            initialization_options: lsp_handler.initialization_options(),
+            capabilities,
+            trace: lsp_handler.lang_settings.trace,
+            workspace_folders: Some(lsp_handler.workspace_folders()),
+        };
+        lsp_handler.lsp_request::<Initialize>(
+            &init_params,
+            Box::new(|editor: &mut E, handler, response| {
+                handler.initialize_response(response)?;
+
+                editor.server_ready(&ServerReady {
+                    lang_id: handler.lang_id.clone(),
+                    completion_trigger_characters: handler.completion_trigger_characters().to_vec(),
+                    on_type_formatting_trigger_characters: handler
+                        .on_type_formatting_trigger_characters()
+                        .to_vec(),
+                })?;
+                editor.message("LangServer initialized")?;
+                editor.track_all_buffers()?;
+                Ok(())
+            }),
+        )?;
+        lsp_handler
+            .expect_initialize_by(Instant::now() + Duration::from_millis(INITIALIZE_TIMEOUT_MS));
+
+        self.lsp_handlers.push(lsp_handler);
+
+        Ok(())
+    }
+
+    // Diagnostics of all the servers of the document
+    fn file_diagnostics(&self, uri: &Url) -> Vec<Diagnostic> {
+        self.diagnostics
+            .borrow()
+            .get(uri)
+            .map(merge_diagnostics)
+            .unwrap_or_default()
+    }
+
+    // Diagnostics of all the servers of the document, in the byte columns of
+    // the editor
+    fn editor_diagnostics(&self, uri: &Url) -> Result<Vec<Diagnostic>, EditorError> {
+        let all_diagnostics = self.diagnostics.borrow();
+        let file_diagnostics = match all_diagnostics.get(uri) {
+            Some(file_diagnostics) => file_diagnostics,
+            None => return Ok(Vec::new()),
+        };
+
+        let mut editor_diagnostics = Vec::new();
+        for (handler_id, diagnostics) in file_diagnostics {
+            let encoding = self
+                .lsp_handlers
+                .iter()
+                .find(|handler| handler.id == *handler_id)
+                .map(|handler| handler.position_encoding())
+                .unwrap_or_default();
+            let mut columns = EditorColumns::new(&self.editor, encoding);
+            for diagnostic in diagnostics {
+                let mut diagnostic = diagnostic.clone();
+                diagnostic.range = columns.range(uri, diagnostic.range)?;
+                editor_diagnostics.push(diagnostic);
+            }
+        }
+
+        Ok(editor_diagnostics)
+    }
+
+    fn feature_enabled(&self, uri: &Url, feature: Feature) -> bool {
+        self.disabled_features
+            .get(uri)
+            .map_or(true, |disabled| !disabled.contains(&feature))
+    }
+
+    pub(crate) fn handle_editor_event(
+        &mut self,
+        event: Event<E::BufferId>,
+    ) -> Result<(), LspcError> {
+        match event {
+            Event::Hello => {
+                self.editor.say_hello().map_err(|e| LspcError::Editor(e))?;
+            }
+            Event::Status => {
+                let statuses = self
+                    .lsp_handlers
+                    .iter()
+                    .map(|handler| {
+                        let tracked = self
+                            .tracking_files
+                            .values()
+                            .flatten()
+                            .filter(|tracking_file| tracking_file.handler_id == handler.id)
+                            .count();
+                        format!(
+                            "{}: {}, {} file{} tracked",
+                            handler.lang_id,
+                            handler.server_name(),
+                            tracked,
+                            if tracked == 1 { "" } else { "s" }
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                if statuses.is_empty() {
+                    self.editor.message("No server started")?;
+                } else {
+                    self.editor.message(&statuses.join("; "))?;
+                }
+            }
+            Event::Exit => {
+                self.tracking_files.clear();
+                self.server_configs.clear();
+                // Failures are logged, the editor is going away anyway
+                let _ = LangServerHandler::shutdown_all(
+                    &mut self.lsp_handlers,
+                    Duration::from_millis(SHUTDOWN_TIMEOUT_MS),
+                );
+                self.lsp_handlers.clear();
+            }
+            Event::StartServer {
+                lang_id,
+                config,
+                cur_path,
+            } => {
+                let cur_path = PathBuf::from(cur_path);
+                let root =
+                    find_root_path(&cur_path, &config.root_markers, config.require_root_marker)
+                        .map(|path| path.to_str())
+                        .ok_or_else(|| LspcError::Editor(EditorError::RootPathNotFound))?
+                        .ok_or_else(|| LspcError::Editor(EditorError::RootPathNotFound))?;
+
+                self.start_server(lang_id, config, root)?;
+            }
+            Event::PrewarmServer {
+                lang_id,
+                config,
+                root_path,
+            } => {
+                self.start_server(lang_id, config, &root_path)?;
+            }
+            // Every server started for the language is stopped
+            Event::StopServer { lang_id } => {
+                if !self
+                    .lsp_handlers
+                    .iter()
+                    .any(|handler| handler.lang_id == lang_id)
+                {
+                    return Err(LspcError::NotStarted);
+                }
+                let mut stopped = Vec::new();
+                while let Some(index) = self
+                    .lsp_handlers
+                    .iter()
+                    .position(|handler| handler.lang_id == lang_id)
+                {
+                    let handler = self.lsp_handlers.remove(index);
+                    self.untrack_files_of(handler.id)?;
+                    self.server_configs.remove(&handler.id);
+                    stopped.push(handler);
+                }
+
+                LangServerHandler::shutdown_all(
+                    &mut stopped,
+                    Duration::from_millis(SHUTDOWN_TIMEOUT_MS),
+                )?;
+            }
+            Event::Hover {
+                buf_id,
+                text_document,
+                position,
+            } => {
+                if !self.feature_enabled(&text_document.uri, Feature::Hover) {
+                    return Ok(());
+                }
+                self.flush_file(&text_document.uri)?;
+                let (handler, tracking_file, editor) = self
+                    .handler_for_request(&text_document.uri, HoverRequest::METHOD)
+                    .ok_or_else(|| {
+                        log::info!("Nontracking file: {:?}", text_document);
+                        MainLoopError::IgnoredMessage
+                    })?;
+                if !handler.supports(HoverRequest::METHOD) {
+                    editor.message("Server doesn't support hover")?;
+                    return Ok(());
+                }
+                // Only the latest hover of the buffer is shown
+                if let Some(id) = tracking_file.pending_hover.take() {
+                    handler.cancel(id)?;
+                }
+                let text_document_clone = text_document.clone();
+                let hover_mode = handler.lang_settings.hover_mode;
+                let position = server_position(
+                    editor,
+                    &text_document.uri,
+                    position,
+                    handler.position_encoding(),
+                )?;
+                let params = lsp_types::TextDocumentPositionParams {
+                    text_document,
+                    position,
+                };
+                let id = handler.lsp_request::<HoverRequest>(
+                    &params,
+                    Box::new(move |editor: &mut E, _handler, response| {
+                        if let Some(hover) = response {
+                            editor.show_hover(buf_id, &text_document_clone, &hover, hover_mode)?;
+                        }
+                        Ok(())
+                    }),
+                )?;
+                tracking_file.pending_hover = Some(id);
+            }
+            Event::CancelRequest { text_document, id } => {
+                let (handler, _, _) =
+                    self.handler_for_file(&text_document.uri).ok_or_else(|| {
+                        log::info!("Nontracking file: {:?}", text_document);
+                        MainLoopError::IgnoredMessage
+                    })?;
+                handler.cancel(id)?;
+            }
+            Event::GotoDefinition {
+                text_document,
+                position,
+                target,
+            } => {
+                self.flush_file(&text_document.uri)?;
+                let (handler, _, editor) = self
+                    .handler_for_request(&text_document.uri, GotoDefinition::METHOD)
+                    .ok_or_else(|| {
+                        log::info!("Nontracking file: {:?}", text_document);
+                        MainLoopError::IgnoredMessage
+                    })?;
+                if !handler.supports(GotoDefinition::METHOD) {
+                    editor.message("Server doesn't support goto definition")?;
+                    return Ok(());
+                }
+                let position = server_position(
+                    editor,
+                    &text_document.uri,
+                    position,
+                    handler.position_encoding(),
+                )?;
+                let params = lsp_types::TextDocumentPositionParams {
+                    text_document,
+                    position,
+                };
+                handler.lsp_request::<GotoDefinition>(
+                    &params,
+                    Box::new(move |editor: &mut E, handler, response| {
+                        goto_locations(editor, handler.position_encoding(), target, response)?;
+
+                        Ok(())
+                    }),
+                )?;
+            }
+            Event::UpdateServerSettings {
+                lang_id,
+                settings,
+                replace,
+            } => {
+                let mut handlers = self
+                    .lsp_handlers
+                    .iter_mut()
+                    .filter(|handler| handler.lang_id == lang_id)
+                    .peekable();
+                if handlers.peek().is_none() {
+                    return Err(LspcError::NotStarted);
+                }
+                for handler in handlers {
+                    handler.update_settings(settings.clone(), replace)?;
+                }
+            }
+            Event::SetTrace { lang_id, value } => {
+                let mut handlers = self
+                    .lsp_handlers
+                    .iter_mut()
+                    .filter(|handler| handler.lang_id == lang_id)
+                    .peekable();
+                if handlers.peek().is_none() {
+                    return Err(LspcError::NotStarted);
+                }
+                for handler in handlers {
+                    handler.set_trace(value)?;
+                }
+            }
+            Event::AddWorkspaceFolder { lang_id, path } => {
+                let handler = self
+                    .lsp_handlers
+                    .iter_mut()
+                    .find(|handler| handler.lang_id == lang_id)
+                    .ok_or(LspcError::NotStarted)?;
+                handler.add_workspace_folder(path)?;
+            }
+            Event::RemoveWorkspaceFolder { lang_id, path } => {
+                let handler = self
+                    .lsp_handlers
+                    .iter_mut()
+                    .find(|handler| handler.lang_id == lang_id)
+                    .ok_or(LspcError::NotStarted)?;
+                handler.remove_workspace_folder(&path)?;
+                self.tracking_files.retain(|uri, tracking_files| {
+                    tracking_files.retain(|tracking_file| {
+                        tracking_file.handler_id != handler.id || handler.include_file(uri)
+                    });
+                    !tracking_files.is_empty()
+                });
+            }
+            // Servers are only told about the files matching their filters
+            Event::DidCreateFiles { uris } => {
+                for handler in &mut self.lsp_handlers {
+                    let files = uris
+                        .iter()
+                        .filter(|uri| handler.wants_file_operation(DidCreateFiles::METHOD, uri))
+                        .map(|uri| FileCreate { uri: uri.clone() })
+                        .collect::<Vec<_>>();
+                    if !files.is_empty() {
+                        handler.lsp_notify::<DidCreateFiles>(&CreateFilesParams { files })?;
+                    }
+                }
+            }
+            Event::FilesChanged { changes } => {
+                for handler in &mut self.lsp_handlers {
+                    let changes = changes
+                        .iter()
+                        .filter(|change| handler.wants_watched_file(change))
+                        .cloned()
+                        .collect::<Vec<_>>();
+                    if !changes.is_empty() {
+                        handler.lsp_notify::<noti::DidChangeWatchedFiles>(
+                            &lsp::DidChangeWatchedFilesParams { changes },
+                        )?;
+                    }
+                }
+            }
+            Event::DidRenameFiles { renames } => {
+                for handler in &mut self.lsp_handlers {
+                    let files = renames
+                        .iter()
+                        .filter(|(old_uri, new_uri)| {
+                            handler.wants_file_operation(DidRenameFiles::METHOD, old_uri)
+                                || handler.wants_file_operation(DidRenameFiles::METHOD, new_uri)
+                        })
+                        .map(|(old_uri, new_uri)| FileRename {
+                            old_uri: old_uri.clone(),
+                            new_uri: new_uri.clone(),
+                        })
+                        .collect::<Vec<_>>();
+                    if !files.is_empty() {
+                        handler.lsp_notify::<DidRenameFiles>(&RenameFilesParams { files })?;
+                    }
+                }
+            }
+            Event::DidDeleteFiles { uris } => {
+                for handler in &mut self.lsp_handlers {
+                    let files = uris
+                        .iter()
+                        .filter(|uri| handler.wants_file_operation(DidDeleteFiles::METHOD, uri))
+                        .map(|uri| FileDelete { uri: uri.clone() })
+                        .collect::<Vec<_>>();
+                    if !files.is_empty() {
+                        handler.lsp_notify::<DidDeleteFiles>(&DeleteFilesParams { files })?;
+                    }
+                }
+            }
+            Event::GotoTypeDefinition {
+                text_document,
+                position,
+            } => {
+                self.flush_file(&text_document.uri)?;
+                let (handler, _, editor) = self
+                    .handler_for_request(&text_document.uri, GotoTypeDefinition::METHOD)
+                    .ok_or_else(|| {
+                        log::info!("Nontracking file: {:?}", text_document);
+                        MainLoopError::IgnoredMessage
+                    })?;
+                if !handler.supports(GotoTypeDefinition::METHOD) {
+                    editor.message("Server doesn't support goto type definition")?;
+                    return Ok(());
+                }
+                let position = server_position(
+                    editor,
+                    &text_document.uri,
+                    position,
+                    handler.position_encoding(),
+                )?;
+                let params = lsp_types::TextDocumentPositionParams {
+                    text_document,
+                    position,
+                };
+                handler.lsp_request::<GotoTypeDefinition>(
+                    &params,
+                    Box::new(move |editor: &mut E, handler, response| {
+                        goto_locations(
+                            editor,
+                            handler.position_encoding(),
+                            GotoTarget::Edit,
+                            response,
+                        )?;
+
+                        Ok(())
+                    }),
+                )?;
+            }
+            Event::GotoDeclaration {
+                text_document,
+                position,
+            } => {
+                self.flush_file(&text_document.uri)?;
+                let (handler, _, editor) = self
+                    .handler_for_request(&text_document.uri, GotoDeclaration::METHOD)
+                    .ok_or_else(|| {
+                        log::info!("Nontracking file: {:?}", text_document);
+                        MainLoopError::IgnoredMessage
+                    })?;
+                if !handler.supports(GotoDeclaration::METHOD) {
+                    editor.message("Server doesn't support goto declaration")?;
+                    return Ok(());
+                }
+                let position = server_position(
+                    editor,
+                    &text_document.uri,
+                    position,
+                    handler.position_encoding(),
+                )?;
+                let params = lsp_types::TextDocumentPositionParams {
+                    text_document,
+                    position,
+                };
+                handler.lsp_request::<GotoDeclaration>(
+                    &params,
+                    Box::new(move |editor: &mut E, handler, response| {
+                        goto_locations(
+                            editor,
+                            handler.position_encoding(),
+                            GotoTarget::Edit,
+                            response,
+                        )?;
+
+                        Ok(())
+                    }),
+                )?;
+            }
+            Event::GotoImplementation {
+                text_document,
+                position,
+            } => {
+                self.flush_file(&text_document.uri)?;
+                let (handler, _, editor) = self
+                    .handler_for_request(&text_document.uri, GotoImplementation::METHOD)
+                    .ok_or_else(|| {
+                        log::info!("Nontracking file: {:?}", text_document);
+                        MainLoopError::IgnoredMessage
+                    })?;
+                if !handler.can_goto_implementation() {
+                    editor.message("Server doesn't support goto implementation")?;
+                    return Ok(());
+                }
+                let position = server_position(
+                    editor,
+                    &text_document.uri,
+                    position,
+                    handler.position_encoding(),
+                )?;
+                let params = lsp_types::TextDocumentPositionParams {
+                    text_document,
+                    position,
+                };
+                handler.lsp_request::<GotoImplementation>(
+                    &params,
+                    Box::new(move |editor: &mut E, handler, response| {
+                        goto_locations(
+                            editor,
+                            handler.position_encoding(),
+                            GotoTarget::Edit,
+                            response,
+                        )?;
+
+                        Ok(())
+                    }),
+                )?;
+            }
+            Event::InlayHints {
+                buf_id,
+                text_document,
+            } => {
+                if !self.feature_enabled(&text_document.uri, Feature::InlayHints) {
+                    return Ok(());
+                }
+                self.flush_file(&text_document.uri)?;
+                let (handler, _, _) = self
+                    .handler_for_request(&text_document.uri, InlayHintRequest::METHOD)
+                    .ok_or_else(|| {
+                        log::info!("Nontracking file: {:?}", text_document);
+                        MainLoopError::IgnoredMessage
+                    })?;
+                if handler.quirks.rust_analyzer_inlay_hints {
+                    let text_document_clone = text_document.clone();
+                    let params = InlayHintsParams { text_document };
+                    handler.lsp_request::<InlayHints>(
+                        &params,
+                        Box::new(move |editor: &mut E, _handler, response| {
+                            editor.inline_hints(buf_id, &text_document_clone, &response)?;
+
+                            Ok(())
+                        }),
+                    )?;
+                    return Ok(());
+                }
+
+                let params = InlayHintParams {
+                    text_document: text_document.clone(),
+                    // Whole document, servers clamp it to the document's end
+                    range: Range::new(Position::new(0, 0), Position::new(i32::MAX as u64, 0)),
+                };
+                let inlay_hint_locations = Rc::clone(&self.inlay_hint_locations);
+                handler.lsp_request::<InlayHintRequest>(
+                    &params,
+                    Box::new(move |editor: &mut E, _handler, response| {
+                        let hints = response.unwrap_or_default();
+                        let locations = hints
+                            .iter()
+                            .filter_map(|hint| {
+                                inlay_hint_location(hint)
+                                    .map(|location| (hint.position, location.clone()))
+                            })
+                            .collect();
+                        inlay_hint_locations
+                            .borrow_mut()
+                            .insert(text_document.uri.clone(), locations);
+
+                        let hints = hints.into_iter().map(to_inlay_hint).collect::<Vec<_>>();
+                        editor.inline_hints(buf_id, &text_document, &hints)?;
+
+                        Ok(())
+                    }),
+                )?;
+            }
+            Event::InlayHintGoto {
+                text_document,
+                position,
+            } => {
+                let encoding = self
+                    .handler_for_request(&text_document.uri, InlayHintRequest::METHOD)
+                    .map(|(handler, _, _)| handler.position_encoding())
+                    .unwrap_or_default();
+                let position =
+                    server_position(&self.editor, &text_document.uri, position, encoding)?;
+                let location = self
+                    .inlay_hint_locations
+                    .borrow()
+                    .get(&text_document.uri)
+                    .and_then(|locations| inlay_hint_location_at(locations, position))
+                    .cloned();
+                match location {
+                    Some(location) => self.editor.goto(&location, encoding, GotoTarget::Edit)?,
+                    None => self.editor.message("No inlay hint location on this line")?,
+                }
+            }
+            Event::FormatDoc {
+                buf_id,
+                text_document_lines,
+                text_document,
+            } => {
+                self.flush_file(&text_document.uri)?;
+                let (handler, _, editor) = self
+                    .handler_for_request(&text_document.uri, Formatting::METHOD)
+                    .ok_or_else(|| {
+                        log::info!("Nontracking file: {:?}", text_document);
+                        MainLoopError::IgnoredMessage
+                    })?;
+                if !handler.supports(Formatting::METHOD) {
+                    editor.message("Server doesn't support formatting")?;
+                    return Ok(());
+                }
+                let options = FormattingOptions {
+                    tab_size: handler.lang_settings.indentation,
+                    insert_spaces: handler.lang_settings.indentation_with_space,
+                    properties: HashMap::new(),
+                };
+                let params = DocumentFormattingParams {
+                    text_document,
+                    options,
+                };
+                handler.lsp_request::<Formatting>(
+                    &params,
+                    Box::new(move |editor: &mut E, handler, response| {
+                        if let Some(edits) = response {
+                            let encoding = handler.position_encoding();
+                            editor.apply_edits(buf_id, &text_document_lines, &edits, encoding)?;
+                        }
+
+                        Ok(())
+                    }),
+                )?;
+            }
+            Event::FormatRange {
+                buf_id,
+                text_document_lines,
+                text_document,
+                range,
+            } => {
+                self.flush_file(&text_document.uri)?;
+                let (handler, _, editor) = self
+                    .handler_for_request(&text_document.uri, RangeFormatting::METHOD)
+                    .ok_or_else(|| {
+                        log::info!("Nontracking file: {:?}", text_document);
+                        MainLoopError::IgnoredMessage
+                    })?;
+                if !handler.supports(RangeFormatting::METHOD) {
+                    editor.message("Server doesn't support range formatting")?;
+                    return Ok(());
+                }
+                let options = FormattingOptions {
+                    tab_size: handler.lang_settings.indentation,
+                    insert_spaces: handler.lang_settings.indentation_with_space,
+                    properties: HashMap::new(),
+                };
+                let params = lsp::DocumentRangeFormattingParams {
+                    text_document,
+                    range,
+                    options,
+                };
+                handler.lsp_request::<RangeFormatting>(
+                    &params,
+                    Box::new(move |editor: &mut E, handler, response| {
+                        if let Some(edits) = response {
+                            let edits = edits_within(&range, edits);
+                            let encoding = handler.position_encoding();
+                            editor.apply_edits(buf_id, &text_document_lines, &edits, encoding)?;
+                        }
+
+                        Ok(())
+                    }),
+                )?;
+            }
+            Event::OnTypeFormat {
+                buf_id,
+                text_document_lines,
+                text_document,
+                position,
+                ch,
+            } => {
+                self.flush_file(&text_document.uri)?;
+                let (handler, _, editor) = self
+                    .handler_for_request(&text_document.uri, OnTypeFormatting::METHOD)
+                    .ok_or_else(|| {
+                        log::info!("Nontracking file: {:?}", text_document);
+                        MainLoopError::IgnoredMessage
+                    })?;
+                if !handler.supports(OnTypeFormatting::METHOD) {
+                    log::info!("Server doesn't support {}", OnTypeFormatting::METHOD);
+                    return Ok(());
+                }
+                if !handler
+                    .on_type_formatting_trigger_characters()
+                    .contains(&ch)
+                {
+                    log::debug!("Not an on type formatting trigger: {:?}", ch);
+                    return Ok(());
+                }
+                let options = FormattingOptions {
+                    tab_size: handler.lang_settings.indentation,
+                    insert_spaces: handler.lang_settings.indentation_with_space,
+                    properties: HashMap::new(),
+                };
+                let position = server_position(
+                    editor,
+                    &text_document.uri,
+                    position,
+                    handler.position_encoding(),
+                )?;
+                let params = lsp::DocumentOnTypeFormattingParams {
+                    text_document_position: lsp::TextDocumentPositionParams {
+                        text_document,
+                        position,
+                    },
+                    ch,
+                    options,
+                };
+                handler.lsp_request::<OnTypeFormatting>(
+                    &params,
+                    Box::new(move |editor: &mut E, handler, response| {
+                        if let Some(edits) = response {
+                            let encoding = handler.position_encoding();
+                            editor.apply_edits(buf_id, &text_document_lines, &edits, encoding)?;
+                        }
+
+                        Ok(())
+                    }),
+                )?;
+            }
+            Event::References {
+                text_document,
+                position,
+                include_declaration,
+            } => {
+                self.flush_file(&text_document.uri)?;
+                let (handler, _, editor) = self
+                    .handler_for_request(&text_document.uri, References::METHOD)
+                    .ok_or_else(|| {
+                        log::info!("Nontracking file: {:?}", text_document);
+                        MainLoopError::IgnoredMessage
+                    })?;
+                if !handler.supports(References::METHOD) {
+                    editor.message("Server doesn't support references")?;
+                    return Ok(());
+                }
+                let position = server_position(
+                    editor,
+                    &text_document.uri,
+                    position,
+                    handler.position_encoding(),
+                )?;
+                let params = lsp::ReferenceParams {
+                    text_document_position: lsp::TextDocumentPositionParams {
+                        text_document,
+                        position,
+                    },
+                    context: lsp::ReferenceContext {
+                        include_declaration,
+                    },
+                };
+
+                handler.lsp_request::<References>(
+                    &params,
+                    Box::new(move |editor: &mut E, handler, response| {
+                        let locations = EditorColumns::new(&*editor, handler.position_encoding())
+                            .locations(&response.unwrap_or_default())?;
+                        editor.show_references(&locations)?;
+
+                        Ok(())
+                    }),
+                )?;
+            }
+            Event::Completion {
+                text_document,
+                position,
+            } => {
+                if !self.feature_enabled(&text_document.uri, Feature::Completion) {
+                    return Ok(());
+                }
+                self.flush_file(&text_document.uri)?;
+                let (handler, _, editor) = self
+                    .handler_for_request(&text_document.uri, CompletionRequest::METHOD)
+                    .ok_or_else(|| {
+                        log::info!("Nontracking file: {:?}", text_document);
+                        MainLoopError::IgnoredMessage
+                    })?;
+                if !handler.supports(CompletionRequest::METHOD) {
+                    log::info!("Server doesn't support {}", CompletionRequest::METHOD);
+                    return Ok(());
+                }
+                let position = server_position(
+                    editor,
+                    &text_document.uri,
+                    position,
+                    handler.position_encoding(),
+                )?;
+                let params = lsp::CompletionParams {
+                    text_document_position: lsp::TextDocumentPositionParams {
+                        text_document,
+                        position,
+                    },
+                    context: None,
+                };
+
+                handler.lsp_request::<CompletionRequest>(
+                    &params,
+                    Box::new(move |editor: &mut E, _handler, response| {
+                        if let Some(response) = response {
+                            let items = match response {
+                                CompletionResponse::Array(items) => items,
+                                CompletionResponse::List(list) => list.items,
+                            };
+                            editor.show_completions(&items)?;
+                        }
+
+                        Ok(())
+                    }),
+                )?;
+            }
+            Event::ResolveCompletion {
+                text_document,
+                item,
+            } => {
+                let (handler, _, _) = self
+                    .handler_for_request(&text_document.uri, CompletionRequest::METHOD)
+                    .ok_or_else(|| {
+                        log::info!("Nontracking file: {:?}", text_document);
+                        MainLoopError::IgnoredMessage
+                    })?;
+                if !handler.can_resolve_completion() {
+                    return Ok(());
+                }
+
+                let params = item.clone();
+                handler.lsp_request::<ResolveCompletionItem>(
+                    &params,
+                    Box::new(move |editor: &mut E, _handler, resolved| {
+                        let item = merge_resolved_completion(item, resolved);
+                        editor.show_completion_item(&item)?;
+
+                        Ok(())
+                    }),
+                )?;
+            }
+            Event::CompletionDone {
+                buf_id,
+                text_document_lines,
+                text_document,
+                item,
+            } => {
+                let snippet = completion_snippet(&item)
+                    .filter(|_| supports_snippets(&self.editor.capabilities()))
+                    .map(str::to_owned);
+                let (handler, _, editor) = self
+                    .handler_for_request(&text_document.uri, CompletionRequest::METHOD)
+                    .ok_or_else(|| {
+                        log::info!("Nontracking file: {:?}", text_document);
+                        MainLoopError::IgnoredMessage
+                    })?;
+
+                // Edits like auto-imports are usually only computed on resolve.
+                // They replace the buffer with the edited lines, so the snippet
+                // is only expanded afterwards. The buffer may have changed while
+                // resolving, the edits are applied to its current lines.
+                if item.base.additional_text_edits.is_none() && handler.can_resolve_completion() {
+                    handler.lsp_request::<ResolveCompletionItem>(
+                        &item,
+                        Box::new(move |editor: &mut E, handler, resolved| {
+                            if let Some(edits) = resolved.base.additional_text_edits {
+                                let encoding = handler.position_encoding();
+                                let lines = editor.buffer_lines(buf_id)?;
+                                editor.apply_edits(buf_id, &lines, &edits, encoding)?;
+                            }
+                            if let Some(snippet) = snippet {
+                                editor.expand_snippet(buf_id, &snippet)?;
+                            }
+
+                            Ok(())
+                        }),
+                    )?;
+                } else {
+                    if let Some(edits) = item.base.additional_text_edits {
+                        let encoding = handler.position_encoding();
+                        editor.apply_edits(buf_id, &text_document_lines, &edits, encoding)?;
+                    }
+                    if let Some(snippet) = snippet {
+                        editor.expand_snippet(buf_id, &snippet)?;
+                    }
+                }
+            }
+            Event::SignatureHelp {
+                text_document,
+                position,
+            } => {
+                if !self.feature_enabled(&text_document.uri, Feature::SignatureHelp) {
+                    return Ok(());
+                }
+                self.flush_file(&text_document.uri)?;
+                let (handler, _, editor) = self
+                    .handler_for_request(&text_document.uri, SignatureHelpRequest::METHOD)
+                    .ok_or_else(|| {
+                        log::info!("Nontracking file: {:?}", text_document);
+                        MainLoopError::IgnoredMessage
+                    })?;
+                if !handler.supports(SignatureHelpRequest::METHOD) {
+                    log::info!("Server doesn't support {}", SignatureHelpRequest::METHOD);
+                    return Ok(());
+                }
+                let position = server_position(
+                    editor,
+                    &text_document.uri,
+                    position,
+                    handler.position_encoding(),
+                )?;
+                let params = lsp_types::TextDocumentPositionParams {
+                    text_document,
+                    position,
+                };
+
+                handler.lsp_request::<SignatureHelpRequest>(
+                    &params,
+                    Box::new(move |editor: &mut E, _handler, response| {
+                        let help = response.unwrap_or_else(|| SignatureHelp {
+                            signatures: Vec::new(),
+                            active_signature: None,
+                            active_parameter: None,
+                        });
+                        editor.show_signature_help(&help)?;
+
+                        Ok(())
+                    }),
+                )?;
+            }
+            Event::DiagnosticsToLoclist { text_document } => {
+                let mut diagnostics = self.editor_diagnostics(&text_document.uri)?;
+                if diagnostics.is_empty() {
+                    self.editor.message("No diagnostics")?;
+                    return Ok(());
+                }
+                diagnostics.sort_by_key(|diagnostic| {
+                    (
+                        diagnostic.range.start.line,
+                        diagnostic.range.start.character,
+                    )
+                });
+                self.editor.list_diagnostics(&text_document, &diagnostics)?;
+            }
+            Event::ToggleDiagnostics {
+                buf_id,
+                text_document,
+                enabled,
+            } => {
+                if enabled {
+                    self.suppressed_diagnostics.borrow_mut().remove(&buf_id);
+                    let diagnostics = self.file_diagnostics(&text_document.uri);
+                    self.editor.show_diagnostics(&text_document, &diagnostics)?;
+                } else {
+                    self.suppressed_diagnostics
+                        .borrow_mut()
+                        .insert(buf_id, text_document.uri.clone());
+                    self.editor.show_diagnostics(&text_document, &Vec::new())?;
+                }
+            }
+            Event::SetBufferFeatures {
+                text_document,
+                features,
+            } => {
+                let disabled = self
+                    .disabled_features
+                    .entry(text_document.uri)
+                    .or_insert_with(HashSet::new);
+                for (feature, enabled) in features {
+                    if enabled {
+                        disabled.remove(&feature);
+                    } else {
+                        disabled.insert(feature);
+                    }
+                }
+            }
+            Event::Rename {
+                text_document,
+                position,
+                new_name,
+            } => {
+                self.flush_file(&text_document.uri)?;
+                let (handler, _, editor) = self
+                    .handler_for_request(&text_document.uri, Rename::METHOD)
+                    .ok_or_else(|| {
+                        log::info!("Nontracking file: {:?}", text_document);
+                        MainLoopError::IgnoredMessage
+                    })?;
+                if !handler.supports(Rename::METHOD) {
+                    editor.message("Server doesn't support rename")?;
+                    return Ok(());
+                }
+                let cursor = position;
+                let position = server_position(
+                    editor,
+                    &text_document.uri,
+                    position,
+                    handler.position_encoding(),
+                )?;
+                let params = lsp::TextDocumentPositionParams {
+                    text_document,
+                    position,
+                };
+
+                // The server tells whether the symbol can be renamed before
+                // the user is asked for the new name
+                if handler.can_prepare_rename() {
+                    handler.lsp_request::<PrepareRenameRequest>(
+                        &params.clone(),
+                        Box::new(move |editor: &mut E, handler, response| {
+                            let default = match response {
+                                Some(lsp::PrepareRenameResponse::RangeWithPlaceholder {
+                                    placeholder,
+                                    ..
+                                }) => placeholder,
+                                Some(lsp::PrepareRenameResponse::Range(range)) => range_text(
+                                    editor,
+                                    &params.text_document.uri,
+                                    range,
+                                    handler.position_encoding(),
+                                )?,
+                                None => {
+                                    editor.message("Symbol can not be renamed")?;
+                                    return Ok(());
+                                }
+                            };
+
+                            request_rename(editor, handler, params, new_name, &default)
+                        }),
+                    )?;
+                } else {
+                    let line = editor.document_line(&params.text_document.uri, cursor.line)?;
+                    let default = word_at(&line, cursor.character as usize);
+                    request_rename(editor, handler, params, new_name, default)?;
+                }
+            }
+            Event::CodeAction {
+                text_document,
+                range,
+                diagnostics,
+            } => {
+                let uri = &text_document.uri;
+                let range = Range::new(
+                    self.cursor_position(uri, CodeActionRequest::METHOD, range.start)?,
+                    self.cursor_position(uri, CodeActionRequest::METHOD, range.end)?,
+                );
+                // Fall back to the published diagnostics under the requested range
+                let diagnostics = if diagnostics.is_empty() {
+                    self.file_diagnostics(&text_document.uri)
+                        .into_iter()
+                        .filter(|diagnostic| ranges_overlap(&diagnostic.range, &range))
+                        .collect()
+                } else {
+                    diagnostics
+                };
+                self.flush_file(&text_document.uri)?;
+                let (handler, _, editor) = self
+                    .handler_for_request(&text_document.uri, CodeActionRequest::METHOD)
+                    .ok_or_else(|| {
+                        log::info!("Nontracking file: {:?}", text_document);
+                        MainLoopError::IgnoredMessage
+                    })?;
+                if !handler.supports(CodeActionRequest::METHOD) {
+                    editor.message("Server doesn't support code actions")?;
+                    return Ok(());
+                }
+                let params = lsp::CodeActionParams {
+                    text_document,
+                    range,
+                    context: lsp::CodeActionContext {
+                        diagnostics,
+                        only: None,
+                    },
+                };
+
+                handler.lsp_request::<CodeActionRequest>(
+                    &params,
+                    Box::new(move |editor: &mut E, handler, response| {
+                        let actions = response.unwrap_or_default();
+                        if actions.is_empty() {
+                            editor.message("No code actions available")?;
+                            return Ok(());
+                        }
+
+                        let chosen = editor
+                            .show_code_actions(&actions)?
+                            .and_then(|index| actions.get(index));
+                        match chosen {
+                            Some(CodeActionOrCommand::Command(command)) => {
+                                handler.execute_command(command)?;
+                            }
+                            Some(CodeActionOrCommand::CodeAction(action)) => {
+                                // Edit is applied before executing the command
+                                if let Some(ref edit) = action.edit {
+                                    editor
+                                        .apply_workspace_edit(edit, handler.position_encoding())?;
+                                }
+                                if let Some(ref command) = action.command {
+                                    handler.execute_command(command)?;
+                                }
+                            }
+                            None => {}
+                        }
+
+                        Ok(())
+                    }),
+                )?;
+            }
+            Event::GotoWorkspaceSymbol {
+                text_document,
+                query,
+            } => {
+                let (handler, _, editor) = self
+                    .handler_for_request(&text_document.uri, WorkspaceSymbolRequest::METHOD)
+                    .ok_or_else(|| {
+                        log::info!("Nontracking file: {:?}", text_document);
+                        MainLoopError::IgnoredMessage
+                    })?;
+                if !handler.supports(WorkspaceSymbolRequest::METHOD) {
+                    editor.message("Server doesn't support workspace symbols")?;
+                    return Ok(());
+                }
+                let params = lsp::WorkspaceSymbolParams { query };
+
+                handler.lsp_request::<WorkspaceSymbolRequest>(
+                    &params,
+                    Box::new(move |editor: &mut E, handler, response| {
+                        let symbols = response.unwrap_or_default();
+                        if symbols.is_empty() {
+                            editor.message("No symbols found")?;
+                            return Ok(());
+                        }
+
+                        let chosen = editor
+                            .choose_workspace_symbol(&symbols)?
+                            .and_then(|index| symbols.get(index));
+                        if let Some(symbol) = chosen {
+                            goto_workspace_symbol(editor, handler, symbol)?;
+                        }
+
+                        Ok(())
+                    }),
+                )?;
+            }
+            Event::WorkspaceSymbols { query } => {
+                if self.lsp_handlers.is_empty() {
+                    return Err(LspcError::NotStarted);
+                }
+
+                // Results of every server are merged, shown once the last one responds
+                let batch = Rc::new(RefCell::new(BatchResponses::new(self.lsp_handlers.len())));
+                let params = lsp::WorkspaceSymbolParams { query };
+                for (index, handler) in self.lsp_handlers.iter_mut().enumerate() {
+                    let batch = Rc::clone(&batch);
+                    let failed_batch = Rc::clone(&batch);
+                    handler.lsp_request_or_else::<WorkspaceSymbolRequest>(
+                        &params,
+                        Box::new(move |editor: &mut E, handler, response| {
+                            // Converted before merging, each server has its encoding
+                            let mut columns =
+                                EditorColumns::new(&*editor, handler.position_encoding());
+                            let symbols = response
+                                .unwrap_or_default()
+                                .into_iter()
+                                .map(|symbol| {
+                                    let mut symbol = to_symbol_information(symbol);
+                                    symbol.location = columns.location(&symbol.location)?;
+                                    Ok(symbol)
+                                })
+                                .collect::<Result<Vec<_>, EditorError>>()?;
+                            let responses = batch.borrow_mut().insert(index, symbols);
+                            show_workspace_symbols(editor, responses)
+                        }),
+                        // A server failing to answer doesn't hide the others' symbols
+                        Box::new(move |editor: &mut E, _handler| {
+                            let responses = failed_batch.borrow_mut().insert(index, Vec::new());
+                            show_workspace_symbols(editor, responses)
+                        }),
+                    )?;
+                }
+            }
+            Event::MultiHover {
+                text_document,
+                positions,
+            } => {
+                if !self.feature_enabled(&text_document.uri, Feature::Hover) {
+                    return Ok(());
+                }
+                self.flush_file(&text_document.uri)?;
+                let (handler, _, editor) = self
+                    .handler_for_request(&text_document.uri, HoverRequest::METHOD)
+                    .ok_or_else(|| {
+                        log::info!("Nontracking file: {:?}", text_document);
+                        MainLoopError::IgnoredMessage
+                    })?;
+                if !handler.supports(HoverRequest::METHOD) {
+                    editor.message("Server doesn't support hover")?;
+                    return Ok(());
+                }
+
+                // One request per position, shown together once all are answered
+                let encoding = handler.position_encoding();
+                let batch = Rc::new(RefCell::new(BatchResponses::new(positions.len())));
+                for (index, position) in positions.into_iter().enumerate() {
+                    let batch = Rc::clone(&batch);
+                    let failed_batch = Rc::clone(&batch);
+                    let text_document_clone = text_document.clone();
+                    let failed_text_document = text_document.clone();
+                    let params = lsp_types::TextDocumentPositionParams {
+                        text_document: text_document.clone(),
+                        position: server_position(editor, &text_document.uri, position, encoding)?,
+                    };
+                    handler.lsp_request_or_else::<HoverRequest>(
+                        &params,
+                        Box::new(move |editor: &mut E, _handler, response| {
+                            let responses = batch
+                                .borrow_mut()
+                                .insert(index, response.map(|hover| (position, hover)));
+                            show_hovers(editor, &text_document_clone, responses)
+                        }),
+                        Box::new(move |editor: &mut E, _handler| {
+                            let responses = failed_batch.borrow_mut().insert(index, None);
+                            show_hovers(editor, &failed_text_document, responses)
+                        }),
+                    )?;
+                }
+            }
+            Event::PrepareCallHierarchy {
+                text_document,
+                position,
+            } => {
+                self.flush_file(&text_document.uri)?;
+                let (handler, _, editor) = self
+                    .handler_for_request(&text_document.uri, CallHierarchyPrepare::METHOD)
+                    .ok_or_else(|| {
+                        log::info!("Nontracking file: {:?}", text_document);
+                        MainLoopError::IgnoredMessage
+                    })?;
+                if !handler.can_call_hierarchy() {
+                    editor.message("Server doesn't support call hierarchy")?;
+                    return Ok(());
+                }
+                let call_hierarchy_items = Rc::clone(&self.call_hierarchy_items);
+                let uri = text_document.uri.clone();
+                let position = server_position(
+                    editor,
+                    &text_document.uri,
+                    position,
+                    handler.position_encoding(),
+                )?;
+                let params = lsp::TextDocumentPositionParams {
+                    text_document,
+                    position,
+                };
+                handler.lsp_request::<CallHierarchyPrepare>(
+                    &params,
+                    Box::new(move |editor: &mut E, _handler, response| {
+                        match response.and_then(|items| items.into_iter().next()) {
+                            Some(item) => {
+                                editor.message(&format!("Call hierarchy of {}", item.name))?;
+                                call_hierarchy_items.borrow_mut().insert(uri, item);
+                            }
+                            None => editor.message("No call hierarchy under cursor")?,
+                        }
+
+                        Ok(())
+                    }),
+                )?;
+            }
+            Event::CallHierarchyIncomingCalls { text_document } => {
+                let item = self
+                    .call_hierarchy_items
+                    .borrow()
+                    .get(&text_document.uri)
+                    .cloned();
+                let item = match item {
+                    Some(item) => item,
+                    None => {
+                        self.editor.message("Call hierarchy is not prepared")?;
+                        return Ok(());
+                    }
+                };
+                let (handler, _, _) = self
+                    .handler_for_request(&text_document.uri, CallHierarchyPrepare::METHOD)
+                    .ok_or_else(|| {
+                        log::info!("Nontracking file: {:?}", text_document);
+                        MainLoopError::IgnoredMessage
+                    })?;
+                let params = CallHierarchyCallsParams { item: item.clone() };
+                handler.lsp_request::<CallHierarchyIncomingCalls>(
+                    &params,
+                    Box::new(move |editor: &mut E, handler, response| {
+                        // One entry per call site
+                        let calls = response
+                            .unwrap_or_default()
+                            .into_iter()
+                            .flat_map(|call| {
+                                let from = call.from;
+                                call.from_ranges.into_iter().map(move |range| {
+                                    let location = Location::new(from.uri.clone(), range);
+                                    (from.clone(), location)
+                                })
+                            })
+                            .collect::<Vec<_>>();
+                        let mut columns = EditorColumns::new(&*editor, handler.position_encoding());
+                        let calls = calls
+                            .into_iter()
+                            .map(|(call, location)| Ok((call, columns.location(&location)?)))
+                            .collect::<Result<Vec<_>, EditorError>>()?;
+                        editor.show_call_hierarchy(CallDirection::Incoming, &item, &calls)?;
+
+                        Ok(())
+                    }),
+                )?;
+            }
+            Event::CallHierarchyOutgoingCalls { text_document } => {
+                let item = self
+                    .call_hierarchy_items
+                    .borrow()
+                    .get(&text_document.uri)
+                    .cloned();
+                let item = match item {
+                    Some(item) => item,
+                    None => {
+                        self.editor.message("Call hierarchy is not prepared")?;
+                        return Ok(());
+                    }
+                };
+                let (handler, _, _) = self
+                    .handler_for_request(&text_document.uri, CallHierarchyPrepare::METHOD)
+                    .ok_or_else(|| {
+                        log::info!("Nontracking file: {:?}", text_document);
+                        MainLoopError::IgnoredMessage
+                    })?;
+                let params = CallHierarchyCallsParams { item: item.clone() };
+                handler.lsp_request::<CallHierarchyOutgoingCalls>(
+                    &params,
+                    Box::new(move |editor: &mut E, handler, response| {
+                        let calls = response
+                            .unwrap_or_default()
+                            .into_iter()
+                            .map(|call| {
+                                let location =
+                                    Location::new(call.to.uri.clone(), call.to.selection_range);
+                                (call.to, location)
+                            })
+                            .collect::<Vec<_>>();
+                        let mut columns = EditorColumns::new(&*editor, handler.position_encoding());
+                        let calls = calls
+                            .into_iter()
+                            .map(|(call, location)| Ok((call, columns.location(&location)?)))
+                            .collect::<Result<Vec<_>, EditorError>>()?;
+                        editor.show_call_hierarchy(CallDirection::Outgoing, &item, &calls)?;
+
+                        Ok(())
+                    }),
+                )?;
+            }
+            Event::SelectionRange {
+                buf_id,
+                text_document,
+                positions,
+            } => {
+                self.flush_file(&text_document.uri)?;
+                let (handler, _, editor) = self
+                    .handler_for_request(&text_document.uri, SelectionRangeRequest::METHOD)
+                    .ok_or_else(|| {
+                        log::info!("Nontracking file: {:?}", text_document);
+                        MainLoopError::IgnoredMessage
+                    })?;
+                if !handler.supports(SelectionRangeRequest::METHOD) {
+                    editor.message("Server doesn't support selection ranges")?;
+                    return Ok(());
+                }
+                let encoding = handler.position_encoding();
+                let positions = positions
+                    .into_iter()
+                    .map(|position| server_position(editor, &text_document.uri, position, encoding))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let params = SelectionRangeParams {
+                    text_document,
+                    positions,
+                };
+                handler.lsp_request::<SelectionRangeRequest>(
+                    &params,
+                    Box::new(move |editor: &mut E, _handler, response| {
+                        editor.set_selection_ranges(buf_id, &response.unwrap_or_default())?;
+
+                        Ok(())
+                    }),
+                )?;
+            }
+            Event::DocumentHighlight {
+                buf_id,
+                text_document,
+                position,
+            } => {
+                if !self.feature_enabled(&text_document.uri, Feature::DocumentHighlight) {
+                    return Ok(());
+                }
+                self.flush_file(&text_document.uri)?;
+                let (handler, _, editor) = self
+                    .handler_for_request(&text_document.uri, DocumentHighlightRequest::METHOD)
+                    .ok_or_else(|| {
+                        log::info!("Nontracking file: {:?}", text_document);
+                        MainLoopError::IgnoredMessage
+                    })?;
+                if !handler.supports(DocumentHighlightRequest::METHOD) {
+                    log::info!(
+                        "Server doesn't support {}",
+                        DocumentHighlightRequest::METHOD
+                    );
+                    return Ok(());
+                }
+                let position = server_position(
+                    editor,
+                    &text_document.uri,
+                    position,
+                    handler.position_encoding(),
+                )?;
+                let uri = text_document.uri.clone();
+                let params = lsp::TextDocumentPositionParams {
+                    text_document,
+                    position,
+                };
+                handler.lsp_request::<DocumentHighlightRequest>(
+                    &params,
+                    Box::new(move |editor: &mut E, handler, response| {
+                        // Clear stale highlights on empty response as well
+                        let highlights = EditorColumns::new(&*editor, handler.position_encoding())
+                            .ranged(&uri, &response.unwrap_or_default(), |highlight| {
+                                &mut highlight.range
+                            })?;
+                        editor.highlight_references(buf_id, &highlights)?;
+
+                        Ok(())
+                    }),
+                )?;
+            }
+            Event::DocumentSymbols { text_document } => {
+                self.flush_file(&text_document.uri)?;
+                let (handler, _, editor) = self
+                    .handler_for_request(&text_document.uri, DocumentSymbolRequest::METHOD)
+                    .ok_or_else(|| {
+                        log::info!("Nontracking file: {:?}", text_document);
+                        MainLoopError::IgnoredMessage
+                    })?;
+                if !handler.supports(DocumentSymbolRequest::METHOD) {
+                    editor.message("Server doesn't support document symbols")?;
+                    return Ok(());
+                }
+                let uri = text_document.uri.clone();
+                let params = lsp::DocumentSymbolParams { text_document };
+                handler.lsp_request::<DocumentSymbolRequest>(
+                    &params,
+                    Box::new(move |editor: &mut E, handler, response| {
+                        let response = match response {
+                            Some(symbols) => Some(
+                                EditorColumns::new(&*editor, handler.position_encoding())
+                                    .document_symbols(&uri, symbols)?,
+                            ),
+                            None => None,
+                        };
+                        let is_empty = match response {
+                            Some(DocumentSymbolResponse::Flat(ref symbols)) => symbols.is_empty(),
+                            Some(DocumentSymbolResponse::Nested(ref symbols)) => symbols.is_empty(),
+                            None => true,
+                        };
+                        match response {
+                            Some(ref symbols) if !is_empty => {
+                                editor.show_document_symbols(symbols)?
+                            }
+                            _ => editor.message("No document symbols")?,
+                        }
+
+                        Ok(())
+                    }),
+                )?;
+            }
+            Event::CodeLens {
+                buf_id,
+                text_document,
+            } => {
+                if !self.feature_enabled(&text_document.uri, Feature::CodeLens) {
+                    return Ok(());
+                }
+                self.flush_file(&text_document.uri)?;
+                let (handler, _, editor) = self
+                    .handler_for_request(&text_document.uri, CodeLensRequest::METHOD)
+                    .ok_or_else(|| {
+                        log::info!("Nontracking file: {:?}", text_document);
+                        MainLoopError::IgnoredMessage
+                    })?;
+                if !handler.supports(CodeLensRequest::METHOD) {
+                    editor.message("Server doesn't support code lens")?;
+                    return Ok(());
+                }
+                let params = lsp::CodeLensParams { text_document };
+                handler.lsp_request::<CodeLensRequest>(
+                    &params,
+                    Box::new(move |editor: &mut E, handler, response| {
+                        show_code_lenses(editor, handler, buf_id, response.unwrap_or_default())
+                    }),
+                )?;
+            }
+            Event::RequestDiagnostics { text_document } => {
+                self.flush_file(&text_document.uri)?;
+                let diagnostics = Rc::clone(&self.diagnostics);
+                let suppressed_diagnostics = Rc::clone(&self.suppressed_diagnostics);
+                let (handler, tracking_file, editor) = self
+                    .handler_for_request(&text_document.uri, DocumentDiagnosticRequest::METHOD)
+                    .ok_or_else(|| {
+                        log::info!("Nontracking file: {:?}", text_document);
+                        MainLoopError::IgnoredMessage
+                    })?;
+                if !handler.supports(DocumentDiagnosticRequest::METHOD) {
+                    editor.message("Server doesn't support pulling diagnostics")?;
+                    return Ok(());
+                }
+                let result_id = Rc::clone(&tracking_file.diagnostic_result_id);
+                let params = DocumentDiagnosticParams {
+                    text_document: text_document.clone(),
+                    identifier: None,
+                    previous_result_id: result_id.borrow().clone(),
+                };
+                handler.lsp_request::<DocumentDiagnosticRequest>(
+                    &params,
+                    Box::new(move |editor: &mut E, handler, response| match response {
+                        DocumentDiagnosticReport::Full {
+                            result_id: new_result_id,
+                            mut items,
+                        } => {
+                            *result_id.borrow_mut() = new_result_id;
+                            let level = handler.lang_settings.min_diagnostic_severity;
+                            items.retain(|diagnostic| level.includes(diagnostic.severity));
+                            store_diagnostics(
+                                editor,
+                                &diagnostics,
+                                &suppressed_diagnostics,
+                                handler.id,
+                                text_document,
+                                items,
+                            )
+                        }
+                        // The shown diagnostics are still current
+                        DocumentDiagnosticReport::Unchanged {
+                            result_id: new_result_id,
+                        } => {
+                            *result_id.borrow_mut() = Some(new_result_id);
+                            Ok(())
+                        }
+                    }),
+                )?;
+            }
+            Event::SemanticTokens {
+                buf_id,
+                text_document,
+            } => {
+                self.flush_file(&text_document.uri)?;
+                let (handler, tracking_file, editor) = self
+                    .handler_for_request(&text_document.uri, SemanticTokensFullRequest::METHOD)
+                    .ok_or_else(|| {
+                        log::info!("Nontracking file: {:?}", text_document);
+                        MainLoopError::IgnoredMessage
+                    })?;
+                let legend = match handler.semantic_tokens_legend() {
+                    Some(legend) => legend.clone(),
+                    None => {
+                        editor.message("Server doesn't support semantic tokens")?;
+                        return Ok(());
+                    }
+                };
+                let cache = Rc::clone(&tracking_file.semantic_tokens);
+                let uri = text_document.uri.clone();
+                let previous_result_id = if handler.can_request_semantic_tokens_delta() {
+                    cache
+                        .borrow()
+                        .as_ref()
+                        .and_then(|tokens| tokens.result_id.clone())
+                } else {
+                    None
+                };
+
+                if let Some(previous_result_id) = previous_result_id {
+                    let params = SemanticTokensDeltaParams {
+                        text_document,
+                        previous_result_id,
+                    };
+                    handler.lsp_request::<SemanticTokensFullDeltaRequest>(
+                        &params,
+                        Box::new(move |editor: &mut E, handler, response| {
+                            let tokens = match response {
+                                Some(SemanticTokensFullDeltaResult::Tokens(tokens)) => tokens,
+                                Some(SemanticTokensFullDeltaResult::Delta(delta)) => {
+                                    let cached = cache.borrow_mut().take();
+                                    let mut data = match cached {
+                                        Some(cached) => cached.data,
+                                        None => {
+                                            log::warn!("Semantic tokens delta without base");
+                                            return Ok(());
+                                        }
+                                    };
+                                    apply_semantic_tokens_edits(&mut data, delta.edits);
+                                    SemanticTokens {
+                                        result_id: delta.result_id,
+                                        data,
+                                    }
+                                }
+                                None => return Ok(()),
+                            };
+
+                            show_semantic_tokens(
+                                editor, handler, &legend, buf_id, &uri, &cache, tokens,
+                            )
+                        }),
+                    )?;
+                } else {
+                    let params = SemanticTokensParams { text_document };
+                    handler.lsp_request::<SemanticTokensFullRequest>(
+                        &params,
+                        Box::new(move |editor: &mut E, handler, response| match response {
+                            Some(tokens) => show_semantic_tokens(
+                                editor, handler, &legend, buf_id, &uri, &cache, tokens,
+                            ),
+                            None => Ok(()),
+                        }),
+                    )?;
+                }
+            }
+            Event::DocumentLinks {
+                buf_id,
+                text_document,
+            } => {
+                self.flush_file(&text_document.uri)?;
+                let (handler, _, editor) = self
+                    .handler_for_request(&text_document.uri, DocumentLinkRequest::METHOD)
+                    .ok_or_else(|| {
+                        log::info!("Nontracking file: {:?}", text_document);
+                        MainLoopError::IgnoredMessage
+                    })?;
+                if !handler.supports(DocumentLinkRequest::METHOD) {
+                    editor.message("Server doesn't support document links")?;
+                    return Ok(());
+                }
+                let params = lsp::DocumentLinkParams {
+                    text_document: text_document.clone(),
+                };
+                let document_links = Rc::clone(&self.document_links);
+                handler.lsp_request::<DocumentLinkRequest>(
+                    &params,
+                    Box::new(move |editor: &mut E, handler, response| {
+                        // Kept in the server encoding to be found from the cursor
+                        let links = response.unwrap_or_default();
+                        let shown_links = EditorColumns::new(&*editor, handler.position_encoding())
+                            .ranged(&text_document.uri, &links, |link| &mut link.range)?;
+                        editor.show_document_links(buf_id, &shown_links)?;
+                        document_links.borrow_mut().insert(text_document.uri, links);
+
+                        Ok(())
+                    }),
+                )?;
+            }
+            Event::GotoDocumentLink {
+                text_document,
+                position,
+            } => {
+                let position = self.cursor_position(
+                    &text_document.uri,
+                    DocumentLinkRequest::METHOD,
+                    position,
+                )?;
+                let link = self
+                    .document_links
+                    .borrow()
+                    .get(&text_document.uri)
+                    .and_then(|links| {
+                        links
+                            .iter()
+                            .find(|link| range_contains(&link.range, position))
+                    })
+                    .cloned();
+                let link = match link {
+                    Some(link) => link,
+                    None => {
+                        self.editor.message("No document link under cursor")?;
+                        return Ok(());
+                    }
+                };
+                let (handler, _, editor) = self
+                    .handler_for_request(&text_document.uri, DocumentLinkRequest::METHOD)
+                    .ok_or_else(|| {
+                        log::info!("Nontracking file: {:?}", text_document);
+                        MainLoopError::IgnoredMessage
+                    })?;
+                match link.target {
+                    Some(ref target) => goto_document_link(editor, target)?,
+                    None if handler.can_resolve_document_link() => {
+                        handler.lsp_request::<DocumentLinkResolve>(
+                            &link,
+                            Box::new(move |editor: &mut E, _handler, resolved| {
+                                match resolved.target {
+                                    Some(ref target) => goto_document_link(editor, target)?,
+                                    None => editor.message("Document link has no target")?,
+                                }
+
+                                Ok(())
+                            }),
+                        )?;
+                    }
+                    None => editor.message("Document link has no target")?,
+                }
+            }
+            Event::DocumentColor {
+                buf_id,
+                text_document,
+            } => {
+                self.flush_file(&text_document.uri)?;
+                let (handler, _, editor) = self
+                    .handler_for_request(&text_document.uri, DocumentColor::METHOD)
+                    .ok_or_else(|| {
+                        log::info!("Nontracking file: {:?}", text_document);
+                        MainLoopError::IgnoredMessage
+                    })?;
+                if !handler.supports(DocumentColor::METHOD) {
+                    editor.message("Server doesn't support document colors")?;
+                    return Ok(());
+                }
+                let params = lsp::DocumentColorParams {
+                    text_document: text_document.clone(),
+                };
+                let document_colors = Rc::clone(&self.document_colors);
+                handler.lsp_request::<DocumentColor>(
+                    &params,
+                    Box::new(move |editor: &mut E, handler, colors| {
+                        let shown_colors = EditorColumns::new(
+                            &*editor,
+                            handler.position_encoding(),
+                        )
+                        .ranged(&text_document.uri, &colors, |color| &mut color.range)?;
+                        editor.show_document_colors(buf_id, &shown_colors)?;
+                        document_colors
+                            .borrow_mut()
+                            .insert(text_document.uri, colors);
+
+                        Ok(())
+                    }),
+                )?;
+            }
+            Event::ColorPresentation {
+                buf_id,
+                text_document_lines,
+                text_document,
+                position,
+            } => {
+                let position = self.cursor_position(
+                    &text_document.uri,
+                    ColorPresentationRequest::METHOD,
+                    position,
+                )?;
+                let color = self
+                    .document_colors
+                    .borrow()
+                    .get(&text_document.uri)
+                    .and_then(|colors| {
+                        colors
+                            .iter()
+                            .find(|color| range_contains(&color.range, position))
+                    })
+                    .cloned();
+                let color = match color {
+                    Some(color) => color,
+                    None => {
+                        self.editor.message("No color under cursor")?;
+                        return Ok(());
+                    }
+                };
+                self.flush_file(&text_document.uri)?;
+                let (handler, _, editor) = self
+                    .handler_for_request(&text_document.uri, ColorPresentationRequest::METHOD)
+                    .ok_or_else(|| {
+                        log::info!("Nontracking file: {:?}", text_document);
+                        MainLoopError::IgnoredMessage
+                    })?;
+                if !handler.supports(ColorPresentationRequest::METHOD) {
+                    editor.message("Server doesn't support color presentations")?;
+                    return Ok(());
+                }
+                let range = color.range;
+                let params = lsp::ColorPresentationParams {
+                    text_document,
+                    color: color.color,
+                    range,
+                };
+                handler.lsp_request::<ColorPresentationRequest>(
+                    &params,
+                    Box::new(move |editor: &mut E, handler, presentations| {
+                        let chosen = editor
+                            .choose_color_presentation(&presentations)?
+                            .and_then(|index| presentations.get(index));
+                        if let Some(presentation) = chosen {
+                            let edits = color_presentation_edits(range, presentation);
+                            let encoding = handler.position_encoding();
+                            editor.apply_edits(buf_id, &text_document_lines, &edits, encoding)?;
+                        }
+
+                        Ok(())
+                    }),
+                )?;
+            }
+            Event::DidOpen {
+                buf_id,
+                text_document,
+                language_id,
+            } => {
+                let tracking_files =
+                    handlers_of(&self.lsp_handlers, &text_document.uri, &language_id)
+                        .into_iter()
+                        .map(|handler| {
+                            let language_id = if language_id.is_empty() {
+                                handler.lang_id.clone()
+                            } else {
+                                language_id.clone()
+                            };
+                            TrackingFile::new(
+                                handler.id,
+                                text_document.uri.clone(),
+                                language_id,
+                                handler.sync_kind(),
+                            )
+                        })
+                        .collect::<Vec<_>>();
+                if tracking_files.is_empty() {
+                    log::info!("Unmanaged file: {:?}", text_document.uri);
+                    return Err(MainLoopError::IgnoredMessage.into());
+                }
+
+                self.editor.watch_file_events(buf_id)?;
+                self.tracking_files
+                    .insert(text_document.uri, tracking_files);
+            }
+            Event::DidChange {
+                text_document,
+                version,
+                content_change,
+            } => {
+                log::debug!(
+                    "Received did change event: {:?}, {}, {:?}",
+                    text_document,
+                    version,
+                    content_change
+                );
+                let handlers = self.handlers_for_file(&text_document.uri);
+                if handlers.is_empty() {
+                    log::info!(
+                        "Received changed event for nontracking file: {:?}",
+                        text_document
+                    );
+                    return Err(MainLoopError::IgnoredMessage.into());
+                }
+
+                for (handler, tracking_file) in handlers {
+                    tracking_file.track_change(version, &content_change);
+
+                    if !tracking_file.sent_did_open {
+                        handler.lsp_notify::<noti::DidOpenTextDocument>(
+                            &tracking_file.did_open_params(version, content_change.text.clone()),
+                        )?;
+                        tracking_file.sent_did_open = true;
+                    } else {
+                        tracking_file.delay_sync_in(
+                            Instant::now(),
+                            Duration::from_millis(handler.lang_settings.sync_delay_ms),
+                            handler.lang_settings.sync_burst_threshold,
+                        );
+                    }
+                }
+            }
+            Event::WillSave {
+                text_document,
+                reason,
+            } => {
+                let handlers = self.handlers_for_file(&text_document.uri);
+                if handlers.is_empty() {
+                    log::info!("Nontracking file: {:?}", text_document);
+                    return Err(MainLoopError::IgnoredMessage.into());
+                }
+
+                let params = lsp::WillSaveTextDocumentParams {
+                    text_document,
+                    reason,
+                };
+                for (handler, _) in handlers {
+                    if handler.sync_option_enabled("willSave") {
+                        handler.lsp_notify::<noti::WillSaveTextDocument>(&params)?;
+                    }
+                }
+            }
+            Event::WillSaveWaitUntil {
+                buf_id,
+                text_document_lines,
+                text_document,
+                reason,
+            } => {
+                // The editor waits for `will_save_done` whatever happens
+                if self.handler_for_file(&text_document.uri).is_none() {
+                    log::info!("Nontracking file: {:?}", text_document);
+                    self.editor.will_save_done(buf_id)?;
+                    return Ok(());
+                }
+                self.flush_file(&text_document.uri)?;
+                let deadline = Instant::now() + Duration::from_millis(WILL_SAVE_TIMEOUT_MS);
+                let uri = text_document.uri.clone();
+                let format_on_save = self.handler_for_request(&uri, Formatting::METHOD).map_or(
+                    false,
+                    |(handler, _, _)| {
+                        handler.lang_settings.format_on_save && handler.supports(Formatting::METHOD)
+                    },
+                );
+                // Formatted before the write so the formatted text is saved. The
+                // server isn't also asked for its own edits to the same text
+                if format_on_save {
+                    let (handler, _, _) =
+                        self.handler_for_request(&uri, Formatting::METHOD).unwrap();
+                    let params = DocumentFormattingParams {
+                        text_document,
+                        options: FormattingOptions {
+                            tab_size: handler.lang_settings.indentation,
+                            insert_spaces: handler.lang_settings.indentation_with_space,
+                            properties: HashMap::new(),
+                        },
+                    };
+                    handler.lsp_request_or_else::<Formatting>(
+                        &params,
+                        Box::new(move |editor: &mut E, handler, response| {
+                            if Instant::now() > deadline {
+                                log::warn!("Formatting of {} came too late", uri);
+                                return Ok(());
+                            }
+                            if let Some(edits) = response {
+                                let encoding = handler.position_encoding();
+                                editor.apply_edits(
+                                    buf_id,
+                                    &text_document_lines,
+                                    &edits,
+                                    encoding,
+                                )?;
+                            }
+                            editor.will_save_done(buf_id)?;
+
+                            Ok(())
+                        }),
+                        // Saved unformatted
+                        Box::new(move |editor: &mut E, _handler| {
+                            editor.will_save_done(buf_id)?;
+                            Ok(())
+                        }),
+                    )?;
+                    return Ok(());
+                }
+
+                let (handler, _, editor) = self.handler_for_file(&uri).unwrap();
+                if !handler.sync_option_enabled("willSaveWaitUntil") {
+                    editor.will_save_done(buf_id)?;
+                    return Ok(());
+                }
+
+                let params = lsp::WillSaveTextDocumentParams {
+                    text_document: text_document.clone(),
+                    reason,
+                };
+                handler.lsp_request_or_else::<WillSaveWaitUntil>(
+                    &params,
+                    Box::new(move |editor: &mut E, handler, response| {
+                        if Instant::now() > deadline {
+                            log::warn!("Edits before saving {} came too late", text_document.uri);
+                            return Ok(());
+                        }
+                        if let Some(edits) = response {
+                            let encoding = handler.position_encoding();
+                            editor.apply_edits(buf_id, &text_document_lines, &edits, encoding)?;
+                        }
+                        editor.will_save_done(buf_id)?;
+
+                        Ok(())
+                    }),
+                    // Saved without the server edits
+                    Box::new(move |editor: &mut E, _handler| {
+                        editor.will_save_done(buf_id)?;
+                        Ok(())
+                    }),
+                )?;
+            }
+            Event::DidSave { text_document } => {
+                self.flush_file(&text_document.uri)?;
+                let handlers = self.handlers_for_file(&text_document.uri);
+                if handlers.is_empty() {
+                    log::info!("Nontracking file: {:?}", text_document);
+                    return Err(MainLoopError::IgnoredMessage.into());
+                }
+
+                for (handler, tracking_file) in handlers {
+                    notify_did_save(handler, text_document.clone(), tracking_file.text())?;
+                }
+            }
+            Event::DidClose { text_document } => {
+                let handlers = self.handlers_for_file(&text_document.uri);
+                if handlers.is_empty() {
+                    log::info!(
+                        "Received changed event for nontracking file: {:?}",
+                        text_document
+                    );
+                    return Err(MainLoopError::IgnoredMessage.into());
+                }
+
+                for (handler, tracking_file) in handlers {
+                    let pending_changes = tracking_file.fetch_pending_changes();
+                    if let Some(params) = pending_changes {
+                        handler.lsp_notify::<noti::DidChangeTextDocument>(&params)?;
+                    }
+                    handler.lsp_notify::<noti::DidCloseTextDocument>(
+                        &lsp::DidCloseTextDocumentParams {
+                            text_document: text_document.clone(),
+                        },
+                    )?;
+                }
+
+                // Nothing is kept about closed documents
+                let uri = &text_document.uri;
+                self.tracking_files.remove(uri);
+                self.disabled_features.remove(uri);
+                if self.diagnostics.borrow_mut().remove(uri).is_some() {
+                    self.editor.show_diagnostics(&text_document, &Vec::new())?;
+                }
+                self.suppressed_diagnostics
+                    .borrow_mut()
+                    .retain(|_, suppressed_uri| suppressed_uri != uri);
+                self.document_links.borrow_mut().remove(uri);
+                self.document_colors.borrow_mut().remove(uri);
+                self.inlay_hint_locations.borrow_mut().remove(uri);
+                self.call_hierarchy_items.borrow_mut().remove(uri);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn handle_lsp_msg(
+        &mut self,
+        index: usize,
+        msg: LspMessage,
+    ) -> Result<(), LspcError> {
+        let lsp_handler = &mut self.lsp_handlers[index];
+        match msg {
+            LspMessage::Request(mut req) => {
+                req = match req.cast::<ApplyWorkspaceEdit>() {
+                    Ok((id, params)) => {
+                        let encoding = lsp_handler.position_encoding();
+                        let applied = match self.editor.apply_workspace_edit(&params.edit, encoding)
+                        {
+                            Ok(()) => true,
+                            Err(e) => {
+                                log::error!("Failed to apply workspace edit: {:?}", e);
+                                false
+                            }
+                        };
+                        let result = lsp::ApplyWorkspaceEditResponse { applied };
+                        lsp_handler
+                            .lsp_respond(RawResponse::ok::<ApplyWorkspaceEdit>(id, &result))?;
+
+                        return Ok(());
+                    }
+                    Err(req) => req,
+                };
+                req = match req.cast::<ShowMessageRequest>() {
+                    Ok((id, params)) => {
+                        let action = match self.editor.show_message_request(&params) {
+                            Ok(action) => action,
+                            Err(e) => {
+                                log::error!("Failed to show message request: {:?}", e);
+                                None
+                            }
+                        };
+                        lsp_handler
+                            .lsp_respond(RawResponse::ok::<ShowMessageRequest>(id, &action))?;
+
+                        return Ok(());
+                    }
+                    Err(req) => req,
+                };
+                req = match req.cast::<WorkDoneProgressCreate>() {
+                    Ok((id, _params)) => {
+                        lsp_handler
+                            .lsp_respond(RawResponse::ok::<WorkDoneProgressCreate>(id, &()))?;
+
+                        return Ok(());
+                    }
+                    Err(req) => req,
+                };
+                req = match req.cast::<WorkspaceConfiguration>() {
+                    Ok((id, params)) => {
+                        let result = params
+                            .items
+                            .iter()
+                            .map(|item| {
+                                lsp_handler
+                                    .settings_section(item.section.as_ref().map(String::as_str))
+                            })
+                            .collect::<Vec<_>>();
+                        lsp_handler
+                            .lsp_respond(RawResponse::ok::<WorkspaceConfiguration>(id, &result))?;
+
+                        return Ok(());
+                    }
+                    Err(req) => req,
+                };
+                req = match req.cast::<WorkspaceFoldersRequest>() {
+                    Ok((id, _params)) => {
+                        let folders = lsp_handler.workspace_folders();
+                        lsp_handler.lsp_respond(RawResponse::ok::<WorkspaceFoldersRequest>(
+                            id,
+                            &Some(folders),
+                        ))?;
+
+                        return Ok(());
+                    }
+                    Err(req) => req,
+                };
+                req = match req.cast::<RegisterCapability>() {
+                    Ok((id, params)) => {
+                        for registration in params.registrations {
+                            lsp_handler.register_capability(registration);
+                        }
+                        lsp_handler.lsp_respond(RawResponse::ok::<RegisterCapability>(id, &()))?;
+
+                        return Ok(());
+                    }
+                    Err(req) => req,
+                };
+                req = match req.cast::<UnregisterCapability>() {
+                    Ok((id, params)) => {
+                        for unregistration in &params.unregisterations {
+                            lsp_handler.unregister_capability(unregistration);
+                        }
+                        lsp_handler
+                            .lsp_respond(RawResponse::ok::<UnregisterCapability>(id, &()))?;
+
+                        return Ok(());
+                    }
+                    Err(req) => req,
+                };
+
+                log::warn!("Not supported request: {:?}", req);
+                lsp_handler.lsp_respond(RawResponse::err(
+                    req.id,
+                    ErrorCode::MethodNotFound as i32,
+                    format!("Unsupported method: {}", req.method),
+                ))?;
+            }
+            LspMessage::Notification(mut noti) => {
+                noti = match noti.cast::<noti::ShowMessage>() {
+                    Ok(params) => {
+                        self.editor.show_message(&params)?;
+
+                        return Ok(());
+                    }
+                    Err(noti) => noti,
+                };
+                noti = match noti.cast::<LogTrace>() {
+                    Ok(params) => {
+                        log::info!("[{} trace] {}", lsp_handler.lang_id, params.message);
+                        if let Some(verbose) = params.verbose {
+                            log::info!("[{} trace] {}", lsp_handler.lang_id, verbose);
+                        }
+
+                        return Ok(());
+                    }
+                    Err(noti) => noti,
+                };
+                noti = match noti.cast::<Progress>() {
+                    Ok(params) => {
+                        let status = lsp_handler.track_progress(params.token.clone(), params.value);
+                        self.editor
+                            .show_progress(&params.token, status.as_ref().map(String::as_str))?;
+
+                        return Ok(());
+                    }
+                    Err(noti) => noti,
+                };
+                noti = match noti.cast::<PublishDiagnostics>() {
+                    Ok(mut params) => {
+                        let level = lsp_handler.lang_settings.min_diagnostic_severity;
+                        params
+                            .diagnostics
+                            .retain(|diagnostic| level.includes(diagnostic.severity));
+                        let handler_id = lsp_handler.id;
+                        self.publish_diagnostics(handler_id, params)?;
+
+                        return Ok(());
+                    }
+                    Err(noti) => noti,
+                };
+
+                if lsp_handler.lang_settings.forward_notifications {
+                    self.editor
+                        .forward_notification(&noti.method, &noti.params)?;
+                } else {
+                    log::warn!("Not supported notification: {:?}", noti);
+                }
+            }
+            LspMessage::Response(res) => {
+                if let Some(callback) = lsp_handler.callback_for(res.id) {
+                    if res.error.is_some() {
+                        (callback.on_failure)(&mut self.editor, lsp_handler)?;
+                        return Err(LangServerError::from(res).into());
+                    }
+                    (callback.func)(&mut self.editor, lsp_handler, res)?;
+                } else {
+                    log::error!("not requested response: {:?}", res);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn publish_diagnostics(
+        &mut self,
+        handler_id: u64,
+        params: PublishDiagnosticsParams,
+    ) -> Result<(), LspcError> {
+        // Positions of diagnostics computed for an older version may be off
+        let current_version = self
+            .tracking_files
+            .get(&params.uri)
+            .and_then(|files| files.iter().find(|file| file.handler_id == handler_id))
+            .map(|file| file.version());
+        if let (Some(version), Some(current_version)) = (params.version, current_version) {
+            if version < current_version {
+                log::info!(
+                    "Stale diagnostics for {}: version {} < {}",
+                    params.uri,
+                    version,
+                    current_version
+                );
+                return Ok(());
+            }
+        }
+        store_diagnostics(
+            &mut self.editor,
+            &self.diagnostics,
+            &self.suppressed_diagnostics,
+            handler_id,
+            TextDocumentIdentifier::new(params.uri),
+            params.diagnostics,
+        )
+    }
+
+    fn handle_timer_tick(&mut self) -> Result<(), LspcError> {
+        let now = Instant::now();
+        self.remove_hung_servers(now)?;
+        while let Some(index) = self
+            .lsp_handlers
+            .iter_mut()
+            .position(|handler| handler.has_exited())
+        {
+            self.handle_server_exit(index)?;
+        }
+        for handler in self.lsp_handlers.iter_mut() {
+            for on_failure in handler.cancel_overdue_requests(now)? {
+                on_failure(&mut self.editor, handler)?;
+            }
+        }
+
+        let sync_due_files = self
+            .tracking_files
+            .iter()
+            .filter(|(_, bufs)| bufs.iter().any(|buf| buf.sync_due(now)))
+            .map(|(file_url, _)| file_url)
+            .cloned()
+            .collect::<Vec<_>>();
+
+        for uri in sync_due_files {
+            log::debug!("File changes due: {:?}", uri);
+            for (handler, tracking_file) in self.handlers_for_file(&uri) {
+                if !tracking_file.sync_due(now) {
+                    continue;
+                }
+                let pending_changes = tracking_file.fetch_pending_changes();
+                if let Some(params) = pending_changes {
+                    handler.lsp_notify::<noti::DidChangeTextDocument>(&params)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn remove_hung_servers(&mut self, now: Instant) -> Result<(), LspcError> {
+        while let Some(index) = self
+            .lsp_handlers
+            .iter()
+            .position(|handler| handler.initialize_overdue(now))
+        {
+            let mut handler = self.lsp_handlers.remove(index);
+            self.untrack_files_of(handler.id)?;
+            self.server_configs.remove(&handler.id);
+            if let Err(e) = handler.kill() {
+                log::warn!("Failed to kill {} server: {:?}", handler.lang_id, e);
+            }
+            self.editor
+                .message(&format!("{} server failed to initialize", handler.lang_id))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<E: Editor> Lspc<E> {
+    pub fn new(editor: E) -> Self {
+        Lspc {
+            editor,
+            lsp_handlers: Vec::new(),
+            tracking_files: HashMap::new(),
+            diagnostics: Rc::new(RefCell::new(HashMap::new())),
+            suppressed_diagnostics: Rc::new(RefCell::new(HashMap::new())),
+            disabled_features: HashMap::new(),
+            document_links: Rc::new(RefCell::new(HashMap::new())),
+            document_colors: Rc::new(RefCell::new(HashMap::new())),
+            inlay_hint_locations: Rc::new(RefCell::new(HashMap::new())),
+            call_hierarchy_items: Rc::new(RefCell::new(HashMap::new())),
+            server_configs: HashMap::new(),
+            next_handler_id: 0,
+        }
+    }
+
+    // The finest tick asked by the started servers
+    fn timer_tick_ms(&self) -> u64 {
+        self.server_configs
+            .values()
+            .map(|config| config.timer_tick_ms)
+            .min()
+            .unwrap_or(TIMER_TICK_MS)
+    }
+
+    pub fn main_loop(mut self) {
+        let event_receiver = self.editor.events();
+        let mut timer_tick_ms = self.timer_tick_ms();
+        let mut timer_tick = tick(Duration::from_millis(timer_tick_ms));
+
+        loop {
+            if timer_tick_ms != self.timer_tick_ms() {
+                timer_tick_ms = self.timer_tick_ms();
+                timer_tick = tick(Duration::from_millis(timer_tick_ms));
+            }
+            let selected = select(&event_receiver, &timer_tick, &self.lsp_handlers);
+            let exiting = match selected {
+                SelectedMsg::Editor(Event::Exit) => true,
+                _ => false,
+            };
+            let result = match selected {
+                SelectedMsg::Editor(event) => self.handle_editor_event(event),
+                SelectedMsg::Lsp(index, msg) => self.handle_lsp_msg(index, msg),
+                SelectedMsg::LspExited(index) => self.handle_server_exit(index),
+                SelectedMsg::TimerTick => self.handle_timer_tick(),
+            };
+            if let Err(e) = result {
+                log::error!("Handle error: {:?}", e);
+            }
+            if exiting {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        testing::{MockBufferId, MockEditor},
+        *,
+    };
+    use crate::rpc::{self, testing::pipe};
+    use lsp_types::Range;
+    use std::net::TcpListener;
+
+    fn test_uri() -> Url {
+        #[cfg(not(target_os = "windows"))]
+        let file_path = "/a/b/c/d";
+        #[cfg(target_os = "windows")]
+        let file_path = r#"C:\\a\b\d"#;
+
+        Url::from_file_path(file_path).unwrap()
+    }
+
+    // Handler talking to an in-memory server, returns the server's end
+    // of the transport
+    fn handler_with_fake_server(
+        root_path: &str,
+    ) -> (LangServerHandler<MockEditor>, rpc::Client<LspMessage>) {
+        numbered_handler_with_fake_server(1, root_path)
+    }
+
+    fn numbered_handler_with_fake_server(
+        id: u64,
+        root_path: &str,
+    ) -> (LangServerHandler<MockEditor>, rpc::Client<LspMessage>) {
+        let (server_reader, client_writer) = pipe();
+        let (client_reader, server_writer) = pipe();
+        let handler = LangServerHandler::with_client(
+            id,
+            "rust".to_owned(),
+            rpc::Client::new(move || client_reader, move || client_writer),
+            test_lang_settings(),
+            root_path.to_owned(),
+        );
+        let fake_server = rpc::Client::new(move || server_reader, move || server_writer);
+
+        (handler, fake_server)
+    }
+
+    fn test_lang_settings() -> LangSettings {
+        LangSettings {
+            indentation: 4,
+            indentation_with_space: true,
+            sync_burst_threshold: 10,
+            sync_delay_ms: SYNC_DELAY_MS,
+            forward_notifications: false,
+            hover_mode: HoverMode::Preview,
+            format_on_save: false,
+            min_diagnostic_severity: DiagnosticLevel::Hint,
+            request_timeout_ms: 60_000,
+            trace: TraceValue::Off,
+        }
+    }
+
+    fn test_config() -> LsConfig {
+        LsConfig {
+            command: Vec::new(),
+            root_markers: Vec::new(),
+            indentation: 4,
+            indentation_with_space: true,
+            sync_burst_threshold: 10,
+            sync_delay_ms: SYNC_DELAY_MS,
+            timer_tick_ms: TIMER_TICK_MS,
+            forward_notifications: false,
+            hover_mode: HoverMode::Preview,
+            format_on_save: false,
+            min_diagnostic_severity: DiagnosticLevel::Hint,
+            request_timeout_ms: 60_000,
+            transport: Transport::Stdio,
+            restart_on_crash: false,
+            settings: serde_json::Value::Null,
+            workspace_folders: Vec::new(),
+            initialization_options: serde_json::Value::Null,
+            env: HashMap::new(),
+            cwd: None,
+            trace: TraceValue::Off,
+            require_root_marker: false,
+        }
+    }
+
+    // Spawned server sending a notification with `params`, expanded by the
+    // shell, then exiting
+    #[cfg(unix)]
+    fn notifying_server(config: LsConfig, params: &str) -> RawNotification {
+        let script = format!(
+            r#"msg="{{\"jsonrpc\":\"2.0\",\"method\":\"test\",\"params\":\"{}\"}}"
+printf 'Content-Length: %d\r\n\r\n%s' ${{#msg}} "$msg""#,
+            params
+        );
+        let config = LsConfig {
+            command: vec!["sh".to_owned(), "-c".to_owned(), script],
+            ..config
+        };
+
+        let handler = LangServerHandler::<MockEditor>::new(
+            1,
+            "rust".to_owned(),
+            &config,
+            test_lang_settings(),
+            "/".to_owned(),
+        )
+        .unwrap();
+
+        match handler.receiver().recv_timeout(Duration::from_secs(5)) {
+            Ok(LspMessage::Notification(notification)) => notification,
+            msg => panic!("Expected notification, got {:?}", msg),
+        }
+    }
+
+    // Lspc with one handler tracking `test_uri`, talking to an in-memory
+    // server. Returns the server's end of the transport.
+    fn lspc_with_fake_server() -> (Lspc<MockEditor>, rpc::Client<LspMessage>) {
+        let (handler, fake_server) = handler_with_fake_server("/");
+
+        let mut lspc = Lspc::new(MockEditor::default());
+        lspc.lsp_handlers.push(handler);
+        lspc.tracking_files.insert(
+            test_uri(),
+            vec![TrackingFile::new(
+                1,
+                test_uri(),
+                "rust".to_owned(),
+                lsp::TextDocumentSyncKind::Full,
+            )],
+        );
+
+        (lspc, fake_server)
+    }
+
+    fn next_request(fake_server: &rpc::Client<LspMessage>) -> RawRequest {
+        match fake_server.receiver.recv_timeout(Duration::from_secs(1)) {
+            Ok(LspMessage::Request(request)) => request,
+            msg => panic!("Expected request, got {:?}", msg),
+        }
+    }
+
+    fn next_notification(fake_server: &rpc::Client<LspMessage>) -> RawNotification {
+        match fake_server.receiver.recv_timeout(Duration::from_secs(1)) {
+            Ok(LspMessage::Notification(notification)) => notification,
+            msg => panic!("Expected notification, got {:?}", msg),
+        }
+    }
+
+    // Handle the events scripted on the editor so far
+    fn handle_scripted_events(lspc: &mut Lspc<MockEditor>) {
+        let events = lspc.editor.events();
+        while let Ok(event) = events.try_recv() {
+            lspc.handle_editor_event(event).unwrap();
+        }
+    }
+
+    fn diagnostic(line: u64, message: &str) -> Diagnostic {
+        Diagnostic::new_simple(
+            Range::new(Position::new(line, 0), Position::new(line, 1)),
+            message.to_owned(),
+        )
+    }
+
+    #[test]
+    fn file_is_open_in_every_server_of_its_language() {
+        let (linter, linter_server) = numbered_handler_with_fake_server(1, "/");
+        let (language, language_server) = numbered_handler_with_fake_server(2, "/");
+        let mut lspc = Lspc::new(MockEditor::default());
+        lspc.lsp_handlers.push(linter);
+        lspc.lsp_handlers.push(language);
+        for (handler, capabilities) in lspc
+            .lsp_handlers
+            .iter_mut()
+            .zip(vec![json!({}), json!({ "hoverProvider": true })])
+        {
+            handler
+                .initialize_response(types::InitializeResult {
+                    capabilities,
+                    server_info: None,
+                })
+                .unwrap();
+        }
+        assert_eq!("initialized", next_notification(&linter_server).method);
+        assert_eq!("initialized", next_notification(&language_server).method);
+
+        lspc.handle_editor_event(Event::DidOpen {
+            buf_id: MockBufferId(1),
+            text_document: TextDocumentIdentifier::new(test_uri()),
+            language_id: "rust".to_owned(),
+        })
+        .unwrap();
+        lspc.handle_editor_event(Event::DidChange {
+            text_document: TextDocumentIdentifier::new(test_uri()),
+            version: 1,
+            content_change: lsp::TextDocumentContentChangeEvent {
+                range: None,
+                range_length: None,
+                text: "fn main() {}".to_owned(),
+            },
+        })
+        .unwrap();
+        assert_eq!(
+            "textDocument/didOpen",
+            next_notification(&linter_server).method
+        );
+        assert_eq!(
+            "textDocument/didOpen",
+            next_notification(&language_server).method
+        );
+
+        // Requests go to the first server supporting them
+        lspc.handle_editor_event(Event::Hover {
+            buf_id: MockBufferId(1),
+            text_document: TextDocumentIdentifier::new(test_uri()),
+            position: Position::new(0, 3),
+        })
+        .unwrap();
+        assert_eq!("textDocument/hover", next_request(&language_server).method);
+
+        // Diagnostics of a server only replace its own
+        let publish = |lspc: &mut Lspc<MockEditor>, handler_id, diagnostics| {
+            lspc.publish_diagnostics(
+                handler_id,
+                PublishDiagnosticsParams {
+                    uri: test_uri(),
+                    diagnostics,
+                    version: None,
+                },
+            )
+            .unwrap();
+        };
+        publish(&mut lspc, 1, vec![diagnostic(0, "unused")]);
+        publish(&mut lspc, 2, vec![diagnostic(1, "mismatched types")]);
+        assert_eq!(
+            vec![diagnostic(0, "unused"), diagnostic(1, "mismatched types")],
+            lspc.file_diagnostics(&test_uri())
+        );
+        publish(&mut lspc, 1, Vec::new());
+        assert_eq!(
+            Some(&(test_uri(), vec![diagnostic(1, "mismatched types")])),
+            lspc.editor.shown_diagnostics.last()
+        );
+    }
+
+    #[test]
+    fn diagnostics_of_exited_server_are_withdrawn() {
+        let (linter, _linter_server) = numbered_handler_with_fake_server(1, "/");
+        let (language, _language_server) = numbered_handler_with_fake_server(2, "/");
+        let mut lspc = Lspc::new(MockEditor::default());
+        lspc.lsp_handlers.push(linter);
+        lspc.lsp_handlers.push(language);
+        for (handler_id, message) in vec![(1, "unused"), (2, "mismatched types")] {
+            lspc.publish_diagnostics(
+                handler_id,
+                PublishDiagnosticsParams {
+                    uri: test_uri(),
+                    diagnostics: vec![diagnostic(0, message)],
+                    version: None,
+                },
+            )
+            .unwrap();
+        }
+
+        lspc.handle_server_exit(0).unwrap();
+
+        assert_eq!(
+            vec![diagnostic(0, "mismatched types")],
+            lspc.file_diagnostics(&test_uri())
+        );
+        assert_eq!(
+            Some(&(test_uri(), vec![diagnostic(0, "mismatched types")])),
+            lspc.editor.shown_diagnostics.last()
+        );
+    }
+
+    #[test]
+    fn settings_are_sent_to_every_server_of_the_language() {
+        let (linter, linter_server) = numbered_handler_with_fake_server(1, "/");
+        let (language, language_server) = numbered_handler_with_fake_server(2, "/");
+        let mut lspc = Lspc::new(MockEditor::default());
+        lspc.lsp_handlers.push(linter);
+        lspc.lsp_handlers.push(language);
+
+        lspc.handle_editor_event(Event::UpdateServerSettings {
+            lang_id: "rust".to_owned(),
+            settings: json!({ "checkOnSave": { "command": "clippy" } }),
+            replace: false,
+        })
+        .unwrap();
+
+        for fake_server in &[linter_server, language_server] {
+            let notification = next_notification(fake_server)
+                .cast::<noti::DidChangeConfiguration>()
+                .unwrap();
+            assert_eq!(
+                json!({ "checkOnSave": { "command": "clippy" } }),
+                notification.settings
+            );
+        }
+    }
+
+    #[test]
+    fn toggle_diagnostics_restores_last_published() {
+        let uri = test_uri();
+        let text_document = TextDocumentIdentifier::new(uri.clone());
+        let mut lspc = Lspc::new(MockEditor::default());
+
+        lspc.publish_diagnostics(
+            1,
+            PublishDiagnosticsParams {
+                uri: uri.clone(),
+                diagnostics: vec![diagnostic(0, "first")],
+                version: None,
+            },
+        )
+        .unwrap();
+        lspc.handle_editor_event(Event::ToggleDiagnostics {
+            buf_id: MockBufferId(1),
+            text_document: text_document.clone(),
+            enabled: false,
+        })
+        .unwrap();
+        assert_eq!(
+            Some(&(uri.clone(), Vec::new())),
+            lspc.editor.shown_diagnostics.last()
+        );
+
+        // Stored but not rendered while suppressed
+        lspc.publish_diagnostics(
+            1,
+            PublishDiagnosticsParams {
+                uri: uri.clone(),
+                diagnostics: vec![diagnostic(1, "second")],
+                version: None,
+            },
+        )
+        .unwrap();
+        assert_eq!(2, lspc.editor.shown_diagnostics.len());
+
+        lspc.handle_editor_event(Event::ToggleDiagnostics {
+            buf_id: MockBufferId(1),
+            text_document,
+            enabled: true,
+        })
+        .unwrap();
+        assert_eq!(
+            Some(&(uri, vec![diagnostic(1, "second")])),
+            lspc.editor.shown_diagnostics.last()
+        );
+    }
+
+    #[test]
+    fn last_diagnostics_are_listed_by_position() {
+        let uri = test_uri();
+        let mut lspc = Lspc::new(MockEditor::default());
+
+        lspc.publish_diagnostics(
+            1,
+            PublishDiagnosticsParams {
+                uri: uri.clone(),
+                diagnostics: vec![diagnostic(3, "later"), diagnostic(1, "earlier")],
+                version: None,
+            },
+        )
+        .unwrap();
+        lspc.handle_editor_event(Event::DiagnosticsToLoclist {
+            text_document: TextDocumentIdentifier::new(uri),
+        })
+        .unwrap();
+
+        assert_eq!(
+            vec![vec![diagnostic(1, "earlier"), diagnostic(3, "later")]],
+            lspc.editor.listed_diagnostics
+        );
+    }
+
+    fn snippet_item(insert_text: &str) -> CompletionItem {
+        let mut base = lsp::CompletionItem::new_simple("push".to_owned(), "".to_owned());
+        base.insert_text = Some(insert_text.to_owned());
+        base.insert_text_format = Some(lsp::InsertTextFormat::Snippet);
+
+        CompletionItem {
+            base,
+            label_details: None,
+        }
+    }
+
+    fn completion_done(item: CompletionItem) -> Event<MockBufferId> {
+        Event::CompletionDone {
+            buf_id: MockBufferId(1),
+            text_document_lines: vec!["v.push".to_owned()],
+            text_document: TextDocumentIdentifier::new(test_uri()),
+            item,
+        }
+    }
+
+    #[test]
+    fn committed_snippet_item_is_expanded() {
+        let (mut lspc, _fake_server) = lspc_with_fake_server();
+        lspc.editor.capabilities = lsp::ClientCapabilities {
+            text_document: Some(lsp::TextDocumentClientCapabilities {
+                completion: Some(lsp::CompletionCapability {
+                    completion_item: Some(lsp::CompletionItemCapability {
+                        snippet_support: Some(true),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let mut plain = snippet_item("push");
+        plain.base.insert_text_format = Some(lsp::InsertTextFormat::PlainText);
+        lspc.handle_editor_event(completion_done(plain)).unwrap();
+        lspc.handle_editor_event(completion_done(snippet_item("push(${1:value})$0")))
+            .unwrap();
+
+        assert_eq!(
+            vec![(MockBufferId(1), "push(${1:value})$0".to_owned())],
+            lspc.editor.expanded_snippets
+        );
+    }
+
+    #[test]
+    fn snippet_is_not_expanded_without_snippet_support() {
+        let (mut lspc, _fake_server) = lspc_with_fake_server();
+
+        lspc.handle_editor_event(completion_done(snippet_item("push(${1:value})$0")))
+            .unwrap();
+
+        assert!(lspc.editor.expanded_snippets.is_empty());
+    }
+
+    #[test]
+    fn resolved_edits_are_applied_to_current_lines() {
+        let (mut lspc, fake_server) = lspc_with_fake_server();
+        lspc.lsp_handlers[0]
+            .initialize_response(types::InitializeResult {
+                capabilities: json!({ "completionProvider": { "resolveProvider": true } }),
+                server_info: None,
+            })
+            .unwrap();
+        assert_eq!("initialized", next_notification(&fake_server).method);
+        let item = snippet_item("push");
+        lspc.handle_editor_event(completion_done(item.clone()))
+            .unwrap();
+        let request = next_request(&fake_server);
+        assert_eq!("completionItem/resolve", request.method);
+
+        // Typed while resolving
+        let current_lines = vec!["v.push".to_owned(), "v.len()".to_owned()];
+        lspc.editor
+            .buffer_lines
+            .insert(MockBufferId(1), current_lines.clone());
+        let mut resolved = item;
+        resolved.base.additional_text_edits = Some(vec![TextEdit::new(
+            Range::new(Position::new(0, 0), Position::new(0, 0)),
+            "use std::vec::Vec;\n".to_owned(),
+        )]);
+        let response = RawResponse::ok::<ResolveCompletionItem>(request.id, &resolved);
+        lspc.handle_lsp_msg(0, LspMessage::Response(response))
+            .unwrap();
+
+        assert_eq!(vec![current_lines], *lspc.editor.edited_lines.borrow());
+    }
+
+    fn prepared_rename_server() -> (Lspc<MockEditor>, rpc::Client<LspMessage>) {
+        let (mut lspc, fake_server) = lspc_with_fake_server();
+        lspc.lsp_handlers[0]
+            .initialize_response(types::InitializeResult {
+                capabilities: json!({ "renameProvider": { "prepareProvider": true } }),
+                server_info: None,
+            })
+            .unwrap();
+        assert_eq!("initialized", next_notification(&fake_server).method);
+        lspc.handle_editor_event(Event::Rename {
+            text_document: TextDocumentIdentifier::new(test_uri()),
+            position: Position::new(0, 5),
+            new_name: None,
+        })
+        .unwrap();
+
+        (lspc, fake_server)
+    }
+
+    #[test]
+    fn new_name_is_asked_once_rename_is_prepared() {
+        let (mut lspc, fake_server) = prepared_rename_server();
+        lspc.editor.input = Some("total".to_owned());
+        let request = next_request(&fake_server);
+        assert_eq!("textDocument/prepareRename", request.method);
+
+        let prepared = lsp::PrepareRenameResponse::RangeWithPlaceholder {
+            range: Range::new(Position::new(0, 4), Position::new(0, 9)),
+            placeholder: "count".to_owned(),
+        };
+        let response = RawResponse::ok::<PrepareRenameRequest>(request.id, &Some(prepared));
+        lspc.handle_lsp_msg(0, LspMessage::Response(response))
+            .unwrap();
+
+        assert_eq!(vec!["count".to_owned()], lspc.editor.input_defaults);
+        let request = next_request(&fake_server);
+        assert_eq!("textDocument/rename", request.method);
+        assert_eq!(json!("total"), request.params["newName"]);
+    }
+
+    #[test]
+    fn new_name_is_not_asked_when_rename_is_rejected() {
+        let (mut lspc, fake_server) = prepared_rename_server();
+        let request = next_request(&fake_server);
+
+        let response = RawResponse::ok::<PrepareRenameRequest>(request.id, &None);
+        lspc.handle_lsp_msg(0, LspMessage::Response(response))
+            .unwrap();
+
+        assert!(lspc.editor.input_defaults.is_empty());
+        assert_eq!(
+            vec!["Symbol can not be renamed".to_owned()],
+            lspc.editor.messages
+        );
+    }
+
+    #[test]
+    fn word_under_cursor_is_the_default_new_name() {
+        assert_eq!("count", word_at("let count = 1;", 6));
+        assert_eq!("count", word_at("let count = 1;", 4));
+        assert_eq!("count", word_at("let count = 1;", 9));
+        assert_eq!("", word_at("a = b", 2));
+        assert_eq!("café", word_at("let café = 1;", 8));
+    }
+
+    #[test]
+    fn closed_document_is_forgotten() {
+        let (mut lspc, fake_server) = lspc_with_fake_server();
+        lspc.publish_diagnostics(
+            1,
+            PublishDiagnosticsParams {
+                uri: test_uri(),
+                diagnostics: vec![diagnostic(0, "unused")],
+                version: None,
+            },
+        )
+        .unwrap();
+        let mut features = HashMap::new();
+        features.insert(Feature::Hover, false);
+        lspc.handle_editor_event(Event::SetBufferFeatures {
+            text_document: TextDocumentIdentifier::new(test_uri()),
+            features,
+        })
+        .unwrap();
+
+        lspc.handle_editor_event(Event::DidClose {
+            text_document: TextDocumentIdentifier::new(test_uri()),
+        })
+        .unwrap();
+
+        // The last content is synced before closing
+        assert_eq!(
+            "textDocument/didChange",
+            next_notification(&fake_server).method
+        );
+        assert_eq!(
+            "textDocument/didClose",
+            next_notification(&fake_server).method
+        );
+        assert!(lspc.tracking_files.is_empty());
+        assert!(lspc.diagnostics.borrow().is_empty());
+        assert!(lspc.disabled_features.is_empty());
+        assert_eq!(
+            Some(&(test_uri(), Vec::new())),
+            lspc.editor.shown_diagnostics.last()
+        );
+    }
+
+    #[test]
+    fn diagnostics_below_min_severity_are_hidden() {
+        let (mut lspc, _fake_server) = lspc_with_fake_server();
+        lspc.lsp_handlers[0].lang_settings.min_diagnostic_severity = DiagnosticLevel::Warning;
+        let with_severity = |line, severity| Diagnostic {
+            severity,
+            ..diagnostic(line, "diagnostic")
+        };
+
+        let params = PublishDiagnosticsParams {
+            uri: test_uri(),
+            diagnostics: vec![
+                with_severity(0, Some(DiagnosticSeverity::Hint)),
+                with_severity(1, Some(DiagnosticSeverity::Error)),
+                with_severity(2, Some(DiagnosticSeverity::Information)),
+                with_severity(3, Some(DiagnosticSeverity::Warning)),
+                with_severity(4, None),
+            ],
+            version: None,
+        };
+        lspc.handle_lsp_msg(
+            0,
+            LspMessage::Notification(RawNotification::new::<PublishDiagnostics>(&params)),
+        )
+        .unwrap();
+
+        assert_eq!(
+            Some(&(
+                test_uri(),
+                vec![
+                    with_severity(1, Some(DiagnosticSeverity::Error)),
+                    with_severity(3, Some(DiagnosticSeverity::Warning)),
+                    with_severity(4, None),
+                ]
+            )),
+            lspc.editor.shown_diagnostics.last()
+        );
+    }
+
+    #[test]
+    fn stale_diagnostics_are_dropped() {
+        let (mut lspc, _fake_server) = lspc_with_fake_server();
+        lspc.tracking_files.get_mut(&test_uri()).unwrap()[0].track_change(
+            3,
+            &lsp::TextDocumentContentChangeEvent {
+                range: None,
+                range_length: None,
+                text: "fn main() {}".to_owned(),
+            },
+        );
+
+        lspc.publish_diagnostics(
+            1,
+            PublishDiagnosticsParams {
+                uri: test_uri(),
+                diagnostics: vec![diagnostic(0, "stale")],
+                version: Some(2),
+            },
+        )
+        .unwrap();
+        assert!(lspc.editor.shown_diagnostics.is_empty());
+
+        lspc.publish_diagnostics(
+            1,
+            PublishDiagnosticsParams {
+                uri: test_uri(),
+                diagnostics: vec![diagnostic(0, "current")],
+                version: Some(3),
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            vec![(test_uri(), vec![diagnostic(0, "current")])],
+            lspc.editor.shown_diagnostics
+        );
+    }
+
+    #[test]
+    fn partial_settings_update_keeps_unrelated_settings() {
+        let (mut lspc, fake_server) = lspc_with_fake_server();
+        lspc.handle_editor_event(Event::UpdateServerSettings {
+            lang_id: "rust".to_owned(),
+            settings: json!({ "checkOnSave": { "enable": true, "command": "check" }, "cargo": {} }),
+            replace: false,
+        })
+        .unwrap();
+        next_notification(&fake_server);
+
+        lspc.handle_editor_event(Event::UpdateServerSettings {
+            lang_id: "rust".to_owned(),
+            settings: json!({ "checkOnSave": { "command": "clippy" } }),
+            replace: false,
+        })
+        .unwrap();
+        let notification = next_notification(&fake_server)
+            .cast::<noti::DidChangeConfiguration>()
+            .unwrap();
+
+        assert_eq!(
+            json!({ "checkOnSave": { "enable": true, "command": "clippy" }, "cargo": {} }),
+            notification.settings
+        );
+    }
+
+    #[test]
+    fn configuration_is_answered_from_updated_config() {
+        let (mut lspc, fake_server) = lspc_with_fake_server();
+        let settings = json!({ "rust-analyzer": { "cargo": { "features": ["serde"] } } });
+        lspc.handle_editor_event(Event::UpdateServerSettings {
+            lang_id: "rust".to_owned(),
+            settings: settings.clone(),
+            replace: true,
+        })
+        .unwrap();
+        let notification = next_notification(&fake_server)
+            .cast::<noti::DidChangeConfiguration>()
+            .unwrap();
+        assert_eq!(settings, notification.settings);
+
+        let params = lsp::ConfigurationParams {
+            items: vec![
+                lsp::ConfigurationItem {
+                    scope_uri: None,
+                    section: Some("rust-analyzer.cargo".to_owned()),
+                },
+                lsp::ConfigurationItem {
+                    scope_uri: None,
+                    section: Some("rust-analyzer.checkOnSave".to_owned()),
+                },
+            ],
+        };
+        let request = RawRequest::new::<WorkspaceConfiguration>(1, &params);
+        lspc.handle_lsp_msg(0, LspMessage::Request(request))
+            .unwrap();
+        match fake_server.receiver.recv_timeout(Duration::from_secs(1)) {
+            Ok(LspMessage::Response(response)) => {
+                assert_eq!(1, response.id);
+                assert_eq!(
+                    Some(json!([{ "features": ["serde"] }, null])),
+                    response.result
+                );
+            }
+            msg => panic!("Expected response, got {:?}", msg),
+        }
+    }
+
+    #[test]
+    fn trace_is_changed_at_runtime() {
+        let (mut lspc, fake_server) = lspc_with_fake_server();
+        lspc.handle_editor_event(Event::SetTrace {
+            lang_id: "rust".to_owned(),
+            value: TraceValue::Verbose,
+        })
+        .unwrap();
+
+        let notification = next_notification(&fake_server);
+        assert_eq!("$/setTrace", notification.method);
+        assert_eq!(json!({ "value": "verbose" }), notification.params);
+        assert_eq!(
+            TraceValue::Verbose,
+            lspc.lsp_handlers[0].lang_settings.trace
+        );
+    }
+
+    #[test]
+    fn multi_hover_keeps_requested_order() {
+        let (mut lspc, fake_server) = lspc_with_fake_server();
+        let positions = vec![Position::new(1, 2), Position::new(3, 4)];
+        lspc.handle_editor_event(Event::MultiHover {
+            text_document: TextDocumentIdentifier::new(test_uri()),
+            positions: positions.clone(),
+        })
+        .unwrap();
+        let first = next_request(&fake_server);
+        let second = next_request(&fake_server);
+
+        let hover = |text: &str| Hover {
+            contents: lsp::HoverContents::Scalar(lsp::MarkedString::String(text.to_owned())),
+            range: None,
+        };
+        // Answered out of order
+        let response = RawResponse::ok::<HoverRequest>(second.id, &Some(hover("second")));
+        lspc.handle_lsp_msg(0, LspMessage::Response(response))
+            .unwrap();
+        assert!(lspc.editor.shown_hovers.is_empty());
+        let response = RawResponse::ok::<HoverRequest>(first.id, &Some(hover("first")));
+        lspc.handle_lsp_msg(0, LspMessage::Response(response))
+            .unwrap();
+
+        assert_eq!(
+            vec![vec![
+                (positions[0], hover("first")),
+                (positions[1], hover("second")),
+            ]],
+            lspc.editor.shown_hovers
+        );
+    }
+
+    #[test]
+    fn multi_hover_is_shown_when_one_request_fails() {
+        let (mut lspc, fake_server) = lspc_with_fake_server();
+        let positions = vec![Position::new(1, 2), Position::new(3, 4)];
+        lspc.handle_editor_event(Event::MultiHover {
+            text_document: TextDocumentIdentifier::new(test_uri()),
+            positions: positions.clone(),
+        })
+        .unwrap();
+        let first = next_request(&fake_server);
+        let second = next_request(&fake_server);
+
+        let hover = Hover {
+            contents: lsp::HoverContents::Scalar(lsp::MarkedString::String("first".to_owned())),
+            range: None,
+        };
+        let response = RawResponse::ok::<HoverRequest>(first.id, &Some(hover.clone()));
+        lspc.handle_lsp_msg(0, LspMessage::Response(response))
+            .unwrap();
+        let response = RawResponse::err(
+            second.id,
+            ErrorCode::InternalError as i32,
+            "no hover".to_owned(),
+        );
+        assert!(lspc
+            .handle_lsp_msg(0, LspMessage::Response(response))
+            .is_err());
+
+        assert_eq!(vec![vec![(positions[0], hover)]], lspc.editor.shown_hovers);
+    }
+
+    #[test]
+    fn disabled_feature_only_affects_its_buffer() {
+        let (mut lspc, fake_server) = lspc_with_fake_server();
+        let other_uri = test_uri().join("e").unwrap();
+        lspc.tracking_files.insert(
+            other_uri.clone(),
+            vec![TrackingFile::new(
+                1,
+                other_uri.clone(),
+                "rust".to_owned(),
+                lsp::TextDocumentSyncKind::Full,
+            )],
+        );
+        let mut features = HashMap::new();
+        features.insert(Feature::Hover, false);
+        lspc.handle_editor_event(Event::SetBufferFeatures {
+            text_document: TextDocumentIdentifier::new(test_uri()),
+            features,
+        })
+        .unwrap();
+
+        lspc.handle_editor_event(Event::Hover {
+            buf_id: MockBufferId(1),
+            text_document: TextDocumentIdentifier::new(test_uri()),
+            position: Position::new(1, 2),
+        })
+        .unwrap();
+        lspc.handle_editor_event(Event::Hover {
+            buf_id: MockBufferId(1),
+            text_document: TextDocumentIdentifier::new(other_uri.clone()),
+            position: Position::new(1, 2),
+        })
+        .unwrap();
+
+        let request = next_request(&fake_server);
+        assert_eq!("textDocument/hover", request.method);
+        assert_eq!(
+            json!(other_uri.as_str()),
+            request.params["textDocument"]["uri"]
+        );
+        assert!(fake_server
+            .receiver
+            .recv_timeout(Duration::from_millis(100))
+            .is_err());
+    }
+
+    #[test]
+    fn new_hover_cancels_the_pending_one() {
+        let (mut lspc, fake_server) = lspc_with_fake_server();
+        let hover = |lspc: &mut Lspc<MockEditor>| {
+            lspc.handle_editor_event(Event::Hover {
+                buf_id: MockBufferId(1),
+                text_document: TextDocumentIdentifier::new(test_uri()),
+                position: Position::new(1, 2),
+            })
+            .unwrap();
+        };
+
+        hover(&mut lspc);
+        let first = next_request(&fake_server);
+        hover(&mut lspc);
+
+        let cancel = next_notification(&fake_server);
+        assert_eq!("$/cancelRequest", cancel.method);
+        assert_eq!(json!(first.id), cancel.params["id"]);
+        let second = next_request(&fake_server);
+        assert_eq!("textDocument/hover", second.method);
+
+        // Already cancelled
+        lspc.handle_editor_event(Event::CancelRequest {
+            text_document: TextDocumentIdentifier::new(test_uri()),
+            id: first.id,
+        })
+        .unwrap();
+        assert!(fake_server
+            .receiver
+            .recv_timeout(Duration::from_millis(100))
+            .is_err());
+    }
+
+    #[test]
+    fn overdue_request_is_cancelled() {
+        let (mut lspc, fake_server) = lspc_with_fake_server();
+        lspc.lsp_handlers[0].lang_settings.request_timeout_ms = 0;
+
+        lspc.handle_editor_event(Event::Hover {
+            buf_id: MockBufferId(1),
+            text_document: TextDocumentIdentifier::new(test_uri()),
+            position: Position::new(1, 2),
+        })
+        .unwrap();
+        let hover = next_request(&fake_server);
+        lspc.handle_timer_tick().unwrap();
+
+        let cancel = next_notification(&fake_server);
+        assert_eq!("$/cancelRequest", cancel.method);
+        assert_eq!(json!(hover.id), cancel.params["id"]);
+
+        // The late response is ignored
+        let late = Hover {
+            contents: lsp::HoverContents::Scalar(lsp::MarkedString::String("late".to_owned())),
+            range: None,
+        };
+        let response = RawResponse::ok::<HoverRequest>(hover.id, &Some(late));
+        lspc.handle_lsp_msg(0, LspMessage::Response(response))
+            .unwrap();
+        assert!(lspc.editor.shown_hovers.is_empty());
+    }
+
+    #[test]
+    fn goto_definition_handles_arrays_and_links() {
+        let (mut lspc, fake_server) = lspc_with_fake_server();
+        let goto_definition = |lspc: &mut Lspc<MockEditor>| {
+            lspc.handle_editor_event(Event::GotoDefinition {
+                text_document: TextDocumentIdentifier::new(test_uri()),
+                position: Position::new(1, 2),
+                target: GotoTarget::Edit,
+            })
+            .unwrap();
+            next_request(&fake_server)
+        };
+        let location = |line: u64| {
+            Location::new(
+                test_uri(),
+                Range::new(Position::new(line, 0), Position::new(line, 0)),
+            )
+        };
+
+        let request = goto_definition(&mut lspc);
+        let response = RawResponse::ok::<GotoDefinition>(
+            request.id,
+            &Some(GotoDefinitionResponse::Array(vec![
+                location(3),
+                location(7),
+            ])),
+        );
+        lspc.handle_lsp_msg(0, LspMessage::Response(response))
+            .unwrap();
+        assert_eq!(
+            vec![vec![location(3), location(7)]],
+            lspc.editor.shown_references
+        );
+
+        let request = goto_definition(&mut lspc);
+        let link = LocationLink {
+            origin_selection_range: None,
+            target_uri: test_uri(),
+            target_range: Range::new(Position::new(10, 0), Position::new(14, 1)),
+            target_selection_range: Range::new(Position::new(10, 0), Position::new(10, 3)),
+        };
+        let response = RawResponse::ok::<GotoDefinition>(
+            request.id,
+            &Some(GotoDefinitionResponse::Link(vec![link])),
+        );
+        lspc.handle_lsp_msg(0, LspMessage::Response(response))
+            .unwrap();
+        assert_eq!(vec![location(10)], lspc.editor.gotos);
+    }
+
+    #[test]
+    fn goto_definition_opens_in_requested_window() {
+        let (mut lspc, fake_server) = lspc_with_fake_server();
+        lspc.handle_editor_event(Event::GotoDefinition {
+            text_document: TextDocumentIdentifier::new(test_uri()),
+            position: Position::new(1, 2),
+            target: GotoTarget::Vsplit,
+        })
+        .unwrap();
+        let request = next_request(&fake_server);
+        let location = Location::new(
+            test_uri(),
+            Range::new(Position::new(3, 0), Position::new(3, 0)),
+        );
+
+        let response = RawResponse::ok::<GotoDefinition>(
+            request.id,
+            &Some(GotoDefinitionResponse::Scalar(location.clone())),
+        );
+        lspc.handle_lsp_msg(0, LspMessage::Response(response))
+            .unwrap();
+
+        assert_eq!(vec![location], lspc.editor.gotos);
+        assert_eq!(vec![GotoTarget::Vsplit], lspc.editor.goto_targets);
+    }
+
+    #[test]
+    fn prewarmed_handler_is_reused_on_open() {
+        let (handler, fake_server) = handler_with_fake_server("/a/b");
+        let mut lspc = Lspc::new(MockEditor::default());
+
+        lspc.initialize_server(handler, "/a/b").unwrap();
+        assert_eq!("initialize", next_request(&fake_server).method);
+
+        lspc.handle_editor_event(Event::DidOpen {
+            buf_id: MockBufferId(1),
+            text_document: TextDocumentIdentifier::new(test_uri()),
+            language_id: "rust".to_owned(),
+        })
+        .unwrap();
+
+        assert_eq!(1, lspc.lsp_handlers.len());
+        assert_eq!(1, lspc.tracking_files[&test_uri()][0].handler_id);
+    }
+
+    #[test]
+    fn root_path_falls_back_to_file_directory() {
+        let dir = std::env::temp_dir().join(format!("lspc-root-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        let file = dir.join("src").join("main.rs");
+        std::fs::write(&file, "").unwrap();
+        let markers = vec!["lspc-root-marker".to_owned()];
+
+        assert_eq!(
+            Some(dir.join("src").as_path()),
+            find_root_path(&file, &markers, false)
+        );
+        assert_eq!(None, find_root_path(&file, &markers, true));
+
+        std::fs::write(dir.join("lspc-root-marker"), "").unwrap();
+        assert_eq!(Some(dir.as_path()), find_root_path(&file, &markers, true));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn handler_includes_files_by_path_components() {
+        let (handler, _fake_server) = handler_with_fake_server("/home/me/proj");
+        let uri = |path: &str| Url::parse(&format!("file://{}", path)).unwrap();
+
+        assert!(handler.include_file(&uri("/home/me/proj/src/main.rs")));
+        assert!(!handler.include_file(&uri("/home/me/proj-other/src/main.rs")));
+        assert!(!handler.include_file(&uri("/home/me/pro")));
+
+        let (handler, _fake_server) = handler_with_fake_server("/home/me/my proj");
+        assert!(handler.include_file(&uri("/home/me/my%20proj/main.rs")));
+        assert!(!handler.include_file(&uri("/home/me/my%20proj-other/main.rs")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn handler_includes_new_files_under_symlinked_root() {
+        let dir = std::env::temp_dir().join(format!("lspc-symlink-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("real")).unwrap();
+        std::os::unix::fs::symlink(dir.join("real"), dir.join("link")).unwrap();
+        let (handler, _fake_server) = handler_with_fake_server(dir.join("link").to_str().unwrap());
+
+        // Neither file exists, only their directory is resolved
+        assert!(handler.include_file(&Url::from_file_path(dir.join("real/new.rs")).unwrap()));
+        assert!(handler.include_file(&Url::from_file_path(dir.join("link/new.rs")).unwrap()));
+        assert!(!handler.include_file(&Url::from_file_path(dir.join("new.rs")).unwrap()));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn file_under_second_workspace_folder_is_handled() {
+        let (mut handler, fake_server) = handler_with_fake_server("/x");
+        handler.add_workspace_folder("/a".to_owned()).unwrap();
+        let mut lspc = Lspc::new(MockEditor::default());
+
+        lspc.initialize_server(handler, "/x").unwrap();
+        let request = next_request(&fake_server);
+        assert_eq!("initialize", request.method);
+        let folders = request.params["workspaceFolders"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|folder| folder["name"].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(vec![json!("x"), json!("a")], folders);
+
+        lspc.handle_editor_event(Event::DidOpen {
+            buf_id: MockBufferId(1),
+            text_document: TextDocumentIdentifier::new(test_uri()),
+            language_id: "rust".to_owned(),
+        })
+        .unwrap();
+        assert_eq!(1, lspc.tracking_files[&test_uri()][0].handler_id);
+    }
+
+    #[test]
+    fn files_of_one_handler_are_opened_with_their_own_language() {
+        #[cfg(not(target_os = "windows"))]
+        let (ts_path, tsx_path) = ("/a/b/index.ts", "/a/b/app.tsx");
+        #[cfg(target_os = "windows")]
+        let (ts_path, tsx_path) = (r#"C:\\a\b\index.ts"#, r#"C:\\a\b\app.tsx"#);
+
+        let (mut lspc, fake_server) = lspc_with_fake_server();
+        for (buf_id, path, language_id) in
+            vec![(1, ts_path, "typescript"), (2, tsx_path, "typescriptreact")]
+        {
+            let text_document = TextDocumentIdentifier::new(Url::from_file_path(path).unwrap());
+            lspc.handle_editor_event(Event::DidOpen {
+                buf_id: MockBufferId(buf_id),
+                text_document: text_document.clone(),
+                language_id: language_id.to_owned(),
+            })
+            .unwrap();
+            lspc.handle_editor_event(Event::DidChange {
+                text_document,
+                version: 1,
+                content_change: lsp::TextDocumentContentChangeEvent {
+                    range: None,
+                    range_length: None,
+                    text: "export {}".to_owned(),
+                },
+            })
+            .unwrap();
+        }
+
+        for language_id in &["typescript", "typescriptreact"] {
+            let notification = next_notification(&fake_server)
+                .cast::<noti::DidOpenTextDocument>()
+                .unwrap();
+            assert_eq!(*language_id, notification.text_document.language_id);
+        }
+    }
+
+    #[test]
+    fn document_link_under_cursor_is_opened() {
+        let (mut lspc, fake_server) = lspc_with_fake_server();
+        lspc.handle_editor_event(Event::DocumentLinks {
+            buf_id: MockBufferId(1),
+            text_document: TextDocumentIdentifier::new(test_uri()),
+        })
+        .unwrap();
+        let request = next_request(&fake_server);
+
+        let target = test_uri().join("e").unwrap();
+        let link = DocumentLink {
+            range: Range::new(Position::new(2, 4), Position::new(2, 10)),
+            target: Some(target.clone()),
+            tooltip: None,
+            data: None,
+        };
+        let response = RawResponse::ok::<DocumentLinkRequest>(request.id, &Some(vec![link]));
+        lspc.handle_lsp_msg(0, LspMessage::Response(response))
+            .unwrap();
+
+        lspc.handle_editor_event(Event::GotoDocumentLink {
+            text_document: TextDocumentIdentifier::new(test_uri()),
+            position: Position::new(2, 6),
+        })
+        .unwrap();
+
+        assert_eq!(
+            vec![Location::new(
+                target,
+                Range::new(Position::new(0, 0), Position::new(0, 0))
+            )],
+            lspc.editor.gotos
+        );
+    }
+
+    #[test]
+    fn inlay_hint_goto_jumps_to_label_location() {
+        let (mut lspc, fake_server) = lspc_with_fake_server();
+        lspc.handle_editor_event(Event::InlayHints {
+            buf_id: MockBufferId(1),
+            text_document: TextDocumentIdentifier::new(test_uri()),
+        })
+        .unwrap();
+        let request = next_request(&fake_server);
+        assert_eq!("textDocument/inlayHint", request.method);
+
+        let location = |line: u64| {
+            Location::new(
+                test_uri().join("types.rs").unwrap(),
+                Range::new(Position::new(line, 0), Position::new(line, 4)),
+            )
+        };
+        let hint = |character: u64, line: u64| StandardInlayHint {
+            position: Position::new(3, character),
+            label: InlayHintLabel::Parts(vec![
+                types::InlayHintLabelPart {
+                    value: ": ".to_owned(),
+                    location: None,
+                },
+                types::InlayHintLabelPart {
+                    value: "Type".to_owned(),
+                    location: Some(location(line)),
+                },
+            ]),
+            kind: Some(1),
+            padding_left: None,
+            padding_right: None,
+        };
+        let response =
+            RawResponse::ok::<InlayHintRequest>(request.id, &Some(vec![hint(5, 10), hint(20, 30)]));
+        lspc.handle_lsp_msg(0, LspMessage::Response(response))
+            .unwrap();
+
+        lspc.handle_editor_event(Event::InlayHintGoto {
+            text_document: TextDocumentIdentifier::new(test_uri()),
+            position: Position::new(3, 18),
+        })
+        .unwrap();
+
+        assert_eq!(vec![location(30)], lspc.editor.gotos);
+    }
+
+    #[test]
+    fn ready_notification_has_completion_trigger_characters() {
+        let (handler, fake_server) = handler_with_fake_server("/");
+        let mut lspc = Lspc::new(MockEditor::default());
+        lspc.initialize_server(handler, "/").unwrap();
+        let request = next_request(&fake_server);
+
+        let capabilities = lsp::ServerCapabilities {
+            completion_provider: Some(lsp::CompletionOptions {
+                resolve_provider: None,
+                trigger_characters: Some(vec![".".to_owned(), "::".to_owned()]),
+            }),
+            ..Default::default()
+        };
+        let response = RawResponse::ok::<Initialize>(
+            request.id,
+            &types::InitializeResult {
+                capabilities: serde_json::to_value(capabilities).unwrap(),
+                server_info: None,
+            },
+        );
+        lspc.handle_lsp_msg(0, LspMessage::Response(response))
+            .unwrap();
+
+        assert_eq!(
+            vec![ServerReady {
+                lang_id: "rust".to_owned(),
+                completion_trigger_characters: vec![".".to_owned(), "::".to_owned()],
+                on_type_formatting_trigger_characters: Vec::new(),
+            }],
+            lspc.editor.ready_servers
+        );
+    }
+
+    #[test]
+    fn position_encoding_is_negotiated() {
+        let (handler, fake_server) = handler_with_fake_server("/");
+        let mut lspc = Lspc::new(MockEditor::default());
+        lspc.initialize_server(handler, "/").unwrap();
+        let request = next_request(&fake_server);
+        assert_eq!(
+            json!(["utf-8", "utf-32", "utf-16"]),
+            request.params["capabilities"]["general"]["positionEncodings"]
+        );
+        assert_eq!(
+            PositionEncoding::Utf16,
+            lspc.lsp_handlers[0].position_encoding()
+        );
+
+        let response = RawResponse::ok::<Initialize>(
+            request.id,
+            &types::InitializeResult {
+                capabilities: json!({ "positionEncoding": "utf-8" }),
+                server_info: None,
+            },
+        );
+        lspc.handle_lsp_msg(0, LspMessage::Response(response))
+            .unwrap();
+
+        assert_eq!(
+            PositionEncoding::Utf8,
+            lspc.lsp_handlers[0].position_encoding()
+        );
+    }
+
+    #[test]
+    fn cursor_position_is_sent_in_server_encoding() {
+        let (mut lspc, fake_server) = lspc_with_fake_server();
+        // `é` is two bytes but one UTF-16 code unit
+        lspc.editor
+            .document_lines
+            .insert(test_uri(), vec!["let café = 1;".to_owned()]);
+
+        lspc.handle_editor_event(Event::Hover {
+            buf_id: MockBufferId(1),
+            text_document: TextDocumentIdentifier::new(test_uri()),
+            position: Position::new(0, 10),
+        })
+        .unwrap();
+
+        let request = next_request(&fake_server);
+        assert_eq!(
+            json!({ "line": 0, "character": 9 }),
+            request.params["position"]
+        );
+    }
+
+    #[test]
+    fn references_are_listed_in_byte_columns() {
+        let (mut lspc, fake_server) = lspc_with_fake_server();
+        lspc.editor
+            .document_lines
+            .insert(test_uri(), vec!["let café = 1;".to_owned()]);
+
+        lspc.handle_editor_event(Event::References {
+            text_document: TextDocumentIdentifier::new(test_uri()),
+            position: Position::new(0, 0),
+            include_declaration: true,
+        })
+        .unwrap();
+        let request = next_request(&fake_server);
+        let location = |character| {
+            Location::new(
+                test_uri(),
+                Range::new(Position::new(0, character), Position::new(0, character)),
+            )
+        };
+        let response = RawResponse::ok::<References>(request.id, &Some(vec![location(9)]));
+        lspc.handle_lsp_msg(0, LspMessage::Response(response))
+            .unwrap();
+
+        assert_eq!(vec![vec![location(10)]], lspc.editor.shown_references);
+    }
+
+    #[test]
+    fn highlights_and_code_action_ranges_are_converted() {
+        let (mut lspc, fake_server) = lspc_with_fake_server();
+        lspc.lsp_handlers[0]
+            .initialize_response(types::InitializeResult {
+                capabilities: json!({
+                    "documentHighlightProvider": true,
+                    "codeActionProvider": true,
+                }),
+                server_info: None,
+            })
+            .unwrap();
+        assert_eq!("initialized", next_notification(&fake_server).method);
+        // `é` is two bytes but one UTF-16 code unit
+        lspc.editor
+            .document_lines
+            .insert(test_uri(), vec!["let café = café;".to_owned()]);
+        let range = |start, end| Range::new(Position::new(0, start), Position::new(0, end));
+
+        lspc.handle_editor_event(Event::DocumentHighlight {
+            buf_id: MockBufferId(1),
+            text_document: TextDocumentIdentifier::new(test_uri()),
+            position: Position::new(0, 4),
+        })
+        .unwrap();
+        let request = next_request(&fake_server);
+        let highlight = |range| DocumentHighlight { range, kind: None };
+        let response = RawResponse::ok::<DocumentHighlightRequest>(
+            request.id,
+            &Some(vec![highlight(range(11, 15))]),
+        );
+        lspc.handle_lsp_msg(0, LspMessage::Response(response))
+            .unwrap();
+        assert_eq!(
+            vec![vec![highlight(range(12, 17))]],
+            lspc.editor.highlighted_references
+        );
+
+        lspc.handle_editor_event(Event::CodeAction {
+            text_document: TextDocumentIdentifier::new(test_uri()),
+            range: range(12, 17),
+            diagnostics: Vec::new(),
+        })
+        .unwrap();
+        let request = next_request(&fake_server);
+        assert_eq!(json!(range(11, 15)), request.params["range"]);
+    }
+
+    #[test]
+    fn range_formatting_options_are_supported() {
+        let (mut lspc, fake_server) = lspc_with_fake_server();
+        lspc.lsp_handlers[0]
+            .initialize_response(types::InitializeResult {
+                capabilities: json!({
+                    "documentRangeFormattingProvider": { "workDoneProgress": true }
+                }),
+                server_info: None,
+            })
+            .unwrap();
+        assert_eq!("initialized", next_notification(&fake_server).method);
+
+        lspc.handle_editor_event(Event::FormatRange {
+            buf_id: MockBufferId(1),
+            text_document_lines: vec!["fn main(){}".to_owned()],
+            text_document: TextDocumentIdentifier::new(test_uri()),
+            range: Range::new(Position::new(0, 0), Position::new(0, 11)),
+        })
+        .unwrap();
+        assert_eq!(RangeFormatting::METHOD, next_request(&fake_server).method);
+    }
+
+    #[test]
+    fn declaration_options_are_supported() {
+        let (mut lspc, fake_server) = lspc_with_fake_server();
+        lspc.lsp_handlers[0]
+            .initialize_response(types::InitializeResult {
+                capabilities: json!({ "declarationProvider": { "workDoneProgress": true } }),
+                server_info: None,
+            })
+            .unwrap();
+        assert_eq!("initialized", next_notification(&fake_server).method);
+
+        lspc.handle_editor_event(Event::GotoDeclaration {
+            text_document: TextDocumentIdentifier::new(test_uri()),
+            position: Position::new(0, 0),
+        })
+        .unwrap();
+        assert_eq!(GotoDeclaration::METHOD, next_request(&fake_server).method);
+    }
+
+    #[test]
+    fn ready_notification_without_trigger_characters() {
+        let (handler, fake_server) = handler_with_fake_server("/");
+        let mut lspc = Lspc::new(MockEditor::default());
+        lspc.initialize_server(handler, "/").unwrap();
+        let request = next_request(&fake_server);
 
-                        Ok(())
-                    }),
-                )?;
-            }
-            Event::References {
-                text_document,
-                position,
-                include_declaration,
-            } => {
-                let (handler, _, _) =
-                    self.handler_for_file(&text_document.uri).ok_or_else(|| {
-                        log::info!("Nontracking file: {:?}", text_document);
-                        MainLoopError::IgnoredMessage
-                    })?;
-                let params = lsp::ReferenceParams {
-                    text_document_position: lsp::TextDocumentPositionParams {
-                        text_document,
-                        position,
-                    },
-                    context: lsp::ReferenceContext {
-                        include_declaration,
-                    },
-                };
+        let capabilities = lsp::ServerCapabilities {
+            completion_provider: Some(lsp::CompletionOptions {
+                resolve_provider: None,
+                trigger_characters: None,
+            }),
+            ..Default::default()
+        };
+        let response = RawResponse::ok::<Initialize>(
+            request.id,
+            &types::InitializeResult {
+                capabilities: serde_json::to_value(capabilities).unwrap(),
+                server_info: None,
+            },
+        );
+        lspc.handle_lsp_msg(0, LspMessage::Response(response))
+            .unwrap();
 
-                handler.lsp_request::<References>(
-                    &params,
-                    Box::new(move |editor: &mut E, _handler, response| {
-                        if let Some(locations) = response {
-                            editor.show_references(&locations)?;
-                        }
+        assert_eq!(1, lspc.editor.ready_servers.len());
+        assert!(lspc.editor.ready_servers[0]
+            .completion_trigger_characters
+            .is_empty());
+    }
 
-                        Ok(())
-                    }),
-                )?;
-            }
-            Event::DidOpen { text_document } => {
-                let file_path = text_document.uri.path();
-                let handler = handler_of(&mut self.lsp_handlers, &file_path).ok_or_else(|| {
-                    log::info!("Unmanaged file: {:?}", text_document.uri);
-                    MainLoopError::IgnoredMessage
-                })?;
+    #[test]
+    fn range_formatting_keeps_edits_inside_range() {
+        let edit = |start_line: u64, end_line: u64| {
+            TextEdit::new(
+                Range::new(Position::new(start_line, 0), Position::new(end_line, 0)),
+                "    ".to_owned(),
+            )
+        };
+        let range = Range::new(Position::new(2, 0), Position::new(5, 0));
 
-                self.editor.watch_file_events(&text_document)?;
-                self.tracking_files.insert(
-                    text_document.uri.clone(),
-                    TrackingFile::new(handler.id, text_document.uri, handler.sync_kind()),
-                );
-            }
-            Event::DidChange {
-                text_document,
+        assert_eq!(
+            vec![edit(2, 3), edit(4, 5)],
+            edits_within(&range, vec![edit(0, 1), edit(2, 3), edit(4, 5), edit(4, 6)])
+        );
+    }
+
+    #[test]
+    fn exit_is_sent_after_shutdown_response() {
+        let (mut handler, fake_server) = handler_with_fake_server("/");
+        let server = std::thread::spawn(move || {
+            let request = next_request(&fake_server);
+            assert_eq!("shutdown", request.method);
+            // Nothing else until shutdown is answered
+            assert!(fake_server
+                .receiver
+                .recv_timeout(Duration::from_millis(100))
+                .is_err());
+
+            let response = RawResponse::ok::<lsp::request::Shutdown>(request.id, &());
+            fake_server.sender.send(response.into()).unwrap();
+            assert_eq!("exit", next_notification(&fake_server).method);
+        });
+
+        handler.shutdown(Duration::from_secs(1)).unwrap();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn crashed_server_is_restarted() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (mut lspc, _fake_server) = lspc_with_fake_server();
+        lspc.server_configs.insert(
+            1,
+            LsConfig {
+                transport: Transport::Tcp {
+                    host: "127.0.0.1".to_owned(),
+                    port,
+                },
+                restart_on_crash: true,
+                ..test_config()
+            },
+        );
+
+        lspc.handle_server_exit(0).unwrap();
+
+        assert!(lspc.tracking_files.is_empty());
+        assert_eq!(
+            vec!["rust server exited unexpectedly"],
+            lspc.editor.messages
+        );
+        let (stream, _) = listener.accept().unwrap();
+        let reader = stream.try_clone().unwrap();
+        let restarted = rpc::Client::<LspMessage>::new(move || reader, move || stream);
+        assert_eq!("initialize", next_request(&restarted).method);
+        assert_eq!(1, lspc.lsp_handlers.len());
+        assert!(lspc.server_configs.contains_key(&lspc.lsp_handlers[0].id));
+    }
+
+    #[test]
+    fn configured_sync_delay_and_timer_tick_are_used() {
+        let (mut lspc, fake_server) = lspc_with_fake_server();
+        lspc.lsp_handlers[0].lang_settings.sync_delay_ms = 2000;
+        assert_eq!(TIMER_TICK_MS, lspc.timer_tick_ms());
+        lspc.server_configs.insert(
+            1,
+            LsConfig {
+                timer_tick_ms: 20,
+                ..test_config()
+            },
+        );
+        lspc.server_configs.insert(2, test_config());
+        assert_eq!(20, lspc.timer_tick_ms());
+
+        for version in 1..3 {
+            lspc.handle_editor_event(Event::DidChange {
+                text_document: TextDocumentIdentifier::new(test_uri()),
                 version,
-                content_change,
-            } => {
-                log::debug!(
-                    "Received did change event: {:?}, {}, {:?}",
-                    text_document,
-                    version,
-                    content_change
-                );
-                let (handler, tracking_file, _) =
-                    self.handler_for_file(&text_document.uri).ok_or_else(|| {
-                        log::info!(
-                            "Received changed event for nontracking file: {:?}",
-                            text_document
-                        );
-                        MainLoopError::IgnoredMessage
-                    })?;
+                content_change: lsp::TextDocumentContentChangeEvent {
+                    range: None,
+                    range_length: None,
+                    text: format!("version {}", version),
+                },
+            })
+            .unwrap();
+        }
+        assert_eq!(
+            "textDocument/didOpen",
+            next_notification(&fake_server).method
+        );
 
-                tracking_file.track_change(version, &content_change);
+        let tracking_file = &lspc.tracking_files[&test_uri()][0];
+        assert!(!tracking_file.sync_due(Instant::now() + Duration::from_millis(1500)));
+        assert!(tracking_file.sync_due(Instant::now() + Duration::from_millis(2000)));
+    }
 
-                if !tracking_file.sent_did_open {
-                    handler.lsp_notify::<noti::DidOpenTextDocument>(
-                        &lsp::DidOpenTextDocumentParams {
-                            text_document: lsp::TextDocumentItem {
-                                uri: text_document.uri.clone(),
-                                language_id: handler.lang_id.clone(),
-                                version,
-                                text: content_change.text,
-                            },
-                        },
-                    )?;
-                    tracking_file.sent_did_open = true;
-                } else {
-                    tracking_file.delay_sync_in(Duration::from_millis(SYNC_DELAY_MS));
-                }
-            }
-            Event::DidClose { text_document } => {
-                let (handler, tracking_file, _) =
-                    self.handler_for_file(&text_document.uri).ok_or_else(|| {
-                        log::info!(
-                            "Received changed event for nontracking file: {:?}",
-                            text_document
-                        );
-                        MainLoopError::IgnoredMessage
-                    })?;
+    #[test]
+    fn scripted_hover_is_shown() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let hover = Hover {
+            contents: lsp::HoverContents::Scalar(lsp::MarkedString::String("fn main()".to_owned())),
+            range: None,
+        };
+        let server_hover = hover.clone();
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let reader = stream.try_clone().unwrap();
+            let server = rpc::Client::<LspMessage>::new(move || reader, move || stream);
+            let request = next_request(&server);
+            let capabilities = lsp::ServerCapabilities {
+                hover_provider: Some(true),
+                ..Default::default()
+            };
+            let response = RawResponse::ok::<Initialize>(
+                request.id,
+                &types::InitializeResult {
+                    capabilities: serde_json::to_value(capabilities).unwrap(),
+                    server_info: None,
+                },
+            );
+            server.sender.send(response.into()).unwrap();
 
-                let pending_changes = tracking_file.fetch_pending_changes();
-                if let Some(params) = pending_changes {
-                    handler.lsp_notify::<noti::DidChangeTextDocument>(&params)?;
+            for msg in server.receiver.iter() {
+                if let LspMessage::Request(request) = msg {
+                    assert_eq!(HoverRequest::METHOD, request.method);
+                    let response = RawResponse::ok::<HoverRequest>(request.id, &Some(server_hover));
+                    server.sender.send(response.into()).unwrap();
+                    break;
                 }
-                handler.lsp_notify::<noti::DidCloseTextDocument>(
-                    &lsp::DidCloseTextDocumentParams {
-                        text_document: text_document,
-                    },
-                )?;
             }
-        }
+        });
+        let mut lspc = Lspc::new(MockEditor::default());
+        let text_document = TextDocumentIdentifier::new(test_uri());
 
-        Ok(())
+        lspc.editor.script(Event::StartServer {
+            lang_id: "rust".to_owned(),
+            config: LsConfig {
+                transport: Transport::Tcp {
+                    host: "127.0.0.1".to_owned(),
+                    port,
+                },
+                ..test_config()
+            },
+            cur_path: test_uri().path().to_owned(),
+        });
+        handle_scripted_events(&mut lspc);
+        let initialized = lspc.lsp_handlers[0].receiver().recv().unwrap();
+        lspc.handle_lsp_msg(0, initialized).unwrap();
+
+        lspc.editor.script(Event::DidOpen {
+            buf_id: MockBufferId(1),
+            text_document: text_document.clone(),
+            language_id: "rust".to_owned(),
+        });
+        lspc.editor.script(Event::Hover {
+            buf_id: MockBufferId(1),
+            text_document,
+            position: Position::new(0, 3),
+        });
+        handle_scripted_events(&mut lspc);
+        let response = lspc.lsp_handlers[0].receiver().recv().unwrap();
+        lspc.handle_lsp_msg(0, response).unwrap();
+        server.join().unwrap();
+
+        assert_eq!(vec![hover], lspc.editor.hovers);
     }
 
-    fn handle_lsp_msg(&mut self, index: usize, msg: LspMessage) -> Result<(), LspcError> {
-        let lsp_handler = &mut self.lsp_handlers[index];
-        match msg {
-            LspMessage::Request(_req) => {}
-            LspMessage::Notification(mut noti) => {
-                noti = match noti.cast::<noti::ShowMessage>() {
-                    Ok(params) => {
-                        self.editor.show_message(&params)?;
+    #[cfg(unix)]
+    #[test]
+    fn spawned_server_sees_configured_env() {
+        let mut config = test_config();
+        config
+            .env
+            .insert("LSPC_TEST".to_owned(), "visible".to_owned());
 
-                        return Ok(());
-                    }
-                    Err(noti) => noti,
-                };
+        let notification = notifying_server(config, "$LSPC_TEST");
+        assert_eq!(json!("visible"), notification.params);
+    }
 
-                log::warn!("Not supported notification: {:?}", noti);
-            }
-            LspMessage::Response(res) => {
-                if let Some(callback) = lsp_handler.callback_for(res.id) {
-                    (callback.func)(&mut self.editor, lsp_handler, res)?;
-                } else {
-                    log::error!("not requested response: {:?}", res);
-                }
+    #[cfg(unix)]
+    #[test]
+    fn spawned_server_runs_in_the_root_by_default() {
+        let notification = notifying_server(test_config(), "$(pwd)");
+        assert_eq!(json!("/"), notification.params);
+
+        let config = LsConfig {
+            cwd: Some("/tmp".to_owned()),
+            ..test_config()
+        };
+        let notification = notifying_server(config, "$(pwd)");
+        assert_eq!(json!("/tmp"), notification.params);
+    }
+
+    #[test]
+    fn servers_are_shut_down_when_editor_exits() {
+        let (mut lspc, fake_server) = lspc_with_fake_server();
+        let server = std::thread::spawn(move || {
+            let request = next_request(&fake_server);
+            assert_eq!("shutdown", request.method);
+            let response = RawResponse::ok::<lsp::request::Shutdown>(request.id, &());
+            fake_server.sender.send(response.into()).unwrap();
+            assert_eq!("exit", next_notification(&fake_server).method);
+        });
+
+        lspc.handle_editor_event(Event::Exit).unwrap();
+        server.join().unwrap();
+
+        assert!(lspc.lsp_handlers.is_empty());
+        assert!(lspc.tracking_files.is_empty());
+    }
+
+    #[test]
+    fn exit_is_sent_after_shutdown_timeout() {
+        let (mut handler, fake_server) = handler_with_fake_server("/");
+
+        handler.shutdown(Duration::from_millis(100)).unwrap();
+
+        assert_eq!("shutdown", next_request(&fake_server).method);
+        assert_eq!("exit", next_notification(&fake_server).method);
+    }
+
+    #[test]
+    fn servers_are_shut_down_together() {
+        let (first, first_server) = numbered_handler_with_fake_server(1, "/");
+        let (second, second_server) = numbered_handler_with_fake_server(2, "/");
+        let mut handlers = vec![first, second];
+
+        let started = Instant::now();
+        LangServerHandler::shutdown_all(&mut handlers, Duration::from_millis(500)).unwrap();
+
+        // One after another, unanswered shutdowns would take a second
+        assert!(started.elapsed() < Duration::from_millis(900));
+        for fake_server in &[first_server, second_server] {
+            assert_eq!("shutdown", next_request(fake_server).method);
+            assert_eq!("exit", next_notification(fake_server).method);
+        }
+    }
+
+    #[test]
+    fn progress_keeps_its_title_until_the_end() {
+        let (mut lspc, _fake_server) = lspc_with_fake_server();
+        let token = ProgressToken::String("indexing".to_owned());
+        let mut report = |value: serde_json::Value| {
+            let notification = RawNotification {
+                method: Progress::METHOD.to_owned(),
+                params: json!({ "token": "indexing", "value": value }),
+            };
+            lspc.handle_lsp_msg(0, LspMessage::Notification(notification))
+                .unwrap();
+        };
+
+        report(json!({ "kind": "begin", "title": "Indexing", "percentage": 0 }));
+        report(json!({ "kind": "report", "message": "2/4 crates", "percentage": 50 }));
+        report(json!({ "kind": "end" }));
+
+        assert_eq!(
+            vec![
+                (token.clone(), Some("Indexing (0%)".to_owned())),
+                (token.clone(), Some("Indexing 2/4 crates (50%)".to_owned())),
+                (token, None),
+            ],
+            lspc.editor.progress
+        );
+    }
+
+    #[test]
+    fn chosen_message_action_is_sent_back() {
+        let (mut lspc, fake_server) = lspc_with_fake_server();
+        let params = ShowMessageRequestParams {
+            typ: lsp::MessageType::Info,
+            message: "Reload workspace?".to_owned(),
+            actions: Some(vec![
+                MessageActionItem {
+                    title: "Reload".to_owned(),
+                },
+                MessageActionItem {
+                    title: "Later".to_owned(),
+                },
+            ]),
+        };
+        let request = RawRequest::new::<ShowMessageRequest>(1, &params);
+        lspc.handle_lsp_msg(0, LspMessage::Request(request))
+            .unwrap();
+
+        assert_eq!(vec!["Reload workspace?"], lspc.editor.message_requests);
+        match fake_server.receiver.recv_timeout(Duration::from_secs(1)) {
+            Ok(LspMessage::Response(response)) => {
+                assert_eq!(1, response.id);
+                assert_eq!(Some(json!({ "title": "Reload" })), response.result);
             }
+            msg => panic!("Expected response, got {:?}", msg),
         }
+    }
 
-        Ok(())
+    #[test]
+    fn status_reports_server_and_tracked_files() {
+        let (mut lspc, fake_server) = lspc_with_fake_server();
+        lspc.handle_editor_event(Event::Status).unwrap();
+        lspc.lsp_handlers[0]
+            .initialize_response(types::InitializeResult {
+                capabilities: json!({}),
+                server_info: Some(types::ServerInfo {
+                    name: "rust-analyzer".to_owned(),
+                    version: Some("0.3.1".to_owned()),
+                }),
+            })
+            .unwrap();
+        assert_eq!("initialized", next_notification(&fake_server).method);
+        lspc.handle_editor_event(Event::Status).unwrap();
+
+        assert_eq!(
+            vec![
+                "rust: unnamed server, 1 file tracked",
+                "rust: rust-analyzer 0.3.1, 1 file tracked"
+            ],
+            lspc.editor.messages
+        );
     }
 
-    fn handle_timer_tick(&mut self) -> Result<(), LspcError> {
-        let now = Instant::now();
-        let sync_due_files = self
-            .tracking_files
-            .iter()
-            .filter(|(_, buf)| {
-                if let Some(instant) = buf.scheduled_sync_at {
-                    instant <= now
-                } else {
-                    false
-                }
+    #[test]
+    fn dynamically_registered_formatting_is_supported() {
+        let (mut lspc, fake_server) = lspc_with_fake_server();
+        lspc.lsp_handlers[0]
+            .initialize_response(types::InitializeResult {
+                capabilities: json!({}),
+                server_info: None,
             })
-            .map(|(file_url, _)| file_url)
-            .cloned()
-            .collect::<Vec<_>>();
+            .unwrap();
+        assert_eq!("initialized", next_notification(&fake_server).method);
+        assert!(!lspc.lsp_handlers[0].supports(Formatting::METHOD));
 
-        for uri in sync_due_files {
-            log::debug!("File changes due: {:?}", uri);
-            let (handler, tracking_file, _) = self.handler_for_file(&uri).ok_or_else(|| {
-                log::info!("Received changed event for nontracking file: {:?}", uri);
-                MainLoopError::IgnoredMessage
-            })?;
-            let pending_changes = tracking_file.fetch_pending_changes();
-            if let Some(params) = pending_changes {
-                handler.lsp_notify::<noti::DidChangeTextDocument>(&params)?;
-            }
+        let registration = lsp::RegistrationParams {
+            registrations: vec![lsp::Registration {
+                id: "format".to_owned(),
+                method: Formatting::METHOD.to_owned(),
+                register_options: None,
+            }],
+        };
+        let request = RawRequest::new::<RegisterCapability>(1, &registration);
+        lspc.handle_lsp_msg(0, LspMessage::Request(request))
+            .unwrap();
+        match fake_server.receiver.recv_timeout(Duration::from_secs(1)) {
+            Ok(LspMessage::Response(response)) => assert_eq!(1, response.id),
+            msg => panic!("Expected response, got {:?}", msg),
         }
-        Ok(())
+        assert!(lspc.lsp_handlers[0].supports(Formatting::METHOD));
+
+        let unregistration = lsp::UnregistrationParams {
+            unregisterations: vec![lsp::Unregistration {
+                id: "format".to_owned(),
+                method: Formatting::METHOD.to_owned(),
+            }],
+        };
+        let request = RawRequest::new::<UnregisterCapability>(2, &unregistration);
+        lspc.handle_lsp_msg(0, LspMessage::Request(request))
+            .unwrap();
+        match fake_server.receiver.recv_timeout(Duration::from_secs(1)) {
+            Ok(LspMessage::Response(response)) => assert_eq!(2, response.id),
+            msg => panic!("Expected response, got {:?}", msg),
+        }
+        assert!(!lspc.lsp_handlers[0].supports(Formatting::METHOD));
     }
-}
 
-impl<E: Editor> Lspc<E> {
-    pub fn new(editor: E) -> Self {
-        Lspc {
-            editor,
-            lsp_handlers: Vec::new(),
-            tracking_files: HashMap::new(),
-            next_handler_id: 0,
+    #[test]
+    fn only_watched_file_changes_are_forwarded() {
+        let (mut lspc, fake_server) = lspc_with_fake_server();
+        let registration = lsp::RegistrationParams {
+            registrations: vec![lsp::Registration {
+                id: "watch".to_owned(),
+                method: noti::DidChangeWatchedFiles::METHOD.to_owned(),
+                register_options: Some(
+                    json!({ "watchers": [{ "globPattern": "**/Cargo.toml", "kind": 2 }] }),
+                ),
+            }],
+        };
+        let request = RawRequest::new::<RegisterCapability>(1, &registration);
+        lspc.handle_lsp_msg(0, LspMessage::Request(request))
+            .unwrap();
+        match fake_server.receiver.recv_timeout(Duration::from_secs(1)) {
+            Ok(LspMessage::Response(response)) => assert_eq!(1, response.id),
+            msg => panic!("Expected response, got {:?}", msg),
         }
+
+        let manifest = test_uri().join("Cargo.toml").unwrap();
+        let change = |uri: &Url, typ| lsp::FileEvent {
+            uri: uri.clone(),
+            typ,
+        };
+        lspc.handle_editor_event(Event::FilesChanged {
+            changes: vec![
+                change(
+                    &test_uri().join("notes.txt").unwrap(),
+                    lsp::FileChangeType::Changed,
+                ),
+                change(&manifest, lsp::FileChangeType::Deleted),
+                change(&manifest, lsp::FileChangeType::Changed),
+            ],
+        })
+        .unwrap();
+
+        let notification = next_notification(&fake_server)
+            .cast::<noti::DidChangeWatchedFiles>()
+            .unwrap();
+        assert_eq!(
+            vec![change(&manifest, lsp::FileChangeType::Changed)],
+            notification.changes
+        );
     }
 
-    pub fn main_loop(mut self) {
-        let event_receiver = self.editor.events();
-        let timer_tick = tick(Duration::from_millis(TIMER_TICK_MS));
+    #[test]
+    fn file_operation_outside_registered_patterns_is_not_forwarded() {
+        let (mut lspc, fake_server) = lspc_with_fake_server();
+        let registration = lsp::RegistrationParams {
+            registrations: vec![lsp::Registration {
+                id: "1".to_owned(),
+                method: DidCreateFiles::METHOD.to_owned(),
+                register_options: Some(
+                    json!({ "filters": [{ "pattern": { "glob": "**/*.rs" } }] }),
+                ),
+            }],
+        };
+        let request = RawRequest::new::<RegisterCapability>(1, &registration);
+        lspc.handle_lsp_msg(0, LspMessage::Request(request))
+            .unwrap();
+        match fake_server.receiver.recv_timeout(Duration::from_secs(1)) {
+            Ok(LspMessage::Response(response)) => assert_eq!(1, response.id),
+            msg => panic!("Expected response, got {:?}", msg),
+        }
 
-        loop {
-            let selected = select(&event_receiver, &timer_tick, &self.lsp_handlers);
-            let result = match selected {
-                SelectedMsg::Editor(event) => self.handle_editor_event(event),
-                SelectedMsg::Lsp(index, msg) => self.handle_lsp_msg(index, msg),
-                SelectedMsg::TimerTick => self.handle_timer_tick(),
+        let source = test_uri().join("lib.rs").unwrap();
+        lspc.handle_editor_event(Event::DidCreateFiles {
+            uris: vec![test_uri().join("notes.txt").unwrap(), source.clone()],
+        })
+        .unwrap();
+        let notification = next_notification(&fake_server);
+        assert_eq!(DidCreateFiles::METHOD, notification.method);
+        assert_eq!(
+            CreateFilesParams {
+                files: vec![FileCreate { uri: source }]
+            },
+            serde_json::from_value(notification.params).unwrap()
+        );
+
+        lspc.handle_editor_event(Event::DidCreateFiles {
+            uris: vec![test_uri().join("notes.txt").unwrap()],
+        })
+        .unwrap();
+        assert!(fake_server
+            .receiver
+            .recv_timeout(Duration::from_millis(100))
+            .is_err());
+    }
+
+    #[test]
+    fn semantic_tokens_are_decoded_against_legend() {
+        let legend = SemanticTokensLegend {
+            token_types: vec!["function".to_owned(), "variable".to_owned()],
+            token_modifiers: vec!["declaration".to_owned(), "readonly".to_owned()],
+        };
+        let token =
+            |line, start, length, token_type: &str, token_modifiers: &[&str]| SemanticToken {
+                line,
+                start,
+                length,
+                token_type: token_type.to_owned(),
+                token_modifiers: token_modifiers.iter().map(|m| m.to_string()).collect(),
             };
-            if let Err(e) = result {
-                log::error!("Handle error: {:?}", e);
+        #[rustfmt::skip]
+        let data = vec![
+            2, 5, 3, 0, 1,
+            0, 5, 4, 1, 3,
+            // Unknown type, still moves the position
+            0, 6, 1, 7, 0,
+            3, 2, 7, 1, 0,
+        ];
+
+        assert_eq!(
+            vec![
+                token(2, 5, 3, "function", &["declaration"]),
+                token(2, 10, 4, "variable", &["declaration", "readonly"]),
+                token(5, 2, 7, "variable", &[]),
+            ],
+            decode_semantic_tokens(&legend, &data)
+        );
+    }
+
+    #[test]
+    fn hover_is_shown_with_configured_mode() {
+        let (mut lspc, fake_server) = lspc_with_fake_server();
+        lspc.lsp_handlers[0].lang_settings.hover_mode = HoverMode::Echo;
+
+        lspc.handle_editor_event(Event::Hover {
+            buf_id: MockBufferId(1),
+            text_document: TextDocumentIdentifier::new(test_uri()),
+            position: Position::new(0, 0),
+        })
+        .unwrap();
+        let request = next_request(&fake_server);
+        let hover = Hover {
+            contents: lsp::HoverContents::Scalar(lsp::MarkedString::String("u32".to_owned())),
+            range: None,
+        };
+        let response = RawResponse::ok::<HoverRequest>(request.id, &Some(hover));
+        lspc.handle_lsp_msg(0, LspMessage::Response(response))
+            .unwrap();
+
+        assert_eq!(vec![HoverMode::Echo], lspc.editor.hover_modes);
+    }
+
+    #[test]
+    fn semantic_tokens_delta_is_applied_on_cached_tokens() {
+        let (mut lspc, fake_server) = lspc_with_fake_server();
+        let capabilities = json!({
+            "semanticTokensProvider": {
+                "legend": { "tokenTypes": ["function", "variable"], "tokenModifiers": [] },
+                "full": { "delta": true }
             }
-        }
+        });
+        lspc.lsp_handlers[0]
+            .initialize_response(types::InitializeResult {
+                capabilities,
+                server_info: None,
+            })
+            .unwrap();
+        assert_eq!("initialized", next_notification(&fake_server).method);
+        let text_document = TextDocumentIdentifier::new(test_uri());
+
+        lspc.handle_editor_event(Event::SemanticTokens {
+            buf_id: MockBufferId(1),
+            text_document: text_document.clone(),
+        })
+        .unwrap();
+        let request = next_request(&fake_server);
+        assert_eq!("textDocument/semanticTokens/full", request.method);
+        let tokens = SemanticTokens {
+            result_id: Some("1".to_owned()),
+            data: vec![0, 0, 2, 0, 0, 1, 4, 3, 1, 0],
+        };
+        let response = RawResponse::ok::<SemanticTokensFullRequest>(request.id, &Some(tokens));
+        lspc.handle_lsp_msg(0, LspMessage::Response(response))
+            .unwrap();
+
+        lspc.handle_editor_event(Event::SemanticTokens {
+            buf_id: MockBufferId(1),
+            text_document,
+        })
+        .unwrap();
+        let request = next_request(&fake_server);
+        assert_eq!("textDocument/semanticTokens/full/delta", request.method);
+        assert_eq!("1", request.params["previousResultId"]);
+        let delta = SemanticTokensFullDeltaResult::Delta(types::SemanticTokensDelta {
+            result_id: Some("2".to_owned()),
+            edits: vec![SemanticTokensEdit {
+                start: 6,
+                delete_count: 2,
+                data: Some(vec![2, 5]),
+            }],
+        });
+        let response = RawResponse::ok::<SemanticTokensFullDeltaRequest>(request.id, &Some(delta));
+        lspc.handle_lsp_msg(0, LspMessage::Response(response))
+            .unwrap();
+
+        assert_eq!(
+            Some(SemanticTokens {
+                result_id: Some("2".to_owned()),
+                data: vec![0, 0, 2, 0, 0, 1, 2, 5, 1, 0],
+            }),
+            *lspc.tracking_files[&test_uri()][0].semantic_tokens.borrow()
+        );
+    }
+
+    #[test]
+    fn incoming_calls_are_asked_for_prepared_item() {
+        let (mut lspc, fake_server) = lspc_with_fake_server();
+        lspc.lsp_handlers[0]
+            .initialize_response(types::InitializeResult {
+                capabilities: json!({ "callHierarchyProvider": true }),
+                server_info: None,
+            })
+            .unwrap();
+        assert_eq!("initialized", next_notification(&fake_server).method);
+        let text_document = TextDocumentIdentifier::new(test_uri());
+
+        lspc.handle_editor_event(Event::PrepareCallHierarchy {
+            text_document: text_document.clone(),
+            position: Position::new(1, 4),
+        })
+        .unwrap();
+        let request = next_request(&fake_server);
+        let item = CallHierarchyItem {
+            name: "run".to_owned(),
+            kind: lsp::SymbolKind::Function,
+            detail: None,
+            uri: test_uri(),
+            range: Range::new(Position::new(1, 0), Position::new(3, 1)),
+            selection_range: Range::new(Position::new(1, 3), Position::new(1, 6)),
+            data: Some(json!(7)),
+        };
+        let response =
+            RawResponse::ok::<CallHierarchyPrepare>(request.id, &Some(vec![item.clone()]));
+        lspc.handle_lsp_msg(0, LspMessage::Response(response))
+            .unwrap();
+
+        lspc.handle_editor_event(Event::CallHierarchyIncomingCalls { text_document })
+            .unwrap();
+        let request = next_request(&fake_server);
+        assert_eq!("callHierarchy/incomingCalls", request.method);
+        assert_eq!(serde_json::to_value(&item).unwrap(), request.params["item"]);
+        let caller = CallHierarchyItem {
+            name: "main".to_owned(),
+            uri: test_uri().join("main.rs").unwrap(),
+            ..item
+        };
+        let call_site = Range::new(Position::new(5, 4), Position::new(5, 7));
+        let calls = vec![types::CallHierarchyIncomingCall {
+            from: caller.clone(),
+            from_ranges: vec![call_site],
+        }];
+        let response = RawResponse::ok::<CallHierarchyIncomingCalls>(request.id, &Some(calls));
+        lspc.handle_lsp_msg(0, LspMessage::Response(response))
+            .unwrap();
+
+        assert_eq!(
+            vec![(
+                CallDirection::Incoming,
+                vec![(caller.clone(), Location::new(caller.uri, call_site))]
+            )],
+            lspc.editor.shown_calls
+        );
+    }
+
+    #[test]
+    fn server_not_answering_initialize_is_removed() {
+        let (handler, fake_server) = handler_with_fake_server("/");
+        let mut lspc = Lspc::new(MockEditor::default());
+        lspc.initialize_server(handler, "/").unwrap();
+        assert_eq!("initialize", next_request(&fake_server).method);
+
+        lspc.remove_hung_servers(Instant::now()).unwrap();
+        assert_eq!(1, lspc.lsp_handlers.len());
+
+        let after_timeout = Instant::now() + Duration::from_millis(INITIALIZE_TIMEOUT_MS);
+        lspc.remove_hung_servers(after_timeout).unwrap();
+        assert!(lspc.lsp_handlers.is_empty());
+        assert_eq!(
+            Some(&"rust server failed to initialize".to_owned()),
+            lspc.editor.messages.last()
+        );
+    }
+
+    #[test]
+    fn pending_changes_are_sent_before_hover() {
+        let (mut lspc, fake_server) = lspc_with_fake_server();
+        let tracking_file = &mut lspc.tracking_files.get_mut(&test_uri()).unwrap()[0];
+        tracking_file.track_change(
+            2,
+            &lsp::TextDocumentContentChangeEvent {
+                range: None,
+                range_length: None,
+                text: "fn main() {}\n".to_owned(),
+            },
+        );
+        tracking_file.delay_sync_in(Instant::now(), Duration::from_millis(SYNC_DELAY_MS), 10);
+
+        lspc.handle_editor_event(Event::Hover {
+            buf_id: MockBufferId(1),
+            text_document: TextDocumentIdentifier::new(test_uri()),
+            position: Position::new(0, 3),
+        })
+        .unwrap();
+
+        let notification = next_notification(&fake_server);
+        assert_eq!("textDocument/didChange", notification.method);
+        assert_eq!(
+            "fn main() {}\n",
+            notification.params["contentChanges"][0]["text"]
+        );
+        assert_eq!("textDocument/hover", next_request(&fake_server).method);
+        // Nothing left for the timer
+        assert!(
+            !lspc.tracking_files[&test_uri()][0].sync_due(Instant::now() + Duration::from_secs(1))
+        );
+    }
+
+    #[test]
+    fn unsupported_request_is_not_sent() {
+        let (mut lspc, fake_server) = lspc_with_fake_server();
+        lspc.lsp_handlers[0]
+            .initialize_response(types::InitializeResult {
+                capabilities: json!({ "hoverProvider": false, "definitionProvider": true }),
+                server_info: None,
+            })
+            .unwrap();
+        assert_eq!("initialized", next_notification(&fake_server).method);
+        let text_document = TextDocumentIdentifier::new(test_uri());
+
+        lspc.handle_editor_event(Event::Hover {
+            buf_id: MockBufferId(1),
+            text_document: text_document.clone(),
+            position: Position::new(0, 0),
+        })
+        .unwrap();
+        assert_eq!(
+            Some(&"Server doesn't support hover".to_owned()),
+            lspc.editor.messages.last()
+        );
+
+        lspc.handle_editor_event(Event::GotoDefinition {
+            text_document,
+            position: Position::new(0, 0),
+            target: GotoTarget::Edit,
+        })
+        .unwrap();
+        assert_eq!("textDocument/definition", next_request(&fake_server).method);
+    }
+
+    #[test]
+    fn unchanged_pulled_diagnostics_are_kept() {
+        let (mut lspc, fake_server) = lspc_with_fake_server();
+        lspc.lsp_handlers[0]
+            .initialize_response(types::InitializeResult {
+                capabilities: json!({ "textDocumentSync": 1, "diagnosticProvider": {
+                    "interFileDependencies": false,
+                    "workspaceDiagnostics": false,
+                } }),
+                server_info: None,
+            })
+            .unwrap();
+        assert_eq!("initialized", next_notification(&fake_server).method);
+        let diagnostic = diagnostic(1, "unused");
+
+        let mut pull = |report: DocumentDiagnosticReport| {
+            lspc.handle_editor_event(Event::RequestDiagnostics {
+                text_document: TextDocumentIdentifier::new(test_uri()),
+            })
+            .unwrap();
+            let request = next_request(&fake_server);
+            assert_eq!(DocumentDiagnosticRequest::METHOD, request.method);
+            lspc.handle_lsp_msg(
+                0,
+                LspMessage::Response(RawResponse::ok::<DocumentDiagnosticRequest>(
+                    request.id, &report,
+                )),
+            )
+            .unwrap();
+            request.params
+        };
+        pull(DocumentDiagnosticReport::Full {
+            result_id: Some("1".to_owned()),
+            items: vec![diagnostic.clone()],
+        });
+        let params = pull(DocumentDiagnosticReport::Unchanged {
+            result_id: "1".to_owned(),
+        });
+
+        assert_eq!("1", params["previousResultId"]);
+        assert_eq!(
+            vec![(test_uri(), vec![diagnostic.clone()])],
+            lspc.editor.shown_diagnostics
+        );
+        assert_eq!(vec![diagnostic], lspc.file_diagnostics(&test_uri()));
+    }
+
+    #[test]
+    fn save_waits_for_server_edits() {
+        let (mut lspc, fake_server) = lspc_with_fake_server();
+        lspc.lsp_handlers[0]
+            .initialize_response(types::InitializeResult {
+                capabilities: json!({
+                    "textDocumentSync": { "change": 1, "willSaveWaitUntil": true }
+                }),
+                server_info: None,
+            })
+            .unwrap();
+        assert_eq!("initialized", next_notification(&fake_server).method);
+
+        lspc.handle_editor_event(Event::WillSaveWaitUntil {
+            buf_id: MockBufferId(1),
+            text_document_lines: vec!["fn main() {}".to_owned()],
+            text_document: TextDocumentIdentifier::new(test_uri()),
+            reason: TextDocumentSaveReason::Manual,
+        })
+        .unwrap();
+        let request = next_request(&fake_server);
+        assert_eq!(WillSaveWaitUntil::METHOD, request.method);
+        assert!(lspc.editor.saved_buffers.is_empty());
+
+        let edits: Option<Vec<TextEdit>> = Some(Vec::new());
+        lspc.handle_lsp_msg(
+            0,
+            LspMessage::Response(RawResponse::ok::<WillSaveWaitUntil>(request.id, &edits)),
+        )
+        .unwrap();
+        assert_eq!(vec![MockBufferId(1)], lspc.editor.saved_buffers);
+    }
+
+    #[test]
+    fn document_is_formatted_on_save() {
+        let (mut lspc, fake_server) = lspc_with_fake_server();
+        lspc.lsp_handlers[0]
+            .initialize_response(types::InitializeResult {
+                capabilities: json!({
+                    "textDocumentSync": { "change": 1, "save": { "includeText": true } },
+                    "documentFormattingProvider": true,
+                }),
+                server_info: None,
+            })
+            .unwrap();
+        assert_eq!("initialized", next_notification(&fake_server).method);
+        lspc.lsp_handlers[0].lang_settings.format_on_save = true;
+        let will_save = || Event::WillSaveWaitUntil {
+            buf_id: MockBufferId(1),
+            text_document_lines: vec!["fn main(){}".to_owned()],
+            text_document: TextDocumentIdentifier::new(test_uri()),
+            reason: TextDocumentSaveReason::Manual,
+        };
+
+        // Formatted before the write
+        lspc.handle_editor_event(will_save()).unwrap();
+        let request = next_request(&fake_server);
+        assert_eq!(Formatting::METHOD, request.method);
+        assert!(lspc.editor.saved_buffers.is_empty());
+
+        let edits = vec![TextEdit::new(
+            Range::new(Position::new(0, 9), Position::new(0, 9)),
+            " ".to_owned(),
+        )];
+        lspc.handle_lsp_msg(
+            0,
+            LspMessage::Response(RawResponse::ok::<Formatting>(
+                request.id,
+                &Some(edits.clone()),
+            )),
+        )
+        .unwrap();
+        assert_eq!(
+            vec![(MockBufferId(1), edits)],
+            *lspc.editor.applied_edits.borrow()
+        );
+        assert_eq!(vec![MockBufferId(1)], lspc.editor.saved_buffers);
+
+        // Written unformatted when formatting fails
+        lspc.handle_editor_event(will_save()).unwrap();
+        let request = next_request(&fake_server);
+        let response = RawResponse::err(request.id, -32603, "formatter crashed".to_owned());
+        assert!(lspc
+            .handle_lsp_msg(0, LspMessage::Response(response))
+            .is_err());
+        assert_eq!(
+            vec![MockBufferId(1), MockBufferId(1)],
+            lspc.editor.saved_buffers
+        );
+
+        // The save itself is only notified
+        lspc.handle_editor_event(Event::DidSave {
+            text_document: TextDocumentIdentifier::new(test_uri()),
+        })
+        .unwrap();
+        assert_eq!(
+            "textDocument/didSave",
+            next_notification(&fake_server).method
+        );
+    }
+
+    #[test]
+    fn did_save_includes_text_when_asked() {
+        let (mut lspc, fake_server) = lspc_with_fake_server();
+        lspc.lsp_handlers[0]
+            .initialize_response(types::InitializeResult {
+                capabilities: json!({
+                    "textDocumentSync": { "change": 1, "save": { "includeText": true } }
+                }),
+                server_info: None,
+            })
+            .unwrap();
+        assert_eq!("initialized", next_notification(&fake_server).method);
+        lspc.tracking_files.get_mut(&test_uri()).unwrap()[0].track_change(
+            1,
+            &lsp::TextDocumentContentChangeEvent {
+                range: None,
+                range_length: None,
+                text: "fn main() {}\n".to_owned(),
+            },
+        );
+
+        lspc.handle_editor_event(Event::DidSave {
+            text_document: TextDocumentIdentifier::new(test_uri()),
+        })
+        .unwrap();
+
+        let notification = next_notification(&fake_server);
+        assert_eq!("textDocument/didSave", notification.method);
+        assert_eq!("fn main() {}\n", notification.params["text"]);
+    }
+
+    #[test]
+    fn location_link_jumps_to_target_selection_start() {
+        let link = LocationLink {
+            origin_selection_range: Some(Range::new(Position::new(1, 4), Position::new(1, 7))),
+            target_uri: test_uri(),
+            target_range: Range::new(Position::new(10, 0), Position::new(14, 1)),
+            target_selection_range: Range::new(Position::new(10, 7), Position::new(10, 10)),
+        };
+
+        assert_eq!(
+            Location::new(
+                test_uri(),
+                Range::new(Position::new(10, 7), Position::new(10, 7))
+            ),
+            location_from_link(&link)
+        );
+    }
+
+    #[test]
+    fn unresolved_workspace_symbol_is_resolved_before_goto() {
+        let (mut lspc, fake_server) = lspc_with_fake_server();
+        lspc.handle_editor_event(Event::GotoWorkspaceSymbol {
+            text_document: TextDocumentIdentifier::new(test_uri()),
+            query: "foo".to_owned(),
+        })
+        .unwrap();
+        let request = next_request(&fake_server);
+        assert_eq!("workspace/symbol", request.method);
+
+        let symbol = WorkspaceSymbol {
+            name: "foo".to_owned(),
+            kind: lsp::SymbolKind::Function,
+            container_name: None,
+            location: WorkspaceSymbolLocation::Uri { uri: test_uri() },
+            data: None,
+        };
+        let response =
+            RawResponse::ok::<WorkspaceSymbolRequest>(request.id, &Some(vec![symbol.clone()]));
+        lspc.handle_lsp_msg(0, LspMessage::Response(response))
+            .unwrap();
+        let request = next_request(&fake_server);
+        assert_eq!("workspaceSymbol/resolve", request.method);
+        assert!(lspc.editor.gotos.is_empty());
+
+        let location = Location::new(
+            test_uri(),
+            Range::new(Position::new(3, 4), Position::new(3, 7)),
+        );
+        let resolved = WorkspaceSymbol {
+            location: WorkspaceSymbolLocation::Location(location.clone()),
+            ..symbol
+        };
+        let response = RawResponse::ok::<WorkspaceSymbolResolve>(request.id, &resolved);
+        lspc.handle_lsp_msg(0, LspMessage::Response(response))
+            .unwrap();
+        assert_eq!(vec![location], lspc.editor.gotos);
     }
 }
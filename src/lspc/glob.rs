@@ -0,0 +1,110 @@
+// Glob patterns of the LSP spec: `*` matches within a path segment, `**`
+// matches any number of segments, `?` one character, `{a,b}` one of the
+// alternatives and `[a-z]` / `[!a-z]` one character of (not in) the range.
+pub fn matches(pattern: &str, path: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let path = path.chars().collect::<Vec<_>>();
+
+    expand_braces(&pattern)
+        .iter()
+        .any(|pattern| match_from(pattern, &path))
+}
+
+// `{a,b}` groups are expanded to one pattern per alternative
+fn expand_braces(pattern: &[char]) -> Vec<Vec<char>> {
+    let open = match pattern.iter().position(|c| *c == '{') {
+        Some(open) => open,
+        None => return vec![pattern.to_vec()],
+    };
+    let close = match pattern[open..].iter().position(|c| *c == '}') {
+        Some(close) => open + close,
+        // Unclosed brace, matched literally
+        None => return vec![pattern.to_vec()],
+    };
+
+    pattern[open + 1..close]
+        .split(|c| *c == ',')
+        .flat_map(|alternative| {
+            let mut expanded = pattern[..open].to_vec();
+            expanded.extend_from_slice(alternative);
+            expanded.extend_from_slice(&pattern[close + 1..]);
+            expand_braces(&expanded)
+        })
+        .collect()
+}
+
+fn match_from(pattern: &[char], path: &[char]) -> bool {
+    match pattern.first() {
+        None => path.is_empty(),
+        Some('*') if pattern.get(1) == Some(&'*') => {
+            if pattern.get(2) == Some(&'/') {
+                // `**/` matches whole segments, possibly none
+                let rest = &pattern[3..];
+                (0..=path.len()).any(|skip| {
+                    (skip == 0 || path[skip - 1] == '/') && match_from(rest, &path[skip..])
+                })
+            } else {
+                let rest = &pattern[2..];
+                (0..=path.len()).any(|skip| match_from(rest, &path[skip..]))
+            }
+        }
+        Some('*') => {
+            let rest = &pattern[1..];
+            (0..=path.len())
+                .take_while(|skip| *skip == 0 || path[skip - 1] != '/')
+                .any(|skip| match_from(rest, &path[skip..]))
+        }
+        Some('?') => match path.first() {
+            Some(c) if *c != '/' => match_from(&pattern[1..], &path[1..]),
+            _ => false,
+        },
+        Some('[') => match (pattern.iter().position(|c| *c == ']'), path.first()) {
+            (Some(close), Some(c)) if *c != '/' => {
+                char_class_matches(&pattern[1..close], *c)
+                    && match_from(&pattern[close + 1..], &path[1..])
+            }
+            (Some(_), _) => false,
+            // Unclosed bracket, matched literally
+            (None, first) => first == Some(&'[') && match_from(&pattern[1..], &path[1..]),
+        },
+        Some(c) => path.first() == Some(c) && match_from(&pattern[1..], &path[1..]),
+    }
+}
+
+fn char_class_matches(class: &[char], c: char) -> bool {
+    let (negated, class) = match class.first() {
+        Some('!') => (true, &class[1..]),
+        _ => (false, class),
+    };
+    let mut matched = false;
+    let mut i = 0;
+    while i < class.len() {
+        if i + 2 < class.len() && class[i + 1] == '-' {
+            matched |= class[i] <= c && c <= class[i + 2];
+            i += 3;
+        } else {
+            matched |= class[i] == c;
+            i += 1;
+        }
+    }
+
+    matched != negated
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn glob_matches_lsp_patterns() {
+        assert!(matches("**/*.rs", "/a/b/main.rs"));
+        assert!(matches("**/*.rs", "main.rs"));
+        assert!(!matches("**/*.rs", "/a/b/main.rs.bak"));
+        assert!(matches("/a/*.{ts,js}", "/a/index.js"));
+        assert!(!matches("/a/*.{ts,js}", "/a/b/index.js"));
+        assert!(matches("/a/file?.txt", "/a/file1.txt"));
+        assert!(matches("/a/[0-9].txt", "/a/7.txt"));
+        assert!(!matches("/a/[!0-9].txt", "/a/7.txt"));
+        assert!(matches("/a/**", "/a/b/c"));
+    }
+}
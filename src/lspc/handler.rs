@@ -1,47 +1,127 @@
 use std::{
+    collections::HashMap,
     fmt::Debug,
-    path::Path,
-    process::{Command, Stdio},
+    fs,
+    io::{self, BufRead, BufReader},
+    net::TcpStream,
+    path::{Path, PathBuf},
+    process::{Child, ChildStderr, Command, Stdio},
     sync::atomic::{AtomicU64, Ordering},
+    thread,
+    time::{Duration, Instant},
 };
 
+#[cfg(windows)]
+use std::fs::{File, OpenOptions};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+
 use crossbeam::channel::Receiver;
 use lsp_types::{
     self as lsp,
-    notification::{Initialized, Notification},
-    request::Request,
-    InitializeResult, ServerCapabilities,
+    notification::{
+        Cancel, DidChangeConfiguration, DidChangeWatchedFiles, DidChangeWorkspaceFolders, Exit,
+        Initialized, Notification,
+    },
+    request::{ExecuteCommand, GotoImplementation, Request, Shutdown},
+    ServerCapabilities,
 };
 use serde::{de::DeserializeOwned, Serialize};
+use url::Url;
 
 use super::{
+    glob, merge_json,
     msg::{LspMessage, RawNotification, RawRequest, RawResponse},
-    Editor, LangServerError, LspcError,
+    quirks::Quirks,
+    types::{
+        DidChangeWatchedFilesRegistrationOptions, DidCreateFiles, DidDeleteFiles, DidRenameFiles,
+        FileOperationFilter, FileOperationPatternKind, FileOperationRegistrationOptions,
+        Initialize, InitializeResult, PositionEncoding, ProgressToken, SemanticTokensLegend,
+        ServerInfo, SetTrace, SetTraceParams, TraceValue, WorkDoneProgress,
+    },
+    DiagnosticLevel, Editor, HoverMode, LangServerError, LsConfig, LspcError, Transport,
 };
 use crate::rpc;
 
 pub type RawCallback<E> =
     Box<dyn FnOnce(&mut E, &mut LangServerHandler<E>, RawResponse) -> Result<(), LspcError>>;
+pub type FailureCallback<E> =
+    Box<dyn FnOnce(&mut E, &mut LangServerHandler<E>) -> Result<(), LspcError>>;
+
+// File operation notification methods and their key in the
+// `workspace.fileOperations` capability
+const FILE_OPERATIONS: [(&str, &str); 3] = [
+    (DidCreateFiles::METHOD, "didCreate"),
+    (DidRenameFiles::METHOD, "didRename"),
+    (DidDeleteFiles::METHOD, "didDelete"),
+];
 
 pub struct Callback<E: Editor> {
     pub id: u64,
+    pub method: &'static str,
+    pub deadline: Instant,
     pub func: RawCallback<E>,
+    // Run instead of `func` when the server answers with an error or the
+    // request times out
+    pub on_failure: FailureCallback<E>,
 }
 
 pub struct LangSettings {
     pub indentation: u64,
     pub indentation_with_space: bool,
+    pub sync_burst_threshold: usize,
+    pub sync_delay_ms: u64,
+    pub forward_notifications: bool,
+    pub hover_mode: HoverMode,
+    pub format_on_save: bool,
+    pub min_diagnostic_severity: DiagnosticLevel,
+    pub request_timeout_ms: u64,
+    pub trace: TraceValue,
 }
 
 pub struct LangServerHandler<E: Editor> {
     pub id: u64,
     pub lang_id: String,
     rpc_client: rpc::Client<LspMessage>,
+    // None if the server isn't a child process of lspc
+    child_process: Option<Child>,
     callbacks: Vec<Callback<E>>,
     next_id: AtomicU64,
     root_path: String,
+    // Folders handled besides `root_path`
+    workspace_folders: Vec<String>,
+    // `root_path` then `workspace_folders`, canonicalized once as they're added
+    canonical_roots: Vec<PathBuf>,
+    // Set while the `initialize` response is awaited
+    initialize_deadline: Option<Instant>,
     // None if server is not started
     server_capabilities: Option<ServerCapabilities>,
+    // As sent by the server, including the capabilities lsp-types doesn't know
+    raw_capabilities: Option<serde_json::Value>,
+    // None if server is not started or didn't send it
+    server_info: Option<ServerInfo>,
+    // Unit of the characters of the positions exchanged with the server
+    position_encoding: PositionEncoding,
+    pub quirks: Quirks,
+    // Characters the server wants completion to be requested on
+    completion_trigger_characters: Vec<String>,
+    // Characters the server formats the document on, the first one is mandatory
+    on_type_formatting_trigger_characters: Vec<String>,
+    // File operation notification method -> filters of the files the server
+    // wants to hear about, no entry if it doesn't want the notification
+    file_operation_filters: HashMap<String, Vec<FileOperationFilter>>,
+    // None if the server doesn't provide semantic tokens
+    semantic_tokens_legend: Option<SemanticTokensLegend>,
+    semantic_tokens_delta: bool,
+    call_hierarchy_provider: bool,
+    // Titles of the progress being reported, by token
+    progress_titles: HashMap<ProgressToken, String>,
+    // Capabilities registered after `initialize`, by registration id
+    registrations: HashMap<String, lsp::Registration>,
+    // Last settings sent with `workspace/didChangeConfiguration`
+    settings: serde_json::Value,
+    // Null to send the settings instead
+    initialization_options: serde_json::Value,
     pub lang_settings: LangSettings,
 }
 
@@ -49,40 +129,220 @@ impl<E: Editor> LangServerHandler<E> {
     pub fn new(
         id: u64,
         lang_id: String,
-        command: &String,
+        config: &LsConfig,
+        lang_settings: LangSettings,
+        root_path: String,
+    ) -> Result<Self, LangServerError> {
+        match config.transport {
+            Transport::Stdio => {
+                Self::stdio_transport(id, lang_id, config, lang_settings, root_path)
+            }
+            Transport::Tcp { ref host, port } => {
+                Self::tcp_transport(id, lang_id, host, port, lang_settings, root_path)
+            }
+            Transport::Socket { ref path } => {
+                Self::socket_transport(id, lang_id, path, lang_settings, root_path)
+            }
+        }
+    }
+
+    // The server inherits lspc's environment, with `env` overriding it, and
+    // runs in `cwd` or the root
+    fn stdio_transport(
+        id: u64,
+        lang_id: String,
+        config: &LsConfig,
         lang_settings: LangSettings,
-        args: &[String],
         root_path: String,
     ) -> Result<Self, LangServerError> {
-        let child_process = Command::new(command)
+        let (command, args) = config.command.split_first().ok_or_else(|| {
+            LangServerError::Process(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "No command to start the server",
+            ))
+        })?;
+        let mut child_process = Command::new(command)
             .args(args)
+            .envs(&config.env)
+            .current_dir(config.cwd.as_ref().unwrap_or(&root_path))
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
             .spawn()
             .map_err(|e| LangServerError::Process(e))?;
 
-        let _child_pid = child_process.id();
-        let child_stdout = child_process.stdout.unwrap();
-        let child_stdin = child_process.stdin.unwrap();
+        let child_stdout = child_process.stdout.take().unwrap();
+        let child_stdin = child_process.stdin.take().unwrap();
+        let child_stderr = child_process.stderr.take().unwrap();
+        log_stderr(lang_id.clone(), child_stderr);
 
         let rpc_client = rpc::Client::<LspMessage>::new(move || child_stdout, move || child_stdin);
 
-        Ok(LangServerHandler {
+        let mut handler = Self::with_client(id, lang_id, rpc_client, lang_settings, root_path);
+        handler.child_process = Some(child_process);
+
+        Ok(handler)
+    }
+
+    // The server is already running, it isn't killed on shutdown
+    fn tcp_transport(
+        id: u64,
+        lang_id: String,
+        host: &str,
+        port: u16,
+        lang_settings: LangSettings,
+        root_path: String,
+    ) -> Result<Self, LangServerError> {
+        let address = format!("{}:{}", host, port);
+        let writer = TcpStream::connect(&address)
+            .map_err(|e| LangServerError::Connection(address.clone(), e))?;
+        let reader = writer
+            .try_clone()
+            .map_err(|e| LangServerError::Connection(address, e))?;
+
+        let rpc_client = rpc::Client::<LspMessage>::new(move || reader, move || writer);
+
+        Ok(Self::with_client(
             id,
+            lang_id,
             rpc_client,
+            lang_settings,
+            root_path,
+        ))
+    }
+
+    fn socket_transport(
+        id: u64,
+        lang_id: String,
+        path: &str,
+        lang_settings: LangSettings,
+        root_path: String,
+    ) -> Result<Self, LangServerError> {
+        let connection_error = |e: io::Error| match e.kind() {
+            io::ErrorKind::NotFound => LangServerError::SocketNotFound(path.to_owned()),
+            _ => LangServerError::Connection(path.to_owned(), e),
+        };
+        let writer = connect_socket(path).map_err(connection_error)?;
+        let reader = writer.try_clone().map_err(connection_error)?;
+
+        let rpc_client = rpc::Client::<LspMessage>::new(move || reader, move || writer);
+
+        Ok(Self::with_client(
+            id,
+            lang_id,
+            rpc_client,
+            lang_settings,
+            root_path,
+        ))
+    }
+
+    pub fn with_client(
+        id: u64,
+        lang_id: String,
+        rpc_client: rpc::Client<LspMessage>,
+        lang_settings: LangSettings,
+        root_path: String,
+    ) -> Self {
+        LangServerHandler {
+            id,
+            rpc_client,
+            child_process: None,
             lang_id,
             next_id: AtomicU64::new(1),
+            canonical_roots: vec![canonical_path(&root_path)],
             root_path,
+            workspace_folders: Vec::new(),
             callbacks: Vec::new(),
+            initialize_deadline: None,
             server_capabilities: None,
+            raw_capabilities: None,
+            server_info: None,
+            position_encoding: PositionEncoding::default(),
+            quirks: Quirks::default(),
+            completion_trigger_characters: Vec::new(),
+            on_type_formatting_trigger_characters: Vec::new(),
+            file_operation_filters: HashMap::new(),
+            semantic_tokens_legend: None,
+            semantic_tokens_delta: false,
+            call_hierarchy_provider: false,
+            progress_titles: HashMap::new(),
+            registrations: HashMap::new(),
+            settings: serde_json::Value::Null,
+            initialization_options: serde_json::Value::Null,
             lang_settings,
-        })
+        }
+    }
+
+    // Paths are compared by components once decoded and canonicalized, so a
+    // root `/a/proj` doesn't include `/a/proj-other`
+    pub fn include_file(&self, uri: &Url) -> bool {
+        let file_path = canonical_path(&uri_path(uri));
+
+        self.canonical_roots
+            .iter()
+            .any(|root| file_path.starts_with(root))
     }
 
-    pub fn include_file(&self, file_path: &str) -> bool {
-        let file_path = Path::new(file_path);
+    pub fn workspace_folders(&self) -> Vec<lsp::WorkspaceFolder> {
+        std::iter::once(&self.root_path)
+            .chain(self.workspace_folders.iter())
+            .filter_map(|path| workspace_folder(path))
+            .collect()
+    }
 
-        file_path.starts_with(&self.root_path)
+    // Servers are only notified once initialized, the folders are sent with
+    // `initialize` otherwise
+    pub fn add_workspace_folder(&mut self, path: String) -> Result<(), LangServerError> {
+        if path == self.root_path || self.workspace_folders.contains(&path) {
+            return Ok(());
+        }
+        let added = workspace_folder(&path).into_iter().collect();
+        self.canonical_roots.push(canonical_path(&path));
+        self.workspace_folders.push(path);
+
+        self.notify_workspace_folders_change(added, Vec::new())
+    }
+
+    pub fn remove_workspace_folder(&mut self, path: &str) -> Result<(), LangServerError> {
+        let index = match self
+            .workspace_folders
+            .iter()
+            .position(|folder| folder == path)
+        {
+            Some(index) => index,
+            None => {
+                log::warn!("Not a removable workspace folder: {}", path);
+                return Ok(());
+            }
+        };
+        self.workspace_folders.remove(index);
+        self.canonical_roots.remove(index + 1);
+        let removed = workspace_folder(path).into_iter().collect();
+
+        self.notify_workspace_folders_change(Vec::new(), removed)
+    }
+
+    fn notify_workspace_folders_change(
+        &mut self,
+        added: Vec<lsp::WorkspaceFolder>,
+        removed: Vec<lsp::WorkspaceFolder>,
+    ) -> Result<(), LangServerError> {
+        let capabilities = match self.raw_capabilities {
+            Some(ref capabilities) => capabilities,
+            None => return Ok(()),
+        };
+        // Either `true` or the id of a dynamic registration
+        match capabilities["workspace"]["workspaceFolders"]["changeNotifications"] {
+            serde_json::Value::Null | serde_json::Value::Bool(false) => {
+                log::info!("Server doesn't support workspace folder changes");
+                return Ok(());
+            }
+            _ => {}
+        }
+
+        self.lsp_notify::<DidChangeWorkspaceFolders>(&lsp::DidChangeWorkspaceFoldersParams {
+            event: lsp::WorkspaceFoldersChangeEvent { added, removed },
+        })
     }
 
     pub fn sync_kind(&self) -> lsp::TextDocumentSyncKind {
@@ -100,6 +360,348 @@ impl<E: Editor> LangServerHandler<E> {
         lsp::TextDocumentSyncKind::Full
     }
 
+    // None if the server doesn't want `didSave`, otherwise whether it wants
+    // the saved text with it
+    pub fn save_include_text(&self) -> Option<bool> {
+        let save = self
+            .raw_capabilities
+            .as_ref()
+            .map(|capabilities| &capabilities["textDocumentSync"]["save"]);
+        match save {
+            Some(serde_json::Value::Bool(true)) => Some(false),
+            Some(serde_json::Value::Object(options)) => {
+                Some(options.get("includeText") == Some(&serde_json::Value::Bool(true)))
+            }
+            _ => None,
+        }
+    }
+
+    // `willSave` and `willSaveWaitUntil` are only sent when the server opts in
+    pub fn sync_option_enabled(&self, option: &str) -> bool {
+        self.raw_capabilities
+            .as_ref()
+            .map_or(false, |capabilities| {
+                capabilities["textDocumentSync"][option] == serde_json::Value::Bool(true)
+            })
+    }
+
+    pub fn server_info(&self) -> Option<&ServerInfo> {
+        self.server_info.as_ref()
+    }
+
+    // Name and version the server gave, to tell which binary was started
+    pub fn server_name(&self) -> String {
+        match self.server_info {
+            Some(ServerInfo {
+                ref name,
+                version: Some(ref version),
+            }) => format!("{} {}", name, version),
+            Some(ServerInfo { ref name, .. }) => name.clone(),
+            None => "unnamed server".to_owned(),
+        }
+    }
+
+    pub fn position_encoding(&self) -> PositionEncoding {
+        self.position_encoding
+    }
+
+    pub fn completion_trigger_characters(&self) -> &[String] {
+        &self.completion_trigger_characters
+    }
+
+    pub fn on_type_formatting_trigger_characters(&self) -> &[String] {
+        &self.on_type_formatting_trigger_characters
+    }
+
+    pub fn semantic_tokens_legend(&self) -> Option<&SemanticTokensLegend> {
+        self.semantic_tokens_legend.as_ref()
+    }
+
+    pub fn can_request_semantic_tokens_delta(&self) -> bool {
+        self.semantic_tokens_delta
+    }
+
+    pub fn can_call_hierarchy(&self) -> bool {
+        self.call_hierarchy_provider
+    }
+
+    // Whether the server announced the capability of a request method.
+    // Methods without a capability, and every method before the server is
+    // initialized, are considered supported.
+    pub fn supports(&self, method: &str) -> bool {
+        if self.registered(method) {
+            return true;
+        }
+        let capabilities = match self.raw_capabilities {
+            Some(ref capabilities) => capabilities,
+            None => return true,
+        };
+        let capability = match method {
+            "textDocument/hover" => "hoverProvider",
+            "textDocument/completion" => "completionProvider",
+            "textDocument/signatureHelp" => "signatureHelpProvider",
+            "textDocument/definition" => "definitionProvider",
+            "textDocument/declaration" => "declarationProvider",
+            "textDocument/typeDefinition" => "typeDefinitionProvider",
+            "textDocument/implementation" => "implementationProvider",
+            "textDocument/references" => "referencesProvider",
+            "textDocument/documentHighlight" => "documentHighlightProvider",
+            "textDocument/documentSymbol" => "documentSymbolProvider",
+            "workspace/symbol" => "workspaceSymbolProvider",
+            "textDocument/codeAction" => "codeActionProvider",
+            "textDocument/codeLens" => "codeLensProvider",
+            "textDocument/formatting" => "documentFormattingProvider",
+            "textDocument/rangeFormatting" => "documentRangeFormattingProvider",
+            "textDocument/onTypeFormatting" => "documentOnTypeFormattingProvider",
+            "textDocument/rename" => "renameProvider",
+            "textDocument/documentLink" => "documentLinkProvider",
+            "textDocument/documentColor" | "textDocument/colorPresentation" => "colorProvider",
+            "textDocument/selectionRange" => "selectionRangeProvider",
+            "textDocument/prepareCallHierarchy" => "callHierarchyProvider",
+            "textDocument/semanticTokens/full" => "semanticTokensProvider",
+            "textDocument/inlayHint" => "inlayHintProvider",
+            "textDocument/diagnostic" => "diagnosticProvider",
+            _ => return true,
+        };
+
+        // Either `true` or the capability's options
+        match capabilities[capability] {
+            serde_json::Value::Null | serde_json::Value::Bool(false) => false,
+            _ => true,
+        }
+    }
+
+    pub fn can_resolve_completion(&self) -> bool {
+        self.server_capabilities
+            .as_ref()
+            .and_then(|cap| cap.completion_provider.as_ref())
+            .and_then(|opts| opts.resolve_provider)
+            .unwrap_or(false)
+    }
+
+    pub fn can_prepare_rename(&self) -> bool {
+        match self
+            .server_capabilities
+            .as_ref()
+            .and_then(|cap| cap.rename_provider.as_ref())
+        {
+            Some(lsp::RenameProviderCapability::Options(opts)) => {
+                opts.prepare_provider == Some(true)
+            }
+            _ => false,
+        }
+    }
+
+    pub fn can_goto_implementation(&self) -> bool {
+        if self.registered(GotoImplementation::METHOD) {
+            return true;
+        }
+        match self
+            .server_capabilities
+            .as_ref()
+            .and_then(|cap| cap.implementation_provider.as_ref())
+        {
+            Some(lsp::ImplementationProviderCapability::Simple(supported)) => *supported,
+            Some(lsp::ImplementationProviderCapability::Options(_)) => true,
+            None => false,
+        }
+    }
+
+    pub fn can_resolve_code_lens(&self) -> bool {
+        self.server_capabilities
+            .as_ref()
+            .and_then(|cap| cap.code_lens_provider.as_ref())
+            .and_then(|opts| opts.resolve_provider)
+            .unwrap_or(false)
+    }
+
+    pub fn can_resolve_document_link(&self) -> bool {
+        self.server_capabilities
+            .as_ref()
+            .and_then(|cap| cap.document_link_provider.as_ref())
+            .and_then(|opts| opts.resolve_provider)
+            .unwrap_or(false)
+    }
+
+    pub fn can_execute_command(&self, command: &str) -> bool {
+        self.server_capabilities
+            .as_ref()
+            .and_then(|cap| cap.execute_command_provider.as_ref())
+            .map(|opts| opts.commands.iter().any(|c| c == command))
+            .unwrap_or(false)
+    }
+
+    // Statusline text of the progress, None once it ended. Reports only
+    // carry the changes, the title is kept from the beginning.
+    pub fn track_progress(
+        &mut self,
+        token: ProgressToken,
+        progress: WorkDoneProgress,
+    ) -> Option<String> {
+        let (title, message, percentage) = match progress {
+            WorkDoneProgress::Begin {
+                title,
+                message,
+                percentage,
+            } => {
+                self.progress_titles.insert(token, title.clone());
+                (title, message, percentage)
+            }
+            WorkDoneProgress::Report {
+                message,
+                percentage,
+            } => {
+                let title = self.progress_titles.get(&token)?.clone();
+                (title, message, percentage)
+            }
+            WorkDoneProgress::End { .. } => {
+                self.progress_titles.remove(&token);
+                return None;
+            }
+        };
+
+        let mut status = title;
+        if let Some(message) = message {
+            status = format!("{} {}", status, message);
+        }
+        if let Some(percentage) = percentage {
+            status = format!("{} ({}%)", status, percentage);
+        }
+        Some(status)
+    }
+
+    fn registered(&self, method: &str) -> bool {
+        self.registrations
+            .values()
+            .any(|registration| registration.method == method)
+    }
+
+    pub fn register_capability(&mut self, registration: lsp::Registration) {
+        log::debug!("Registered: {:?}", registration);
+        let is_file_operation = FILE_OPERATIONS
+            .iter()
+            .any(|(method, _)| *method == registration.method);
+        self.registrations
+            .insert(registration.id.clone(), registration);
+        if is_file_operation {
+            self.refresh_file_operation_filters();
+        }
+    }
+
+    pub fn unregister_capability(&mut self, unregistration: &lsp::Unregistration) {
+        if self.registrations.remove(&unregistration.id).is_none() {
+            log::warn!("Unknown registration: {:?}", unregistration);
+            return;
+        }
+        self.refresh_file_operation_filters();
+    }
+
+    // Dynamic registrations have the same options as the static ones
+    fn refresh_file_operation_filters(&mut self) {
+        let mut options = Vec::new();
+        if let Some(ref raw_capabilities) = self.raw_capabilities {
+            for (method, key) in &FILE_OPERATIONS {
+                let static_options = &raw_capabilities["workspace"]["fileOperations"][key];
+                if !static_options.is_null() {
+                    options.push((method.to_string(), static_options.clone()));
+                }
+            }
+        }
+        for registration in self.registrations.values() {
+            if FILE_OPERATIONS
+                .iter()
+                .any(|(method, _)| *method == registration.method)
+            {
+                options.push((
+                    registration.method.clone(),
+                    registration.register_options.clone().unwrap_or_default(),
+                ));
+            }
+        }
+
+        self.file_operation_filters.clear();
+        for (method, options) in options {
+            match serde_json::from_value::<FileOperationRegistrationOptions>(options) {
+                Ok(options) => {
+                    self.file_operation_filters
+                        .entry(method)
+                        .or_default()
+                        .extend(options.filters);
+                }
+                Err(e) => log::warn!("Invalid {} registration: {}", method, e),
+            }
+        }
+    }
+
+    pub fn wants_file_operation(&self, method: &str, uri: &Url) -> bool {
+        let filters = match self.file_operation_filters.get(method) {
+            Some(filters) => filters,
+            None => return false,
+        };
+        if !self.include_file(uri) {
+            return false;
+        }
+        let path = uri_path(uri);
+
+        filters.iter().any(|filter| {
+            if let Some(ref scheme) = filter.scheme {
+                if scheme != uri.scheme() {
+                    return false;
+                }
+            }
+            let pattern = &filter.pattern;
+            let ignore_case = pattern
+                .options
+                .as_ref()
+                .and_then(|options| options.ignore_case)
+                .unwrap_or(false);
+            let glob_matches = if ignore_case {
+                glob::matches(&pattern.glob.to_lowercase(), &path.to_lowercase())
+            } else {
+                glob::matches(&pattern.glob, &path)
+            };
+            // Deleted files can't be checked anymore, they're let through
+            let kind_matches = match (pattern.matches, Path::new(&path).metadata()) {
+                (Some(FileOperationPatternKind::File), Ok(metadata)) => metadata.is_file(),
+                (Some(FileOperationPatternKind::Folder), Ok(metadata)) => metadata.is_dir(),
+                _ => true,
+            };
+
+            glob_matches && kind_matches
+        })
+    }
+
+    // Servers that didn't register watchers are told about every change of
+    // their files
+    pub fn wants_watched_file(&self, change: &lsp::FileEvent) -> bool {
+        if !self.include_file(&change.uri) {
+            return false;
+        }
+        if !self.registered(DidChangeWatchedFiles::METHOD) {
+            return true;
+        }
+
+        let kind = match change.typ {
+            lsp::FileChangeType::Created => 1,
+            lsp::FileChangeType::Changed => 2,
+            lsp::FileChangeType::Deleted => 4,
+        };
+        let path = uri_path(&change.uri);
+        self.registrations
+            .values()
+            .filter(|registration| registration.method == DidChangeWatchedFiles::METHOD)
+            .filter_map(|registration| {
+                let options = registration.register_options.clone().unwrap_or_default();
+                serde_json::from_value::<DidChangeWatchedFilesRegistrationOptions>(options)
+                    .map_err(|e| log::warn!("Invalid watched files registration: {}", e))
+                    .ok()
+            })
+            .flat_map(|options| options.watchers)
+            .any(|watcher| {
+                watcher.kind.unwrap_or(7) & kind != 0 && glob::matches(&watcher.glob_pattern, &path)
+            })
+    }
+
     fn send_msg(&self, msg: LspMessage) -> Result<(), LangServerError> {
         self.rpc_client
             .sender
@@ -131,14 +733,102 @@ impl<E: Editor> LangServerHandler<E> {
         &mut self,
         response: InitializeResult,
     ) -> Result<(), LangServerError> {
-        let server_capabilities = response.capabilities;
+        self.initialize_deadline = None;
+        let raw_capabilities = response.capabilities;
+        let server_capabilities = serde_json::from_value::<ServerCapabilities>(
+            raw_capabilities.clone(),
+        )
+        .unwrap_or_else(|e| {
+            log::warn!("Invalid server capabilities: {}", e);
+            ServerCapabilities::default()
+        });
+        self.completion_trigger_characters = server_capabilities
+            .completion_provider
+            .as_ref()
+            .and_then(|opts| opts.trigger_characters.clone())
+            .unwrap_or_default();
+        self.on_type_formatting_trigger_characters = server_capabilities
+            .document_on_type_formatting_provider
+            .as_ref()
+            .map(|opts| {
+                let mut characters = vec![opts.first_trigger_character.clone()];
+                characters.extend(opts.more_trigger_character.iter().flatten().cloned());
+                characters
+            })
+            .unwrap_or_default();
+        // Servers not negotiating it use UTF-16
+        self.position_encoding =
+            serde_json::from_value(raw_capabilities["positionEncoding"].clone())
+                .unwrap_or_default();
+        self.semantic_tokens_legend = match raw_capabilities["semanticTokensProvider"]["legend"] {
+            serde_json::Value::Null => None,
+            ref legend => serde_json::from_value(legend.clone())
+                .map_err(|e| log::warn!("Invalid semantic tokens legend: {}", e))
+                .ok(),
+        };
+        self.semantic_tokens_delta =
+            raw_capabilities["semanticTokensProvider"]["full"]["delta"] == true;
+        // Either `true` or registration options
+        self.call_hierarchy_provider = match raw_capabilities["callHierarchyProvider"] {
+            serde_json::Value::Bool(supported) => supported,
+            serde_json::Value::Object(_) => true,
+            _ => false,
+        };
         self.server_capabilities = Some(server_capabilities);
+        self.raw_capabilities = Some(raw_capabilities);
+        self.refresh_file_operation_filters();
+        self.quirks = Quirks::for_server(response.server_info.as_ref());
+        self.server_info = response.server_info;
+        log::info!("Started {} server: {}", self.lang_id, self.server_name());
 
         self.initialized()?;
 
         Ok(())
     }
 
+    pub fn expect_initialize_by(&mut self, deadline: Instant) {
+        self.initialize_deadline = Some(deadline);
+    }
+
+    pub fn initialize_overdue(&self, now: Instant) -> bool {
+        self.initialize_deadline
+            .map_or(false, |deadline| deadline <= now)
+    }
+
+    pub fn initializing(&self) -> bool {
+        self.initialize_deadline.is_some()
+    }
+
+    pub fn root_path(&self) -> &str {
+        &self.root_path
+    }
+
+    // Servers connected through a socket aren't watched, their connection
+    // being closed is noticed instead
+    pub fn has_exited(&mut self) -> bool {
+        match self.child_process {
+            Some(ref mut child_process) => match child_process.try_wait() {
+                Ok(status) => status.is_some(),
+                Err(e) => {
+                    log::warn!("Failed to check {} server: {:?}", self.lang_id, e);
+                    false
+                }
+            },
+            None => false,
+        }
+    }
+
+    // For servers that can't be talked to anymore, nothing is sent
+    pub fn kill(&mut self) -> Result<(), LangServerError> {
+        if let Some(ref mut child_process) = self.child_process {
+            child_process
+                .kill()
+                .map_err(|e| LangServerError::Process(e))?;
+        }
+
+        Ok(())
+    }
+
     pub fn initialized(&mut self) -> Result<(), LangServerError> {
         log::debug!("Sending initialized notification");
 
@@ -149,7 +839,23 @@ impl<E: Editor> LangServerHandler<E> {
         &mut self,
         params: &R::Params,
         cb: Box<dyn FnOnce(&mut E, &mut LangServerHandler<E>, R::Result) -> Result<(), LspcError>>,
-    ) -> Result<(), LangServerError>
+    ) -> Result<u64, LangServerError>
+    where
+        R::Params: Serialize + Debug,
+        R::Result: DeserializeOwned + 'static,
+        E: 'static,
+    {
+        self.lsp_request_or_else::<R>(params, cb, Box::new(|_editor: &mut E, _handler| Ok(())))
+    }
+
+    // Like `lsp_request`, `on_failure` is called when no result comes, so
+    // callers waiting for several requests aren't left hanging
+    pub fn lsp_request_or_else<R: Request>(
+        &mut self,
+        params: &R::Params,
+        cb: Box<dyn FnOnce(&mut E, &mut LangServerHandler<E>, R::Result) -> Result<(), LspcError>>,
+        on_failure: FailureCallback<E>,
+    ) -> Result<u64, LangServerError>
     where
         R::Params: Serialize + Debug,
         R::Result: DeserializeOwned + 'static,
@@ -166,14 +872,241 @@ impl<E: Editor> LangServerHandler<E> {
                 cb(e, handler, response)
             });
         let func = Box::new(raw_callback);
-        self.callbacks.push(Callback { id, func });
-        self.request(request)
+        let deadline =
+            Instant::now() + Duration::from_millis(self.lang_settings.request_timeout_ms);
+        self.callbacks.push(Callback {
+            id,
+            method: R::METHOD,
+            deadline,
+            func,
+            on_failure,
+        });
+        self.request(request)?;
+
+        Ok(id)
+    }
+
+    // The response won't be handled anymore, nothing is sent if it was
+    // already received
+    pub fn cancel(&mut self, id: u64) -> Result<(), LangServerError> {
+        self.cancel_callback(id)?;
+
+        Ok(())
+    }
+
+    fn cancel_callback(&mut self, id: u64) -> Result<Option<Callback<E>>, LangServerError> {
+        let callback = match self.callback_for(id) {
+            Some(callback) => callback,
+            None => return Ok(None),
+        };
+        self.lsp_notify::<Cancel>(&lsp::CancelParams {
+            id: lsp::NumberOrString::Number(id),
+        })?;
+
+        Ok(Some(callback))
+    }
+
+    // `initialize` isn't cancelled here, a server not answering it is
+    // considered hung and removed instead. Returns the failure callbacks of
+    // the cancelled requests, to be run with the editor.
+    pub fn cancel_overdue_requests(
+        &mut self,
+        now: Instant,
+    ) -> Result<Vec<FailureCallback<E>>, LangServerError> {
+        let overdue = self
+            .callbacks
+            .iter()
+            .filter(|cb| cb.deadline <= now && cb.method != Initialize::METHOD)
+            .map(|cb| (cb.id, cb.method))
+            .collect::<Vec<_>>();
+
+        let mut failures = Vec::new();
+        for (id, method) in overdue {
+            log::warn!("Request {} ({}) timed out, cancelling", id, method);
+            if let Some(callback) = self.cancel_callback(id)? {
+                failures.push(callback.on_failure);
+            }
+        }
+
+        Ok(failures)
+    }
+
+    pub fn execute_command(&mut self, command: &lsp::Command) -> Result<(), LangServerError> {
+        if !self.can_execute_command(&command.command) {
+            log::warn!("Server doesn't support command: {}", command.command);
+            return Ok(());
+        }
+
+        let params = lsp::ExecuteCommandParams {
+            command: command.command.clone(),
+            arguments: command.arguments.clone().unwrap_or_default(),
+        };
+
+        self.lsp_request::<ExecuteCommand>(
+            &params,
+            Box::new(|_editor: &mut E, _handler, _response| Ok(())),
+        )?;
+
+        Ok(())
+    }
+
+    // Unless replacing them, `settings` are deep-merged into the stored ones,
+    // the server is notified with the merged result so unrelated settings are kept
+    pub fn update_settings(
+        &mut self,
+        settings: serde_json::Value,
+        replace: bool,
+    ) -> Result<(), LangServerError> {
+        if replace {
+            self.settings = settings;
+        } else {
+            merge_json(&mut self.settings, settings);
+        }
+
+        self.lsp_notify::<DidChangeConfiguration>(&lsp::DidChangeConfigurationParams {
+            settings: self.settings.clone(),
+        })
+    }
+
+    // The server isn't notified, for the settings sent with `initialize`
+    pub fn set_settings(&mut self, settings: serde_json::Value) {
+        self.settings = settings;
+    }
+
+    pub fn settings(&self) -> &serde_json::Value {
+        &self.settings
+    }
+
+    pub fn set_trace(&mut self, value: TraceValue) -> Result<(), LangServerError> {
+        self.lang_settings.trace = value;
+
+        self.lsp_notify::<SetTrace>(&SetTraceParams { value })
+    }
+
+    pub fn set_initialization_options(&mut self, options: serde_json::Value) {
+        self.initialization_options = options;
+    }
+
+    // Servers like rust-analyzer take their settings as initialization
+    // options, those are sent unless other options were given
+    pub fn initialization_options(&self) -> Option<serde_json::Value> {
+        Some(&self.initialization_options)
+            .filter(|options| !options.is_null())
+            .or_else(|| Some(&self.settings).filter(|settings| !settings.is_null()))
+            .cloned()
+    }
+
+    // `section` is a dotted path in the settings, e.g. `rust-analyzer.cargo`,
+    // null if there is nothing there
+    pub fn settings_section(&self, section: Option<&str>) -> serde_json::Value {
+        let section = match section {
+            Some(section) => section,
+            None => return self.settings.clone(),
+        };
+
+        section
+            .split('.')
+            .try_fold(&self.settings, |settings, key| settings.get(key))
+            .cloned()
+            .unwrap_or(serde_json::Value::Null)
+    }
+
+    // `exit` is only sent once `shutdown` is answered, or after `timeout`
+    // in which case the process is killed as well. Messages arriving in
+    // the meantime are dropped.
+    pub fn shutdown(&mut self, timeout: Duration) -> Result<(), LangServerError> {
+        Self::shutdown_all(std::slice::from_mut(self), timeout)
+    }
+
+    // Every server is asked to shut down before any of them is waited for,
+    // so stopping several servers takes at most `timeout` for the answers
+    // and `timeout` for the exits, however many there are. Servers failing
+    // are logged and the first failure is returned once all are done.
+    pub fn shutdown_all(handlers: &mut [Self], timeout: Duration) -> Result<(), LangServerError> {
+        let mut result = Ok(());
+        let mut fail = |handler: &Self, e: LangServerError| {
+            log::warn!("Failed to shut down {} server: {:?}", handler.lang_id, e);
+            if result.is_ok() {
+                result = Err(e);
+            }
+        };
+
+        let mut pending = Vec::new();
+        for handler in handlers.iter_mut() {
+            let id = handler.fetch_id();
+            match handler.request(RawRequest::new::<Shutdown>(id, &())) {
+                Ok(()) => pending.push((handler, id)),
+                Err(e) => fail(handler, e),
+            }
+        }
+
+        let deadline = Instant::now() + timeout;
+        for (handler, id) in pending.iter_mut() {
+            if let Err(e) = handler.exit_after_shutdown(*id, deadline) {
+                fail(handler, e);
+            }
+        }
+
+        let deadline = Instant::now() + timeout;
+        for (handler, _) in pending {
+            if let Err(e) = handler.wait_for_exit(deadline) {
+                fail(handler, e);
+            }
+        }
+
+        result
+    }
+
+    fn exit_after_shutdown(&mut self, id: u64, deadline: Instant) -> Result<(), LangServerError> {
+        let responded = loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match self.rpc_client.receiver.recv_timeout(remaining) {
+                Ok(LspMessage::Response(ref response)) if response.id == id => break true,
+                Ok(msg) => log::debug!("Dropped while shutting down: {:?}", msg),
+                Err(_) => break false,
+            }
+        };
+
+        self.lsp_notify::<Exit>(&())?;
+
+        if !responded {
+            log::warn!("{} server didn't answer shutdown, killing it", self.lang_id);
+            self.kill()?;
+        }
+
+        Ok(())
+    }
+
+    // Servers still running after `deadline` are killed
+    fn wait_for_exit(&mut self, deadline: Instant) -> Result<(), LangServerError> {
+        let child_process = match self.child_process {
+            Some(ref mut child_process) => child_process,
+            None => return Ok(()),
+        };
+
+        while Instant::now() < deadline {
+            match child_process.try_wait() {
+                Ok(Some(_)) => return Ok(()),
+                Ok(None) => thread::sleep(Duration::from_millis(10)),
+                Err(e) => return Err(LangServerError::Process(e)),
+            }
+        }
+
+        log::warn!("{} server didn't exit, killing it", self.lang_id);
+        child_process.kill().map_err(LangServerError::Process)?;
+        child_process.wait().map_err(LangServerError::Process)?;
+
+        Ok(())
     }
 
     fn request(&mut self, request: RawRequest) -> Result<(), LangServerError> {
         self.send_msg(LspMessage::Request(request))
     }
 
+    pub fn lsp_respond(&mut self, response: RawResponse) -> Result<(), LangServerError> {
+        self.send_msg(LspMessage::Response(response))
+    }
+
     pub fn lsp_notify<R: Notification>(&mut self, params: &R::Params) -> Result<(), LangServerError>
     where
         R::Params: Serialize + Debug,
@@ -182,3 +1115,61 @@ impl<E: Editor> LangServerHandler<E> {
         self.send_msg(LspMessage::Notification(noti))
     }
 }
+
+fn workspace_folder(path: &str) -> Option<lsp::WorkspaceFolder> {
+    let uri = Url::from_directory_path(path).ok()?;
+    let name = Path::new(path)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(path)
+        .to_owned();
+
+    Some(lsp::WorkspaceFolder { uri, name })
+}
+
+// Symlinks are resolved when the path exists, or through its parent directory
+// for a file not written yet
+fn canonical_path(path: &str) -> PathBuf {
+    let path = Path::new(path);
+    fs::canonicalize(path).unwrap_or_else(|_| match (path.parent(), path.file_name()) {
+        (Some(parent), Some(file_name)) => fs::canonicalize(parent)
+            .map(|parent| parent.join(file_name))
+            .unwrap_or_else(|_| path.to_path_buf()),
+        _ => path.to_path_buf(),
+    })
+}
+
+// Paths are matched against the file path, or the URI path for other schemes
+fn uri_path(uri: &Url) -> String {
+    uri.to_file_path()
+        .ok()
+        .and_then(|path| path.to_str().map(|path| path.to_owned()))
+        .unwrap_or_else(|| uri.path().to_owned())
+}
+
+// Logged rather than inherited, it would clobber the editor's UI. The thread
+// ends with the server, once stderr is closed
+fn log_stderr(lang_id: String, stderr: ChildStderr) {
+    thread::spawn(move || {
+        for line in BufReader::new(stderr).lines() {
+            match line {
+                Ok(line) => log::warn!("[{} stderr] {}", lang_id, line),
+                Err(e) => {
+                    log::warn!("Failed to read {} server stderr: {:?}", lang_id, e);
+                    break;
+                }
+            }
+        }
+    });
+}
+
+#[cfg(unix)]
+fn connect_socket(path: &str) -> io::Result<UnixStream> {
+    UnixStream::connect(path)
+}
+
+// Named pipes are opened as files by their clients
+#[cfg(windows)]
+fn connect_socket(path: &str) -> io::Result<File> {
+    OpenOptions::new().read(true).write(true).open(path)
+}
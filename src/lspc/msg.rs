@@ -89,6 +89,8 @@ pub struct RawNotification {
     pub params: Value,
 }
 impl Message for LspMessage {
+    type Id = u64;
+
     fn read(r: &mut impl BufRead) -> Result<Option<LspMessage>, RpcError> {
         let text = match read_msg_text(r).map_err(|e| RpcError::Read(e))? {
             None => return Ok(None),
@@ -120,6 +122,21 @@ impl Message for LspMessage {
             _ => false,
         }
     }
+
+    fn id(&self) -> Option<u64> {
+        match self {
+            LspMessage::Request(r) => Some(r.id),
+            LspMessage::Response(r) => Some(r.id),
+            LspMessage::Notification(_) => None,
+        }
+    }
+
+    fn is_response(&self) -> bool {
+        match self {
+            LspMessage::Response(_) => true,
+            _ => false,
+        }
+    }
 }
 
 impl RawRequest {
@@ -0,0 +1,41 @@
+use super::types::ServerInfo;
+
+// Per-server protocol workarounds, chosen by `serverInfo.name`
+#[derive(Debug, Default, PartialEq)]
+pub struct Quirks {
+    // Inlay hints come from `rust-analyzer/inlayHints`, not the standard request
+    pub rust_analyzer_inlay_hints: bool,
+}
+
+impl Quirks {
+    pub fn for_server(server_info: Option<&ServerInfo>) -> Self {
+        match server_info.map(|info| info.name.as_str()) {
+            // Servers predating `serverInfo`, e.g. ra_lsp_server
+            None | Some("rust-analyzer") => Quirks {
+                rust_analyzer_inlay_hints: true,
+            },
+            Some(_) => Quirks::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn server_info(name: &str) -> ServerInfo {
+        ServerInfo {
+            name: name.to_owned(),
+            version: None,
+        }
+    }
+
+    #[test]
+    fn known_server_sets_quirks() {
+        let rust_analyzer = Quirks::for_server(Some(&server_info("rust-analyzer")));
+        let gopls = Quirks::for_server(Some(&server_info("gopls")));
+
+        assert_eq!(true, rust_analyzer.rust_analyzer_inlay_hints);
+        assert_eq!(false, gopls.rust_analyzer_inlay_hints);
+    }
+}
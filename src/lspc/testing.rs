@@ -0,0 +1,351 @@
+use std::{cell::RefCell, collections::HashMap};
+
+use crossbeam::channel::{self, Receiver, Sender};
+use lsp_types::{
+    CodeActionOrCommand, CodeLens, ColorInformation, ColorPresentation, Diagnostic,
+    DocumentHighlight, DocumentSymbolResponse, Hover, Location, MessageActionItem, Position,
+    ShowMessageParams, ShowMessageRequestParams, SignatureHelp, SymbolInformation,
+    TextDocumentIdentifier, TextEdit, WorkspaceEdit,
+};
+use url::Url;
+
+use super::{
+    types::{
+        CallHierarchyItem, CompletionItem, DocumentLink, InlayHint, PositionEncoding,
+        ProgressToken, SelectionRange, WorkspaceSymbol,
+    },
+    BufferId, CallDirection, Editor, EditorError, Event, GotoTarget, HoverMode, SemanticToken,
+    ServerReady,
+};
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct MockBufferId(pub u64);
+
+impl BufferId for MockBufferId {}
+
+// Editor recording what lspc asks it to do. Events sent with `script` are
+// received by `events`, to be handled in order.
+pub struct MockEditor {
+    pub capabilities: lsp_types::ClientCapabilities,
+    pub shown_diagnostics: Vec<(Url, Vec<Diagnostic>)>,
+    pub listed_diagnostics: Vec<Vec<Diagnostic>>,
+    pub hovers: Vec<Hover>,
+    pub shown_hovers: Vec<Vec<(Position, Hover)>>,
+    pub hover_modes: Vec<HoverMode>,
+    pub gotos: Vec<Location>,
+    pub goto_targets: Vec<GotoTarget>,
+    pub shown_references: Vec<Vec<Location>>,
+    pub highlighted_references: Vec<Vec<DocumentHighlight>>,
+    pub shown_calls: Vec<(CallDirection, Vec<(CallHierarchyItem, Location)>)>,
+    pub applied_edits: RefCell<Vec<(MockBufferId, Vec<TextEdit>)>>,
+    // Lines the edits were applied to
+    pub edited_lines: RefCell<Vec<Vec<String>>>,
+    pub saved_buffers: Vec<MockBufferId>,
+    pub ready_servers: Vec<ServerReady>,
+    pub messages: Vec<String>,
+    pub message_requests: Vec<String>,
+    pub progress: Vec<(ProgressToken, Option<String>)>,
+    pub expanded_snippets: Vec<(MockBufferId, String)>,
+    // Lines of the documents, missing lines are empty
+    pub document_lines: HashMap<Url, Vec<String>>,
+    pub buffer_lines: HashMap<MockBufferId, Vec<String>>,
+    // Typed for every input, the defaults offered are recorded
+    pub input: Option<String>,
+    pub input_defaults: Vec<String>,
+    event_sender: Sender<Event<MockBufferId>>,
+    event_receiver: Receiver<Event<MockBufferId>>,
+}
+
+impl Default for MockEditor {
+    fn default() -> Self {
+        let (event_sender, event_receiver) = channel::unbounded();
+
+        MockEditor {
+            capabilities: Default::default(),
+            shown_diagnostics: Vec::new(),
+            listed_diagnostics: Vec::new(),
+            hovers: Vec::new(),
+            shown_hovers: Vec::new(),
+            hover_modes: Vec::new(),
+            gotos: Vec::new(),
+            goto_targets: Vec::new(),
+            shown_references: Vec::new(),
+            highlighted_references: Vec::new(),
+            shown_calls: Vec::new(),
+            applied_edits: RefCell::new(Vec::new()),
+            edited_lines: RefCell::new(Vec::new()),
+            saved_buffers: Vec::new(),
+            ready_servers: Vec::new(),
+            messages: Vec::new(),
+            message_requests: Vec::new(),
+            progress: Vec::new(),
+            expanded_snippets: Vec::new(),
+            document_lines: HashMap::new(),
+            buffer_lines: HashMap::new(),
+            input: None,
+            input_defaults: Vec::new(),
+            event_sender,
+            event_receiver,
+        }
+    }
+}
+
+impl MockEditor {
+    pub fn script(&self, event: Event<MockBufferId>) {
+        self.event_sender.send(event).unwrap();
+    }
+}
+
+impl Editor for MockEditor {
+    type BufferId = MockBufferId;
+
+    fn events(&self) -> Receiver<Event<MockBufferId>> {
+        self.event_receiver.clone()
+    }
+    fn capabilities(&self) -> lsp_types::ClientCapabilities {
+        self.capabilities.clone()
+    }
+    fn say_hello(&self) -> Result<(), EditorError> {
+        Ok(())
+    }
+    fn server_ready(&mut self, ready: &ServerReady) -> Result<(), EditorError> {
+        self.ready_servers.push(ready.clone());
+        Ok(())
+    }
+    fn message(&mut self, msg: &str) -> Result<(), EditorError> {
+        self.messages.push(msg.to_owned());
+        Ok(())
+    }
+    fn show_hover(
+        &mut self,
+        _buf_id: MockBufferId,
+        _text_document: &TextDocumentIdentifier,
+        hover: &Hover,
+        mode: HoverMode,
+    ) -> Result<(), EditorError> {
+        self.hovers.push(hover.clone());
+        self.hover_modes.push(mode);
+        Ok(())
+    }
+    fn show_hovers(
+        &mut self,
+        _text_document: &TextDocumentIdentifier,
+        hovers: &[(Position, Hover)],
+    ) -> Result<(), EditorError> {
+        self.shown_hovers.push(hovers.to_vec());
+        Ok(())
+    }
+    fn inline_hints(
+        &mut self,
+        _buf_id: MockBufferId,
+        _text_document: &TextDocumentIdentifier,
+        _hints: &Vec<InlayHint>,
+    ) -> Result<(), EditorError> {
+        Ok(())
+    }
+    fn forward_notification(
+        &mut self,
+        _method: &str,
+        _params: &serde_json::Value,
+    ) -> Result<(), EditorError> {
+        Ok(())
+    }
+    fn show_message(&mut self, _params: &ShowMessageParams) -> Result<(), EditorError> {
+        Ok(())
+    }
+    fn show_progress(
+        &mut self,
+        token: &ProgressToken,
+        status: Option<&str>,
+    ) -> Result<(), EditorError> {
+        self.progress
+            .push((token.clone(), status.map(ToOwned::to_owned)));
+        Ok(())
+    }
+    // Always picks the first action
+    fn show_message_request(
+        &mut self,
+        params: &ShowMessageRequestParams,
+    ) -> Result<Option<MessageActionItem>, EditorError> {
+        self.message_requests.push(params.message.clone());
+        Ok(params
+            .actions
+            .as_ref()
+            .and_then(|actions| actions.first().cloned()))
+    }
+    fn show_references(&mut self, locations: &Vec<Location>) -> Result<(), EditorError> {
+        self.shown_references.push(locations.clone());
+        Ok(())
+    }
+    fn show_completions(&mut self, _items: &Vec<CompletionItem>) -> Result<(), EditorError> {
+        Ok(())
+    }
+    fn show_completion_item(&mut self, _item: &CompletionItem) -> Result<(), EditorError> {
+        Ok(())
+    }
+    fn expand_snippet(&mut self, buf_id: MockBufferId, snippet: &str) -> Result<(), EditorError> {
+        self.expanded_snippets.push((buf_id, snippet.to_owned()));
+        Ok(())
+    }
+    fn show_signature_help(&mut self, _help: &SignatureHelp) -> Result<(), EditorError> {
+        Ok(())
+    }
+    fn show_diagnostics(
+        &mut self,
+        text_document: &TextDocumentIdentifier,
+        diagnostics: &Vec<Diagnostic>,
+    ) -> Result<(), EditorError> {
+        self.shown_diagnostics
+            .push((text_document.uri.clone(), diagnostics.clone()));
+        Ok(())
+    }
+    fn show_code_actions(
+        &mut self,
+        _actions: &[CodeActionOrCommand],
+    ) -> Result<Option<usize>, EditorError> {
+        Ok(None)
+    }
+    fn set_selection_ranges(
+        &mut self,
+        _buf_id: MockBufferId,
+        _ranges: &[SelectionRange],
+    ) -> Result<(), EditorError> {
+        Ok(())
+    }
+    fn highlight_references(
+        &mut self,
+        _buf_id: MockBufferId,
+        highlights: &[DocumentHighlight],
+    ) -> Result<(), EditorError> {
+        self.highlighted_references.push(highlights.to_vec());
+        Ok(())
+    }
+    fn list_diagnostics(
+        &mut self,
+        _text_document: &TextDocumentIdentifier,
+        diagnostics: &[Diagnostic],
+    ) -> Result<(), EditorError> {
+        self.listed_diagnostics.push(diagnostics.to_vec());
+        Ok(())
+    }
+    fn show_document_symbols(
+        &mut self,
+        _symbols: &DocumentSymbolResponse,
+    ) -> Result<(), EditorError> {
+        Ok(())
+    }
+    fn show_call_hierarchy(
+        &mut self,
+        direction: CallDirection,
+        _item: &CallHierarchyItem,
+        calls: &[(CallHierarchyItem, Location)],
+    ) -> Result<(), EditorError> {
+        self.shown_calls.push((direction, calls.to_vec()));
+        Ok(())
+    }
+    fn show_workspace_symbols(
+        &mut self,
+        _symbols: &[SymbolInformation],
+    ) -> Result<(), EditorError> {
+        Ok(())
+    }
+    fn show_code_lens(
+        &mut self,
+        _buf_id: MockBufferId,
+        _lenses: &[CodeLens],
+    ) -> Result<(), EditorError> {
+        Ok(())
+    }
+    fn apply_semantic_tokens(
+        &mut self,
+        _buf_id: MockBufferId,
+        _tokens: &[SemanticToken],
+    ) -> Result<(), EditorError> {
+        Ok(())
+    }
+    fn show_document_links(
+        &mut self,
+        _buf_id: MockBufferId,
+        _links: &[DocumentLink],
+    ) -> Result<(), EditorError> {
+        Ok(())
+    }
+    fn show_document_colors(
+        &mut self,
+        _buf_id: MockBufferId,
+        _colors: &[ColorInformation],
+    ) -> Result<(), EditorError> {
+        Ok(())
+    }
+    fn choose_color_presentation(
+        &mut self,
+        presentations: &[ColorPresentation],
+    ) -> Result<Option<usize>, EditorError> {
+        Ok(if presentations.is_empty() {
+            None
+        } else {
+            Some(0)
+        })
+    }
+    fn choose_workspace_symbol(
+        &mut self,
+        symbols: &[WorkspaceSymbol],
+    ) -> Result<Option<usize>, EditorError> {
+        Ok(if symbols.is_empty() { None } else { Some(0) })
+    }
+    fn document_line(&self, uri: &Url, line: u64) -> Result<String, EditorError> {
+        Ok(self
+            .document_lines
+            .get(uri)
+            .and_then(|lines| lines.get(line as usize))
+            .cloned()
+            .unwrap_or_default())
+    }
+    fn input(&mut self, _prompt: &str, default: &str) -> Result<Option<String>, EditorError> {
+        self.input_defaults.push(default.to_owned());
+        Ok(self.input.clone())
+    }
+    fn buffer_lines(&self, buf_id: MockBufferId) -> Result<Vec<String>, EditorError> {
+        Ok(self.buffer_lines.get(&buf_id).cloned().unwrap_or_default())
+    }
+    fn goto(
+        &mut self,
+        location: &Location,
+        _encoding: PositionEncoding,
+        target: GotoTarget,
+    ) -> Result<(), EditorError> {
+        self.gotos.push(location.clone());
+        self.goto_targets.push(target);
+        Ok(())
+    }
+    fn apply_edits(
+        &self,
+        buf_id: MockBufferId,
+        lines: &Vec<String>,
+        edits: &Vec<TextEdit>,
+        _encoding: PositionEncoding,
+    ) -> Result<(), EditorError> {
+        self.applied_edits
+            .borrow_mut()
+            .push((buf_id, edits.clone()));
+        self.edited_lines.borrow_mut().push(lines.clone());
+        Ok(())
+    }
+    fn apply_workspace_edit(
+        &mut self,
+        _edit: &WorkspaceEdit,
+        _encoding: PositionEncoding,
+    ) -> Result<(), EditorError> {
+        Ok(())
+    }
+    fn will_save_done(&mut self, buf_id: MockBufferId) -> Result<(), EditorError> {
+        self.saved_buffers.push(buf_id);
+        Ok(())
+    }
+    fn track_all_buffers(&self) -> Result<(), EditorError> {
+        Ok(())
+    }
+    fn watch_file_events(&mut self, _buf_id: MockBufferId) -> Result<(), EditorError> {
+        Ok(())
+    }
+}
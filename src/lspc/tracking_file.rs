@@ -1,11 +1,107 @@
 use lsp_types::{self as lsp};
 use ropey::Rope;
-use std::time::{Duration, Instant};
+use std::{
+    cell::RefCell,
+    rc::Rc,
+    time::{Duration, Instant},
+};
 use url::Url;
 
+use super::types::{PositionEncoding, SemanticTokens};
+
+// Changes closer than this to the previous one are counted as a burst
+const BURST_INTERVAL: Duration = Duration::from_millis(100);
+
+// Char offsets of the starts of the lines of a document. Lines of the editor
+// and the servers only break on `\n`, the rope's own line index also breaks
+// them on a lone `\r`, U+2028 and the other Unicode separators. The content is
+// walked once to build it, a position then only walks its own line.
+pub struct LineIndex {
+    line_starts: Vec<usize>,
+}
+
+impl LineIndex {
+    pub fn new(content: &Rope) -> Self {
+        let mut line_starts = vec![0];
+        line_starts.extend(
+            content
+                .chars()
+                .enumerate()
+                .filter(|(_, c)| *c == '\n')
+                .map(|(index, _)| index + 1),
+        );
+
+        LineIndex { line_starts }
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.line_starts.len()
+    }
+
+    // Keeps the index up to date once the chars `start..end` of the content
+    // are replaced by `text`, without walking the rest of the content
+    pub fn replace(&mut self, start: usize, end: usize, text: &str) {
+        let first_removed = match self.line_starts.binary_search(&(start + 1)) {
+            Ok(index) | Err(index) => index,
+        };
+        let first_kept = match self.line_starts.binary_search(&(end + 1)) {
+            Ok(index) | Err(index) => index,
+        };
+        let inserted_len = text.chars().count();
+        for line_start in &mut self.line_starts[first_kept..] {
+            *line_start = *line_start - (end - start) + inserted_len;
+        }
+        let inserted = text
+            .chars()
+            .enumerate()
+            .filter(|(_, c)| *c == '\n')
+            .map(|(index, _)| start + index + 1);
+        self.line_starts.splice(first_removed..first_kept, inserted);
+    }
+
+    // Char index of the start of `line`, `None` past the last line
+    pub fn line_to_char(&self, line: usize) -> Option<usize> {
+        self.line_starts.get(line).cloned()
+    }
+
+    // Char index of `pos` in `content`, the indexed rope. `pos.character` is
+    // counted in code units of `encoding`. Positions past the end of their line
+    // are clamped to it, before the `\r` of a CRLF line, positions past the
+    // last line to the end of the content.
+    pub fn position_to_char(
+        &self,
+        content: &Rope,
+        pos: lsp::Position,
+        encoding: PositionEncoding,
+    ) -> usize {
+        let line_start = match self.line_to_char(pos.line as usize) {
+            Some(line_start) => line_start,
+            None => return content.len_chars(),
+        };
+
+        let character = pos.character as usize;
+        let mut chars = content.chars_at(line_start).peekable();
+        let mut offset = 0;
+        let mut column = 0;
+        while offset < character {
+            match chars.next() {
+                None | Some('\n') => break,
+                Some('\r') if chars.peek() == Some(&'\n') => break,
+                Some(c) => {
+                    offset += encoding.char_len(c);
+                    column += 1;
+                }
+            }
+        }
+
+        line_start + column
+    }
+}
+
 enum SyncData {
     Incremental(lsp::DidChangeTextDocumentParams),
-    Full(Rope),
+    // The index is kept along the content so changes don't walk all of it
+    Full(Rope, LineIndex),
     None,
 }
 
@@ -13,13 +109,27 @@ pub struct TrackingFile {
     pub handler_id: u64,
     pub sent_did_open: bool,
     pub scheduled_sync_at: Option<Instant>,
+    // Request id of the hover not answered yet
+    pub pending_hover: Option<u64>,
+    last_change_at: Option<Instant>,
+    burst_len: usize,
     version: i64,
     uri: Url,
+    language_id: String,
     sync_data: SyncData,
+    // Last tokens received, deltas are applied on them
+    pub semantic_tokens: Rc<RefCell<Option<SemanticTokens>>>,
+    // Id of the last pulled diagnostics report
+    pub diagnostic_result_id: Rc<RefCell<Option<String>>>,
 }
 
 impl TrackingFile {
-    pub fn new(handler_id: u64, uri: Url, sync_kind: lsp::TextDocumentSyncKind) -> Self {
+    pub fn new(
+        handler_id: u64,
+        uri: Url,
+        language_id: String,
+        sync_kind: lsp::TextDocumentSyncKind,
+    ) -> Self {
         let sync_data = match sync_kind {
             lsp::TextDocumentSyncKind::None => SyncData::None,
             lsp::TextDocumentSyncKind::Incremental => {
@@ -31,16 +141,49 @@ impl TrackingFile {
                     content_changes: Vec::new(),
                 })
             }
-            lsp::TextDocumentSyncKind::Full => SyncData::Full(Rope::new()),
+            lsp::TextDocumentSyncKind::Full => {
+                let content = Rope::new();
+                let line_index = LineIndex::new(&content);
+                SyncData::Full(content, line_index)
+            }
         };
 
         TrackingFile {
             handler_id,
             sent_did_open: false,
             scheduled_sync_at: None,
+            pending_hover: None,
+            last_change_at: None,
+            burst_len: 0,
             version: 0,
             uri,
+            language_id,
             sync_data,
+            semantic_tokens: Rc::new(RefCell::new(None)),
+            diagnostic_result_id: Rc::new(RefCell::new(None)),
+        }
+    }
+
+    pub fn version(&self) -> i64 {
+        self.version
+    }
+
+    // Only known when the whole document is synced
+    pub fn text(&self) -> Option<String> {
+        match self.sync_data {
+            SyncData::Full(ref content, _) => Some(content.to_string()),
+            _ => None,
+        }
+    }
+
+    pub fn did_open_params(&self, version: i64, text: String) -> lsp::DidOpenTextDocumentParams {
+        lsp::DidOpenTextDocumentParams {
+            text_document: lsp::TextDocumentItem {
+                uri: self.uri.clone(),
+                language_id: self.language_id.clone(),
+                version,
+                text,
+            },
         }
     }
 
@@ -66,22 +209,54 @@ impl TrackingFile {
                     changes.content_changes.push(content_change.clone());
                 }
             }
-            SyncData::Full(ref mut content) => {
-                println!("Before sync content: {:?}", content);
-                println!("Sync content change: {:?}", content_change);
-                if content_change.range.is_none() {
-                    let new_rope = Rope::from_str(&content_change.text);
-                    std::mem::replace(content, new_rope);
-                } else {
-                    let start_line = content_change.range.unwrap().start.line as usize;
-                    let end_line = content_change.range.unwrap().end.line as isize;
-                    let end_line = end_line as usize;
-                    let start_char = content.line_to_char(start_line);
-                    let end_char = content.line_to_char(end_line);
+            SyncData::Full(ref mut content, ref mut line_index) => {
+                log::debug!("Before sync content: {:?}", content);
+                log::debug!("Sync content change: {:?}", content_change);
+                if let Some(range) = content_change.range {
+                    // Every line of the text ends with a newline
+                    let line_count = line_index.line_count();
+                    let start_line = (range.start.line as usize).min(line_count);
+                    let end_line = (range.end.line as usize).min(line_count);
+                    let (start_char, end_char, text) = if end_line == line_count {
+                        // The last line has no trailing newline, replacing the
+                        // last lines takes the one ending the line before them
+                        let text = &content_change.text;
+                        let text = if text.ends_with('\n') {
+                            &text[..text.len() - 1]
+                        } else {
+                            text
+                        };
+                        if start_line == 0 {
+                            (0, content.len_chars(), text.to_owned())
+                        } else {
+                            let start_char = match line_index.line_to_char(start_line) {
+                                Some(line_start) => line_start - 1,
+                                None => content.len_chars(),
+                            };
+                            let text = if content_change.text.is_empty() {
+                                String::new()
+                            } else {
+                                format!("\n{}", text)
+                            };
+                            (start_char, content.len_chars(), text)
+                        }
+                    } else {
+                        // Changes of the editor cover whole lines, the
+                        // encoding of the character doesn't matter
+                        let encoding = PositionEncoding::default();
+                        let start_char =
+                            line_index.position_to_char(content, range.start, encoding);
+                        let end_char = line_index.position_to_char(content, range.end, encoding);
+                        (start_char, end_char, content_change.text.clone())
+                    };
                     content.remove(start_char..end_char);
-                    content.insert(start_char, &content_change.text);
+                    content.insert(start_char, &text);
+                    line_index.replace(start_char, end_char, &text);
+                } else {
+                    *content = Rope::from_str(&content_change.text);
+                    *line_index = LineIndex::new(content);
                 }
-                println!("After sync content: {:?}", content);
+                log::debug!("After sync content: {:?}", content);
             }
             SyncData::None => {}
         }
@@ -106,7 +281,7 @@ impl TrackingFile {
                     None
                 }
             }
-            SyncData::Full(ref mut content) => {
+            SyncData::Full(ref content, _) => {
                 sync_content
                     .content_changes
                     .push(lsp::TextDocumentContentChangeEvent {
@@ -120,11 +295,29 @@ impl TrackingFile {
         }
     }
 
-    pub fn delay_sync_in(&mut self, duration: Duration) {
-        if let None = self.scheduled_sync_at {
-            self.scheduled_sync_at = Some(Instant::now() + duration);
+    // Sync is scheduled `duration` after the first pending change. Once
+    // `burst_threshold` changes arrive in a burst (e.g. a large paste), it's
+    // pushed back on every change so only one sync is sent after it settles.
+    pub fn delay_sync_in(&mut self, now: Instant, duration: Duration, burst_threshold: usize) {
+        let in_burst = self
+            .last_change_at
+            .map(|last_change_at| now.duration_since(last_change_at) <= BURST_INTERVAL)
+            .unwrap_or(false);
+        self.burst_len = if in_burst { self.burst_len + 1 } else { 1 };
+        self.last_change_at = Some(now);
+
+        if self.burst_len >= burst_threshold {
+            self.scheduled_sync_at = Some(now + duration);
+        } else if let None = self.scheduled_sync_at {
+            self.scheduled_sync_at = Some(now + duration);
         }
     }
+
+    pub fn sync_due(&self, now: Instant) -> bool {
+        self.scheduled_sync_at
+            .map(|instant| instant <= now)
+            .unwrap_or(false)
+    }
 }
 
 #[cfg(test)]
@@ -140,6 +333,7 @@ mod test {
         let mut tracking_file = TrackingFile::new(
             1,
             Url::from_file_path(file_path).unwrap(),
+            "rust".to_owned(),
             lsp::TextDocumentSyncKind::Full,
         );
         let change_event = lsp::TextDocumentContentChangeEvent {
@@ -176,7 +370,7 @@ mod test {
                 },
             }),
             range_length: None,
-            text: "line1\nline2\nline3".to_owned(),
+            text: "line1\nline2\nline3\n".to_owned(),
         };
         tracking_file.track_change(6, &change_event);
 
@@ -184,7 +378,10 @@ mod test {
 
         assert_eq!(6, sync_request.text_document.version.unwrap());
         assert_eq!(1, sync_request.content_changes.len());
-        assert_eq!("line1\nline2\nline3", sync_request.content_changes[0].text);
+        assert_eq!(
+            "line1\nline2\nline3\n",
+            sync_request.content_changes[0].text
+        );
 
         // Remove two lines
         // nvim_buf_lines_event[{buf}, {changedtick}, 1, 3, [], v:false]
@@ -210,4 +407,254 @@ mod test {
         assert_eq!(1, sync_request.content_changes.len());
         assert_eq!("line1\n", sync_request.content_changes[0].text);
     }
+
+    #[test]
+    fn tracking_file_full_delete_through_end() {
+        #[cfg(not(target_os = "windows"))]
+        let file_path = "/a/b/c/d";
+        #[cfg(target_os = "windows")]
+        let file_path = r#"C:\\a\b\d"#;
+
+        let mut tracking_file = TrackingFile::new(
+            1,
+            Url::from_file_path(file_path).unwrap(),
+            "rust".to_owned(),
+            lsp::TextDocumentSyncKind::Full,
+        );
+        let lines_event = |start, end, text: &str| lsp::TextDocumentContentChangeEvent {
+            range: Some(lsp::Range::new(
+                lsp::Position::new(start, 0),
+                lsp::Position::new(end, 0),
+            )),
+            range_length: None,
+            text: text.to_owned(),
+        };
+        tracking_file.track_change(
+            1,
+            &lsp::TextDocumentContentChangeEvent {
+                range: None,
+                range_length: None,
+                text: "line1\nline2\nline3".to_owned(),
+            },
+        );
+
+        // Range past the last line, e.g. `:2,$d`
+        // nvim_buf_lines_event[{buf}, {changedtick}, 1, 5, [], v:false]
+        tracking_file.track_change(2, &lines_event(1, 5, ""));
+        assert_eq!(Some("line1".to_owned()), tracking_file.text());
+
+        // Line appended after the last one
+        // nvim_buf_lines_event[{buf}, {changedtick}, 1, 1, ["line2"], v:false]
+        tracking_file.track_change(3, &lines_event(1, 1, "line2\n"));
+        assert_eq!(Some("line1\nline2".to_owned()), tracking_file.text());
+
+        // Last line replaced
+        // nvim_buf_lines_event[{buf}, {changedtick}, 1, 2, ["last"], v:false]
+        tracking_file.track_change(4, &lines_event(1, 2, "last\n"));
+        assert_eq!(Some("line1\nlast".to_owned()), tracking_file.text());
+
+        // Lines inserted before the first one
+        // nvim_buf_lines_event[{buf}, {changedtick}, 0, 0, ["a", "b"], v:false]
+        tracking_file.track_change(5, &lines_event(0, 0, "a\nb\n"));
+        assert_eq!(Some("a\nb\nline1\nlast".to_owned()), tracking_file.text());
+
+        // Line replaced in the middle
+        // nvim_buf_lines_event[{buf}, {changedtick}, 1, 2, ["x"], v:false]
+        tracking_file.track_change(6, &lines_event(1, 2, "x\n"));
+        assert_eq!(Some("a\nx\nline1\nlast".to_owned()), tracking_file.text());
+
+        // Line cleared in the middle is kept, deleted one isn't
+        // nvim_buf_lines_event[{buf}, {changedtick}, 1, 2, [""], v:false]
+        tracking_file.track_change(7, &lines_event(1, 2, "\n"));
+        assert_eq!(Some("a\n\nline1\nlast".to_owned()), tracking_file.text());
+        // nvim_buf_lines_event[{buf}, {changedtick}, 1, 2, [], v:false]
+        tracking_file.track_change(8, &lines_event(1, 2, ""));
+        assert_eq!(Some("a\nline1\nlast".to_owned()), tracking_file.text());
+
+        // Last line cleared
+        // nvim_buf_lines_event[{buf}, {changedtick}, 2, 3, [""], v:false]
+        tracking_file.track_change(9, &lines_event(2, 3, "\n"));
+        assert_eq!(Some("a\nline1\n".to_owned()), tracking_file.text());
+    }
+
+    #[test]
+    fn line_index_follows_replacements() {
+        let mut content = Rope::from_str("ab\ncd\nef\n");
+        let mut line_index = LineIndex::new(&content);
+        let mut replace = |start, end, text: &str| {
+            content.remove(start..end);
+            content.insert(start, text);
+            line_index.replace(start, end, text);
+            assert_eq!(LineIndex::new(&content).line_starts, line_index.line_starts);
+        };
+
+        // Newline removed, then lines inserted in the middle of one
+        replace(1, 4, "");
+        replace(1, 1, "x\ny\nz");
+        // Across lines, at the end and at the start
+        replace(2, 7, "\n");
+        replace(5, 7, "\n\n");
+        replace(0, 0, "\n");
+    }
+
+    #[test]
+    fn tracking_file_full_changes_on_large_file() {
+        let mut tracking_file = TrackingFile::new(
+            1,
+            Url::parse("file:///large.rs").unwrap(),
+            "rust".to_owned(),
+            lsp::TextDocumentSyncKind::Full,
+        );
+        let lines = (0..50_000)
+            .map(|i| format!("let   x{} = {};\n", i, i))
+            .collect::<String>();
+        tracking_file.track_change(
+            1,
+            &lsp::TextDocumentContentChangeEvent {
+                range: None,
+                range_length: None,
+                text: lines[..lines.len() - 1].to_owned(),
+            },
+        );
+
+        // One lines event per line, as when a whole buffer is reindented
+        for line in 0..50_000 {
+            tracking_file.track_change(
+                line as i64 + 2,
+                &lsp::TextDocumentContentChangeEvent {
+                    range: Some(lsp::Range::new(
+                        lsp::Position::new(line, 0),
+                        lsp::Position::new(line + 1, 0),
+                    )),
+                    range_length: None,
+                    text: format!("let x{} = {};\n", line, line),
+                },
+            );
+        }
+
+        let text = tracking_file.text().unwrap();
+        let mut lines = text.split('\n');
+        assert_eq!(Some("let x0 = 0;"), lines.next());
+        assert_eq!(Some("let x49999 = 49999;"), lines.last());
+    }
+
+    #[test]
+    fn position_to_char_counts_code_units_of_encoding() {
+        let content = Rope::from_str("let 😀 = 1;\nx");
+        let index = LineIndex::new(&content);
+        let position = |line, character| lsp::Position::new(line, character);
+        let utf16 = PositionEncoding::Utf16;
+
+        // `😀` is two UTF-16 code units, four bytes but one char
+        assert_eq!(5, index.position_to_char(&content, position(0, 6), utf16));
+        assert_eq!(
+            5,
+            index.position_to_char(&content, position(0, 8), PositionEncoding::Utf8)
+        );
+        assert_eq!(
+            5,
+            index.position_to_char(&content, position(0, 5), PositionEncoding::Utf32)
+        );
+        // Past the end of the line, before its newline
+        assert_eq!(10, index.position_to_char(&content, position(0, 42), utf16));
+        assert_eq!(11, index.position_to_char(&content, position(1, 0), utf16));
+        assert_eq!(12, index.position_to_char(&content, position(5, 0), utf16));
+    }
+
+    #[test]
+    fn position_to_char_only_breaks_lines_on_newline() {
+        let content = Rope::from_str("a\u{2028}b\rc\nd");
+        let index = LineIndex::new(&content);
+        let position = |line, character| lsp::Position::new(line, character);
+        let utf16 = PositionEncoding::Utf16;
+
+        // U+2028 and a lone `\r` are characters of the line
+        assert_eq!(4, index.position_to_char(&content, position(0, 4), utf16));
+        assert_eq!(5, index.position_to_char(&content, position(0, 42), utf16));
+        assert_eq!(6, index.position_to_char(&content, position(1, 0), utf16));
+        assert_eq!(7, index.position_to_char(&content, position(2, 0), utf16));
+    }
+
+    #[test]
+    fn position_to_char_clamps_before_crlf() {
+        let content = Rope::from_str("ab\r\ncd\r\n");
+        let index = LineIndex::new(&content);
+        let position = |line, character| lsp::Position::new(line, character);
+        let utf16 = PositionEncoding::Utf16;
+
+        assert_eq!(2, index.position_to_char(&content, position(0, 2), utf16));
+        assert_eq!(2, index.position_to_char(&content, position(0, 42), utf16));
+        assert_eq!(4, index.position_to_char(&content, position(1, 0), utf16));
+        assert_eq!(6, index.position_to_char(&content, position(1, 42), utf16));
+        assert_eq!(8, index.position_to_char(&content, position(2, 0), utf16));
+    }
+
+    #[test]
+    fn did_open_params_use_file_language_id() {
+        #[cfg(not(target_os = "windows"))]
+        let (ts_path, tsx_path) = ("/a/b/index.ts", "/a/b/app.tsx");
+        #[cfg(target_os = "windows")]
+        let (ts_path, tsx_path) = (r#"C:\\a\b\index.ts"#, r#"C:\\a\b\app.tsx"#);
+
+        let ts_file = TrackingFile::new(
+            1,
+            Url::from_file_path(ts_path).unwrap(),
+            "typescript".to_owned(),
+            lsp::TextDocumentSyncKind::Full,
+        );
+        let tsx_file = TrackingFile::new(
+            1,
+            Url::from_file_path(tsx_path).unwrap(),
+            "typescriptreact".to_owned(),
+            lsp::TextDocumentSyncKind::Full,
+        );
+
+        let ts_params = ts_file.did_open_params(1, "".to_owned());
+        let tsx_params = tsx_file.did_open_params(1, "".to_owned());
+
+        assert_eq!("typescript", ts_params.text_document.language_id);
+        assert_eq!("typescriptreact", tsx_params.text_document.language_id);
+    }
+
+    #[test]
+    fn burst_of_changes_is_synced_once() {
+        #[cfg(not(target_os = "windows"))]
+        let file_path = "/a/b/c/d";
+        #[cfg(target_os = "windows")]
+        let file_path = r#"C:\\a\b\d"#;
+
+        let mut tracking_file = TrackingFile::new(
+            1,
+            Url::from_file_path(file_path).unwrap(),
+            "rust".to_owned(),
+            lsp::TextDocumentSyncKind::Full,
+        );
+        let delay = Duration::from_millis(500);
+        let start = Instant::now();
+        let mut syncs = 0;
+
+        // Lasts longer than the sync delay
+        for i in 0..30 {
+            let now = start + Duration::from_millis(i * 30);
+            let change_event = lsp::TextDocumentContentChangeEvent {
+                range: None,
+                range_length: None,
+                text: format!("line{}", i),
+            };
+            tracking_file.track_change(i as i64, &change_event);
+            tracking_file.delay_sync_in(now, delay, 10);
+
+            if tracking_file.sync_due(now) {
+                tracking_file.fetch_pending_changes();
+                syncs += 1;
+            }
+        }
+        assert_eq!(0, syncs);
+
+        let settled = start + Duration::from_millis(29 * 30) + delay;
+        assert!(tracking_file.sync_due(settled));
+        let sync_request = tracking_file.fetch_pending_changes().unwrap();
+        assert_eq!(29, sync_request.text_document.version.unwrap());
+        assert_eq!("line29", sync_request.content_changes[0].text);
+    }
 }
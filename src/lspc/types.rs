@@ -1,5 +1,214 @@
-use lsp_types::{request::Request, Range, TextDocumentIdentifier};
+use lsp_types::{
+    self as lsp, notification::Notification, request::Request, Diagnostic, DocumentLinkParams,
+    Location, Position, Range, SymbolKind, TextDocumentIdentifier, WorkspaceSymbolParams,
+};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use url::Url;
+
+// `initialize` with raw client capabilities, so capabilities newer than
+// lsp-types can be advertised
+pub enum Initialize {}
+
+impl Request for Initialize {
+    type Params = InitializeParams;
+    type Result = InitializeResult;
+    const METHOD: &'static str = "initialize";
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct InitializeParams {
+    pub process_id: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub root_path: Option<String>,
+    pub root_uri: Option<Url>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub initialization_options: Option<Value>,
+    pub capabilities: Value,
+    pub trace: TraceValue,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub workspace_folders: Option<Vec<lsp::WorkspaceFolder>>,
+}
+
+// Verbosity of the server's `$/logTrace` notifications
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TraceValue {
+    Off,
+    Messages,
+    Verbose,
+}
+
+impl Default for TraceValue {
+    fn default() -> Self {
+        TraceValue::Off
+    }
+}
+
+// Unit of `Position.character` negotiated with the server (LSP 3.17)
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PositionEncoding {
+    #[serde(rename = "utf-8")]
+    Utf8,
+    #[serde(rename = "utf-16")]
+    Utf16,
+    #[serde(rename = "utf-32")]
+    Utf32,
+}
+
+impl Default for PositionEncoding {
+    fn default() -> Self {
+        PositionEncoding::Utf16
+    }
+}
+
+impl PositionEncoding {
+    // Code units of `c` in this encoding
+    pub fn char_len(self, c: char) -> usize {
+        match self {
+            PositionEncoding::Utf8 => c.len_utf8(),
+            PositionEncoding::Utf16 => c.len_utf16(),
+            PositionEncoding::Utf32 => 1,
+        }
+    }
+
+    // Byte index of `s` after `character` code units, clamped to its length
+    pub fn byte_offset(self, s: &str, character: usize) -> usize {
+        let mut offset = 0;
+        for (byte_offset, c) in s.char_indices() {
+            if offset >= character {
+                return byte_offset;
+            }
+            offset += self.char_len(c);
+        }
+        s.len()
+    }
+
+    // Code units of `s` before the byte index `byte_offset`, bytes past the
+    // end of `s` count one unit each
+    pub fn character(self, s: &str, byte_offset: usize) -> usize {
+        let mut character = 0;
+        for (offset, c) in s.char_indices() {
+            if offset >= byte_offset {
+                return character;
+            }
+            character += self.char_len(c);
+        }
+        character + byte_offset.saturating_sub(s.len())
+    }
+}
+
+pub enum LogTrace {}
+
+impl Notification for LogTrace {
+    type Params = LogTraceParams;
+    const METHOD: &'static str = "$/logTrace";
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LogTraceParams {
+    pub message: String,
+    // Only sent with the `verbose` trace
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verbose: Option<String>,
+}
+
+pub enum SetTrace {}
+
+impl Notification for SetTrace {
+    type Params = SetTraceParams;
+    const METHOD: &'static str = "$/setTrace";
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SetTraceParams {
+    pub value: TraceValue,
+}
+
+// `InitializeResult` with the server's name and version (LSP 3.15).
+// Capabilities are kept raw, lsp-types drops the ones it doesn't know.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct InitializeResult {
+    pub capabilities: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server_info: Option<ServerInfo>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ServerInfo {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+}
+
+// `textDocument/publishDiagnostics` with the document version (LSP 3.15)
+pub enum PublishDiagnostics {}
+
+impl Notification for PublishDiagnostics {
+    type Params = PublishDiagnosticsParams;
+    const METHOD: &'static str = "textDocument/publishDiagnostics";
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PublishDiagnosticsParams {
+    pub uri: Url,
+    pub diagnostics: Vec<Diagnostic>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<i64>,
+}
+
+// `textDocument/completion` and `completionItem/resolve` with items carrying
+// `labelDetails` (LSP 3.17)
+pub enum CompletionRequest {}
+
+impl Request for CompletionRequest {
+    type Params = lsp::CompletionParams;
+    type Result = Option<CompletionResponse>;
+    const METHOD: &'static str = "textDocument/completion";
+}
+
+pub enum ResolveCompletionItem {}
+
+impl Request for ResolveCompletionItem {
+    type Params = CompletionItem;
+    type Result = CompletionItem;
+    const METHOD: &'static str = "completionItem/resolve";
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum CompletionResponse {
+    Array(Vec<CompletionItem>),
+    List(CompletionList),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CompletionList {
+    pub is_incomplete: bool,
+    pub items: Vec<CompletionItem>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CompletionItem {
+    #[serde(flatten)]
+    pub base: lsp::CompletionItem,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label_details: Option<CompletionItemLabelDetails>,
+}
+
+// Shown right after the label, e.g. `detail`: the signature and
+// `description`: the return type
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CompletionItemLabelDetails {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
 
 pub enum InlayHints {}
 
@@ -18,6 +227,7 @@ pub struct InlayHintsParams {
 #[derive(Debug, PartialEq, Eq, Deserialize, Serialize)]
 pub enum InlayKind {
     TypeHint,
+    ParameterHint,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -26,3 +236,470 @@ pub struct InlayHint {
     pub kind: InlayKind,
     pub label: String,
 }
+
+// Standard `textDocument/inlayHint` (LSP 3.17), for servers other than
+// rust-analyzer
+pub enum InlayHintRequest {}
+
+impl Request for InlayHintRequest {
+    type Params = InlayHintParams;
+    type Result = Option<Vec<StandardInlayHint>>;
+    const METHOD: &'static str = "textDocument/inlayHint";
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct InlayHintParams {
+    pub text_document: TextDocumentIdentifier,
+    pub range: Range,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct StandardInlayHint {
+    pub position: Position,
+    pub label: InlayHintLabel,
+    // 1: type, 2: parameter
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub padding_left: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub padding_right: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum InlayHintLabel {
+    String(String),
+    Parts(Vec<InlayHintLabelPart>),
+}
+
+// A part may point to e.g. the definition of the hinted type
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct InlayHintLabelPart {
+    pub value: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<Location>,
+}
+
+// `workspace/symbol` answered with `WorkspaceSymbol`s (LSP 3.17)
+pub enum WorkspaceSymbolRequest {}
+
+impl Request for WorkspaceSymbolRequest {
+    type Params = WorkspaceSymbolParams;
+    type Result = Option<Vec<WorkspaceSymbol>>;
+    const METHOD: &'static str = "workspace/symbol";
+}
+
+pub enum WorkspaceSymbolResolve {}
+
+impl Request for WorkspaceSymbolResolve {
+    type Params = WorkspaceSymbol;
+    type Result = WorkspaceSymbol;
+    const METHOD: &'static str = "workspaceSymbol/resolve";
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkspaceSymbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub container_name: Option<String>,
+    pub location: WorkspaceSymbolLocation,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+// Lazy symbols only carry the uri, the range is filled by
+// `workspaceSymbol/resolve`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum WorkspaceSymbolLocation {
+    Location(Location),
+    Uri { uri: Url },
+}
+
+pub enum SelectionRangeRequest {}
+
+impl Request for SelectionRangeRequest {
+    type Params = SelectionRangeParams;
+    type Result = Option<Vec<SelectionRange>>;
+    const METHOD: &'static str = "textDocument/selectionRange";
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SelectionRangeParams {
+    pub text_document: TextDocumentIdentifier,
+    pub positions: Vec<Position>,
+}
+
+// Linked list of ranges, each `parent` contains its child
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SelectionRange {
+    pub range: Range,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent: Option<Box<SelectionRange>>,
+}
+
+// `textDocument/documentLink` with optional targets, filled by
+// `documentLink/resolve` (LSP 3.15)
+pub enum DocumentLinkRequest {}
+
+impl Request for DocumentLinkRequest {
+    type Params = DocumentLinkParams;
+    type Result = Option<Vec<DocumentLink>>;
+    const METHOD: &'static str = "textDocument/documentLink";
+}
+
+pub enum DocumentLinkResolve {}
+
+impl Request for DocumentLinkResolve {
+    type Params = DocumentLink;
+    type Result = DocumentLink;
+    const METHOD: &'static str = "documentLink/resolve";
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DocumentLink {
+    pub range: Range,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<Url>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tooltip: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+// `workspace/didCreateFiles`, `workspace/didRenameFiles` and
+// `workspace/didDeleteFiles` (LSP 3.16)
+pub enum DidCreateFiles {}
+
+impl Notification for DidCreateFiles {
+    type Params = CreateFilesParams;
+    const METHOD: &'static str = "workspace/didCreateFiles";
+}
+
+pub enum DidRenameFiles {}
+
+impl Notification for DidRenameFiles {
+    type Params = RenameFilesParams;
+    const METHOD: &'static str = "workspace/didRenameFiles";
+}
+
+pub enum DidDeleteFiles {}
+
+impl Notification for DidDeleteFiles {
+    type Params = DeleteFilesParams;
+    const METHOD: &'static str = "workspace/didDeleteFiles";
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CreateFilesParams {
+    pub files: Vec<FileCreate>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FileCreate {
+    pub uri: Url,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RenameFilesParams {
+    pub files: Vec<FileRename>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct FileRename {
+    pub old_uri: Url,
+    pub new_uri: Url,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DeleteFilesParams {
+    pub files: Vec<FileDelete>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FileDelete {
+    pub uri: Url,
+}
+
+// Registered statically in `workspace.fileOperations` or dynamically
+// with the notification's method
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FileOperationRegistrationOptions {
+    pub filters: Vec<FileOperationFilter>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FileOperationFilter {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scheme: Option<String>,
+    pub pattern: FileOperationPattern,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FileOperationPattern {
+    pub glob: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub matches: Option<FileOperationPatternKind>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub options: Option<FileOperationPatternOptions>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum FileOperationPatternKind {
+    File,
+    Folder,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct FileOperationPatternOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ignore_case: Option<bool>,
+}
+
+// Registered dynamically with `workspace/didChangeWatchedFiles`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DidChangeWatchedFilesRegistrationOptions {
+    pub watchers: Vec<FileSystemWatcher>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct FileSystemWatcher {
+    pub glob_pattern: String,
+    // Bitmask of create (1), change (2) and delete (4), all if missing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind: Option<u8>,
+}
+
+// `textDocument/semanticTokens/full` (LSP 3.16)
+pub enum SemanticTokensFullRequest {}
+
+impl Request for SemanticTokensFullRequest {
+    type Params = SemanticTokensParams;
+    type Result = Option<SemanticTokens>;
+    const METHOD: &'static str = "textDocument/semanticTokens/full";
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SemanticTokensParams {
+    pub text_document: TextDocumentIdentifier,
+}
+
+// `data` is 5 integers per token, positions are relative to the previous one
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SemanticTokens {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result_id: Option<String>,
+    pub data: Vec<u64>,
+}
+
+// Server asking to report progress with `token`
+pub enum WorkDoneProgressCreate {}
+
+impl Request for WorkDoneProgressCreate {
+    type Params = WorkDoneProgressCreateParams;
+    type Result = ();
+    const METHOD: &'static str = "window/workDoneProgress/create";
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct WorkDoneProgressCreateParams {
+    pub token: ProgressToken,
+}
+
+pub enum Progress {}
+
+impl Notification for Progress {
+    type Params = ProgressParams;
+    const METHOD: &'static str = "$/progress";
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(untagged)]
+pub enum ProgressToken {
+    Number(i64),
+    String(String),
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ProgressParams {
+    pub token: ProgressToken,
+    pub value: WorkDoneProgress,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum WorkDoneProgress {
+    Begin {
+        title: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        message: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        percentage: Option<u64>,
+    },
+    Report {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        message: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        percentage: Option<u64>,
+    },
+    End {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        message: Option<String>,
+    },
+}
+
+// Diagnostics pulled by the client, the server may answer that they didn't
+// change since `previous_result_id`
+pub enum DocumentDiagnosticRequest {}
+
+impl Request for DocumentDiagnosticRequest {
+    type Params = DocumentDiagnosticParams;
+    type Result = DocumentDiagnosticReport;
+    const METHOD: &'static str = "textDocument/diagnostic";
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DocumentDiagnosticParams {
+    pub text_document: TextDocumentIdentifier,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub identifier: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous_result_id: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum DocumentDiagnosticReport {
+    #[serde(rename_all = "camelCase")]
+    Full {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        result_id: Option<String>,
+        items: Vec<Diagnostic>,
+    },
+    #[serde(rename_all = "camelCase")]
+    Unchanged { result_id: String },
+}
+
+// Answered with full tokens when the server can't compute a delta
+pub enum SemanticTokensFullDeltaRequest {}
+
+impl Request for SemanticTokensFullDeltaRequest {
+    type Params = SemanticTokensDeltaParams;
+    type Result = Option<SemanticTokensFullDeltaResult>;
+    const METHOD: &'static str = "textDocument/semanticTokens/full/delta";
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SemanticTokensDeltaParams {
+    pub text_document: TextDocumentIdentifier,
+    pub previous_result_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum SemanticTokensFullDeltaResult {
+    Tokens(SemanticTokens),
+    Delta(SemanticTokensDelta),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SemanticTokensDelta {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result_id: Option<String>,
+    pub edits: Vec<SemanticTokensEdit>,
+}
+
+// `start` and `delete_count` index the integers of the previous `data`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SemanticTokensEdit {
+    pub start: usize,
+    pub delete_count: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<Vec<u64>>,
+}
+
+// Names of the token types and modifiers, indexed by the encoded tokens
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct SemanticTokensLegend {
+    pub token_types: Vec<String>,
+    pub token_modifiers: Vec<String>,
+}
+
+// `textDocument/prepareCallHierarchy`, `callHierarchy/incomingCalls` and
+// `callHierarchy/outgoingCalls` (LSP 3.16)
+pub enum CallHierarchyPrepare {}
+
+impl Request for CallHierarchyPrepare {
+    type Params = lsp::TextDocumentPositionParams;
+    type Result = Option<Vec<CallHierarchyItem>>;
+    const METHOD: &'static str = "textDocument/prepareCallHierarchy";
+}
+
+pub enum CallHierarchyIncomingCalls {}
+
+impl Request for CallHierarchyIncomingCalls {
+    type Params = CallHierarchyCallsParams;
+    type Result = Option<Vec<CallHierarchyIncomingCall>>;
+    const METHOD: &'static str = "callHierarchy/incomingCalls";
+}
+
+pub enum CallHierarchyOutgoingCalls {}
+
+impl Request for CallHierarchyOutgoingCalls {
+    type Params = CallHierarchyCallsParams;
+    type Result = Option<Vec<CallHierarchyOutgoingCall>>;
+    const METHOD: &'static str = "callHierarchy/outgoingCalls";
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CallHierarchyItem {
+    pub name: String,
+    pub kind: SymbolKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    pub uri: Url,
+    pub range: Range,
+    pub selection_range: Range,
+    // Kept for the follow-up requests
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CallHierarchyCallsParams {
+    pub item: CallHierarchyItem,
+}
+
+// `from_ranges` are the call sites, in `from`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CallHierarchyIncomingCall {
+    pub from: CallHierarchyItem,
+    pub from_ranges: Vec<Range>,
+}
+
+// `from_ranges` are the call sites, in the item the calls were asked for
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CallHierarchyOutgoingCall {
+    pub to: CallHierarchyItem,
+    pub from_ranges: Vec<Range>,
+}
@@ -3,6 +3,8 @@ use std::{
     error::Error,
     fmt,
     io::{BufRead, Write},
+    iter::Peekable,
+    str::Chars,
     sync::{
         atomic::{AtomicU64, Ordering},
         Arc, Mutex,
@@ -12,12 +14,20 @@ use std::{
 };
 
 use bimap::BiMap;
-use crossbeam::channel::{self, Receiver, Sender};
+use crossbeam::channel::{self, Receiver};
 
 use lsp_types::{
-    self as lsp, GotoCapability, Hover, HoverCapability, HoverContents, Location, MarkedString,
-    MarkupContent, MarkupKind, Position, ShowMessageParams, TextDocumentClientCapabilities,
-    TextDocumentIdentifier, TextEdit,
+    self as lsp, CodeActionCapability, CodeActionKindLiteralSupport, CodeActionLiteralSupport,
+    CodeActionOrCommand, CodeLens, Color, ColorInformation, ColorPresentation,
+    CompletionCapability, CompletionItemCapability, Diagnostic, DiagnosticSeverity,
+    DocumentHighlight, DocumentHighlightKind, DocumentSymbol, DocumentSymbolCapability,
+    DocumentSymbolResponse, Documentation, FileChangeType, FileEvent, GenericCapability,
+    GotoCapability, Hover, HoverCapability, HoverContents, Location, MarkedString, MarkupContent,
+    MarkupKind, MessageActionItem, ParameterInformationSettings, ParameterLabel, Position,
+    PublishDiagnosticsCapability, RenameCapability, ShowMessageParams, ShowMessageRequestParams,
+    SignatureHelp, SignatureHelpCapability, SignatureInformationSettings, SymbolInformation,
+    SynchronizationCapability, TextDocumentClientCapabilities, TextDocumentIdentifier,
+    TextDocumentSaveReason, TextEdit, WorkspaceClientCapabilities,
 };
 use rmpv::{
     decode::read_value,
@@ -25,6 +35,7 @@ use rmpv::{
     ext::{from_value, to_value},
     Value,
 };
+use ropey::Rope;
 use serde::{
     self,
     de::{self, SeqAccess, Visitor},
@@ -33,14 +44,43 @@ use serde::{
 };
 use url::Url;
 
-use crate::lspc::{types::InlayHint, BufferId, Editor, EditorError, Event, LsConfig};
+use crate::lspc::{
+    completion_snippet,
+    types::{
+        CallHierarchyItem, CompletionItem, DocumentLink, InlayHint, PositionEncoding,
+        ProgressToken, SelectionRange, TraceValue, WorkspaceSymbol,
+    },
+    BufferId, CallDirection, DiagnosticLevel, Editor, EditorError, Event, Feature, GotoTarget,
+    HoverMode, LsConfig, SemanticToken, ServerReady,
+};
 use crate::rpc::{self, Message, RpcError};
 
+// Namespace shared by the features whose own one can't be created
+const FALLBACK_NAMESPACE: &str = "lspc";
+// Inlay hints are set in batches of `nvim_call_atomic`, bounded so a big file
+// doesn't hold Neovim on a single huge request
+const INLAY_HINTS_PER_CALL: usize = 200;
+
+// Kinds of the code actions offered as literals, the chosen one is applied
+// whatever its kind
+const CODE_ACTION_KINDS: &[&str] = &[
+    "",
+    "quickfix",
+    "refactor",
+    "refactor.extract",
+    "refactor.inline",
+    "refactor.rewrite",
+    "source",
+    "source.organizeImports",
+];
+
 pub struct Neovim {
     rpc_client: rpc::Client<NvimMessage>,
-    event_receiver: Receiver<Event>,
+    event_receiver: Receiver<Event<BufferHandler>>,
     next_id: AtomicU64,
-    subscription_sender: Sender<(u64, Sender<NvimMessage>)>,
+    namespaces: HashMap<String, u64>,
+    buf_mapper: Arc<Mutex<BiMap<i64, Url>>>,
+    request_timeout: Duration,
     thread: JoinHandle<()>,
 }
 
@@ -114,43 +154,369 @@ impl ToDisplay for Hover {
     }
 }
 
+impl ToDisplay for Documentation {
+    fn to_display(&self) -> Vec<String> {
+        match self {
+            Documentation::String(ref s) => s.to_display(),
+            Documentation::MarkupContent(ref mc) => mc.to_display(),
+        }
+    }
+
+    fn vim_filetype(&self) -> Option<String> {
+        match self {
+            Documentation::String(_) => Some("text".to_string()),
+            Documentation::MarkupContent(ref mc) => mc.vim_filetype(),
+        }
+    }
+}
+
+impl ToDisplay for CompletionItem {
+    fn to_display(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        if let Some(ref detail) = self.base.detail {
+            lines.extend(detail.to_display());
+        }
+        if let Some(ref documentation) = self.base.documentation {
+            if !lines.is_empty() {
+                lines.push(String::new());
+            }
+            lines.extend(documentation.to_display());
+        }
+        lines
+    }
+}
+
+// Flatten a `DocumentSymbol` tree in pre-order, pairing each symbol with its depth
+fn flatten_document_symbols<'a>(
+    symbols: &'a [DocumentSymbol],
+    depth: usize,
+    flattened: &mut Vec<(usize, &'a DocumentSymbol)>,
+) {
+    for symbol in symbols {
+        flattened.push((depth, symbol));
+        if let Some(ref children) = symbol.children {
+            flatten_document_symbols(children, depth + 1, flattened);
+        }
+    }
+}
+
+impl ToDisplay for DocumentSymbol {
+    fn to_display(&self) -> Vec<String> {
+        let mut flattened = Vec::new();
+        flatten_document_symbols(std::slice::from_ref(self), 0, &mut flattened);
+
+        flattened
+            .iter()
+            .map(|(depth, symbol)| format!("{}{}", "  ".repeat(*depth), symbol.name))
+            .collect()
+    }
+}
+
+// Outline entries of `symbols` as (uri, position, text), nested symbols are
+// indented by their depth, the outline folds them from it. Entries without uri
+// belong to the requested document.
+fn document_symbol_entries(
+    symbols: &DocumentSymbolResponse,
+) -> Vec<(Option<&Url>, Position, String)> {
+    match symbols {
+        DocumentSymbolResponse::Flat(symbols) => symbols
+            .iter()
+            .map(|symbol| {
+                (
+                    Some(&symbol.location.uri),
+                    symbol.location.range.start,
+                    format!("[{:?}] {}", symbol.kind, symbol.name),
+                )
+            })
+            .collect(),
+        DocumentSymbolResponse::Nested(symbols) => {
+            let mut flattened = Vec::new();
+            flatten_document_symbols(symbols, 0, &mut flattened);
+
+            flattened
+                .into_iter()
+                .map(|(depth, symbol)| {
+                    (
+                        None,
+                        symbol.selection_range.start,
+                        format!("{}[{:?}] {}", "  ".repeat(depth), symbol.kind, symbol.name),
+                    )
+                })
+                .collect()
+        }
+    }
+}
+
 impl ToDisplay for str {
     fn to_display(&self) -> Vec<String> {
         self.lines().map(String::from).collect()
     }
 }
 
-fn apply_edits(lines: &Vec<String>, edits: &Vec<TextEdit>) -> String {
+// Line ending of a document whose lines were split on `\n`, lines of a CRLF
+// document read as `fileformat=unix` keep their trailing `\r`
+fn line_ending(lines: &[String]) -> &'static str {
+    let mut separated = lines.iter().rev().skip(1).peekable();
+    if separated.peek().is_some() && separated.all(|line| line.ends_with('\r')) {
+        "\r\n"
+    } else {
+        "\n"
+    }
+}
+
+// The `\r` of CRLF lines stays part of the line so positions are unchanged,
+// only the inserted text is converted to the document's line ending.
+fn apply_edits(lines: &Vec<String>, edits: &Vec<TextEdit>, encoding: PositionEncoding) -> String {
+    let line_ending = line_ending(lines);
     let mut sorted_edits = edits.clone();
-    let mut editted_content = lines.join("\n");
-    sorted_edits.sort_by_key(|i| (i.range.start.line, i.range.start.character));
-    let mut last_modified_offset = editted_content.len();
-    for edit in sorted_edits.iter().rev() {
-        let start_offset = to_document_offset(&lines, edit.range.start);
-        let end_offset = to_document_offset(&lines, edit.range.end);
-
-        if end_offset <= last_modified_offset {
-            editted_content = format!(
-                "{}{}{}",
-                &editted_content[..start_offset],
-                edit.new_text,
-                &editted_content[end_offset..]
-            );
-        } else {
-            log::debug!("Overlapping edit!");
+    let mut editted_content = Rope::from_str(&lines.join("\n"));
+    let line_index = LineIndex::new(&editted_content);
+    // Edits may be adjacent: an insertion at the start of a replaced range
+    // goes before it, insertions at the same position keep their order.
+    // The sort is stable so the latter holds.
+    sorted_edits.sort_by_key(|i| {
+        (
+            i.range.start.line,
+            i.range.start.character,
+            i.range.end.line,
+            i.range.end.character,
+        )
+    });
+    // Positions refer to the original content, they're all converted before
+    // edits are applied from the last one so the offsets of the others stay valid
+    let offsets = sorted_edits
+        .iter()
+        .map(|edit| {
+            (
+                line_index.position_to_char(&editted_content, edit.range.start, encoding),
+                line_index.position_to_char(&editted_content, edit.range.end, encoding),
+            )
+        })
+        .collect::<Vec<_>>();
+    let mut last_modified_offset = editted_content.len_chars();
+    for (edit, (start_offset, end_offset)) in sorted_edits.iter().zip(offsets).rev() {
+        if end_offset > last_modified_offset {
+            log::warn!("Overlapping edit skipped: {:?}", edit.range);
+            continue;
+        }
+        if end_offset < start_offset {
+            log::warn!("Reversed edit skipped: {:?}", edit.range);
+            continue;
         }
 
+        editted_content.remove(start_offset..end_offset);
+        let new_text = edit
+            .new_text
+            .replace("\r\n", "\n")
+            .replace('\n', line_ending);
+        editted_content.insert(start_offset, &new_text);
         last_modified_offset = start_offset;
     }
-    editted_content
+    editted_content.to_string()
+}
+
+// Collect the text edits of a workspace edit per document,
+// resource operations (create/rename/delete) are not supported yet.
+fn workspace_edit_changes(edit: &WorkspaceEdit) -> Vec<(Url, Vec<TextEdit>)> {
+    if let Some(ref document_changes) = edit.document_changes {
+        let text_document_edits: Vec<&lsp::TextDocumentEdit> = match document_changes {
+            lsp::DocumentChanges::Edits(edits) => edits.iter().collect(),
+            lsp::DocumentChanges::Operations(operations) => operations
+                .iter()
+                .filter_map(|operation| match operation {
+                    lsp::DocumentChangeOperation::Edit(edit) => Some(edit),
+                    lsp::DocumentChangeOperation::Op(op) => {
+                        log::warn!("Unsupported resource operation: {:?}", op);
+                        None
+                    }
+                })
+                .collect(),
+        };
+
+        text_document_edits
+            .into_iter()
+            .map(|edit| (edit.text_document.uri.clone(), edit.edits.clone()))
+            .collect()
+    } else if let Some(ref changes) = edit.changes {
+        changes
+            .iter()
+            .map(|(uri, edits)| (uri.clone(), edits.clone()))
+            .collect()
+    } else {
+        Vec::new()
+    }
+}
+
+// Byte range of the parameter inside its signature label, offsets are counted
+// in UTF-16 code units
+fn parameter_label_range(label: &str, parameter: &ParameterLabel) -> Option<(usize, usize)> {
+    match parameter {
+        ParameterLabel::Simple(ref s) => {
+            let start = label.find(s.as_str())?;
+            Some((start, start + s.len()))
+        }
+        ParameterLabel::LabelOffsets(ref offsets) => Some((
+            PositionEncoding::Utf16.byte_offset(label, offsets[0] as usize),
+            PositionEncoding::Utf16.byte_offset(label, offsets[1] as usize),
+        )),
+    }
+}
+
+// Ranges of a selection range chain, from the innermost range outwards
+fn selection_range_chain(selection_range: &SelectionRange) -> Vec<lsp::Range> {
+    let mut chain = Vec::new();
+    let mut current = Some(selection_range);
+    while let Some(selection_range) = current {
+        chain.push(selection_range.range);
+        current = selection_range.parent.as_ref().map(|parent| &**parent);
+    }
+
+    chain
+}
+
+fn document_highlight_hl_group(kind: Option<&DocumentHighlightKind>) -> &'static str {
+    match kind {
+        Some(DocumentHighlightKind::Read) => "LspcHighlightRead",
+        Some(DocumentHighlightKind::Write) => "LspcHighlightWrite",
+        // Text is the default kind
+        _ => "LspcHighlightText",
+    }
 }
 
-fn to_document_offset(lines: &Vec<String>, pos: Position) -> usize {
-    lines[..pos.line as usize]
+// First line with content, code fences skipped. Kept shorter than `width` so
+// echoing it doesn't trigger the hit-enter prompt.
+fn hover_echo_line(lines: &[String], width: usize) -> Option<String> {
+    let line = lines
         .iter()
-        .map(String::len)
-        .fold(0, |acc, current| acc + current + 1)
-        + pos.character as usize
+        .map(|line| line.trim())
+        .find(|line| !line.is_empty() && !line.starts_with("```"))?;
+    let max_width = width.saturating_sub(1);
+    if line.chars().count() <= max_width {
+        return Some(line.to_owned());
+    }
+
+    let mut truncated = line
+        .chars()
+        .take(max_width.saturating_sub(1))
+        .collect::<String>();
+    truncated.push('…');
+    Some(truncated)
+}
+
+// e.g. `LspcSemanticTypeParameter` for `typeParameter`
+fn semantic_token_hl_group(token_type: &str) -> String {
+    let mut chars = token_type.chars();
+    match chars.next() {
+        Some(first) => format!("LspcSemantic{}{}", first.to_uppercase(), chars.as_str()),
+        None => "LspcSemantic".to_owned(),
+    }
+}
+
+// `rrggbb` of the color, channels outside of [0, 1] are clamped
+fn color_hex(color: &Color) -> String {
+    let channel = |value: f64| {
+        let value = if value.is_finite() { value } else { 0.0 };
+        (value.max(0.0).min(1.0) * 255.0).round() as u8
+    };
+
+    format!(
+        "{:02x}{:02x}{:02x}",
+        channel(color.red),
+        channel(color.green),
+        channel(color.blue)
+    )
+}
+
+// Arguments of `nvim_buf_set_extmark` showing the chunks at the end of the line
+fn virtual_text_args(
+    buffer_id: u64,
+    ns_id: u64,
+    line: u64,
+    chunks: Vec<(&str, &str)>,
+) -> Vec<Value> {
+    let chunks = chunks
+        .into_iter()
+        .map(|(label, hl_group)| Value::Array(vec![Value::from(label), Value::from(hl_group)]))
+        .collect::<Vec<_>>()
+        .into();
+    let opts = Value::Map(vec![
+        ("virt_text".into(), chunks),
+        ("virt_text_pos".into(), "eol".into()),
+    ]);
+
+    vec![
+        buffer_id.into(),
+        ns_id.into(),
+        line.into(),
+        Value::from(0),
+        opts,
+    ]
+}
+
+// Path of a `file:` URI with its percent-encoded characters decoded, other
+// URIs are kept whole
+fn uri_filename(uri: &Url) -> String {
+    uri.to_file_path()
+        .ok()
+        .and_then(|path| path.to_str().map(ToOwned::to_owned))
+        .unwrap_or_else(|| uri.to_string())
+}
+
+// Like `fnameescape()`, so paths with spaces or `%` are opened as is.
+// Backslashes separate the path on Windows.
+fn escape_filename(filename: &str) -> String {
+    let special = if cfg!(windows) {
+        " \t\n*?[{`$%#'\"|!<"
+    } else {
+        " \t\n*?[{`$\\%#'\"|!<"
+    };
+    let mut escaped = String::with_capacity(filename.len());
+    for c in filename.chars() {
+        if special.contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn goto_command(target: GotoTarget) -> &'static str {
+    match target {
+        GotoTarget::Edit => "edit",
+        GotoTarget::Split => "split",
+        GotoTarget::Vsplit => "vsplit",
+        GotoTarget::Tab => "tabedit",
+    }
+}
+
+// Key of the gutter sign in the `diagnostic_signs` config
+fn diagnostic_severity_name(severity: Option<DiagnosticSeverity>) -> &'static str {
+    match severity {
+        Some(DiagnosticSeverity::Warning) => "warning",
+        Some(DiagnosticSeverity::Information) => "information",
+        Some(DiagnosticSeverity::Hint) => "hint",
+        Some(DiagnosticSeverity::Error) | None => "error",
+    }
+}
+
+// Type of the location list entry: error, warning, info or note
+fn diagnostic_list_type(severity: Option<DiagnosticSeverity>) -> &'static str {
+    match severity {
+        Some(DiagnosticSeverity::Warning) => "W",
+        Some(DiagnosticSeverity::Information) => "I",
+        Some(DiagnosticSeverity::Hint) => "N",
+        Some(DiagnosticSeverity::Error) | None => "E",
+    }
+}
+
+// Diagnostics without severity are treated as errors
+fn diagnostic_hl_group(severity: Option<DiagnosticSeverity>) -> &'static str {
+    match severity {
+        Some(DiagnosticSeverity::Warning) => "LspcDiagnosticWarning",
+        Some(DiagnosticSeverity::Information) => "LspcDiagnosticInformation",
+        Some(DiagnosticSeverity::Hint) => "LspcDiagnosticHint",
+        Some(DiagnosticSeverity::Error) | None => "LspcDiagnosticError",
+    }
 }
 
 fn text_document_from_path_str<'de, D>(deserializer: D) -> Result<TextDocumentIdentifier, D::Error>
@@ -164,13 +530,172 @@ where
     Ok(TextDocumentIdentifier::new(uri))
 }
 
-fn to_event(msg: NvimMessage, buf_mapper: &Mutex<BiMap<i64, Url>>) -> Result<Event, EditorError> {
+fn save_reason_from_int<'de, D>(deserializer: D) -> Result<TextDocumentSaveReason, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let reason: i64 = Deserialize::deserialize(deserializer)?;
+    match reason {
+        2 => Ok(TextDocumentSaveReason::AfterDelay),
+        3 => Ok(TextDocumentSaveReason::FocusOut),
+        _ => Ok(TextDocumentSaveReason::Manual),
+    }
+}
+
+fn uris_from_paths(paths: Vec<String>) -> Result<Vec<Url>, EditorError> {
+    paths
+        .into_iter()
+        .map(|path| {
+            Url::from_file_path(path)
+                .map_err(|_| EditorError::Parse("could not convert path to URI"))
+        })
+        .collect()
+}
+
+fn completion_item_from_json_str<'de, D>(deserializer: D) -> Result<CompletionItem, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let s: String = Deserialize::deserialize(deserializer)?;
+    serde_json::from_str(&s)
+        .map_err(|_| <D::Error as de::Error>::custom("could not parse completion item"))
+}
+
+// Items in the server's intended order, by `sort_text` falling back to
+// `label`. Sorting is stable so equal keys keep the server's order.
+fn sort_completion_items(items: &[CompletionItem]) -> Vec<&CompletionItem> {
+    let mut sorted = items.iter().collect::<Vec<_>>();
+    sorted.sort_by_key(|item| {
+        item.base
+            .sort_text
+            .clone()
+            .unwrap_or_else(|| item.base.label.clone())
+    });
+
+    sorted
+}
+
+// Text of a snippet with its tabstops and placeholders filled by their
+// defaults: `foo(${1:a}, $2)$0` is `foo(a, )`
+fn snippet_text(snippet: &str) -> String {
+    let mut text = String::new();
+    push_snippet_text(&mut snippet.chars().peekable(), &mut text, false);
+
+    text
+}
+
+fn push_snippet_text(chars: &mut Peekable<Chars>, text: &mut String, in_placeholder: bool) {
+    fn is_name_char(c: &char) -> bool {
+        c.is_alphanumeric() || *c == '_'
+    }
+    fn skip_name(chars: &mut Peekable<Chars>) {
+        while chars.peek().map_or(false, is_name_char) {
+            chars.next();
+        }
+    }
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => text.extend(chars.next()),
+            '}' if in_placeholder => return,
+            '$' if chars.peek() == Some(&'{') => {
+                chars.next();
+                skip_name(chars);
+                match chars.next() {
+                    Some(':') => push_snippet_text(chars, text, true),
+                    // `${1|one,two|}` choices, the first one is the default
+                    Some('|') => {
+                        let choices = chars.by_ref().take_while(|c| *c != '|').collect::<String>();
+                        text.push_str(choices.split(',').next().unwrap_or(""));
+                        chars.next();
+                    }
+                    _ => {}
+                }
+            }
+            '$' if chars.peek().map_or(false, is_name_char) => skip_name(chars),
+            c => text.push(c),
+        }
+    }
+}
+
+// Vim complete-item, the original LSP item is kept in `user_data`
+// so it can be sent back for resolving and on commit:
+//
+//   {
+//     "word": insert text or label, snippets without their placeholders,
+//     "abbr": label and label details' detail,
+//     "kind": label details' description or kind name, "menu": detail,
+//     "info": detail and documentation,
+//     "sort_text": sort text or label,
+//     "preselect": bool, pre-highlighted by `lspc#command#show_completions`,
+//     "deprecated": bool,
+//     "user_data": the CompletionItem as JSON string
+//   }
+fn to_complete_item(item: &CompletionItem) -> Result<Value, EditorError> {
+    let label_details = item.label_details.as_ref();
+    let word = match completion_snippet(item) {
+        Some(snippet) => snippet_text(snippet),
+        None => item
+            .base
+            .insert_text
+            .as_ref()
+            .unwrap_or(&item.base.label)
+            .clone(),
+    };
+    let abbr = match label_details.and_then(|details| details.detail.as_ref()) {
+        Some(detail) => format!("{}{}", item.base.label, detail),
+        None => item.base.label.clone(),
+    };
+    let kind = match label_details.and_then(|details| details.description.as_ref()) {
+        Some(description) => description.clone(),
+        None => item
+            .base
+            .kind
+            .as_ref()
+            .map(|kind| format!("{:?}", kind))
+            .unwrap_or_default(),
+    };
+    let detail = item.base.detail.as_ref().map(String::as_str).unwrap_or("");
+    let deprecated = item.base.deprecated.unwrap_or(false);
+    let menu = if deprecated {
+        format!("(deprecated) {}", detail)
+    } else {
+        detail.to_owned()
+    };
+    let sort_text = item.base.sort_text.as_ref().unwrap_or(&item.base.label);
+    let user_data = serde_json::to_string(item)
+        .map_err(|e| EditorError::Failed(format!("Failed to encode completion item: {}", e)))?;
+
+    Ok(Value::Map(vec![
+        ("word".into(), word.into()),
+        ("abbr".into(), abbr.into()),
+        ("kind".into(), kind.into()),
+        ("menu".into(), menu.into()),
+        ("info".into(), item.to_display().join("\n").into()),
+        ("sort_text".into(), sort_text.as_str().into()),
+        (
+            "preselect".into(),
+            item.base.preselect.unwrap_or(false).into(),
+        ),
+        ("deprecated".into(), deprecated.into()),
+        ("user_data".into(), user_data.into()),
+    ]))
+}
+
+fn to_event(
+    msg: NvimMessage,
+    buf_mapper: &Mutex<BiMap<i64, Url>>,
+) -> Result<Event<BufferHandler>, EditorError> {
     log::debug!("Trying to convert msg: {:?} to event", msg);
     match msg {
         NvimMessage::RpcNotification { method, params } => {
             // Command messages
             if method == "hello" {
                 Ok(Event::Hello)
+            } else if method == "status" {
+                Ok(Event::Status)
+            } else if method == "exit" {
+                Ok(Event::Exit)
             } else if method == "start_lang_server" {
                 #[derive(Deserialize)]
                 struct StartLangServerParams(String, LsConfig, String);
@@ -183,6 +708,67 @@ fn to_event(msg: NvimMessage, buf_mapper: &Mutex<BiMap<i64, Url>>) -> Result<Eve
                     config: start_lang_params.1,
                     cur_path: start_lang_params.2,
                 })
+            } else if method == "stop_lang_server" {
+                let (lang_id,): (String,) = Deserialize::deserialize(params)
+                    .map_err(|_e| EditorError::Parse("failed to parse stop lang server params"))?;
+
+                Ok(Event::StopServer { lang_id })
+            } else if method == "did_create_files" {
+                let (paths,): (Vec<String>,) = Deserialize::deserialize(params)
+                    .map_err(|_e| EditorError::Parse("failed to parse did create files params"))?;
+
+                Ok(Event::DidCreateFiles {
+                    uris: uris_from_paths(paths)?,
+                })
+            } else if method == "did_rename_file" {
+                let (old_path, new_path): (String, String) = Deserialize::deserialize(params)
+                    .map_err(|_e| EditorError::Parse("failed to parse did rename file params"))?;
+                let mut uris = uris_from_paths(vec![old_path, new_path])?;
+                let new_uri = uris.pop().unwrap();
+                let old_uri = uris.pop().unwrap();
+
+                Ok(Event::DidRenameFiles {
+                    renames: vec![(old_uri, new_uri)],
+                })
+            } else if method == "did_delete_files" {
+                let (paths,): (Vec<String>,) = Deserialize::deserialize(params)
+                    .map_err(|_e| EditorError::Parse("failed to parse did delete files params"))?;
+
+                Ok(Event::DidDeleteFiles {
+                    uris: uris_from_paths(paths)?,
+                })
+            } else if method == "files_changed" {
+                let (changes,): (Vec<(String, u8)>,) = Deserialize::deserialize(params)
+                    .map_err(|_e| EditorError::Parse("failed to parse files changed params"))?;
+                let changes = changes
+                    .into_iter()
+                    .map(|(path, typ)| {
+                        let uri = Url::from_file_path(path)
+                            .map_err(|_| EditorError::Parse("could not convert path to URI"))?;
+                        let typ = match typ {
+                            1 => FileChangeType::Created,
+                            2 => FileChangeType::Changed,
+                            3 => FileChangeType::Deleted,
+                            _ => return Err(EditorError::Parse("unknown file change type")),
+                        };
+
+                        Ok(FileEvent { uri, typ })
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                Ok(Event::FilesChanged { changes })
+            } else if method == "prewarm_server" {
+                #[derive(Deserialize)]
+                struct PrewarmServerParams(String, LsConfig, String);
+
+                let prewarm_params: PrewarmServerParams = Deserialize::deserialize(params)
+                    .map_err(|_e| EditorError::Parse("failed to parse prewarm server params"))?;
+
+                Ok(Event::PrewarmServer {
+                    lang_id: prewarm_params.0,
+                    config: prewarm_params.1,
+                    root_path: prewarm_params.2,
+                })
             } else if method == "hover" {
                 #[derive(Deserialize)]
                 struct HoverParams(
@@ -204,9 +790,156 @@ fn to_event(msg: NvimMessage, buf_mapper: &Mutex<BiMap<i64, Url>>) -> Result<Eve
                     .insert(buf_id.0, text_document.uri.clone());
 
                 Ok(Event::Hover {
+                    buf_id,
                     text_document,
                     position: hover_params.2,
                 })
+            } else if method == "update_server_settings" {
+                #[derive(Deserialize)]
+                struct UpdateServerSettingsParams(String, serde_json::Value, bool);
+
+                let update_params: UpdateServerSettingsParams = Deserialize::deserialize(params)
+                    .map_err(|_e| {
+                        EditorError::Parse("failed to parse update server settings params")
+                    })?;
+
+                Ok(Event::UpdateServerSettings {
+                    lang_id: update_params.0,
+                    settings: update_params.1,
+                    replace: update_params.2,
+                })
+            } else if method == "set_trace" {
+                let (lang_id, value): (String, TraceValue) = Deserialize::deserialize(params)
+                    .map_err(|_e| EditorError::Parse("failed to parse set trace params"))?;
+
+                Ok(Event::SetTrace { lang_id, value })
+            } else if method == "add_workspace_folder" || method == "remove_workspace_folder" {
+                let (lang_id, path): (String, String) = Deserialize::deserialize(params)
+                    .map_err(|_e| EditorError::Parse("failed to parse workspace folder params"))?;
+
+                if method == "add_workspace_folder" {
+                    Ok(Event::AddWorkspaceFolder { lang_id, path })
+                } else {
+                    Ok(Event::RemoveWorkspaceFolder { lang_id, path })
+                }
+            } else if method == "type_definition" {
+                #[derive(Deserialize)]
+                struct GotoTypeDefinitionParams(
+                    i64,
+                    #[serde(deserialize_with = "text_document_from_path_str")]
+                    TextDocumentIdentifier,
+                    Position,
+                );
+
+                let goto_type_definition_params: GotoTypeDefinitionParams =
+                    Deserialize::deserialize(params).map_err(|_e| {
+                        EditorError::Parse("failed to parse goto type definition params")
+                    })?;
+
+                let buf_id = BufferHandler(goto_type_definition_params.0);
+                let text_document = goto_type_definition_params.1;
+
+                buf_mapper
+                    .lock()
+                    .unwrap()
+                    .insert(buf_id.0, text_document.uri.clone());
+
+                Ok(Event::GotoTypeDefinition {
+                    text_document,
+                    position: goto_type_definition_params.2,
+                })
+            } else if method == "declaration" {
+                #[derive(Deserialize)]
+                struct GotoDeclarationParams(
+                    i64,
+                    #[serde(deserialize_with = "text_document_from_path_str")]
+                    TextDocumentIdentifier,
+                    Position,
+                );
+
+                let goto_declaration_params: GotoDeclarationParams =
+                    Deserialize::deserialize(params).map_err(|_e| {
+                        EditorError::Parse("failed to parse goto declaration params")
+                    })?;
+
+                let buf_id = BufferHandler(goto_declaration_params.0);
+                let text_document = goto_declaration_params.1;
+
+                buf_mapper
+                    .lock()
+                    .unwrap()
+                    .insert(buf_id.0, text_document.uri.clone());
+
+                Ok(Event::GotoDeclaration {
+                    text_document,
+                    position: goto_declaration_params.2,
+                })
+            } else if method == "implementation" {
+                #[derive(Deserialize)]
+                struct GotoImplementationParams(
+                    i64,
+                    #[serde(deserialize_with = "text_document_from_path_str")]
+                    TextDocumentIdentifier,
+                    Position,
+                );
+
+                let goto_implementation_params: GotoImplementationParams =
+                    Deserialize::deserialize(params).map_err(|_e| {
+                        EditorError::Parse("failed to parse goto implementation params")
+                    })?;
+
+                let buf_id = BufferHandler(goto_implementation_params.0);
+                let text_document = goto_implementation_params.1;
+
+                buf_mapper
+                    .lock()
+                    .unwrap()
+                    .insert(buf_id.0, text_document.uri.clone());
+
+                Ok(Event::GotoImplementation {
+                    text_document,
+                    position: goto_implementation_params.2,
+                })
+            } else if method == "multi_hover" {
+                #[derive(Deserialize)]
+                struct MultiHoverParams(
+                    i64,
+                    #[serde(deserialize_with = "text_document_from_path_str")]
+                    TextDocumentIdentifier,
+                    Vec<Position>,
+                );
+
+                let multi_hover_params: MultiHoverParams = Deserialize::deserialize(params)
+                    .map_err(|_e| EditorError::Parse("failed to parse multi hover params"))?;
+
+                let buf_id = BufferHandler(multi_hover_params.0);
+                let text_document = multi_hover_params.1;
+
+                buf_mapper
+                    .lock()
+                    .unwrap()
+                    .insert(buf_id.0, text_document.uri.clone());
+
+                Ok(Event::MultiHover {
+                    text_document,
+                    positions: multi_hover_params.2,
+                })
+            } else if method == "cancel_request" {
+                #[derive(Deserialize)]
+                struct CancelRequestParams(
+                    i64,
+                    #[serde(deserialize_with = "text_document_from_path_str")]
+                    TextDocumentIdentifier,
+                    u64,
+                );
+
+                let cancel_params: CancelRequestParams = Deserialize::deserialize(params)
+                    .map_err(|_e| EditorError::Parse("failed to parse cancel request params"))?;
+
+                Ok(Event::CancelRequest {
+                    text_document: cancel_params.1,
+                    id: cancel_params.2,
+                })
             } else if method == "goto_definition" {
                 #[derive(Deserialize)]
                 struct GotoDefinitionParams(
@@ -214,6 +947,7 @@ fn to_event(msg: NvimMessage, buf_mapper: &Mutex<BiMap<i64, Url>>) -> Result<Eve
                     #[serde(deserialize_with = "text_document_from_path_str")]
                     TextDocumentIdentifier,
                     Position,
+                    GotoTarget,
                 );
 
                 let goto_definition_params: GotoDefinitionParams = Deserialize::deserialize(params)
@@ -230,6 +964,7 @@ fn to_event(msg: NvimMessage, buf_mapper: &Mutex<BiMap<i64, Url>>) -> Result<Eve
                 Ok(Event::GotoDefinition {
                     text_document,
                     position: goto_definition_params.2,
+                    target: goto_definition_params.3,
                 })
             } else if method == "inlay_hints" {
                 #[derive(Deserialize)]
@@ -250,7 +985,26 @@ fn to_event(msg: NvimMessage, buf_mapper: &Mutex<BiMap<i64, Url>>) -> Result<Eve
                     .unwrap()
                     .insert(buf_id.0, text_document.uri.clone());
 
-                Ok(Event::InlayHints { text_document })
+                Ok(Event::InlayHints {
+                    buf_id,
+                    text_document,
+                })
+            } else if method == "inlay_hint_goto" {
+                #[derive(Deserialize)]
+                struct InlayHintGotoParams(
+                    i64,
+                    #[serde(deserialize_with = "text_document_from_path_str")]
+                    TextDocumentIdentifier,
+                    Position,
+                );
+
+                let inlay_hint_goto_params: InlayHintGotoParams = Deserialize::deserialize(params)
+                    .map_err(|_e| EditorError::Parse("failed to parse inlay hint goto params"))?;
+
+                Ok(Event::InlayHintGoto {
+                    text_document: inlay_hint_goto_params.1,
+                    position: inlay_hint_goto_params.2,
+                })
             } else if method == "format_doc" {
                 #[derive(Deserialize)]
                 struct FormatDocParams(
@@ -272,37 +1026,91 @@ fn to_event(msg: NvimMessage, buf_mapper: &Mutex<BiMap<i64, Url>>) -> Result<Eve
                     .insert(buf_id.0, text_document.uri.clone());
 
                 Ok(Event::FormatDoc {
+                    buf_id,
                     text_document,
                     text_document_lines: format_doc_params.2,
                 })
-            } else if method == "did_open" {
+            } else if method == "format_range" {
                 #[derive(Deserialize)]
-                struct DidOpenParams(
+                struct FormatRangeParams(
                     i64,
                     #[serde(deserialize_with = "text_document_from_path_str")]
                     TextDocumentIdentifier,
+                    Vec<String>,
+                    lsp::Range,
                 );
-                let did_open_params: DidOpenParams = Deserialize::deserialize(params)
-                    .map_err(|_e| EditorError::Parse("failed to parse did_open params"))?;
 
-                let text_document = did_open_params.1;
-                let buf_id = BufferHandler(did_open_params.0);
+                let format_range_params: FormatRangeParams = Deserialize::deserialize(params)
+                    .map_err(|_e| EditorError::Parse("failed to parse format range params"))?;
+
+                let buf_id = BufferHandler(format_range_params.0);
+                let text_document = format_range_params.1;
 
                 buf_mapper
                     .lock()
                     .unwrap()
                     .insert(buf_id.0, text_document.uri.clone());
 
-                Ok(Event::DidOpen { text_document })
-
-            // Callback messages
-            } else if method == "nvim_buf_lines_event" {
+                Ok(Event::FormatRange {
+                    buf_id,
+                    text_document,
+                    text_document_lines: format_range_params.2,
+                    range: format_range_params.3,
+                })
+            } else if method == "on_type_format" {
                 #[derive(Deserialize)]
-                struct NvimBufLinesEvent(
-                    NvimHandle,                      // bufnr
-                    Option<i64>,                     // changedtick
-                    i64,                             // firstline
-                    i64,                             // lastline
+                struct OnTypeFormatParams(
+                    i64,
+                    #[serde(deserialize_with = "text_document_from_path_str")]
+                    TextDocumentIdentifier,
+                    Vec<String>,
+                    Position,
+                    String,
+                );
+
+                let on_type_format_params: OnTypeFormatParams = Deserialize::deserialize(params)
+                    .map_err(|_e| EditorError::Parse("failed to parse on type format params"))?;
+
+                Ok(Event::OnTypeFormat {
+                    buf_id: BufferHandler(on_type_format_params.0),
+                    text_document: on_type_format_params.1,
+                    text_document_lines: on_type_format_params.2,
+                    position: on_type_format_params.3,
+                    ch: on_type_format_params.4,
+                })
+            } else if method == "did_open" {
+                #[derive(Deserialize)]
+                struct DidOpenParams(
+                    i64,
+                    #[serde(deserialize_with = "text_document_from_path_str")]
+                    TextDocumentIdentifier,
+                    String,
+                );
+                let did_open_params: DidOpenParams = Deserialize::deserialize(params)
+                    .map_err(|_e| EditorError::Parse("failed to parse did_open params"))?;
+
+                let text_document = did_open_params.1;
+                let buf_id = BufferHandler(did_open_params.0);
+
+                buf_mapper
+                    .lock()
+                    .unwrap()
+                    .insert(buf_id.0, text_document.uri.clone());
+
+                Ok(Event::DidOpen {
+                    buf_id,
+                    text_document,
+                    language_id: did_open_params.2,
+                })
+
+            // Callback messages
+            } else if method == "nvim_buf_lines_event" {
+                #[derive(Deserialize)]
+                struct NvimBufLinesEvent(
+                    NvimHandle,                      // bufnr
+                    Option<i64>,                     // changedtick
+                    i64,                             // firstline
+                    i64,                             // lastline
                     Vec<String>,                     // linedata
                     #[serde(default)] Option<Value>, // { more }
                 );
@@ -330,10 +1138,15 @@ fn to_event(msg: NvimMessage, buf_mapper: &Mutex<BiMap<i64, Url>>) -> Result<Eve
                         end: lsp::Position::new(buf_line_event.3 as u64, 0),
                     })
                 };
+                // Whole lines are replaced, each one ends with a newline
                 let content_change = lsp::TextDocumentContentChangeEvent {
                     range,
                     range_length: None,
-                    text: buf_line_event.4.join("\n"),
+                    text: buf_line_event
+                        .4
+                        .iter()
+                        .map(|line| format!("{}\n", line))
+                        .collect(),
                 };
                 let text_document = {
                     let unlocked_buf_mapper = buf_mapper.lock().unwrap();
@@ -371,6 +1184,79 @@ fn to_event(msg: NvimMessage, buf_mapper: &Mutex<BiMap<i64, Url>>) -> Result<Eve
                 };
 
                 Ok(Event::DidClose { text_document })
+            } else if method == "will_save" {
+                #[derive(Deserialize)]
+                struct WillSaveParams(
+                    i64,
+                    #[serde(deserialize_with = "text_document_from_path_str")]
+                    TextDocumentIdentifier,
+                    #[serde(deserialize_with = "save_reason_from_int")] TextDocumentSaveReason,
+                );
+
+                let will_save_params: WillSaveParams = Deserialize::deserialize(params)
+                    .map_err(|_e| EditorError::Parse("failed to parse will save params"))?;
+
+                let buf_id = BufferHandler(will_save_params.0);
+                let text_document = will_save_params.1;
+
+                buf_mapper
+                    .lock()
+                    .unwrap()
+                    .insert(buf_id.0, text_document.uri.clone());
+
+                Ok(Event::WillSave {
+                    text_document,
+                    reason: will_save_params.2,
+                })
+            } else if method == "will_save_wait_until" {
+                #[derive(Deserialize)]
+                struct WillSaveWaitUntilParams(
+                    i64,
+                    #[serde(deserialize_with = "text_document_from_path_str")]
+                    TextDocumentIdentifier,
+                    #[serde(deserialize_with = "save_reason_from_int")] TextDocumentSaveReason,
+                    Vec<String>,
+                );
+
+                let will_save_params: WillSaveWaitUntilParams = Deserialize::deserialize(params)
+                    .map_err(|_e| {
+                        EditorError::Parse("failed to parse will save wait until params")
+                    })?;
+
+                let buf_id = BufferHandler(will_save_params.0);
+                let text_document = will_save_params.1;
+
+                buf_mapper
+                    .lock()
+                    .unwrap()
+                    .insert(buf_id.0, text_document.uri.clone());
+
+                Ok(Event::WillSaveWaitUntil {
+                    buf_id,
+                    text_document_lines: will_save_params.3,
+                    text_document,
+                    reason: will_save_params.2,
+                })
+            } else if method == "did_save" {
+                #[derive(Deserialize)]
+                struct DidSaveParams(
+                    i64,
+                    #[serde(deserialize_with = "text_document_from_path_str")]
+                    TextDocumentIdentifier,
+                );
+
+                let did_save_params: DidSaveParams = Deserialize::deserialize(params)
+                    .map_err(|_e| EditorError::Parse("failed to parse did save params"))?;
+
+                let buf_id = BufferHandler(did_save_params.0);
+                let text_document = did_save_params.1;
+
+                buf_mapper
+                    .lock()
+                    .unwrap()
+                    .insert(buf_id.0, text_document.uri.clone());
+
+                Ok(Event::DidSave { text_document })
             } else if method == "references" {
                 #[derive(Deserialize)]
                 struct ReferencesParams(
@@ -397,758 +1283,3279 @@ fn to_event(msg: NvimMessage, buf_mapper: &Mutex<BiMap<i64, Url>>) -> Result<Eve
                     position: references_params.2,
                     include_declaration: references_params.3,
                 })
-            } else {
-                Err(EditorError::UnexpectedMessage(format!(
-                    "unexpected notification {:?} {:?}",
-                    method, params
-                )))
-            }
-        }
-        _ => Err(EditorError::UnexpectedMessage(format!("{:?}", msg))),
-    }
-}
+            } else if method == "completion" {
+                #[derive(Deserialize)]
+                struct CompletionParams(
+                    i64,
+                    #[serde(deserialize_with = "text_document_from_path_str")]
+                    TextDocumentIdentifier,
+                    Position,
+                );
 
-impl Neovim {
-    pub fn new(rpc_client: rpc::Client<NvimMessage>) -> Self {
-        let (event_sender, event_receiver) = channel::unbounded();
-        let (subscription_sender, subscription_receiver) =
-            channel::bounded::<(u64, Sender<NvimMessage>)>(16);
+                let completion_params: CompletionParams = Deserialize::deserialize(params)
+                    .map_err(|_e| EditorError::Parse("failed to parse completion params"))?;
 
-        let rpc_receiver = rpc_client.receiver.clone();
-        let buf_mapper = Arc::new(Mutex::new(BiMap::new()));
-        let buf_mapper_clone = Arc::clone(&buf_mapper);
+                let buf_id = BufferHandler(completion_params.0);
+                let text_document = completion_params.1;
 
-        let thread = thread::spawn(move || {
-            let mut subscriptions = Vec::<(u64, Sender<NvimMessage>)>::new();
+                buf_mapper
+                    .lock()
+                    .unwrap()
+                    .insert(buf_id.0, text_document.uri.clone());
 
-            for nvim_msg in rpc_receiver {
-                log::debug!("< Neovim: {:?}", nvim_msg);
-                if let NvimMessage::RpcResponse { msgid, .. } = nvim_msg {
-                    while let Ok(sub) = subscription_receiver.try_recv() {
-                        subscriptions.push(sub);
-                    }
-                    if let Some(index) = subscriptions.iter().position(|item| item.0 == msgid) {
-                        let sub = subscriptions.swap_remove(index);
-                        sub.1.send(nvim_msg).unwrap();
-                    } else {
-                        log::error!("Received non-requested response: {}", msgid);
-                    }
-                } else {
-                    match to_event(nvim_msg, &buf_mapper_clone) {
-                        Ok(event) => event_sender.send(event).unwrap(),
-                        Err(e) => log::error!("Cannot convert nvim msg to editor event: {:?}", e),
-                    }
-                }
-            }
-        });
+                Ok(Event::Completion {
+                    text_document,
+                    position: completion_params.2,
+                })
+            } else if method == "signature_help" {
+                #[derive(Deserialize)]
+                struct SignatureHelpParams(
+                    i64,
+                    #[serde(deserialize_with = "text_document_from_path_str")]
+                    TextDocumentIdentifier,
+                    Position,
+                );
 
-        Neovim {
-            next_id: AtomicU64::new(1),
-            subscription_sender,
-            event_receiver,
-            rpc_client,
-            thread,
-        }
-    }
+                let signature_help_params: SignatureHelpParams = Deserialize::deserialize(params)
+                    .map_err(|_e| {
+                    EditorError::Parse("failed to parse signature help params")
+                })?;
 
-    // using nvim_call_atomic rpc call
-    #[allow(dead_code)]
-    fn call_atomic(&self, calls: Value) -> Result<Vec<Value>, EditorError> {
-        let response = self.request("nvim_call_atomic", calls);
-        log::debug!("Response: {:?}", response);
-        if let NvimMessage::RpcResponse { result, error, .. } = response? {
-            if let Some(error) = error.as_array() {
-                let error_msg = error
-                    .get(1)
-                    .ok_or(EditorError::UnexpectedResponse("Expected error message"))?
-                    .as_str()
-                    .ok_or(EditorError::UnexpectedResponse(
-                        "Expected String error message",
-                    ))?;
+                let buf_id = BufferHandler(signature_help_params.0);
+                let text_document = signature_help_params.1;
 
-                return Err(EditorError::Failed(error_msg.into()));
-            }
+                buf_mapper
+                    .lock()
+                    .unwrap()
+                    .insert(buf_id.0, text_document.uri.clone());
 
-            if let Value::Array(results) = result {
-                Ok(results)
-            } else {
-                Err(EditorError::UnexpectedResponse("Expect result array"))
-            }
-        } else {
-            Err(EditorError::UnexpectedResponse("Expected response"))
-        }
-    }
+                Ok(Event::SignatureHelp {
+                    text_document,
+                    position: signature_help_params.2,
+                })
+            } else if method == "toggle_diagnostics" {
+                #[derive(Deserialize)]
+                struct ToggleDiagnosticsParams(
+                    i64,
+                    #[serde(deserialize_with = "text_document_from_path_str")]
+                    TextDocumentIdentifier,
+                    bool,
+                );
 
-    pub fn request(&self, method: &str, params: Value) -> Result<NvimMessage, EditorError> {
-        let msgid = self.next_id.fetch_add(1, Ordering::Relaxed);
-        let req = NvimMessage::RpcRequest {
-            msgid,
-            method: method.into(),
-            params: params,
-        };
+                let toggle_params: ToggleDiagnosticsParams = Deserialize::deserialize(params)
+                    .map_err(|_e| {
+                        EditorError::Parse("failed to parse toggle diagnostics params")
+                    })?;
 
-        let (response_sender, response_receiver) = channel::bounded::<NvimMessage>(1);
-        self.subscription_sender
-            .send((msgid, response_sender))
-            .unwrap();
-        self.rpc_client.sender.send(req).unwrap();
+                let buf_id = BufferHandler(toggle_params.0);
+                let text_document = toggle_params.1;
 
-        response_receiver
-            .recv_timeout(Duration::from_secs(60))
-            .map_err(|_| EditorError::Timeout)
-    }
+                buf_mapper
+                    .lock()
+                    .unwrap()
+                    .insert(buf_id.0, text_document.uri.clone());
 
-    pub fn notify(&self, method: &str, params: &[Value]) -> Result<(), EditorError> {
-        let noti = NvimMessage::RpcNotification {
-            method: method.into(),
-            params: Value::from(params.to_owned()),
-        };
-        // FIXME: add RpcQueueFull to EditorError??
-        self.rpc_client.sender.send(noti).unwrap();
+                Ok(Event::ToggleDiagnostics {
+                    buf_id,
+                    text_document,
+                    enabled: toggle_params.2,
+                })
+            } else if method == "diagnostics_to_loclist" {
+                #[derive(Deserialize)]
+                struct DiagnosticsToLoclistParams(
+                    i64,
+                    #[serde(deserialize_with = "text_document_from_path_str")]
+                    TextDocumentIdentifier,
+                );
 
-        Ok(())
-    }
+                let loclist_params: DiagnosticsToLoclistParams = Deserialize::deserialize(params)
+                    .map_err(|_e| {
+                    EditorError::Parse("failed to parse diagnostics to loclist params")
+                })?;
 
-    pub fn command(&self, command: &str) -> Result<NvimMessage, EditorError> {
-        let params = vec![Value::from(command)].into();
-        self.request("nvim_command", params)
-    }
+                Ok(Event::DiagnosticsToLoclist {
+                    text_document: loclist_params.1,
+                })
+            } else if method == "set_buffer_features" {
+                #[derive(Deserialize)]
+                struct SetBufferFeaturesParams(
+                    i64,
+                    #[serde(deserialize_with = "text_document_from_path_str")]
+                    TextDocumentIdentifier,
+                    HashMap<Feature, bool>,
+                );
 
-    // Call VimL function
-    pub fn call_function(&self, func: &str, args: Value) -> Result<NvimMessage, EditorError> {
-        let params = vec![func.into(), args].into();
-        self.request("nvim_call_function", params)
-    }
+                let features_params: SetBufferFeaturesParams = Deserialize::deserialize(params)
+                    .map_err(|_e| {
+                        EditorError::Parse("failed to parse set buffer features params")
+                    })?;
 
-    pub fn create_namespace(&self, ns_name: &str) -> Result<u64, EditorError> {
-        let params = vec![Value::from(ns_name)].into();
-        let response = self.request("nvim_create_namespace", params)?;
-        log::debug!("Create namespace response: {:?}", response);
-        if let NvimMessage::RpcResponse { ref result, .. } = response {
-            Ok(result.as_u64().ok_or(EditorError::UnexpectedResponse(
-                "Expected nvim_create_namespace respsonse",
-            ))?)
-        } else {
-            Err(EditorError::UnexpectedResponse(
-                "Expected nvim_create_namespace respsonse",
-            ))
-        }
-    }
+                Ok(Event::SetBufferFeatures {
+                    text_document: features_params.1,
+                    features: features_params.2,
+                })
+            } else if method == "rename" {
+                #[derive(Deserialize)]
+                struct RenameParams(
+                    i64,
+                    #[serde(deserialize_with = "text_document_from_path_str")]
+                    TextDocumentIdentifier,
+                    Position,
+                    Option<String>,
+                );
 
-    pub fn set_virtual_text(
-        &self,
-        buffer_id: u64,
-        ns_id: u64,
-        line: u64,
-        chunks: Vec<(&str, &str)>,
-    ) -> Result<(), EditorError> {
-        let chunks = chunks
-            .into_iter()
-            .map(|(label, hl_group)| Value::Array(vec![Value::from(label), Value::from(hl_group)]))
-            .collect::<Vec<_>>()
-            .into();
-        self.notify(
-            "nvim_buf_set_virtual_text",
-            &vec![
-                buffer_id.into(),
+                let rename_params: RenameParams = Deserialize::deserialize(params)
+                    .map_err(|_e| EditorError::Parse("failed to parse rename params"))?;
+
+                let buf_id = BufferHandler(rename_params.0);
+                let text_document = rename_params.1;
+
+                buf_mapper
+                    .lock()
+                    .unwrap()
+                    .insert(buf_id.0, text_document.uri.clone());
+
+                Ok(Event::Rename {
+                    text_document,
+                    position: rename_params.2,
+                    new_name: rename_params.3,
+                })
+            } else if method == "code_action" {
+                #[derive(Deserialize)]
+                struct CodeActionParams(
+                    i64,
+                    #[serde(deserialize_with = "text_document_from_path_str")]
+                    TextDocumentIdentifier,
+                    lsp::Range,
+                );
+
+                let code_action_params: CodeActionParams = Deserialize::deserialize(params)
+                    .map_err(|_e| EditorError::Parse("failed to parse code action params"))?;
+
+                let buf_id = BufferHandler(code_action_params.0);
+                let text_document = code_action_params.1;
+
+                buf_mapper
+                    .lock()
+                    .unwrap()
+                    .insert(buf_id.0, text_document.uri.clone());
+
+                Ok(Event::CodeAction {
+                    text_document,
+                    range: code_action_params.2,
+                    diagnostics: Vec::new(),
+                })
+            } else if method == "selection_range" {
+                #[derive(Deserialize)]
+                struct SelectionRangeParams(
+                    i64,
+                    #[serde(deserialize_with = "text_document_from_path_str")]
+                    TextDocumentIdentifier,
+                    Vec<Position>,
+                );
+
+                let selection_range_params: SelectionRangeParams = Deserialize::deserialize(params)
+                    .map_err(|_e| EditorError::Parse("failed to parse selection range params"))?;
+
+                let buf_id = BufferHandler(selection_range_params.0);
+                let text_document = selection_range_params.1;
+
+                buf_mapper
+                    .lock()
+                    .unwrap()
+                    .insert(buf_id.0, text_document.uri.clone());
+
+                Ok(Event::SelectionRange {
+                    buf_id,
+                    text_document,
+                    positions: selection_range_params.2,
+                })
+            } else if method == "document_highlight" {
+                #[derive(Deserialize)]
+                struct DocumentHighlightParams(
+                    i64,
+                    #[serde(deserialize_with = "text_document_from_path_str")]
+                    TextDocumentIdentifier,
+                    Position,
+                );
+
+                let document_highlight_params: DocumentHighlightParams =
+                    Deserialize::deserialize(params).map_err(|_e| {
+                        EditorError::Parse("failed to parse document highlight params")
+                    })?;
+
+                let buf_id = BufferHandler(document_highlight_params.0);
+                let text_document = document_highlight_params.1;
+
+                buf_mapper
+                    .lock()
+                    .unwrap()
+                    .insert(buf_id.0, text_document.uri.clone());
+
+                Ok(Event::DocumentHighlight {
+                    buf_id,
+                    text_document,
+                    position: document_highlight_params.2,
+                })
+            } else if method == "document_symbols" {
+                #[derive(Deserialize)]
+                struct DocumentSymbolsParams(
+                    i64,
+                    #[serde(deserialize_with = "text_document_from_path_str")]
+                    TextDocumentIdentifier,
+                );
+
+                let document_symbols_params: DocumentSymbolsParams =
+                    Deserialize::deserialize(params).map_err(|_e| {
+                        EditorError::Parse("failed to parse document symbols params")
+                    })?;
+
+                let buf_id = BufferHandler(document_symbols_params.0);
+                let text_document = document_symbols_params.1;
+
+                buf_mapper
+                    .lock()
+                    .unwrap()
+                    .insert(buf_id.0, text_document.uri.clone());
+
+                Ok(Event::DocumentSymbols { text_document })
+            } else if method == "code_lens" {
+                #[derive(Deserialize)]
+                struct CodeLensParams(
+                    i64,
+                    #[serde(deserialize_with = "text_document_from_path_str")]
+                    TextDocumentIdentifier,
+                );
+
+                let code_lens_params: CodeLensParams = Deserialize::deserialize(params)
+                    .map_err(|_e| EditorError::Parse("failed to parse code lens params"))?;
+
+                let buf_id = BufferHandler(code_lens_params.0);
+                let text_document = code_lens_params.1;
+
+                buf_mapper
+                    .lock()
+                    .unwrap()
+                    .insert(buf_id.0, text_document.uri.clone());
+
+                Ok(Event::CodeLens {
+                    buf_id,
+                    text_document,
+                })
+            } else if method == "semantic_tokens" {
+                #[derive(Deserialize)]
+                struct SemanticTokensParams(
+                    i64,
+                    #[serde(deserialize_with = "text_document_from_path_str")]
+                    TextDocumentIdentifier,
+                );
+
+                let semantic_tokens_params: SemanticTokensParams = Deserialize::deserialize(params)
+                    .map_err(|_e| EditorError::Parse("failed to parse semantic tokens params"))?;
+
+                let buf_id = BufferHandler(semantic_tokens_params.0);
+                let text_document = semantic_tokens_params.1;
+
+                buf_mapper
+                    .lock()
+                    .unwrap()
+                    .insert(buf_id.0, text_document.uri.clone());
+
+                Ok(Event::SemanticTokens {
+                    buf_id,
+                    text_document,
+                })
+            } else if method == "request_diagnostics" {
+                #[derive(Deserialize)]
+                struct RequestDiagnosticsParams(
+                    i64,
+                    #[serde(deserialize_with = "text_document_from_path_str")]
+                    TextDocumentIdentifier,
+                );
+
+                let request_diagnostics_params: RequestDiagnosticsParams =
+                    Deserialize::deserialize(params).map_err(|_e| {
+                        EditorError::Parse("failed to parse request diagnostics params")
+                    })?;
+
+                let buf_id = BufferHandler(request_diagnostics_params.0);
+                let text_document = request_diagnostics_params.1;
+
+                buf_mapper
+                    .lock()
+                    .unwrap()
+                    .insert(buf_id.0, text_document.uri.clone());
+
+                Ok(Event::RequestDiagnostics { text_document })
+            } else if method == "prepare_call_hierarchy" {
+                #[derive(Deserialize)]
+                struct PrepareCallHierarchyParams(
+                    i64,
+                    #[serde(deserialize_with = "text_document_from_path_str")]
+                    TextDocumentIdentifier,
+                    Position,
+                );
+
+                let prepare_params: PrepareCallHierarchyParams = Deserialize::deserialize(params)
+                    .map_err(|_e| {
+                    EditorError::Parse("failed to parse prepare call hierarchy params")
+                })?;
+
+                let buf_id = BufferHandler(prepare_params.0);
+                let text_document = prepare_params.1;
+
+                buf_mapper
+                    .lock()
+                    .unwrap()
+                    .insert(buf_id.0, text_document.uri.clone());
+
+                Ok(Event::PrepareCallHierarchy {
+                    text_document,
+                    position: prepare_params.2,
+                })
+            } else if method == "incoming_calls" || method == "outgoing_calls" {
+                #[derive(Deserialize)]
+                struct CallHierarchyCallsParams(
+                    i64,
+                    #[serde(deserialize_with = "text_document_from_path_str")]
+                    TextDocumentIdentifier,
+                );
+
+                let calls_params: CallHierarchyCallsParams = Deserialize::deserialize(params)
+                    .map_err(|_e| EditorError::Parse("failed to parse call hierarchy params"))?;
+
+                let buf_id = BufferHandler(calls_params.0);
+                let text_document = calls_params.1;
+
+                buf_mapper
+                    .lock()
+                    .unwrap()
+                    .insert(buf_id.0, text_document.uri.clone());
+
+                if method == "incoming_calls" {
+                    Ok(Event::CallHierarchyIncomingCalls { text_document })
+                } else {
+                    Ok(Event::CallHierarchyOutgoingCalls { text_document })
+                }
+            } else if method == "document_links" {
+                #[derive(Deserialize)]
+                struct DocumentLinksParams(
+                    i64,
+                    #[serde(deserialize_with = "text_document_from_path_str")]
+                    TextDocumentIdentifier,
+                );
+
+                let document_links_params: DocumentLinksParams = Deserialize::deserialize(params)
+                    .map_err(|_e| {
+                    EditorError::Parse("failed to parse document links params")
+                })?;
+
+                let buf_id = BufferHandler(document_links_params.0);
+                let text_document = document_links_params.1;
+
+                buf_mapper
+                    .lock()
+                    .unwrap()
+                    .insert(buf_id.0, text_document.uri.clone());
+
+                Ok(Event::DocumentLinks {
+                    buf_id,
+                    text_document,
+                })
+            } else if method == "goto_document_link" {
+                #[derive(Deserialize)]
+                struct GotoDocumentLinkParams(
+                    i64,
+                    #[serde(deserialize_with = "text_document_from_path_str")]
+                    TextDocumentIdentifier,
+                    Position,
+                );
+
+                let goto_link_params: GotoDocumentLinkParams = Deserialize::deserialize(params)
+                    .map_err(|_e| {
+                        EditorError::Parse("failed to parse goto document link params")
+                    })?;
+
+                Ok(Event::GotoDocumentLink {
+                    text_document: goto_link_params.1,
+                    position: goto_link_params.2,
+                })
+            } else if method == "document_color" {
+                #[derive(Deserialize)]
+                struct DocumentColorParams(
+                    i64,
+                    #[serde(deserialize_with = "text_document_from_path_str")]
+                    TextDocumentIdentifier,
+                );
+
+                let document_color_params: DocumentColorParams = Deserialize::deserialize(params)
+                    .map_err(|_e| {
+                    EditorError::Parse("failed to parse document color params")
+                })?;
+
+                let buf_id = BufferHandler(document_color_params.0);
+                let text_document = document_color_params.1;
+
+                buf_mapper
+                    .lock()
+                    .unwrap()
+                    .insert(buf_id.0, text_document.uri.clone());
+
+                Ok(Event::DocumentColor {
+                    buf_id,
+                    text_document,
+                })
+            } else if method == "color_presentation" {
+                #[derive(Deserialize)]
+                struct ColorPresentationParams(
+                    i64,
+                    #[serde(deserialize_with = "text_document_from_path_str")]
+                    TextDocumentIdentifier,
+                    Position,
+                    Vec<String>,
+                );
+
+                let color_presentation_params: ColorPresentationParams =
+                    Deserialize::deserialize(params).map_err(|_e| {
+                        EditorError::Parse("failed to parse color presentation params")
+                    })?;
+
+                Ok(Event::ColorPresentation {
+                    buf_id: BufferHandler(color_presentation_params.0),
+                    text_document: color_presentation_params.1,
+                    position: color_presentation_params.2,
+                    text_document_lines: color_presentation_params.3,
+                })
+            } else if method == "workspace_symbol" {
+                let (query,): (String,) = Deserialize::deserialize(params)
+                    .map_err(|_e| EditorError::Parse("failed to parse workspace symbol params"))?;
+
+                Ok(Event::WorkspaceSymbols { query })
+            } else if method == "goto_workspace_symbol" {
+                #[derive(Deserialize)]
+                struct GotoWorkspaceSymbolParams(
+                    i64,
+                    #[serde(deserialize_with = "text_document_from_path_str")]
+                    TextDocumentIdentifier,
+                    String,
+                );
+
+                let goto_workspace_symbol_params: GotoWorkspaceSymbolParams =
+                    Deserialize::deserialize(params).map_err(|_e| {
+                        EditorError::Parse("failed to parse workspace symbol params")
+                    })?;
+
+                let buf_id = BufferHandler(goto_workspace_symbol_params.0);
+                let text_document = goto_workspace_symbol_params.1;
+
+                buf_mapper
+                    .lock()
+                    .unwrap()
+                    .insert(buf_id.0, text_document.uri.clone());
+
+                Ok(Event::GotoWorkspaceSymbol {
+                    text_document,
+                    query: goto_workspace_symbol_params.2,
+                })
+            } else if method == "resolve_completion" {
+                #[derive(Deserialize)]
+                struct ResolveCompletionParams(
+                    i64,
+                    #[serde(deserialize_with = "text_document_from_path_str")]
+                    TextDocumentIdentifier,
+                    #[serde(deserialize_with = "completion_item_from_json_str")] CompletionItem,
+                );
+
+                let resolve_params: ResolveCompletionParams = Deserialize::deserialize(params)
+                    .map_err(|_e| {
+                        EditorError::Parse("failed to parse resolve completion params")
+                    })?;
+
+                Ok(Event::ResolveCompletion {
+                    text_document: resolve_params.1,
+                    item: resolve_params.2,
+                })
+            } else if method == "completion_done" {
+                #[derive(Deserialize)]
+                struct CompletionDoneParams(
+                    i64,
+                    #[serde(deserialize_with = "text_document_from_path_str")]
+                    TextDocumentIdentifier,
+                    Vec<String>,
+                    #[serde(deserialize_with = "completion_item_from_json_str")] CompletionItem,
+                );
+
+                let completion_done_params: CompletionDoneParams = Deserialize::deserialize(params)
+                    .map_err(|_e| EditorError::Parse("failed to parse completion done params"))?;
+
+                Ok(Event::CompletionDone {
+                    buf_id: BufferHandler(completion_done_params.0),
+                    text_document: completion_done_params.1,
+                    text_document_lines: completion_done_params.2,
+                    item: completion_done_params.3,
+                })
+            } else {
+                Err(EditorError::UnexpectedMessage(format!(
+                    "unexpected notification {:?} {:?}",
+                    method, params
+                )))
+            }
+        }
+        _ => Err(EditorError::UnexpectedMessage(format!("{:?}", msg))),
+    }
+}
+
+impl Neovim {
+    pub fn new(rpc_client: rpc::Client<NvimMessage>) -> Self {
+        let (event_sender, event_receiver) = channel::unbounded();
+
+        let rpc_receiver = rpc_client.receiver.clone();
+        let buf_mapper = Arc::new(Mutex::new(BiMap::new()));
+        let buf_mapper_clone = Arc::clone(&buf_mapper);
+
+        // Responses to our requests are routed by the rpc client
+        let thread = thread::spawn(move || {
+            for nvim_msg in rpc_receiver {
+                log::debug!("< Neovim: {:?}", nvim_msg);
+                if let NvimMessage::RpcResponse { msgid, .. } = nvim_msg {
+                    log::error!("Received non-requested response: {}", msgid);
+                } else {
+                    match to_event(nvim_msg, &buf_mapper_clone) {
+                        Ok(event) => event_sender.send(event).unwrap(),
+                        Err(e) => log::error!("Cannot convert nvim msg to editor event: {:?}", e),
+                    }
+                }
+            }
+        });
+
+        Neovim {
+            next_id: AtomicU64::new(1),
+            event_receiver,
+            rpc_client,
+            namespaces: HashMap::new(),
+            buf_mapper,
+            request_timeout: Duration::from_secs(60),
+            thread,
+        }
+    }
+
+    pub fn set_request_timeout(&mut self, timeout: Duration) {
+        self.request_timeout = timeout;
+    }
+
+    // using nvim_call_atomic rpc call, each call is a `[method, args]` pair
+    fn call_atomic(&self, calls: Vec<Value>) -> Result<Vec<Value>, EditorError> {
+        let params = Value::Array(vec![Value::Array(calls)]);
+        let response = self.request("nvim_call_atomic", params);
+        log::debug!("Response: {:?}", response);
+        if let NvimMessage::RpcResponse { result, error, .. } = response? {
+            if let Some(error) = error.as_array() {
+                let error_msg = error
+                    .get(1)
+                    .ok_or(EditorError::UnexpectedResponse("Expected error message"))?
+                    .as_str()
+                    .ok_or(EditorError::UnexpectedResponse(
+                        "Expected String error message",
+                    ))?;
+
+                return Err(EditorError::Failed(error_msg.into()));
+            }
+
+            // The result is `[results, error]`, error being `[index, type, message]`
+            // of the call that failed
+            let mut result = match result {
+                Value::Array(result) if !result.is_empty() => result,
+                _ => return Err(EditorError::UnexpectedResponse("Expect result array")),
+            };
+            if let Some(error) = result.get(1).and_then(Value::as_array) {
+                let error_msg = error
+                    .get(2)
+                    .and_then(Value::as_str)
+                    .ok_or(EditorError::UnexpectedResponse("Expected error message"))?;
+
+                return Err(EditorError::Failed(error_msg.into()));
+            }
+
+            match result.swap_remove(0) {
+                Value::Array(results) => Ok(results),
+                _ => Err(EditorError::UnexpectedResponse("Expect result array")),
+            }
+        } else {
+            Err(EditorError::UnexpectedResponse("Expected response"))
+        }
+    }
+
+    pub fn request(&self, method: &str, params: Value) -> Result<NvimMessage, EditorError> {
+        let msgid = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let req = NvimMessage::RpcRequest {
+            msgid,
+            method: method.into(),
+            params: params,
+        };
+
+        let response_receiver = self.rpc_client.subscribe(msgid);
+        self.rpc_client.sender.send(req).unwrap();
+
+        response_receiver
+            .recv_timeout(self.request_timeout)
+            .map_err(|_| {
+                self.rpc_client.unsubscribe(&msgid);
+                EditorError::Timeout
+            })
+    }
+
+    pub fn notify(&self, method: &str, params: &[Value]) -> Result<(), EditorError> {
+        let noti = NvimMessage::RpcNotification {
+            method: method.into(),
+            params: Value::from(params.to_owned()),
+        };
+        // FIXME: add RpcQueueFull to EditorError??
+        self.rpc_client.sender.send(noti).unwrap();
+
+        Ok(())
+    }
+
+    pub fn command(&self, command: &str) -> Result<NvimMessage, EditorError> {
+        let params = vec![Value::from(command)].into();
+        self.request("nvim_command", params)
+    }
+
+    // Call VimL function
+    pub fn call_function(&self, func: &str, args: Value) -> Result<NvimMessage, EditorError> {
+        let params = vec![func.into(), args].into();
+        self.request("nvim_call_function", params)
+    }
+
+    // Lines of a file, read from disk when it's not loaded in a buffer
+    pub fn file_lines(&self, filepath: &str) -> Result<Vec<String>, EditorError> {
+        let params = vec![Value::from(filepath)].into();
+        let response = self.call_function("lspc#command#file_lines", params)?;
+        if let NvimMessage::RpcResponse { result, .. } = response {
+            from_value(result).map_err(|_| EditorError::UnexpectedResponse("Expected file lines"))
+        } else {
+            Err(EditorError::UnexpectedResponse("Expected response"))
+        }
+    }
+
+    // Text of the 1-based `line` of the current buffer
+    pub fn line_text(&self, line: u64) -> Result<String, EditorError> {
+        let params = vec![Value::from(line)].into();
+        let response = self.call_function("getline", params)?;
+        if let NvimMessage::RpcResponse { result, .. } = response {
+            from_value(result).map_err(|_| EditorError::UnexpectedResponse("Expected line"))
+        } else {
+            Err(EditorError::UnexpectedResponse("Expected response"))
+        }
+    }
+
+    pub fn columns(&self) -> Result<usize, EditorError> {
+        let params = vec![Value::from("columns")].into();
+        let response = self.request("nvim_get_option", params)?;
+        if let NvimMessage::RpcResponse { result, .. } = response {
+            from_value(result).map_err(|_| EditorError::UnexpectedResponse("Expected columns"))
+        } else {
+            Err(EditorError::UnexpectedResponse("Expected response"))
+        }
+    }
+
+    // Let the user pick one of `items`, None when cancelled
+    pub fn choose(&self, prompt: &str, items: &[&str]) -> Result<Option<usize>, EditorError> {
+        let items = items
+            .iter()
+            .map(|item| Value::from(*item))
+            .collect::<Vec<_>>();
+        let params = vec![Value::from(prompt), items.into()].into();
+        let response = self.call_function("lspc#command#choose", params)?;
+        if let NvimMessage::RpcResponse { ref result, .. } = response {
+            let index = result
+                .as_i64()
+                .ok_or(EditorError::UnexpectedResponse("Expected chosen index"))?;
+            Ok(if index < 0 {
+                None
+            } else {
+                Some(index as usize)
+            })
+        } else {
+            Err(EditorError::UnexpectedResponse("Expected response"))
+        }
+    }
+
+    pub fn create_namespace(&self, ns_name: &str) -> Result<u64, EditorError> {
+        let params = vec![Value::from(ns_name)].into();
+        let response = self.request("nvim_create_namespace", params)?;
+        log::debug!("Create namespace response: {:?}", response);
+        if let NvimMessage::RpcResponse { ref result, .. } = response {
+            Ok(result.as_u64().ok_or(EditorError::UnexpectedResponse(
+                "Expected nvim_create_namespace respsonse",
+            ))?)
+        } else {
+            Err(EditorError::UnexpectedResponse(
+                "Expected nvim_create_namespace respsonse",
+            ))
+        }
+    }
+
+    // Cached namespace id of `ns_name`, degrade to the fallback
+    // namespace when it can't be created
+    pub fn namespace(&mut self, ns_name: &str) -> Result<u64, EditorError> {
+        if let Some(ns_id) = self.namespaces.get(ns_name) {
+            return Ok(*ns_id);
+        }
+
+        let ns_id = match self.create_namespace(ns_name) {
+            Ok(ns_id) => ns_id,
+            Err(e) if ns_name != FALLBACK_NAMESPACE => {
+                log::warn!(
+                    "Failed to create namespace {}, using fallback namespace: {:?}",
+                    ns_name,
+                    e
+                );
+                self.namespace(FALLBACK_NAMESPACE)?
+            }
+            Err(e) => return Err(e),
+        };
+        // The fallback is kept as well, so what's added to it is cleared
+        // along with the next uses of `ns_name`
+        self.namespaces.insert(ns_name.to_owned(), ns_id);
+
+        Ok(ns_id)
+    }
+
+    // Buffer number of the buffer showing `uri`, looked up in Neovim when
+    // the buffer isn't tracked. None when the file isn't loaded.
+    pub fn bufnr(&self, uri: &Url) -> Result<Option<u64>, EditorError> {
+        if let Some(buf_id) = self.buf_mapper.lock().unwrap().get_by_right(uri) {
+            return Ok(Some(*buf_id as u64));
+        }
+
+        let params = vec![Value::from(format!("{}$", uri_filename(uri)))].into();
+        let response = self.call_function("bufnr", params)?;
+        if let NvimMessage::RpcResponse { result, .. } = response {
+            let bufnr: i64 = from_value(result)
+                .map_err(|_| EditorError::UnexpectedResponse("Expected bufnr"))?;
+            Ok(if bufnr > 0 { Some(bufnr as u64) } else { None })
+        } else {
+            Err(EditorError::UnexpectedResponse("Expected response"))
+        }
+    }
+
+    pub fn current_bufnr(&self) -> Result<i64, EditorError> {
+        let params = vec![Value::from("%")].into();
+        let response = self.call_function("bufnr", params)?;
+        if let NvimMessage::RpcResponse { result, .. } = response {
+            from_value(result).map_err(|_| EditorError::UnexpectedResponse("Expected bufnr"))
+        } else {
+            Err(EditorError::UnexpectedResponse("Expected response"))
+        }
+    }
+
+    // Replace the whole content of a buffer
+    pub fn set_buffer_lines(&self, buffer_id: u64, lines: Vec<Value>) -> Result<(), EditorError> {
+        let params = Value::Array(vec![
+            buffer_id.into(),
+            0.into(),
+            Value::from(-1),
+            false.into(),
+            Value::Array(lines),
+        ]);
+        self.call_function("nvim_buf_set_lines", params)?;
+
+        Ok(())
+    }
+
+    pub fn set_virtual_text(
+        &self,
+        buffer_id: u64,
+        ns_id: u64,
+        line: u64,
+        chunks: Vec<(&str, &str)>,
+    ) -> Result<(), EditorError> {
+        self.notify(
+            "nvim_buf_set_extmark",
+            &virtual_text_args(buffer_id, ns_id, line, chunks),
+        )?;
+
+        Ok(())
+    }
+
+    pub fn set_hl(&self, hl_group: &str, fg: &str) -> Result<(), EditorError> {
+        self.notify(
+            "nvim_set_hl",
+            &vec![
+                Value::from(0),
+                hl_group.into(),
+                Value::Map(vec![("fg".into(), fg.into())]),
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn add_highlight(
+        &self,
+        buffer_id: u64,
+        ns_id: u64,
+        hl_group: &str,
+        line: u64,
+        col_start: u64,
+        col_end: i64,
+    ) -> Result<(), EditorError> {
+        self.notify(
+            "nvim_buf_add_highlight",
+            &vec![
+                buffer_id.into(),
                 ns_id.into(),
+                hl_group.into(),
                 line.into(),
-                chunks,
-                Value::Map(Vec::new()),
+                col_start.into(),
+                col_end.into(),
             ],
         )?;
 
-        Ok(())
+        Ok(())
+    }
+
+    pub fn clear_namespace(&self, buffer_id: u64, ns_id: u64) -> Result<(), EditorError> {
+        self.notify(
+            "nvim_buf_clear_namespace",
+            &vec![
+                buffer_id.into(),
+                ns_id.into(),
+                Value::from(0),
+                Value::from(-1),
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn receiver(&self) -> &Receiver<NvimMessage> {
+        &self.rpc_client.receiver
+    }
+
+    pub fn close(self) {
+        self.thread.join().unwrap();
+    }
+}
+
+impl BufferId for BufferHandler {}
+
+impl Editor for Neovim {
+    type BufferId = BufferHandler;
+
+    fn events(&self) -> Receiver<Event<BufferHandler>> {
+        self.event_receiver.clone()
+    }
+
+    fn capabilities(&self) -> lsp_types::ClientCapabilities {
+        lsp_types::ClientCapabilities {
+            workspace: Some(WorkspaceClientCapabilities {
+                apply_edit: Some(true),
+                ..Default::default()
+            }),
+            text_document: Some(TextDocumentClientCapabilities {
+                hover: Some(HoverCapability {
+                    dynamic_registration: None,
+                    content_format: Some(vec![MarkupKind::PlainText, MarkupKind::Markdown]),
+                }),
+                definition: Some(GotoCapability {
+                    dynamic_registration: None,
+                    link_support: Some(true),
+                }),
+                declaration: Some(GotoCapability {
+                    dynamic_registration: None,
+                    link_support: Some(true),
+                }),
+                implementation: Some(GotoCapability {
+                    dynamic_registration: None,
+                    link_support: Some(true),
+                }),
+                type_definition: Some(GotoCapability {
+                    dynamic_registration: None,
+                    link_support: Some(true),
+                }),
+                code_lens: Some(GenericCapability {
+                    dynamic_registration: None,
+                }),
+                completion: Some(CompletionCapability {
+                    dynamic_registration: None,
+                    completion_item: Some(CompletionItemCapability {
+                        snippet_support: Some(true),
+                        commit_characters_support: None,
+                        documentation_format: Some(vec![
+                            MarkupKind::PlainText,
+                            MarkupKind::Markdown,
+                        ]),
+                        deprecated_support: Some(true),
+                        preselect_support: Some(true),
+                    }),
+                    completion_item_kind: None,
+                    context_support: None,
+                }),
+                signature_help: Some(SignatureHelpCapability {
+                    dynamic_registration: None,
+                    signature_information: Some(SignatureInformationSettings {
+                        documentation_format: Some(vec![
+                            MarkupKind::PlainText,
+                            MarkupKind::Markdown,
+                        ]),
+                        parameter_information: Some(ParameterInformationSettings {
+                            label_offset_support: Some(true),
+                        }),
+                    }),
+                }),
+                code_action: Some(CodeActionCapability {
+                    dynamic_registration: None,
+                    code_action_literal_support: Some(CodeActionLiteralSupport {
+                        code_action_kind: CodeActionKindLiteralSupport {
+                            value_set: CODE_ACTION_KINDS.iter().map(|&kind| kind.into()).collect(),
+                        },
+                    }),
+                }),
+                rename: Some(RenameCapability {
+                    dynamic_registration: None,
+                    prepare_support: Some(true),
+                }),
+                // Related information isn't shown, servers can report it as
+                // diagnostics of their own
+                publish_diagnostics: Some(PublishDiagnosticsCapability {
+                    related_information: Some(false),
+                }),
+                synchronization: Some(SynchronizationCapability {
+                    dynamic_registration: None,
+                    will_save: Some(true),
+                    will_save_wait_until: Some(true),
+                    did_save: Some(true),
+                }),
+                document_symbol: Some(DocumentSymbolCapability {
+                    dynamic_registration: None,
+                    symbol_kind: None,
+                    hierarchical_document_symbol_support: Some(true),
+                }),
+                ..Default::default()
+            }),
+            window: None,
+            experimental: None,
+        }
+    }
+
+    fn say_hello(&self) -> Result<(), EditorError> {
+        let params = vec![Value::from("echo 'hello from the other side'")].into();
+        self.request("nvim_command", params)
+            .map_err(|_| EditorError::Timeout)?;
+
+        Ok(())
+    }
+
+    fn server_ready(&mut self, ready: &ServerReady) -> Result<(), EditorError> {
+        let ready = to_value(ready)
+            .map_err(|e| EditorError::Failed(format!("Failed to encode server info: {}", e)))?;
+        self.call_function("lspc#on_server_ready", Value::Array(vec![ready]))?;
+
+        Ok(())
+    }
+
+    fn message(&mut self, msg: &str) -> Result<(), EditorError> {
+        self.command(&format!("echo '{}'", msg))?;
+        Ok(())
+    }
+
+    fn show_hover(
+        &mut self,
+        buf_id: BufferHandler,
+        _text_document: &TextDocumentIdentifier,
+        hover: &Hover,
+        mode: HoverMode,
+    ) -> Result<(), EditorError> {
+        // The user has moved on to another buffer
+        if self.current_bufnr()? != buf_id.0 {
+            log::debug!("Dropped hover of buffer {:?}", buf_id);
+            return Ok(());
+        }
+        if mode == HoverMode::Echo {
+            let width = self.columns().unwrap_or(80);
+            if let Some(line) = hover_echo_line(&hover.to_display(), width) {
+                let chunks = vec![Value::from(vec![Value::from(line)])];
+                self.notify(
+                    "nvim_echo",
+                    &[Value::from(chunks), Value::from(false), Value::Map(vec![])],
+                )?;
+            }
+            return Ok(());
+        }
+
+        let display = hover.to_display();
+        if display.iter().all(|line| line.trim().is_empty()) {
+            return self.message("No information available");
+        }
+        let bufname = "__LanguageClient__";
+        let filetype = if let Some(ft) = &hover.vim_filetype() {
+            ft.as_str().into()
+        } else {
+            Value::Nil
+        };
+        let lines = display
+            .iter()
+            .map(|item| Value::from(item.as_str()))
+            .collect::<Vec<_>>()
+            .into();
+        self.call_function(
+            "lspc#command#open_hover_preview",
+            vec![bufname.into(), lines, filetype].into(),
+        )?;
+
+        Ok(())
+    }
+
+    fn show_hovers(
+        &mut self,
+        _text_document: &TextDocumentIdentifier,
+        hovers: &[(Position, Hover)],
+    ) -> Result<(), EditorError> {
+        let bufname = "__LanguageClient__";
+        let filetype = hovers
+            .iter()
+            .filter_map(|(_, hover)| hover.vim_filetype())
+            .next()
+            .map(Value::from)
+            .unwrap_or(Value::Nil);
+        let mut lines = Vec::new();
+        for (position, hover) in hovers {
+            if !lines.is_empty() {
+                lines.push(Value::from(""));
+            }
+            lines.push(Value::from(format!(
+                "{}:{}",
+                position.line + 1,
+                position.character + 1
+            )));
+            lines.extend(hover.to_display().into_iter().map(Value::from));
+        }
+        self.call_function(
+            "lspc#command#open_hover_preview",
+            vec![bufname.into(), lines.into(), filetype].into(),
+        )?;
+
+        Ok(())
+    }
+
+    fn inline_hints(
+        &mut self,
+        buf_id: BufferHandler,
+        text_document: &TextDocumentIdentifier,
+        hints: &Vec<InlayHint>,
+    ) -> Result<(), EditorError> {
+        let buf_id = buf_id.0 as u64;
+        let ns_id = self.namespace(text_document.uri.path())?;
+        // Hints of the previous request are replaced
+        self.clear_namespace(buf_id, ns_id)?;
+        let mut calls = hints.iter().map(|hint| {
+            let args = virtual_text_args(
+                buf_id,
+                ns_id,
+                hint.range.start.line,
+                vec![(&hint.label, "LspcInlayHint")],
+            );
+            Value::Array(vec![
+                Value::from("nvim_buf_set_extmark"),
+                Value::Array(args),
+            ])
+        });
+        loop {
+            let batch = calls
+                .by_ref()
+                .take(INLAY_HINTS_PER_CALL)
+                .collect::<Vec<_>>();
+            if batch.is_empty() {
+                break;
+            }
+            self.call_atomic(batch)?;
+        }
+
+        Ok(())
+    }
+
+    fn show_message(&mut self, params: &ShowMessageParams) -> Result<(), EditorError> {
+        self.command(&format!("echo '[LS-{:?}] {}'", params.typ, params.message))?;
+
+        Ok(())
+    }
+
+    fn show_progress(
+        &mut self,
+        token: &ProgressToken,
+        status: Option<&str>,
+    ) -> Result<(), EditorError> {
+        let token = match token {
+            ProgressToken::Number(number) => number.to_string(),
+            ProgressToken::String(string) => string.clone(),
+        };
+        let status = status.map_or(Value::Nil, Value::from);
+        self.call_function(
+            "lspc#command#show_progress",
+            Value::Array(vec![token.into(), status]),
+        )?;
+
+        Ok(())
+    }
+
+    fn show_message_request(
+        &mut self,
+        params: &ShowMessageRequestParams,
+    ) -> Result<Option<MessageActionItem>, EditorError> {
+        let actions = match params.actions {
+            Some(ref actions) if !actions.is_empty() => actions,
+            _ => {
+                self.show_message(&ShowMessageParams {
+                    typ: params.typ,
+                    message: params.message.clone(),
+                })?;
+                return Ok(None);
+            }
+        };
+        let titles = actions
+            .iter()
+            .map(|action| action.title.as_str())
+            .collect::<Vec<_>>();
+        let prompt = format!("[LS-{:?}] {}", params.typ, params.message);
+
+        Ok(self
+            .choose(&prompt, &titles)?
+            .and_then(|index| actions.get(index).cloned()))
+    }
+
+    fn forward_notification(
+        &mut self,
+        method: &str,
+        params: &serde_json::Value,
+    ) -> Result<(), EditorError> {
+        let params = to_value(params).map_err(|e| {
+            EditorError::Failed(format!("Failed to encode notification params: {}", e))
+        })?;
+        self.call_function(
+            "lspc#on_notification",
+            Value::Array(vec![method.into(), params]),
+        )?;
+
+        Ok(())
+    }
+
+    fn document_line(&self, uri: &Url, line: u64) -> Result<String, EditorError> {
+        let params = vec![Value::from(uri_filename(uri)), Value::from(line)].into();
+        let response = self.call_function("lspc#command#file_line", params)?;
+        if let NvimMessage::RpcResponse { result, .. } = response {
+            from_value(result).map_err(|_| EditorError::UnexpectedResponse("Expected line"))
+        } else {
+            Err(EditorError::UnexpectedResponse("Expected response"))
+        }
+    }
+
+    fn input(&mut self, prompt: &str, default: &str) -> Result<Option<String>, EditorError> {
+        let params = vec![Value::from(prompt), Value::from(default)].into();
+        let response = self.call_function("input", params)?;
+        if let NvimMessage::RpcResponse { result, .. } = response {
+            let input = result
+                .as_str()
+                .ok_or(EditorError::UnexpectedResponse("Expected input"))?;
+            Ok(Some(input.to_owned()).filter(|input| !input.is_empty()))
+        } else {
+            Err(EditorError::UnexpectedResponse("Expected response"))
+        }
+    }
+
+    fn buffer_lines(&self, buf_id: BufferHandler) -> Result<Vec<String>, EditorError> {
+        let params = vec![Value::from(buf_id.0)].into();
+        let response = self.call_function("lspc#buffer#text", params)?;
+        if let NvimMessage::RpcResponse { result, .. } = response {
+            from_value(result).map_err(|_| EditorError::UnexpectedResponse("Expected lines"))
+        } else {
+            Err(EditorError::UnexpectedResponse("Expected response"))
+        }
+    }
+
+    fn goto(
+        &mut self,
+        location: &Location,
+        encoding: PositionEncoding,
+        target: GotoTarget,
+    ) -> Result<(), EditorError> {
+        // Servers may point into archives (`jar:`, `zipfile:`) or sources
+        // that aren't installed, e.g. the standard library
+        let filepath = match location.uri.to_file_path() {
+            Ok(filepath) => filepath,
+            Err(_) => {
+                return self.message(&format!("Cannot open {}: not a local file", location.uri))
+            }
+        };
+        if !filepath.exists() {
+            return self.message(&format!(
+                "Cannot open {}: file not found",
+                filepath.display()
+            ));
+        }
+        let filepath = filepath
+            .to_str()
+            .ok_or(EditorError::CommandDataInvalid("Filepath is not UTF-8"))?;
+        // Jumplist entry so `<C-o>` comes back
+        self.command("normal! m'")?;
+        self.command(&format!(
+            "{} {}",
+            goto_command(target),
+            escape_filename(filepath)
+        ))?;
+        let line = location.range.start.line + 1;
+        let character = location.range.start.character as usize;
+        // Columns of Neovim are byte indexes
+        let byte_offset = match encoding {
+            PositionEncoding::Utf8 => character,
+            _ => encoding.byte_offset(&self.line_text(line)?, character),
+        };
+        let params = Value::Array(vec![line.into(), (byte_offset + 1).into()]);
+        self.call_function("cursor", params)?;
+
+        Ok(())
+    }
+
+    fn apply_edits(
+        &self,
+        buf_id: BufferHandler,
+        lines: &Vec<String>,
+        edits: &Vec<TextEdit>,
+        encoding: PositionEncoding,
+    ) -> Result<(), EditorError> {
+        let editted_content = apply_edits(lines, edits, encoding);
+        let new_lines: Vec<Value> = editted_content.split("\n").map(|e| e.into()).collect();
+        self.set_buffer_lines(buf_id.0 as u64, new_lines)
+    }
+
+    fn show_code_actions(
+        &mut self,
+        actions: &[CodeActionOrCommand],
+    ) -> Result<Option<usize>, EditorError> {
+        let titles = actions
+            .iter()
+            .map(|action| match action {
+                CodeActionOrCommand::Command(command) => command.title.as_str(),
+                CodeActionOrCommand::CodeAction(action) => action.title.as_str(),
+            })
+            .collect::<Vec<_>>();
+
+        self.choose("Code actions:", &titles)
+    }
+
+    fn set_selection_ranges(
+        &mut self,
+        buf_id: BufferHandler,
+        ranges: &[SelectionRange],
+    ) -> Result<(), EditorError> {
+        let chains = ranges.iter().map(selection_range_chain).collect::<Vec<_>>();
+        let chains = to_value(&chains).map_err(|e| {
+            EditorError::Failed(format!("Failed to encode selection ranges: {}", e))
+        })?;
+        self.call_function(
+            "lspc#command#set_selection_ranges",
+            Value::Array(vec![buf_id.0.into(), chains]),
+        )?;
+
+        Ok(())
+    }
+
+    fn highlight_references(
+        &mut self,
+        buf_id: BufferHandler,
+        highlights: &[DocumentHighlight],
+    ) -> Result<(), EditorError> {
+        let buf_id = buf_id.0 as u64;
+        let ns_id = self.namespace("lspc_document_highlight")?;
+        self.clear_namespace(buf_id, ns_id)?;
+        for highlight in highlights {
+            let hl_group = document_highlight_hl_group(highlight.kind.as_ref());
+            let range = highlight.range;
+            for line in range.start.line..=range.end.line {
+                let col_start = if line == range.start.line {
+                    range.start.character
+                } else {
+                    0
+                };
+                // -1 highlights until the end of line
+                let col_end = if line == range.end.line {
+                    range.end.character as i64
+                } else {
+                    -1
+                };
+                self.add_highlight(buf_id, ns_id, hl_group, line, col_start, col_end)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn show_document_links(
+        &mut self,
+        buf_id: BufferHandler,
+        links: &[DocumentLink],
+    ) -> Result<(), EditorError> {
+        let buf_id = buf_id.0 as u64;
+        let ns_id = self.namespace("lspc_document_link")?;
+        self.clear_namespace(buf_id, ns_id)?;
+        for link in links {
+            let range = link.range;
+            // Links spanning several lines are only underlined on the first one
+            let col_end = if range.end.line == range.start.line {
+                range.end.character as i64
+            } else {
+                -1
+            };
+            self.add_highlight(
+                buf_id,
+                ns_id,
+                "LspcDocumentLink",
+                range.start.line,
+                range.start.character,
+                col_end,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn show_document_colors(
+        &mut self,
+        buf_id: BufferHandler,
+        colors: &[ColorInformation],
+    ) -> Result<(), EditorError> {
+        let buf_id = buf_id.0 as u64;
+        let ns_id = self.namespace("lspc_document_color")?;
+        self.clear_namespace(buf_id, ns_id)?;
+        for color in colors {
+            let hex = color_hex(&color.color);
+            let hl_group = format!("LspcColor{}", hex);
+            self.set_hl(&hl_group, &format!("#{}", hex))?;
+            // Virtual text can't be translucent, only fully transparent colors
+            // are told apart
+            let swatch = if color.color.alpha > 0.0 {
+                "■"
+            } else {
+                "□"
+            };
+            self.set_virtual_text(
+                buf_id,
+                ns_id,
+                color.range.start.line,
+                vec![(swatch, hl_group.as_str())],
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn choose_color_presentation(
+        &mut self,
+        presentations: &[ColorPresentation],
+    ) -> Result<Option<usize>, EditorError> {
+        let items = presentations
+            .iter()
+            .map(|presentation| presentation.label.as_str())
+            .collect::<Vec<_>>();
+
+        self.choose("Color presentations:", &items)
+    }
+
+    fn show_code_lens(
+        &mut self,
+        buf_id: BufferHandler,
+        lenses: &[CodeLens],
+    ) -> Result<(), EditorError> {
+        let buf_id = buf_id.0 as u64;
+        let ns_id = self.namespace("lspc_code_lens")?;
+        self.clear_namespace(buf_id, ns_id)?;
+
+        // Lenses of the same line share its virtual text
+        let mut titles: Vec<(u64, Vec<&str>)> = Vec::new();
+        for lens in lenses {
+            let title = match lens.command {
+                Some(ref command) => command.title.as_str(),
+                None => continue,
+            };
+            let line = lens.range.start.line;
+            match titles.iter_mut().find(|(l, _)| *l == line) {
+                Some((_, line_titles)) => line_titles.push(title),
+                None => titles.push((line, vec![title])),
+            }
+        }
+        for (line, line_titles) in titles {
+            let text = line_titles.join(" | ");
+            self.set_virtual_text(buf_id, ns_id, line, vec![(text.as_str(), "LspcCodeLens")])?;
+        }
+
+        Ok(())
+    }
+
+    fn apply_semantic_tokens(
+        &mut self,
+        buf_id: BufferHandler,
+        tokens: &[SemanticToken],
+    ) -> Result<(), EditorError> {
+        let buf_id = buf_id.0 as u64;
+        let ns_id = self.namespace("lspc_semantic_tokens")?;
+        self.clear_namespace(buf_id, ns_id)?;
+        for token in tokens {
+            self.add_highlight(
+                buf_id,
+                ns_id,
+                &semantic_token_hl_group(&token.token_type),
+                token.line,
+                token.start,
+                (token.start + token.length) as i64,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn show_document_symbols(
+        &mut self,
+        symbols: &DocumentSymbolResponse,
+    ) -> Result<(), EditorError> {
+        let items = document_symbol_entries(symbols)
+            .into_iter()
+            .map(|(uri, position, text)| {
+                let mut item: Vec<(Value, Value)> = Vec::new();
+                if let Some(uri) = uri {
+                    item.push(("filename".into(), uri_filename(uri).into()));
+                }
+                item.push(("lnum".into(), (position.line + 1).into()));
+                item.push(("col".into(), (position.character + 1).into()));
+                item.push(("text".into(), text.into()));
+                Value::from(item)
+            })
+            .collect::<Vec<_>>();
+        self.call_function(
+            "lspc#command#show_document_symbols",
+            Value::Array(vec![items.into()]),
+        )?;
+
+        Ok(())
+    }
+
+    fn list_diagnostics(
+        &mut self,
+        text_document: &TextDocumentIdentifier,
+        diagnostics: &[Diagnostic],
+    ) -> Result<(), EditorError> {
+        let items = diagnostics
+            .iter()
+            .map(|diagnostic| {
+                let mut item: Vec<(Value, Value)> = Vec::new();
+                item.push(("filename".into(), uri_filename(&text_document.uri).into()));
+                item.push(("lnum".into(), (diagnostic.range.start.line + 1).into()));
+                item.push(("col".into(), (diagnostic.range.start.character + 1).into()));
+                item.push((
+                    "type".into(),
+                    diagnostic_list_type(diagnostic.severity).into(),
+                ));
+                item.push(("text".into(), diagnostic.message.as_str().into()));
+                Value::from(item)
+            })
+            .collect::<Vec<_>>();
+        self.call_function(
+            "lspc#command#show_diagnostics_list",
+            Value::Array(vec![items.into()]),
+        )?;
+
+        Ok(())
+    }
+
+    fn show_call_hierarchy(
+        &mut self,
+        direction: CallDirection,
+        item: &CallHierarchyItem,
+        calls: &[(CallHierarchyItem, Location)],
+    ) -> Result<(), EditorError> {
+        let title = match direction {
+            CallDirection::Incoming => format!("Lspc calls to {}", item.name),
+            CallDirection::Outgoing => format!("Lspc calls from {}", item.name),
+        };
+        let items = calls
+            .iter()
+            .map(|(call, location)| {
+                let mut item: Vec<(Value, Value)> = Vec::new();
+                item.push(("filename".into(), uri_filename(&location.uri).into()));
+                item.push(("lnum".into(), (location.range.start.line + 1).into()));
+                item.push(("col".into(), (location.range.start.character + 1).into()));
+                item.push((
+                    "text".into(),
+                    format!("[{:?}] {}", call.kind, call.name).into(),
+                ));
+                Value::from(item)
+            })
+            .collect::<Vec<_>>();
+        self.call_function(
+            "lspc#command#show_call_hierarchy",
+            Value::Array(vec![title.into(), items.into()]),
+        )?;
+
+        Ok(())
+    }
+
+    fn show_workspace_symbols(&mut self, symbols: &[SymbolInformation]) -> Result<(), EditorError> {
+        let items = symbols
+            .iter()
+            .map(|symbol| {
+                let mut item: Vec<(Value, Value)> = Vec::new();
+                item.push(("filename".into(), uri_filename(&symbol.location.uri).into()));
+                item.push(("lnum".into(), (symbol.location.range.start.line + 1).into()));
+                item.push((
+                    "col".into(),
+                    (symbol.location.range.start.character + 1).into(),
+                ));
+                item.push((
+                    "text".into(),
+                    format!("[{:?}] {}", symbol.kind, symbol.name).into(),
+                ));
+                Value::from(item)
+            })
+            .collect::<Vec<_>>();
+        self.call_function(
+            "lspc#command#show_workspace_symbols",
+            Value::Array(vec![items.into()]),
+        )?;
+
+        Ok(())
+    }
+
+    fn choose_workspace_symbol(
+        &mut self,
+        symbols: &[WorkspaceSymbol],
+    ) -> Result<Option<usize>, EditorError> {
+        let items = symbols
+            .iter()
+            .map(|symbol| match symbol.container_name {
+                Some(ref container) => {
+                    format!("[{:?}] {} ({})", symbol.kind, symbol.name, container)
+                }
+                None => format!("[{:?}] {}", symbol.kind, symbol.name),
+            })
+            .collect::<Vec<_>>();
+        let items = items.iter().map(String::as_str).collect::<Vec<_>>();
+
+        self.choose("Workspace symbols:", &items)
+    }
+
+    fn apply_workspace_edit(
+        &mut self,
+        edit: &WorkspaceEdit,
+        encoding: PositionEncoding,
+    ) -> Result<(), EditorError> {
+        for (uri, edits) in workspace_edit_changes(edit) {
+            let filepath = uri
+                .to_file_path()
+                .map_err(|_| EditorError::CommandDataInvalid("Edit URI is not file path"))?;
+            let filepath = filepath
+                .to_str()
+                .ok_or(EditorError::CommandDataInvalid("Filepath is not UTF-8"))?;
+
+            let lines = self.file_lines(filepath)?;
+            let editted_content = apply_edits(&lines, &edits, encoding);
+            let new_lines: Vec<Value> = editted_content.split("\n").map(|e| e.into()).collect();
+            match self.bufnr(&uri)? {
+                Some(buf_id) => self.set_buffer_lines(buf_id, new_lines)?,
+                None => {
+                    self.call_function(
+                        "lspc#command#set_file_lines",
+                        Value::Array(vec![filepath.into(), new_lines.into()]),
+                    )?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn show_references(&mut self, locations: &Vec<Location>) -> Result<(), EditorError> {
+        if locations.is_empty() {
+            return self.message("No references found");
+        }
+
+        // Servers may list a location twice, e.g. a declaration also found
+        // as a reference
+        let mut starts = locations
+            .iter()
+            .map(|location| {
+                let start = location.range.start;
+                (uri_filename(&location.uri), start.line, start.character)
+            })
+            .collect::<Vec<_>>();
+        starts.sort();
+        starts.dedup();
+
+        let mut items: Vec<Value> = Vec::new();
+        for (path, line, character) in starts {
+            let mut item: Vec<(Value, Value)> = Vec::new();
+            item.push(("filename".into(), path.into()));
+            item.push(("lnum".into(), (line + 1).into()));
+            item.push(("col".into(), (character + 1).into()));
+            items.push(Value::from(item));
+        }
+        self.call_function(
+            "lspc#command#open_reference_preview",
+            Value::Array(vec![items.into()]),
+        )?;
+
+        Ok(())
+    }
+
+    fn show_completions(&mut self, items: &Vec<CompletionItem>) -> Result<(), EditorError> {
+        let items = sort_completion_items(items)
+            .into_iter()
+            .map(to_complete_item)
+            .collect::<Result<Vec<_>, _>>()?;
+        self.call_function(
+            "lspc#command#show_completions",
+            Value::Array(vec![items.into()]),
+        )?;
+
+        Ok(())
+    }
+
+    fn show_completion_item(&mut self, item: &CompletionItem) -> Result<(), EditorError> {
+        let lines = item
+            .to_display()
+            .iter()
+            .map(|line| Value::from(line.as_str()))
+            .collect::<Vec<_>>()
+            .into();
+        self.call_function(
+            "lspc#command#show_completion_info",
+            Value::Array(vec![lines]),
+        )?;
+
+        Ok(())
+    }
+
+    fn expand_snippet(&mut self, buf_id: BufferHandler, snippet: &str) -> Result<(), EditorError> {
+        let params = vec![
+            Value::from(buf_id.0),
+            Value::from(snippet_text(snippet)),
+            Value::from(snippet),
+        ];
+        self.call_function("lspc#command#expand_snippet", params.into())?;
+
+        Ok(())
+    }
+
+    fn show_signature_help(&mut self, help: &SignatureHelp) -> Result<(), EditorError> {
+        let active_signature = help.active_signature.unwrap_or(0) as usize;
+        let signature = match help.signatures.get(active_signature) {
+            Some(signature) => signature,
+            None => {
+                self.call_function("lspc#command#close_signature_help", Value::Array(vec![]))?;
+                return Ok(());
+            }
+        };
+
+        let active_parameter = help.active_parameter.unwrap_or(0) as usize;
+        let highlight = signature
+            .parameters
+            .as_ref()
+            .and_then(|parameters| parameters.get(active_parameter))
+            .and_then(|parameter| parameter_label_range(&signature.label, &parameter.label))
+            .map(|(start, end)| Value::Array(vec![start.into(), end.into()]))
+            .unwrap_or(Value::Nil);
+        self.call_function(
+            "lspc#command#show_signature_help",
+            Value::Array(vec![signature.label.as_str().into(), highlight]),
+        )?;
+
+        Ok(())
+    }
+
+    fn show_diagnostics(
+        &mut self,
+        text_document: &TextDocumentIdentifier,
+        diagnostics: &Vec<Diagnostic>,
+    ) -> Result<(), EditorError> {
+        let items = diagnostics
+            .iter()
+            .map(|diagnostic| {
+                let message = diagnostic.message.lines().next().unwrap_or("");
+                Value::Map(vec![
+                    ("line".into(), diagnostic.range.start.line.into()),
+                    ("message".into(), message.into()),
+                    (
+                        "hl_group".into(),
+                        diagnostic_hl_group(diagnostic.severity).into(),
+                    ),
+                    (
+                        "severity".into(),
+                        diagnostic_severity_name(diagnostic.severity).into(),
+                    ),
+                ])
+            })
+            .collect::<Vec<_>>();
+        self.call_function(
+            "lspc#command#show_diagnostics",
+            Value::Array(vec![uri_filename(&text_document.uri).into(), items.into()]),
+        )?;
+
+        Ok(())
+    }
+
+    fn will_save_done(&mut self, buf_id: BufferHandler) -> Result<(), EditorError> {
+        self.call_function("lspc#will_save_done", Value::Array(vec![buf_id.0.into()]))?;
+        Ok(())
+    }
+
+    fn track_all_buffers(&self) -> Result<(), EditorError> {
+        self.call_function("lspc#track_all_buffers", Value::Array(vec![]))?;
+        Ok(())
+    }
+
+    fn watch_file_events(&mut self, buf_id: BufferHandler) -> Result<(), EditorError> {
+        // nvim_buf_attach({buffer}, {send_buffer}, {opts})
+        #[derive(Serialize)]
+        struct AttachBufParams(i64, bool, HashMap<(), ()>);
+
+        let attach_buf_params = AttachBufParams(buf_id.0, true, HashMap::new());
+        let params = to_value(attach_buf_params).map_err(|e| {
+            EditorError::Failed(format!("Failed to encode params: {}", e.description()))
+        })?;
+        let _result = self.request("nvim_buf_attach", params)?;
+
+        Ok(())
+    }
+}
+
+impl Message for NvimMessage {
+    type Id = u64;
+
+    fn read(r: &mut impl BufRead) -> Result<Option<NvimMessage>, RpcError> {
+        let value = read_value(r).map_err(|e| RpcError::Read(e.description().into()))?;
+        log::debug!("< Nvim: {:?}", value);
+        let inner: NvimMessage =
+            from_value(value).map_err(|e| RpcError::Deserialize(e.description().into()))?;
+        let r = Some(inner);
+
+        Ok(r)
+    }
+
+    fn write(self, w: &mut impl Write) -> Result<(), RpcError> {
+        log::debug!("> Nvim: {:?}", self);
+
+        let value = Value::from(self);
+        write_value(w, &value).map_err(|e| RpcError::Write(e.description().into()))?;
+        w.flush()
+            .map_err(|e| RpcError::Write(e.description().into()))?;
+
+        Ok(())
+    }
+
+    fn is_exit(&self) -> bool {
+        match self {
+            NvimMessage::RpcNotification { method, .. } => method == "exit",
+            _ => false,
+        }
+    }
+
+    fn id(&self) -> Option<u64> {
+        match self {
+            NvimMessage::RpcRequest { msgid, .. } | NvimMessage::RpcResponse { msgid, .. } => {
+                Some(*msgid)
+            }
+            NvimMessage::RpcNotification { .. } => None,
+        }
+    }
+
+    fn is_response(&self) -> bool {
+        match self {
+            NvimMessage::RpcResponse { .. } => true,
+            _ => false,
+        }
+    }
+}
+
+// Same layout as the `Serialize` impl, but moving method and params into the
+// value instead of copying them, messages are consumed when written anyway
+impl From<NvimMessage> for Value {
+    fn from(msg: NvimMessage) -> Self {
+        match msg {
+            NvimMessage::RpcRequest {
+                msgid,
+                method,
+                params,
+            } => Value::Array(vec![
+                Value::from(0),
+                Value::from(msgid),
+                Value::from(method),
+                params,
+            ]),
+            NvimMessage::RpcResponse {
+                msgid,
+                error,
+                result,
+            } => Value::Array(vec![Value::from(1), Value::from(msgid), error, result]),
+            NvimMessage::RpcNotification { method, params } => {
+                Value::Array(vec![Value::from(2), Value::from(method), params])
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub struct BufferHandler(i64);
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub struct WindowHandler(i64);
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub struct TabpageHandler(i64);
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum NvimHandle {
+    Buffer(BufferHandler),
+    Window(WindowHandler),
+    Tabpage(TabpageHandler),
+}
+
+impl NvimHandle {
+    pub fn is_buf(&self) -> bool {
+        match self {
+            NvimHandle::Buffer(_) => true,
+            _ => false,
+        }
+    }
+
+    pub fn is_win(&self) -> bool {
+        match self {
+            NvimHandle::Window(_) => true,
+            _ => false,
+        }
+    }
+
+    pub fn is_tab(&self) -> bool {
+        match self {
+            NvimHandle::Tabpage(_) => true,
+            _ => false,
+        }
+    }
+
+    pub fn unwrap_buf(self) -> BufferHandler {
+        match self {
+            NvimHandle::Buffer(buf) => buf,
+            _ => panic!("unwrap_buf on non buf"),
+        }
+    }
+
+    pub fn unwrap_win(self) -> WindowHandler {
+        match self {
+            NvimHandle::Window(win) => win,
+            _ => panic!("unwrap_win on non win"),
+        }
+    }
+
+    pub fn unwrap_tab(self) -> TabpageHandler {
+        match self {
+            NvimHandle::Tabpage(tab) => tab,
+            _ => panic!("unwrap_tab on non tab"),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for NvimHandle {
+    fn deserialize<D>(deserializer: D) -> Result<NvimHandle, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct NvimHandleVisitor;
+        impl<'de> Visitor<'de> for NvimHandleVisitor {
+            type Value = NvimHandle;
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("_ExtStruct((i8, &[u8]))")
+            }
+
+            fn visit_newtype_struct<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+            where
+                D: serde::Deserializer<'de>,
+            {
+                let (tag, bytes): (i8, serde_bytes::ByteBuf) =
+                    Deserialize::deserialize(deserializer)?;
+
+                let handle: i64 = match bytes.len() {
+                    1 => bytes[0] as i8 as i64,
+                    2 => {
+                        let mut b: [u8; 2] = [0; 2];
+                        b.copy_from_slice(&bytes[..]);
+                        i16::from_le_bytes(b) as i64
+                    }
+                    4 => {
+                        let mut b: [u8; 4] = [0; 4];
+                        b.copy_from_slice(&bytes[..]);
+                        i32::from_le_bytes(b) as i64
+                    }
+                    8 => {
+                        let mut b: [u8; 8] = [0; 8];
+                        b.copy_from_slice(&bytes[..]);
+                        i64::from_le_bytes(b)
+                    }
+                    _ => return Err(<D::Error as de::Error>::custom("invalid bytes length")),
+                };
+
+                match tag {
+                    0 => Ok(NvimHandle::Buffer(BufferHandler(handle))),
+                    1 => Ok(NvimHandle::Window(WindowHandler(handle))),
+                    2 => Ok(NvimHandle::Tabpage(TabpageHandler(handle))),
+                    _ => Err(<D::Error as de::Error>::custom("unknown tag")),
+                }
+            }
+        }
+
+        deserializer.deserialize_newtype_struct(rmpv::MSGPACK_EXT_STRUCT_NAME, NvimHandleVisitor)
+    }
+}
+
+impl Serialize for NvimHandle {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let ext_data = match self {
+            NvimHandle::Buffer(BufferHandler(buf_id)) => (0, buf_id.to_le_bytes()),
+            NvimHandle::Window(WindowHandler(win_id)) => (1, win_id.to_le_bytes()),
+            NvimHandle::Tabpage(TabpageHandler(tab_id)) => (2, tab_id.to_le_bytes()),
+        };
+
+        serializer.serialize_newtype_struct(rmpv::MSGPACK_EXT_STRUCT_NAME, &ext_data)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum NvimMessage {
+    RpcRequest {
+        msgid: u64,
+        method: String,
+        params: Value,
+    }, // 0
+    RpcResponse {
+        msgid: u64,
+        error: Value,
+        result: Value,
+    }, // 1
+    RpcNotification {
+        method: String,
+        params: Value,
+    }, // 2
+}
+
+impl Serialize for NvimMessage {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        use NvimMessage::*;
+
+        match self {
+            RpcRequest {
+                msgid,
+                method,
+                params,
+            } => {
+                let mut seq = serializer.serialize_seq(Some(4))?;
+                seq.serialize_element(&0)?;
+                seq.serialize_element(&msgid)?;
+                seq.serialize_element(&method)?;
+                seq.serialize_element(&params)?;
+                seq.end()
+            }
+            RpcResponse {
+                msgid,
+                error,
+                result,
+            } => {
+                let mut seq = serializer.serialize_seq(Some(4))?;
+                seq.serialize_element(&1)?;
+                seq.serialize_element(&msgid)?;
+                seq.serialize_element(&error)?;
+                seq.serialize_element(&result)?;
+                seq.end()
+            }
+            RpcNotification { method, params } => {
+                let mut seq = serializer.serialize_seq(Some(3))?;
+                seq.serialize_element(&2)?;
+                seq.serialize_element(&method)?;
+                seq.serialize_element(&params)?;
+                seq.end()
+            }
+        }
+    }
+}
+
+struct RpcVisitor;
+
+impl<'de> Visitor<'de> for RpcVisitor {
+    type Value = NvimMessage;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("seq (tag, [msgid], method, params)")
+    }
+
+    fn visit_seq<V>(self, mut seq: V) -> Result<Self::Value, V::Error>
+    where
+        V: SeqAccess<'de>,
+    {
+        use NvimMessage::*;
+
+        let tag: i64 = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+        if tag == 0 {
+            let msgid = seq
+                .next_element()?
+                .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+            let method: String = seq
+                .next_element()?
+                .ok_or_else(|| de::Error::invalid_length(2, &self))?;
+            let params: Value = seq
+                .next_element()?
+                .ok_or_else(|| de::Error::invalid_length(3, &self))?;
+
+            Ok(RpcRequest {
+                msgid,
+                method,
+                params,
+            })
+        } else if tag == 1 {
+            let msgid = seq
+                .next_element()?
+                .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+            let error: Value = seq
+                .next_element()?
+                .ok_or_else(|| de::Error::invalid_length(2, &self))?;
+            let result: Value = seq
+                .next_element()?
+                .ok_or_else(|| de::Error::invalid_length(3, &self))?;
+
+            Ok(RpcResponse {
+                msgid,
+                error,
+                result,
+            })
+        } else if tag == 2 {
+            let method: String = seq
+                .next_element()?
+                .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+            let params: Value = seq
+                .next_element()?
+                .ok_or_else(|| de::Error::invalid_length(2, &self))?;
+
+            Ok(RpcNotification { method, params })
+        } else {
+            Err(de::Error::invalid_value(
+                de::Unexpected::Other("invalid tag"),
+                &self,
+            ))
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for NvimMessage {
+    fn deserialize<D>(deserializer: D) -> Result<NvimMessage, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_seq(RpcVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lspc::{types::InlayKind, Transport};
+    use crate::rpc::testing::pipe;
+    use lsp_types::{Position, Range, TextEdit};
+
+    fn mock_buf_mapper() -> Mutex<BiMap<i64, Url>> {
+        Mutex::new(BiMap::new())
+    }
+
+    #[test]
+    fn test_apply_edits() {
+        let original_content = String::from("fn   a() {\n  print!(\"hello\");\n}");
+        let lines = original_content
+            .split("\n")
+            .map(String::from)
+            .collect::<Vec<String>>();
+        let edits = vec![
+            TextEdit::new(
+                Range::new(Position::new(0, 3), Position::new(0, 5)),
+                String::from(""),
+            ),
+            TextEdit::new(
+                Range::new(Position::new(1, 0), Position::new(1, 0)),
+                String::from("  "),
+            ),
+        ];
+        let editted_content = apply_edits(&lines, &edits, PositionEncoding::Utf16);
+        let expected_content = String::from("fn a() {\n    print!(\"hello\");\n}");
+        assert_eq!(editted_content, expected_content);
+    }
+
+    #[test]
+    fn test_apply_adjacent_edits() {
+        let lines = vec![String::from("let   x = y;")];
+        let edits = vec![
+            TextEdit::new(
+                Range::new(Position::new(0, 3), Position::new(0, 6)),
+                String::from(" "),
+            ),
+            // Touches the end of the previous edit
+            TextEdit::new(
+                Range::new(Position::new(0, 6), Position::new(0, 7)),
+                String::from("z"),
+            ),
+            // Insertion at the start of the first edit
+            TextEdit::new(
+                Range::new(Position::new(0, 3), Position::new(0, 3)),
+                String::from(" mut"),
+            ),
+        ];
+
+        let editted_content = apply_edits(&lines, &edits, PositionEncoding::Utf16);
+        assert_eq!("let mut z = y;", editted_content);
+    }
+
+    #[test]
+    fn test_apply_edits_skips_overlapping_and_reversed_edits() {
+        let lines = vec![String::from("let x = y;")];
+        let edits = vec![
+            TextEdit::new(
+                Range::new(Position::new(0, 4), Position::new(0, 5)),
+                String::from("z"),
+            ),
+            // Overlaps the previous edit
+            TextEdit::new(
+                Range::new(Position::new(0, 2), Position::new(0, 5)),
+                String::from("t"),
+            ),
+            TextEdit::new(
+                Range::new(Position::new(0, 9), Position::new(0, 8)),
+                String::from("w"),
+            ),
+        ];
+
+        let editted_content = apply_edits(&lines, &edits, PositionEncoding::Utf16);
+        assert_eq!("let z = y;", editted_content);
+    }
+
+    #[test]
+    fn test_apply_edits_with_crlf_line_endings() {
+        let original_content = String::from("fn   a() {\r\n  print!(\"hello\");\r\n}");
+        let lines = original_content
+            .split("\n")
+            .map(String::from)
+            .collect::<Vec<String>>();
+        let edits = vec![
+            TextEdit::new(
+                Range::new(Position::new(0, 3), Position::new(0, 5)),
+                String::from(""),
+            ),
+            TextEdit::new(
+                Range::new(Position::new(1, 0), Position::new(1, 0)),
+                String::from("  "),
+            ),
+            TextEdit::new(
+                Range::new(Position::new(2, 0), Position::new(2, 0)),
+                String::from("// end\n"),
+            ),
+        ];
+        let editted_content = apply_edits(&lines, &edits, PositionEncoding::Utf16);
+        let expected_content = String::from("fn a() {\r\n    print!(\"hello\");\r\n// end\r\n}");
+        assert_eq!(editted_content, expected_content);
+    }
+
+    #[test]
+    fn test_apply_edits_after_multibyte_characters() {
+        let lines = vec![
+            String::from("let café = \"😀\";"),
+            String::from("let   x = 1;"),
+        ];
+        let edits = vec![
+            // `😀` is two UTF-16 code units
+            TextEdit::new(
+                Range::new(Position::new(0, 12), Position::new(0, 14)),
+                String::from("🎉"),
+            ),
+            TextEdit::new(
+                Range::new(Position::new(0, 4), Position::new(0, 8)),
+                String::from("tea"),
+            ),
+            TextEdit::new(
+                Range::new(Position::new(1, 4), Position::new(1, 6)),
+                String::from(""),
+            ),
+        ];
+
+        let editted_content = apply_edits(&lines, &edits, PositionEncoding::Utf16);
+        assert_eq!("let tea = \"🎉\";\nlet x = 1;", editted_content);
     }
 
-    pub fn receiver(&self) -> &Receiver<NvimMessage> {
-        &self.rpc_client.receiver
+    #[test]
+    fn test_apply_edits_in_negotiated_encoding() {
+        let lines = vec![String::from("let café = \"😀\";")];
+        // `é` is two bytes, `😀` four
+        let edits = vec![TextEdit::new(
+            Range::new(Position::new(0, 13), Position::new(0, 17)),
+            String::from("🎉"),
+        )];
+
+        let editted_content = apply_edits(&lines, &edits, PositionEncoding::Utf8);
+        assert_eq!("let café = \"🎉\";", editted_content);
+
+        let edits = vec![TextEdit::new(
+            Range::new(Position::new(0, 12), Position::new(0, 13)),
+            String::from("🎉"),
+        )];
+        let editted_content = apply_edits(&lines, &edits, PositionEncoding::Utf32);
+        assert_eq!("let café = \"🎉\";", editted_content);
     }
 
-    pub fn close(self) {
-        self.thread.join().unwrap();
+    #[test]
+    fn test_apply_edits_on_large_buffer() {
+        let lines = (0..50_000)
+            .map(|i| format!("let   x{} = {};", i, i))
+            .collect::<Vec<_>>();
+        let edits = (0..50_000)
+            .map(|line| {
+                TextEdit::new(
+                    Range::new(Position::new(line, 3), Position::new(line, 6)),
+                    String::from(" "),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let editted_content = apply_edits(&lines, &edits, PositionEncoding::Utf16);
+        let mut editted_lines = editted_content.split('\n');
+        assert_eq!(Some("let x0 = 0;"), editted_lines.next());
+        assert_eq!(Some("let x49999 = 49999;"), editted_lines.last());
     }
-}
 
-impl BufferId for BufferHandler {}
+    #[test]
+    fn test_parameter_label_range() {
+        let label = "fn greet(name: &str, times: usize)";
 
-impl Editor for Neovim {
-    type BufferId = BufferHandler;
+        let simple = ParameterLabel::Simple(String::from("times: usize"));
+        assert_eq!(Some((21, 33)), parameter_label_range(label, &simple));
 
-    fn events(&self) -> Receiver<Event> {
-        self.event_receiver.clone()
-    }
+        let offsets = ParameterLabel::LabelOffsets([9, 19]);
+        assert_eq!(Some((9, 19)), parameter_label_range(label, &offsets));
 
-    fn capabilities(&self) -> lsp_types::ClientCapabilities {
-        lsp_types::ClientCapabilities {
-            workspace: None,
-            text_document: Some(TextDocumentClientCapabilities {
-                hover: Some(HoverCapability {
-                    dynamic_registration: None,
-                    content_format: Some(vec![MarkupKind::PlainText, MarkupKind::Markdown]),
-                }),
-                definition: Some(GotoCapability {
-                    dynamic_registration: None,
-                    link_support: None,
-                }),
-                ..Default::default()
-            }),
-            window: None,
-            experimental: None,
-        }
+        let unicode_label = "fn é(a: i32)";
+        let offsets = ParameterLabel::LabelOffsets([5, 11]);
+        assert_eq!(
+            Some((6, 12)),
+            parameter_label_range(unicode_label, &offsets)
+        );
     }
 
-    fn say_hello(&self) -> Result<(), EditorError> {
-        let params = vec![Value::from("echo 'hello from the other side'")].into();
-        self.request("nvim_command", params)
-            .map_err(|_| EditorError::Timeout)?;
+    #[test]
+    fn test_deserialize_ls_config() {
+        let value = Value::Map(vec![
+            (
+                Value::from("root_markers"),
+                Value::from(vec![Value::from("Cargo.lock")]),
+            ),
+            (
+                Value::from("command"),
+                Value::from(vec![Value::from("rustup"), Value::from("run")]),
+            ),
+            (Value::from("indentation"), Value::from(4)),
+            (Value::from("indentation_with_space"), Value::from(true)),
+        ]);
 
-        Ok(())
+        let ls_config: LsConfig = Deserialize::deserialize(value).unwrap();
+        let expected = LsConfig {
+            command: vec!["rustup".to_owned(), "run".to_owned()],
+            root_markers: vec!["Cargo.lock".to_owned()],
+            indentation: 4,
+            indentation_with_space: true,
+            sync_burst_threshold: 10,
+            sync_delay_ms: 500,
+            timer_tick_ms: 100,
+            forward_notifications: false,
+            hover_mode: HoverMode::Preview,
+            format_on_save: false,
+            min_diagnostic_severity: DiagnosticLevel::Hint,
+            request_timeout_ms: 60_000,
+            transport: Transport::Stdio,
+            restart_on_crash: false,
+            settings: serde_json::Value::Null,
+            workspace_folders: Vec::new(),
+            initialization_options: serde_json::Value::Null,
+            env: HashMap::new(),
+            cwd: None,
+            trace: TraceValue::Off,
+            require_root_marker: false,
+        };
+
+        assert_eq!(expected, ls_config);
     }
 
-    fn message(&mut self, msg: &str) -> Result<(), EditorError> {
-        self.command(&format!("echo '{}'", msg))?;
-        Ok(())
+    #[test]
+    fn test_deserialize_ls_config_initialization_options() {
+        let value = Value::Map(vec![
+            (
+                Value::from("root_markers"),
+                Value::from(Vec::<Value>::new()),
+            ),
+            (
+                Value::from("initialization_options"),
+                Value::Map(vec![(
+                    Value::from("cargo"),
+                    Value::Map(vec![
+                        (Value::from("loadOutDirsFromCheck"), Value::from(true)),
+                        (
+                            Value::from("features"),
+                            Value::from(vec![Value::from("serde")]),
+                        ),
+                    ]),
+                )]),
+            ),
+        ]);
+
+        let ls_config: LsConfig = Deserialize::deserialize(value).unwrap();
+        assert_eq!(
+            serde_json::json!({
+                "cargo": { "loadOutDirsFromCheck": true, "features": ["serde"] }
+            }),
+            ls_config.initialization_options
+        );
     }
 
-    fn show_hover(
-        &mut self,
-        _text_document: &TextDocumentIdentifier,
-        hover: &Hover,
-    ) -> Result<(), EditorError> {
-        // FIXME: check current buffer is `text_document`
-        let bufname = "__LanguageClient__";
-        let filetype = if let Some(ft) = &hover.vim_filetype() {
-            ft.as_str().into()
-        } else {
-            Value::Nil
-        };
-        let lines = hover
-            .to_display()
-            .iter()
-            .map(|item| Value::from(item.as_str()))
-            .collect::<Vec<_>>()
-            .into();
-        self.call_function(
-            "lspc#command#open_hover_preview",
-            vec![bufname.into(), lines, filetype].into(),
-        )?;
+    #[test]
+    fn test_deserialize_tcp_transport() {
+        let value = Value::Map(vec![
+            (Value::from("kind"), Value::from("tcp")),
+            (Value::from("host"), Value::from("127.0.0.1")),
+            (Value::from("port"), Value::from(9257)),
+        ]);
 
-        Ok(())
+        let transport: Transport = Deserialize::deserialize(value).unwrap();
+        assert_eq!(
+            Transport::Tcp {
+                host: "127.0.0.1".to_owned(),
+                port: 9257,
+            },
+            transport
+        );
     }
 
-    fn inline_hints(
-        &mut self,
-        text_document: &TextDocumentIdentifier,
-        hints: &Vec<InlayHint>,
-    ) -> Result<(), EditorError> {
-        // FIXME: check current buffer is `text_document`
-        let ns_id = self.create_namespace(text_document.uri.path())?;
-        for hint in hints {
-            self.set_virtual_text(
-                0,
-                ns_id,
-                hint.range.start.line,
-                vec![(&hint.label, "error")],
-            )?;
-        }
+    #[test]
+    fn test_deserialize_start_lang_server_params() {
+        let start_lang_server_msg = NvimMessage::RpcNotification {
+            method: String::from("start_lang_server"),
+            params: Value::from(vec![
+                Value::from("rust"),
+                Value::Map(vec![
+                    (
+                        Value::from("root_markers"),
+                        Value::from(vec![Value::from("Cargo.lock")]),
+                    ),
+                    (
+                        Value::from("command"),
+                        Value::from(vec![Value::from("rustup")]),
+                    ),
+                    (Value::from("indentation"), Value::from(4)),
+                    (Value::from("indentation_with_space"), Value::from(true)),
+                ]),
+                Value::from("/abc"),
+            ]),
+        };
+        let expected = Event::StartServer {
+            lang_id: String::from("rust"),
+            config: LsConfig {
+                command: vec![String::from("rustup")],
+                root_markers: vec![String::from("Cargo.lock")],
+                indentation: 4,
+                indentation_with_space: true,
+                sync_burst_threshold: 10,
+                sync_delay_ms: 500,
+                timer_tick_ms: 100,
+                forward_notifications: false,
+                hover_mode: HoverMode::Preview,
+                format_on_save: false,
+                min_diagnostic_severity: DiagnosticLevel::Hint,
+                request_timeout_ms: 60_000,
+                transport: Transport::Stdio,
+                restart_on_crash: false,
+                settings: serde_json::Value::Null,
+                workspace_folders: Vec::new(),
+                initialization_options: serde_json::Value::Null,
+                env: HashMap::new(),
+                cwd: None,
+                trace: TraceValue::Off,
+                require_root_marker: false,
+            },
+            cur_path: String::from("/abc"),
+        };
+        let buf_mapper = mock_buf_mapper();
+        assert_eq!(
+            expected,
+            to_event(start_lang_server_msg, &buf_mapper).unwrap()
+        );
+    }
 
-        Ok(())
+    fn to_text_document(s: &str) -> Option<TextDocumentIdentifier> {
+        let uri = Url::from_file_path(s).ok()?;
+        Some(TextDocumentIdentifier::new(uri))
     }
 
-    fn show_message(&mut self, params: &ShowMessageParams) -> Result<(), EditorError> {
-        self.command(&format!("echo '[LS-{:?}] {}'", params.typ, params.message))?;
+    #[test]
+    fn test_deserialize_inlay_hints_params() {
+        #[cfg(not(target_os = "windows"))]
+        let file_path = "/a/b/c/d";
+        #[cfg(target_os = "windows")]
+        let file_path = r#"C:\\a\b\d"#;
 
-        Ok(())
+        let inlay_hints_msg = NvimMessage::RpcNotification {
+            method: String::from("inlay_hints"),
+            params: Value::from(vec![Value::from(1), Value::from(file_path)]),
+        };
+        let text_document = to_text_document(file_path).unwrap();
+        let expected = Event::InlayHints {
+            buf_id: BufferHandler(1),
+            text_document,
+        };
+        let buf_mapper = mock_buf_mapper();
+
+        assert_eq!(expected, to_event(inlay_hints_msg, &buf_mapper).unwrap());
     }
 
-    fn goto(&mut self, location: &Location) -> Result<(), EditorError> {
-        let filepath = location
-            .uri
-            .to_file_path()
-            .map_err(|_| EditorError::CommandDataInvalid("Location URI is not file path"))?;
-        let filepath = filepath
-            .to_str()
-            .ok_or(EditorError::CommandDataInvalid("Filepath is not UTF-8"))?;
-        self.command(&format!("edit {}", filepath))?;
-        let line = location.range.start.line + 1;
-        let col = location.range.start.character + 1;
-        let params = Value::Array(vec![line.into(), col.into()]);
-        self.call_function("cursor", params)?;
+    #[test]
+    fn test_deserialize_code_lens_params() {
+        #[cfg(not(target_os = "windows"))]
+        let file_path = "/a/b/c/d";
+        #[cfg(target_os = "windows")]
+        let file_path = r#"C:\\a\b\d"#;
 
-        Ok(())
+        let code_lens_msg = NvimMessage::RpcNotification {
+            method: String::from("code_lens"),
+            params: Value::from(vec![Value::from(2), Value::from(file_path)]),
+        };
+        let text_document = to_text_document(file_path).unwrap();
+        let expected = Event::CodeLens {
+            buf_id: BufferHandler(2),
+            text_document,
+        };
+        let buf_mapper = mock_buf_mapper();
+
+        assert_eq!(expected, to_event(code_lens_msg, &buf_mapper).unwrap());
     }
 
-    fn apply_edits(&self, lines: &Vec<String>, edits: &Vec<TextEdit>) -> Result<(), EditorError> {
-        let editted_content = apply_edits(lines, edits);
-        let new_lines: Vec<Value> = editted_content.split("\n").map(|e| e.into()).collect();
-        let end_line = if new_lines.len() > lines.len() {
-            new_lines.len() - 1
-        } else {
-            lines.len() - 1
+    #[test]
+    fn test_deserialize_goto_definition_params() {
+        #[cfg(not(target_os = "windows"))]
+        let file_path = "/a/b/c/d";
+        #[cfg(target_os = "windows")]
+        let file_path = r#"C:\\a\b\d"#;
+
+        let goto_definition_msg = NvimMessage::RpcNotification {
+            method: String::from("goto_definition"),
+            params: Value::from(vec![
+                Value::from(1),
+                Value::from(file_path),
+                Value::Map(vec![
+                    ("line".into(), Value::from(2)),
+                    ("character".into(), Value::from(4)),
+                ]),
+                Value::from("vsplit"),
+            ]),
         };
-        let params = Value::Array(vec![
-            0.into(), // 0 for current buff
-            0.into(),
-            end_line.into(),
-            false.into(),
-            Value::Array(new_lines),
-        ]);
-        self.call_function("nvim_buf_set_lines", params)?;
-        Ok(())
+        let text_document = to_text_document(file_path).unwrap();
+        let expected = Event::GotoDefinition {
+            text_document,
+            position: Position::new(2, 4),
+            target: GotoTarget::Vsplit,
+        };
+        let buf_mapper = mock_buf_mapper();
+
+        assert_eq!(
+            expected,
+            to_event(goto_definition_msg, &buf_mapper).unwrap()
+        );
     }
 
-    fn show_references(&mut self, locations: &Vec<Location>) -> Result<(), EditorError> {
-        let mut items: Vec<Value> = Vec::new();
-        for location in locations {
-            let mut item: Vec<(Value, Value)> = Vec::new();
-            item.push(("filename".into(), location.uri.path().into()));
-            item.push(("lnum".into(), (location.range.start.line + 1).into()));
-            item.push(("col".into(), (location.range.start.character + 1).into()));
-            items.push(Value::from(item));
-        }
-        self.call_function(
-            "lspc#command#open_reference_preview",
-            Value::Array(vec![items.into()]),
-        )?;
+    #[test]
+    fn test_deserialize_format_doc_params() {
+        #[cfg(not(target_os = "windows"))]
+        let file_path = "/a/b/c/d";
+        #[cfg(target_os = "windows")]
+        let file_path = r#"C:\\a\b\d"#;
+
+        let format_doc_msg = NvimMessage::RpcNotification {
+            method: String::from("format_doc"),
+            params: Value::from(vec![
+                Value::from(3),
+                Value::from(file_path),
+                Value::from(vec![Value::from("fn main() {}")]),
+            ]),
+        };
+        let expected = Event::FormatDoc {
+            buf_id: BufferHandler(3),
+            text_document_lines: vec![String::from("fn main() {}")],
+            text_document: to_text_document(file_path).unwrap(),
+        };
+        let buf_mapper = mock_buf_mapper();
 
-        Ok(())
+        assert_eq!(expected, to_event(format_doc_msg, &buf_mapper).unwrap());
     }
 
-    fn track_all_buffers(&self) -> Result<(), EditorError> {
-        self.call_function("lspc#track_all_buffers", Value::Array(vec![]))?;
-        Ok(())
+    fn document_symbol(
+        name: &str,
+        kind: lsp::SymbolKind,
+        children: Option<Vec<DocumentSymbol>>,
+    ) -> DocumentSymbol {
+        DocumentSymbol {
+            name: name.to_owned(),
+            detail: None,
+            kind,
+            deprecated: None,
+            range: Range::new(Position::new(0, 0), Position::new(0, 0)),
+            selection_range: Range::new(Position::new(0, 0), Position::new(0, 0)),
+            children,
+        }
     }
 
-    fn watch_file_events(
-        &mut self,
-        _text_document: &TextDocumentIdentifier,
-    ) -> Result<(), EditorError> {
-        // FIXME: check current buffer is `text_document`
-        //
-        // nvim_buf_attach({buffer}, {send_buffer}, {opts})
-        #[derive(Serialize)]
-        struct AttachBufParams(i64, bool, HashMap<(), ()>);
+    #[test]
+    fn test_complete_items_order_and_preselect() {
+        let completion_item = |label: &str, sort_text: &str, preselect: bool| CompletionItem {
+            base: lsp::CompletionItem {
+                sort_text: Some(sort_text.to_owned()),
+                preselect: Some(preselect),
+                ..lsp::CompletionItem::new_simple(label.to_owned(), "".to_owned())
+            },
+            label_details: None,
+        };
+        let items = vec![
+            completion_item("x", "b", false),
+            completion_item("y", "a", false),
+            completion_item("z", "a", true),
+        ];
 
-        let attach_buf_params = AttachBufParams(0, true, HashMap::new());
-        let params = to_value(attach_buf_params).map_err(|e| {
-            EditorError::Failed(format!("Failed to encode params: {}", e.description()))
-        })?;
-        let _result = self.request("nvim_buf_attach", params)?;
+        let sorted = sort_completion_items(&items);
+        let labels = sorted
+            .iter()
+            .map(|item| item.base.label.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(vec!["y", "z", "x"], labels);
 
-        Ok(())
+        let preselects = sorted
+            .into_iter()
+            .map(|item| match to_complete_item(item).unwrap() {
+                Value::Map(entries) => entries
+                    .into_iter()
+                    .find(|(key, _)| key.as_str() == Some("preselect"))
+                    .and_then(|(_, value)| value.as_bool())
+                    .unwrap(),
+                _ => panic!("Expected map"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(vec![false, true, false], preselects);
     }
-}
 
-impl Message for NvimMessage {
-    fn read(r: &mut impl BufRead) -> Result<Option<NvimMessage>, RpcError> {
-        let value = read_value(r).map_err(|e| RpcError::Read(e.description().into()))?;
-        log::debug!("< Nvim: {:?}", value);
-        let inner: NvimMessage =
-            from_value(value).map_err(|e| RpcError::Deserialize(e.description().into()))?;
-        let r = Some(inner);
+    #[test]
+    fn test_completion_label_details_survive_serialization() {
+        let item = CompletionItem {
+            base: lsp::CompletionItem::new_simple("push".to_owned(), "".to_owned()),
+            label_details: Some(crate::lspc::types::CompletionItemLabelDetails {
+                detail: Some("(value: T)".to_owned()),
+                description: Some("()".to_owned()),
+            }),
+        };
 
-        Ok(r)
-    }
+        let entries = match to_complete_item(&item).unwrap() {
+            Value::Map(entries) => entries,
+            _ => panic!("Expected map"),
+        };
+        let entry = |key: &str| {
+            entries
+                .iter()
+                .find(|(k, _)| k.as_str() == Some(key))
+                .and_then(|(_, value)| value.as_str())
+                .unwrap()
+                .to_owned()
+        };
+        assert_eq!("push(value: T)", entry("abbr"));
+        assert_eq!("()", entry("kind"));
 
-    fn write(self, w: &mut impl Write) -> Result<(), RpcError> {
-        log::debug!("> Nvim: {:?}", self);
+        let user_data: CompletionItem = serde_json::from_str(&entry("user_data")).unwrap();
+        assert_eq!(item, user_data);
+    }
 
-        let value = to_value(self).map_err(|e| RpcError::Serialize(e.description().into()))?;
-        write_value(w, &value).map_err(|e| RpcError::Write(e.description().into()))?;
-        w.flush()
-            .map_err(|e| RpcError::Write(e.description().into()))?;
+    #[test]
+    fn test_capabilities_advertise_implemented_features() {
+        let (neovim, _fake_nvim) = fake_neovim();
+        let text_document = neovim.capabilities().text_document.unwrap();
 
-        Ok(())
+        let completion_item = text_document.completion.unwrap().completion_item.unwrap();
+        assert_eq!(Some(true), completion_item.snippet_support);
+        assert_eq!(
+            Some(true),
+            text_document
+                .rename
+                .and_then(|rename| rename.prepare_support)
+        );
+        let code_action_kinds = text_document
+            .code_action
+            .and_then(|code_action| code_action.code_action_literal_support)
+            .map(|support| support.code_action_kind.value_set)
+            .unwrap();
+        assert!(code_action_kinds.iter().any(|kind| kind == "quickfix"));
+        assert!(text_document.signature_help.is_some());
+        assert!(text_document.publish_diagnostics.is_some());
     }
 
-    fn is_exit(&self) -> bool {
-        match self {
-            NvimMessage::RpcNotification { method, .. } => method == "exit",
-            _ => false,
-        }
+    #[test]
+    fn test_snippet_text() {
+        assert_eq!("foo(a, )", snippet_text("foo(${1:a}, $2)$0"));
+        assert_eq!("if x {}", snippet_text("if ${1:${2:x}} {$0}"));
+        assert_eq!("one", snippet_text("${1|one,two|}"));
+        assert_eq!("$1 {}", snippet_text("\\$1 {\\}$TM_FILENAME"));
+
+        let mut base = lsp::CompletionItem::new_simple("push".to_owned(), "".to_owned());
+        base.insert_text = Some("push(${1:value})$0".to_owned());
+        base.insert_text_format = Some(lsp::InsertTextFormat::Snippet);
+        let item = CompletionItem {
+            base,
+            label_details: None,
+        };
+        let word = match to_complete_item(&item).unwrap() {
+            Value::Map(entries) => entries
+                .into_iter()
+                .find(|(key, _)| key.as_str() == Some("word"))
+                .map(|(_, value)| value),
+            _ => panic!("Expected map"),
+        };
+        assert_eq!(Some(Value::from("push(value)")), word);
     }
-}
-
-#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
-pub struct BufferHandler(i64);
-#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
-pub struct WindowHandler(i64);
-#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
-pub struct TabpageHandler(i64);
 
-#[derive(Debug, PartialEq, Clone, Copy)]
-pub enum NvimHandle {
-    Buffer(BufferHandler),
-    Window(WindowHandler),
-    Tabpage(TabpageHandler),
-}
+    #[test]
+    fn test_hover_echo_line() {
+        let lines = vec![
+            "```rust".to_owned(),
+            "".to_owned(),
+            "pub fn frobnicate(input: &str) -> u32".to_owned(),
+            "```".to_owned(),
+        ];
 
-impl NvimHandle {
-    pub fn is_buf(&self) -> bool {
-        match self {
-            NvimHandle::Buffer(_) => true,
-            _ => false,
-        }
+        assert_eq!(
+            Some("pub fn frobnicate(input: &str) -> u32".to_owned()),
+            hover_echo_line(&lines, 80)
+        );
+        assert_eq!(Some("pub fn frob…".to_owned()), hover_echo_line(&lines, 13));
+        assert_eq!(None, hover_echo_line(&lines[..2], 80));
     }
 
-    pub fn is_win(&self) -> bool {
-        match self {
-            NvimHandle::Window(_) => true,
-            _ => false,
-        }
+    #[test]
+    fn test_semantic_token_hl_group() {
+        assert_eq!("LspcSemanticFunction", semantic_token_hl_group("function"));
+        assert_eq!(
+            "LspcSemanticTypeParameter",
+            semantic_token_hl_group("typeParameter")
+        );
     }
 
-    pub fn is_tab(&self) -> bool {
-        match self {
-            NvimHandle::Tabpage(_) => true,
-            _ => false,
-        }
-    }
+    #[test]
+    fn test_color_hex_clamps_out_of_gamut() {
+        let color = Color {
+            red: 1.5,
+            green: -0.2,
+            blue: std::f64::NAN,
+            alpha: 2.0,
+        };
+        assert_eq!("ff0000", color_hex(&color));
 
-    pub fn unwrap_buf(self) -> BufferHandler {
-        match self {
-            NvimHandle::Buffer(buf) => buf,
-            _ => panic!("unwrap_buf on non buf"),
-        }
+        let color = Color {
+            red: 0.5,
+            green: 1.0,
+            blue: 0.0,
+            alpha: 0.5,
+        };
+        assert_eq!("80ff00", color_hex(&color));
     }
 
-    pub fn unwrap_win(self) -> WindowHandler {
-        match self {
-            NvimHandle::Window(win) => win,
-            _ => panic!("unwrap_win on non win"),
-        }
-    }
+    #[test]
+    fn test_selection_range_chains_keep_order() {
+        let range =
+            |start: u64, end: u64| lsp::Range::new(Position::new(0, start), Position::new(0, end));
+        let selection_range =
+            |start: u64, end: u64, parent: Option<SelectionRange>| SelectionRange {
+                range: range(start, end),
+                parent: parent.map(Box::new),
+            };
+        let ranges = vec![
+            selection_range(
+                4,
+                7,
+                Some(selection_range(2, 9, Some(selection_range(0, 10, None)))),
+            ),
+            selection_range(12, 14, Some(selection_range(11, 20, None))),
+        ];
 
-    pub fn unwrap_tab(self) -> TabpageHandler {
-        match self {
-            NvimHandle::Tabpage(tab) => tab,
-            _ => panic!("unwrap_tab on non tab"),
-        }
+        let chains = ranges.iter().map(selection_range_chain).collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                vec![range(4, 7), range(2, 9), range(0, 10)],
+                vec![range(12, 14), range(11, 20)],
+            ],
+            chains
+        );
     }
-}
-
-impl<'de> Deserialize<'de> for NvimHandle {
-    fn deserialize<D>(deserializer: D) -> Result<NvimHandle, D::Error>
-    where
-        D: serde::Deserializer<'de>,
-    {
-        struct NvimHandleVisitor;
-        impl<'de> Visitor<'de> for NvimHandleVisitor {
-            type Value = NvimHandle;
-            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-                formatter.write_str("_ExtStruct((i8, &[u8]))")
-            }
 
-            fn visit_newtype_struct<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
-            where
-                D: serde::Deserializer<'de>,
-            {
-                let (tag, bytes): (i8, serde_bytes::ByteBuf) =
-                    Deserialize::deserialize(deserializer)?;
+    #[test]
+    fn test_deserialize_workspace_symbol_params() {
+        let workspace_symbol_msg = NvimMessage::RpcNotification {
+            method: String::from("workspace_symbol"),
+            params: Value::from(vec![Value::from("Foo")]),
+        };
+        let expected = Event::WorkspaceSymbols {
+            query: "Foo".to_owned(),
+        };
+        let buf_mapper = mock_buf_mapper();
 
-                let handle: i64 = match bytes.len() {
-                    1 => bytes[0] as i8 as i64,
-                    2 => {
-                        let mut b: [u8; 2] = [0; 2];
-                        b.copy_from_slice(&bytes[..]);
-                        i16::from_le_bytes(b) as i64
-                    }
-                    4 => {
-                        let mut b: [u8; 4] = [0; 4];
-                        b.copy_from_slice(&bytes[..]);
-                        i32::from_le_bytes(b) as i64
-                    }
-                    8 => {
-                        let mut b: [u8; 8] = [0; 8];
-                        b.copy_from_slice(&bytes[..]);
-                        i64::from_le_bytes(b)
-                    }
-                    _ => return Err(<D::Error as de::Error>::custom("invalid bytes length")),
-                };
+        assert_eq!(
+            expected,
+            to_event(workspace_symbol_msg, &buf_mapper).unwrap()
+        );
+    }
 
-                match tag {
-                    0 => Ok(NvimHandle::Buffer(BufferHandler(handle))),
-                    1 => Ok(NvimHandle::Window(WindowHandler(handle))),
-                    2 => Ok(NvimHandle::Tabpage(TabpageHandler(handle))),
-                    _ => Err(<D::Error as de::Error>::custom("unknown tag")),
-                }
-            }
-        }
+    #[test]
+    fn test_display_nested_document_symbols() {
+        let symbol = document_symbol(
+            "outer",
+            lsp::SymbolKind::Module,
+            Some(vec![
+                document_symbol(
+                    "Inner",
+                    lsp::SymbolKind::Struct,
+                    Some(vec![document_symbol("field", lsp::SymbolKind::Field, None)]),
+                ),
+                document_symbol("func", lsp::SymbolKind::Function, None),
+            ]),
+        );
+        let expected = vec!["outer", "  Inner", "    field", "  func"];
 
-        deserializer.deserialize_newtype_struct(rmpv::MSGPACK_EXT_STRUCT_NAME, NvimHandleVisitor)
+        assert_eq!(expected, symbol.to_display());
     }
-}
 
-impl Serialize for NvimHandle {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: serde::Serializer,
-    {
-        let ext_data = match self {
-            NvimHandle::Buffer(BufferHandler(buf_id)) => (0, buf_id.to_le_bytes()),
-            NvimHandle::Window(WindowHandler(win_id)) => (1, win_id.to_le_bytes()),
-            NvimHandle::Tabpage(TabpageHandler(tab_id)) => (2, tab_id.to_le_bytes()),
-        };
+    #[test]
+    fn test_document_symbol_entries() {
+        let symbols = DocumentSymbolResponse::Nested(vec![document_symbol(
+            "Outer",
+            lsp::SymbolKind::Struct,
+            Some(vec![document_symbol("field", lsp::SymbolKind::Field, None)]),
+        )]);
+        let texts = document_symbol_entries(&symbols)
+            .into_iter()
+            .map(|(uri, _, text)| {
+                assert_eq!(None, uri);
+                text
+            })
+            .collect::<Vec<_>>();
 
-        serializer.serialize_newtype_struct(rmpv::MSGPACK_EXT_STRUCT_NAME, &ext_data)
+        assert_eq!(vec!["[Struct] Outer", "  [Field] field"], texts);
     }
-}
 
-#[derive(Debug, PartialEq, Clone)]
-pub enum NvimMessage {
-    RpcRequest {
-        msgid: u64,
-        method: String,
-        params: Value,
-    }, // 0
-    RpcResponse {
-        msgid: u64,
-        error: Value,
-        result: Value,
-    }, // 1
-    RpcNotification {
-        method: String,
-        params: Value,
-    }, // 2
-}
+    // Neovim connected to an in-memory peer, returns the peer's end
+    fn fake_neovim() -> (Neovim, rpc::Client<NvimMessage>) {
+        let (nvim_reader, lspc_writer) = pipe();
+        let (lspc_reader, nvim_writer) = pipe();
 
-impl Serialize for NvimMessage {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: serde::Serializer,
-    {
-        use NvimMessage::*;
+        let neovim = Neovim::new(rpc::Client::new(move || lspc_reader, move || lspc_writer));
+        let fake_nvim = rpc::Client::new(move || nvim_reader, move || nvim_writer);
 
-        match self {
-            RpcRequest {
-                msgid,
-                method,
-                params,
-            } => {
-                let mut seq = serializer.serialize_seq(Some(4))?;
-                seq.serialize_element(&0)?;
-                seq.serialize_element(&msgid)?;
-                seq.serialize_element(&method)?;
-                seq.serialize_element(&params)?;
-                seq.end()
-            }
-            RpcResponse {
-                msgid,
-                error,
-                result,
-            } => {
-                let mut seq = serializer.serialize_seq(Some(4))?;
-                seq.serialize_element(&1)?;
-                seq.serialize_element(&msgid)?;
-                seq.serialize_element(&error)?;
-                seq.serialize_element(&result)?;
-                seq.end()
+        (neovim, fake_nvim)
+    }
+
+    // Answer every request with `result`, returns the requested methods
+    fn respond_with(fake_nvim: rpc::Client<NvimMessage>, result: Value) -> Receiver<String> {
+        let (method_sender, method_receiver) = channel::unbounded();
+        thread::spawn(move || {
+            for msg in fake_nvim.receiver.iter() {
+                if let NvimMessage::RpcRequest { msgid, method, .. } = msg {
+                    method_sender.send(method).unwrap();
+                    fake_nvim
+                        .sender
+                        .send(NvimMessage::RpcResponse {
+                            msgid,
+                            error: Value::Nil,
+                            result: result.clone(),
+                        })
+                        .unwrap();
+                }
             }
-            RpcNotification { method, params } => {
-                let mut seq = serializer.serialize_seq(Some(3))?;
-                seq.serialize_element(&2)?;
-                seq.serialize_element(&method)?;
-                seq.serialize_element(&params)?;
-                seq.end()
+        });
+
+        method_receiver
+    }
+
+    #[test]
+    fn test_late_response_does_not_break_next_request() {
+        let (mut neovim, fake_nvim) = fake_neovim();
+        neovim.set_request_timeout(Duration::from_millis(50));
+        thread::spawn(move || {
+            for msg in fake_nvim.receiver.iter() {
+                if let NvimMessage::RpcRequest { msgid, .. } = msg {
+                    // Only the first request is answered too late
+                    if msgid == 1 {
+                        thread::sleep(Duration::from_millis(100));
+                    }
+                    fake_nvim
+                        .sender
+                        .send(NvimMessage::RpcResponse {
+                            msgid,
+                            error: Value::Nil,
+                            result: Value::from(msgid),
+                        })
+                        .unwrap();
+                }
             }
+        });
+
+        match neovim.request("nvim_get_api_info", Value::Array(vec![])) {
+            Err(EditorError::Timeout) => {}
+            other => panic!("expected timeout, got {:?}", other),
+        }
+        match neovim.request("nvim_get_api_info", Value::Array(vec![])) {
+            Ok(NvimMessage::RpcResponse { msgid, .. }) => assert_eq!(2, msgid),
+            other => panic!("expected response, got {:?}", other),
         }
     }
-}
 
-struct RpcVisitor;
+    #[test]
+    fn test_unanswered_request_is_unsubscribed() {
+        let (mut neovim, fake_nvim) = fake_neovim();
+        neovim.set_request_timeout(Duration::from_millis(50));
+        // Keeps the peer connected without ever answering
+        let _requests = thread::spawn(move || fake_nvim.receiver.iter().count());
+
+        match neovim.request("nvim_get_api_info", Value::Array(vec![])) {
+            Err(EditorError::Timeout) => {}
+            other => panic!("expected timeout, got {:?}", other),
+        }
+        assert_eq!(0, neovim.rpc_client.pending_subscriptions());
+    }
 
-impl<'de> Visitor<'de> for RpcVisitor {
-    type Value = NvimMessage;
+    #[test]
+    fn test_namespace_is_cached() {
+        let (mut neovim, fake_nvim) = fake_neovim();
+        let requests = respond_with(fake_nvim, Value::from(7));
 
-    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-        formatter.write_str("seq (tag, [msgid], method, params)")
+        assert_eq!(7, neovim.namespace("lspc").unwrap());
+        assert_eq!(7, neovim.namespace("lspc").unwrap());
+        assert_eq!(
+            vec!["nvim_create_namespace".to_owned()],
+            requests.try_iter().collect::<Vec<_>>()
+        );
     }
 
-    fn visit_seq<V>(self, mut seq: V) -> Result<Self::Value, V::Error>
-    where
-        V: SeqAccess<'de>,
-    {
-        use NvimMessage::*;
-
-        let tag: i64 = seq
-            .next_element()?
-            .ok_or_else(|| de::Error::invalid_length(0, &self))?;
-        if tag == 0 {
-            let msgid = seq
-                .next_element()?
-                .ok_or_else(|| de::Error::invalid_length(1, &self))?;
-            let method: String = seq
-                .next_element()?
-                .ok_or_else(|| de::Error::invalid_length(2, &self))?;
-            let params: Value = seq
-                .next_element()?
-                .ok_or_else(|| de::Error::invalid_length(3, &self))?;
+    // Records every message sent to Neovim, atomic calls succeed without results
+    fn record_messages(fake_nvim: rpc::Client<NvimMessage>) -> Receiver<(String, Value)> {
+        let (message_sender, message_receiver) = channel::unbounded();
+        thread::spawn(move || {
+            for msg in fake_nvim.receiver.iter() {
+                match msg {
+                    NvimMessage::RpcNotification { method, params } => {
+                        message_sender.send((method, params)).unwrap();
+                    }
+                    NvimMessage::RpcRequest {
+                        msgid,
+                        method,
+                        params,
+                    } => {
+                        message_sender.send((method, params)).unwrap();
+                        fake_nvim
+                            .sender
+                            .send(NvimMessage::RpcResponse {
+                                msgid,
+                                error: Value::Nil,
+                                result: Value::from(vec![
+                                    Value::from(Vec::<Value>::new()),
+                                    Value::Nil,
+                                ]),
+                            })
+                            .unwrap();
+                    }
+                    _ => {}
+                }
+            }
+        });
 
-            Ok(RpcRequest {
-                msgid,
-                method,
-                params,
-            })
-        } else if tag == 1 {
-            let msgid = seq
-                .next_element()?
-                .ok_or_else(|| de::Error::invalid_length(1, &self))?;
-            let error: Value = seq
-                .next_element()?
-                .ok_or_else(|| de::Error::invalid_length(2, &self))?;
-            let result: Value = seq
-                .next_element()?
-                .ok_or_else(|| de::Error::invalid_length(3, &self))?;
+        message_receiver
+    }
 
-            Ok(RpcResponse {
-                msgid,
-                error,
-                result,
-            })
-        } else if tag == 2 {
-            let method: String = seq
-                .next_element()?
-                .ok_or_else(|| de::Error::invalid_length(1, &self))?;
-            let params: Value = seq
-                .next_element()?
-                .ok_or_else(|| de::Error::invalid_length(2, &self))?;
+    #[test]
+    fn test_inline_hints_replace_previous_hints() {
+        let (mut neovim, fake_nvim) = fake_neovim();
+        let messages = record_messages(fake_nvim);
+        let uri = Url::parse("file:///a/b/main.rs").unwrap();
+        neovim.namespaces.insert(uri.path().to_owned(), 3);
+        let hints = vec![InlayHint {
+            range: Range::new(Position::new(1, 4), Position::new(1, 4)),
+            kind: InlayKind::TypeHint,
+            label: String::from("i32"),
+        }];
+
+        neovim
+            .inline_hints(BufferHandler(5), &TextDocumentIdentifier::new(uri), &hints)
+            .unwrap();
 
-            Ok(RpcNotification { method, params })
-        } else {
-            Err(de::Error::invalid_value(
-                de::Unexpected::Other("invalid tag"),
-                &self,
-            ))
-        }
+        let (method, _) = messages.recv().unwrap();
+        assert_eq!("nvim_buf_clear_namespace", method);
+        let (method, params) = messages.recv().unwrap();
+        assert_eq!("nvim_call_atomic", method);
+        assert_eq!(
+            Value::from(vec![Value::from(vec![Value::from(vec![
+                Value::from("nvim_buf_set_extmark"),
+                Value::from(vec![
+                    Value::from(5),
+                    Value::from(3),
+                    Value::from(1),
+                    Value::from(0),
+                    Value::Map(vec![
+                        (
+                            "virt_text".into(),
+                            Value::from(vec![Value::from(vec![
+                                Value::from("i32"),
+                                Value::from("LspcInlayHint")
+                            ])])
+                        ),
+                        ("virt_text_pos".into(), "eol".into()),
+                    ]),
+                ]),
+            ])])]),
+            params
+        );
     }
-}
 
-impl<'de> Deserialize<'de> for NvimMessage {
-    fn deserialize<D>(deserializer: D) -> Result<NvimMessage, D::Error>
-    where
-        D: serde::Deserializer<'de>,
-    {
-        deserializer.deserialize_seq(RpcVisitor)
+    #[test]
+    fn test_references_are_sorted_and_deduplicated() {
+        let (mut neovim, fake_nvim) = fake_neovim();
+        let messages = record_messages(fake_nvim);
+        let location = |path: &str, line| {
+            Location::new(
+                Url::parse(&format!("file://{}", path)).unwrap(),
+                Range::new(Position::new(line, 4), Position::new(line, 8)),
+            )
+        };
+        let item = |path: &str, lnum| {
+            Value::Map(vec![
+                ("filename".into(), Value::from(path)),
+                ("lnum".into(), Value::from(lnum)),
+                ("col".into(), Value::from(5)),
+            ])
+        };
+
+        neovim.show_references(&Vec::new()).unwrap();
+        let (method, params) = messages.recv().unwrap();
+        assert_eq!("nvim_command", method);
+        assert_eq!(
+            Value::from(vec![Value::from("echo 'No references found'")]),
+            params
+        );
+
+        let locations = vec![
+            location("/a/b.rs", 7),
+            location("/a/a.rs", 2),
+            location("/a/b.rs", 7),
+            location("/a/b.rs", 1),
+        ];
+        neovim.show_references(&locations).unwrap();
+        let (method, params) = messages.recv().unwrap();
+        assert_eq!("nvim_call_function", method);
+        assert_eq!(
+            Value::from(vec![
+                Value::from("lspc#command#open_reference_preview"),
+                Value::from(vec![Value::from(vec![
+                    item("/a/a.rs", 3),
+                    item("/a/b.rs", 2),
+                    item("/a/b.rs", 8),
+                ])]),
+            ]),
+            params
+        );
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use lsp_types::{Position, Range, TextEdit};
+    #[test]
+    fn test_goto_outside_local_files_is_reported() {
+        let (mut neovim, fake_nvim) = fake_neovim();
+        let messages = record_messages(fake_nvim);
+        let range = Range::new(Position::new(3, 0), Position::new(3, 0));
+
+        let archived = Url::parse("jar:file:///a/lib.jar!/b/C.class").unwrap();
+        neovim
+            .goto(
+                &Location::new(archived, range),
+                PositionEncoding::Utf16,
+                GotoTarget::Edit,
+            )
+            .unwrap();
+        let (method, params) = messages.recv().unwrap();
+        assert_eq!("nvim_command", method);
+        assert_eq!(
+            Value::from(vec![Value::from(
+                "echo 'Cannot open jar:file:///a/lib.jar!/b/C.class: not a local file'"
+            )]),
+            params
+        );
 
-    fn mock_buf_mapper() -> Mutex<BiMap<i64, Url>> {
-        Mutex::new(BiMap::new())
+        #[cfg(not(target_os = "windows"))]
+        let missing = Url::from_file_path("/lspc/missing/lib.rs").unwrap();
+        #[cfg(target_os = "windows")]
+        let missing = Url::from_file_path(r#"C:\\lspc\missing\lib.rs"#).unwrap();
+        neovim
+            .goto(
+                &Location::new(missing, range),
+                PositionEncoding::Utf16,
+                GotoTarget::Edit,
+            )
+            .unwrap();
+        let (_, params) = messages.recv().unwrap();
+        let command = params.as_array().unwrap()[0].as_str().unwrap().to_owned();
+        assert!(command.ends_with(": file not found'"));
     }
 
     #[test]
-    fn test_apply_edits() {
-        let original_content = String::from("fn   a() {\n  print!(\"hello\");\n}");
-        let lines = original_content
-            .split("\n")
-            .map(String::from)
-            .collect::<Vec<String>>();
-        let edits = vec![
-            TextEdit::new(
-                Range::new(Position::new(0, 3), Position::new(0, 5)),
-                String::from(""),
-            ),
-            TextEdit::new(
-                Range::new(Position::new(1, 0), Position::new(1, 0)),
-                String::from("  "),
-            ),
-        ];
-        let editted_content = apply_edits(&lines, &edits);
-        let expected_content = String::from("fn a() {\n    print!(\"hello\");\n}");
-        assert_eq!(editted_content, expected_content);
+    fn test_reference_filenames_are_decoded() {
+        let (mut neovim, fake_nvim) = fake_neovim();
+        let messages = record_messages(fake_nvim);
+        #[cfg(not(target_os = "windows"))]
+        let (uri, filename) = ("file:///a/my%20dir/b.rs", "/a/my dir/b.rs");
+        #[cfg(target_os = "windows")]
+        let (uri, filename) = ("file:///C:/my%20dir/b.rs", r#"C:\my dir\b.rs"#);
+        let location = Location::new(
+            Url::parse(uri).unwrap(),
+            Range::new(Position::new(0, 0), Position::new(0, 0)),
+        );
+
+        neovim.show_references(&vec![location]).unwrap();
+
+        let (_, params) = messages.recv().unwrap();
+        assert_eq!(
+            Value::from(vec![
+                Value::from("lspc#command#open_reference_preview"),
+                Value::from(vec![Value::from(vec![Value::Map(vec![
+                    ("filename".into(), Value::from(filename)),
+                    ("lnum".into(), Value::from(1)),
+                    ("col".into(), Value::from(1)),
+                ])])]),
+            ]),
+            params
+        );
+        assert_eq!("/a/my\\ dir/b\\%.rs", escape_filename("/a/my dir/b%.rs"));
     }
 
     #[test]
-    fn test_deserialize_ls_config() {
-        let value = Value::Map(vec![
-            (
-                Value::from("root_markers"),
-                Value::from(vec![Value::from("Cargo.lock")]),
-            ),
-            (
-                Value::from("command"),
-                Value::from(vec![Value::from("rustup"), Value::from("run")]),
-            ),
-            (Value::from("indentation"), Value::from(4)),
-            (Value::from("indentation_with_space"), Value::from(true)),
-        ]);
+    fn test_inline_hints_are_set_in_batches() {
+        let (mut neovim, fake_nvim) = fake_neovim();
+        let messages = record_messages(fake_nvim);
+        let uri = Url::parse("file:///a/b/main.rs").unwrap();
+        neovim.namespaces.insert(uri.path().to_owned(), 3);
+        let hints = (0..INLAY_HINTS_PER_CALL as u64 * 2 + 1)
+            .map(|line| InlayHint {
+                range: Range::new(Position::new(line, 4), Position::new(line, 4)),
+                kind: InlayKind::TypeHint,
+                label: String::from("i32"),
+            })
+            .collect::<Vec<_>>();
 
-        let ls_config: LsConfig = Deserialize::deserialize(value).unwrap();
-        let expected = LsConfig {
-            command: vec!["rustup".to_owned(), "run".to_owned()],
-            root_markers: vec!["Cargo.lock".to_owned()],
-            indentation: 4,
-            indentation_with_space: true,
-        };
+        neovim
+            .inline_hints(BufferHandler(5), &TextDocumentIdentifier::new(uri), &hints)
+            .unwrap();
 
-        assert_eq!(expected, ls_config);
+        let messages = messages.try_iter().collect::<Vec<_>>();
+        let methods = messages
+            .iter()
+            .map(|(method, _)| method.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                "nvim_buf_clear_namespace",
+                "nvim_call_atomic",
+                "nvim_call_atomic",
+                "nvim_call_atomic",
+            ],
+            methods
+        );
+        let batch_sizes = messages[1..]
+            .iter()
+            .map(|(_, params)| params.as_array().unwrap()[0].as_array().unwrap().len())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![INLAY_HINTS_PER_CALL, INLAY_HINTS_PER_CALL, 1],
+            batch_sizes
+        );
     }
 
     #[test]
-    fn test_deserialize_start_lang_server_params() {
-        let start_lang_server_msg = NvimMessage::RpcNotification {
-            method: String::from("start_lang_server"),
-            params: Value::from(vec![
-                Value::from("rust"),
-                Value::Map(vec![
-                    (
-                        Value::from("root_markers"),
-                        Value::from(vec![Value::from("Cargo.lock")]),
-                    ),
-                    (
-                        Value::from("command"),
-                        Value::from(vec![Value::from("rustup")]),
-                    ),
-                    (Value::from("indentation"), Value::from(4)),
-                    (Value::from("indentation_with_space"), Value::from(true)),
+    fn test_apply_edits_targets_given_buffer() {
+        let (neovim, fake_nvim) = fake_neovim();
+        let (params_sender, params_receiver) = channel::unbounded();
+        thread::spawn(move || {
+            for msg in fake_nvim.receiver.iter() {
+                if let NvimMessage::RpcRequest { msgid, params, .. } = msg {
+                    params_sender.send(params).unwrap();
+                    fake_nvim
+                        .sender
+                        .send(NvimMessage::RpcResponse {
+                            msgid,
+                            error: Value::Nil,
+                            result: Value::Nil,
+                        })
+                        .unwrap();
+                }
+            }
+        });
+        let lines = vec![String::from("let   x = 1;"), String::from("x")];
+        let edits = vec![TextEdit::new(
+            Range::new(Position::new(0, 3), Position::new(0, 6)),
+            String::from(" "),
+        )];
+
+        neovim
+            .apply_edits(BufferHandler(4), &lines, &edits, PositionEncoding::Utf16)
+            .unwrap();
+
+        assert_eq!(
+            Value::from(vec![
+                Value::from("nvim_buf_set_lines"),
+                Value::from(vec![
+                    Value::from(4),
+                    Value::from(0),
+                    Value::from(-1),
+                    Value::from(false),
+                    Value::from(vec![Value::from("let x = 1;"), Value::from("x")]),
                 ]),
-                Value::from("/abc"),
             ]),
+            params_receiver.try_recv().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_bufnr_of_unloaded_file() {
+        let (neovim, fake_nvim) = fake_neovim();
+        let requests = respond_with(fake_nvim, Value::from(-1));
+        let uri = Url::parse("file:///a/b/main.rs").unwrap();
+
+        assert_eq!(None, neovim.bufnr(&uri).unwrap());
+        assert_eq!(
+            vec!["nvim_call_function".to_owned()],
+            requests.try_iter().collect::<Vec<_>>()
+        );
+
+        neovim.buf_mapper.lock().unwrap().insert(5, uri.clone());
+        assert_eq!(Some(5), neovim.bufnr(&uri).unwrap());
+        assert_eq!(0, requests.try_iter().count());
+    }
+
+    #[test]
+    fn test_hover_of_unfocused_buffer_is_dropped() {
+        let (mut neovim, fake_nvim) = fake_neovim();
+        let requests = respond_with(fake_nvim, Value::from(2));
+        let text_document = TextDocumentIdentifier::new(Url::parse("file:///a/main.rs").unwrap());
+        let hover = |value: &str| Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::PlainText,
+                value: value.to_owned(),
+            }),
+            range: None,
         };
-        let expected = Event::StartServer {
-            lang_id: String::from("rust"),
-            config: LsConfig {
-                command: vec![String::from("rustup")],
-                root_markers: vec![String::from("Cargo.lock")],
-                indentation: 4,
-                indentation_with_space: true,
-            },
-            cur_path: String::from("/abc"),
-        };
-        let buf_mapper = mock_buf_mapper();
+
+        neovim
+            .show_hover(
+                BufferHandler(1),
+                &text_document,
+                &hover("fn main()"),
+                HoverMode::Preview,
+            )
+            .unwrap();
         assert_eq!(
-            expected,
-            to_event(start_lang_server_msg, &buf_mapper).unwrap()
+            vec!["nvim_call_function".to_owned()],
+            requests.try_iter().collect::<Vec<_>>()
+        );
+
+        // Nothing to preview in the focused buffer
+        neovim
+            .show_hover(
+                BufferHandler(2),
+                &text_document,
+                &hover(""),
+                HoverMode::Preview,
+            )
+            .unwrap();
+        assert_eq!(
+            vec!["nvim_call_function".to_owned(), "nvim_command".to_owned()],
+            requests.try_iter().collect::<Vec<_>>()
         );
     }
 
-    fn to_text_document(s: &str) -> Option<TextDocumentIdentifier> {
-        let uri = Url::from_file_path(s).ok()?;
-        Some(TextDocumentIdentifier::new(uri))
+    #[test]
+    fn test_namespace_fallback() {
+        let (mut neovim, fake_nvim) = fake_neovim();
+        // Only the fallback namespace can be created
+        thread::spawn(move || {
+            for msg in fake_nvim.receiver.iter() {
+                if let NvimMessage::RpcRequest { msgid, params, .. } = msg {
+                    let ns_name = params.as_array().and_then(|params| params.first());
+                    let result = if ns_name == Some(&Value::from(FALLBACK_NAMESPACE)) {
+                        Value::from(5)
+                    } else {
+                        Value::Nil
+                    };
+                    fake_nvim
+                        .sender
+                        .send(NvimMessage::RpcResponse {
+                            msgid,
+                            error: Value::Nil,
+                            result,
+                        })
+                        .unwrap();
+                }
+            }
+        });
+
+        assert_eq!(5, neovim.namespace("lspc_code_lens").unwrap());
+        assert_eq!(5, neovim.namespace("lspc_document_link").unwrap());
+        assert_eq!(5, neovim.namespace(FALLBACK_NAMESPACE).unwrap());
     }
 
     #[test]
-    fn test_deserialize_inlay_hints_params() {
-        #[cfg(not(target_os = "windows"))]
-        let file_path = "/a/b/c/d";
-        #[cfg(target_os = "windows")]
-        let file_path = r#"C:\\a\b\d"#;
+    fn test_namespace_fails_without_fallback() {
+        let (mut neovim, fake_nvim) = fake_neovim();
+        let _requests = respond_with(fake_nvim, Value::Nil);
 
-        let inlay_hints_msg = NvimMessage::RpcNotification {
-            method: String::from("inlay_hints"),
-            params: Value::from(vec![Value::from(1), Value::from(file_path)]),
-        };
-        let text_document = to_text_document(file_path).unwrap();
-        let expected = Event::InlayHints { text_document };
-        let buf_mapper = mock_buf_mapper();
+        assert!(neovim.namespace("lspc_code_lens").is_err());
+    }
 
-        assert_eq!(expected, to_event(inlay_hints_msg, &buf_mapper).unwrap());
+    #[test]
+    fn test_message_value_matches_serialization() {
+        let messages = vec![
+            NvimMessage::RpcRequest {
+                msgid: 3,
+                method: String::from("nvim_command"),
+                params: Value::from(vec![Value::from("echo 1")]),
+            },
+            NvimMessage::RpcResponse {
+                msgid: 3,
+                error: Value::Nil,
+                result: Value::from(1),
+            },
+            NvimMessage::RpcNotification {
+                method: String::from("nvim_buf_clear_namespace"),
+                params: Value::from(vec![Value::from(1), Value::from(2)]),
+            },
+        ];
+
+        for msg in messages {
+            let serialized = to_value(&msg).unwrap();
+            assert_eq!(serialized, Value::from(msg));
+        }
     }
 
     #[test]
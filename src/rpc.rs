@@ -1,15 +1,24 @@
 use log;
 use std::{
     io::{BufRead, BufReader, Read, Write},
+    sync::{Arc, Mutex},
     thread,
 };
 
 use crossbeam::channel::{bounded, Receiver, Sender};
 
+// Messages queued in each direction before the sender blocks
+pub const DEFAULT_CAPACITY: usize = 16;
+
 pub trait Message: Sized + Send + 'static {
+    type Id: PartialEq + std::fmt::Debug + Send + 'static;
+
     fn read(r: &mut impl BufRead) -> Result<Option<Self>, RpcError>;
     fn write(self, w: &mut impl Write) -> Result<(), RpcError>;
     fn is_exit(&self) -> bool;
+    // Id of the request this message is or answers, `None` for notifications
+    fn id(&self) -> Option<Self::Id>;
+    fn is_response(&self) -> bool;
 }
 
 #[derive(Debug)]
@@ -71,6 +80,7 @@ where
 {
     pub sender: Sender<M>,
     pub receiver: Receiver<M>,
+    subscriptions: Arc<Mutex<Vec<(M::Id, Sender<M>)>>>,
     threads: Threads,
 }
 
@@ -84,7 +94,21 @@ impl<M: Message> Client<M> {
         RF: Send + 'static,
         WF: Send + 'static,
     {
-        let (writer_sender, writer_receiver) = bounded::<M>(16);
+        Client::with_capacity(DEFAULT_CAPACITY, get_reader, get_writer)
+    }
+
+    // A bigger capacity lets bursts of messages (e.g. buffer line events of a
+    // large paste) be read while the writer is still busy
+    pub fn with_capacity<RF, WF, R, W>(capacity: usize, get_reader: RF, get_writer: WF) -> Self
+    where
+        RF: FnOnce() -> R,
+        WF: FnOnce() -> W,
+        R: Read + Sized,
+        W: Write + Sized,
+        RF: Send + 'static,
+        WF: Send + 'static,
+    {
+        let (writer_sender, writer_receiver) = bounded::<M>(capacity);
         let writer = thread::spawn(move || {
             let mut io_writer = get_writer();
             writer_receiver.into_iter().for_each(|msg| {
@@ -95,7 +119,9 @@ impl<M: Message> Client<M> {
             Ok(())
         });
 
-        let (reader_sender, reader_receiver) = bounded::<M>(16);
+        let (reader_sender, reader_receiver) = bounded::<M>(capacity);
+        let subscriptions = Arc::new(Mutex::new(Vec::<(M::Id, Sender<M>)>::new()));
+        let reader_subscriptions = Arc::clone(&subscriptions);
         let reader = thread::spawn(move || {
             let io_reader = get_reader();
             let mut buf_read = BufReader::new(io_reader);
@@ -104,16 +130,39 @@ impl<M: Message> Client<M> {
                     Ok(Some(msg)) => {
                         let is_exit = msg.is_exit();
 
-                        reader_sender.send(msg).unwrap();
+                        let subscription = match msg.id() {
+                            Some(ref id) if msg.is_response() => {
+                                let mut subscriptions = reader_subscriptions.lock().unwrap();
+                                subscriptions
+                                    .iter()
+                                    .position(|(sub_id, _)| sub_id == id)
+                                    .map(|index| subscriptions.swap_remove(index))
+                            }
+                            _ => None,
+                        };
+                        match subscription {
+                            Some((id, sender)) => {
+                                // The requester may time out before unsubscribing
+                                if sender.send(msg).is_err() {
+                                    log::warn!("Response to {:?} arrived after timeout", id);
+                                }
+                            }
+                            // Responses nobody subscribed to, or that arrive
+                            // after the requester unsubscribed, are left to the receiver
+                            None => reader_sender.send(msg).unwrap(),
+                        }
 
                         if is_exit {
                             break;
                         }
                     }
-                    Ok(None) => continue,
+                    // End of stream, the other side is gone
+                    Ok(None) => break,
                     Err(e) => log::error!("Error reading message: {:?}", e),
                 }
             }
+            // No response can arrive anymore, disconnect the waiting requesters
+            reader_subscriptions.lock().unwrap().clear();
             Ok(())
         });
         let threads = Threads { reader, writer };
@@ -121,11 +170,101 @@ impl<M: Message> Client<M> {
         Client {
             sender: writer_sender,
             receiver: reader_receiver,
+            subscriptions,
             threads,
         }
     }
 
+    // Routes the response to request `id` to the returned receiver instead of
+    // `receiver`, subscribe before sending the request to not miss it
+    pub fn subscribe(&self, id: M::Id) -> Receiver<M> {
+        let (response_sender, response_receiver) = bounded::<M>(1);
+        self.subscriptions
+            .lock()
+            .unwrap()
+            .push((id, response_sender));
+
+        response_receiver
+    }
+
+    // Stops waiting for the response to request `id`, e.g. after a timeout,
+    // so a response that never arrives doesn't leave its subscription behind
+    pub fn unsubscribe(&self, id: &M::Id) {
+        self.subscriptions
+            .lock()
+            .unwrap()
+            .retain(|(sub_id, _)| sub_id != id);
+    }
+
+    #[cfg(test)]
+    pub fn pending_subscriptions(&self) -> usize {
+        self.subscriptions.lock().unwrap().len()
+    }
+
     fn close(self) -> Result<(), String> {
         self.threads.join()
     }
 }
+
+#[cfg(test)]
+pub mod testing {
+    use std::io::{self, Read, Write};
+
+    use crossbeam::channel::{unbounded, Receiver, Sender};
+
+    // In-memory byte pipe, reads block until the other end writes
+    pub struct PipeReader {
+        receiver: Receiver<Vec<u8>>,
+        buf: Vec<u8>,
+        pos: usize,
+    }
+
+    pub struct PipeWriter {
+        sender: Sender<Vec<u8>>,
+    }
+
+    pub fn pipe() -> (PipeReader, PipeWriter) {
+        let (sender, receiver) = unbounded();
+        let reader = PipeReader {
+            receiver,
+            buf: Vec::new(),
+            pos: 0,
+        };
+
+        (reader, PipeWriter { sender })
+    }
+
+    impl Read for PipeReader {
+        fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+            while self.pos == self.buf.len() {
+                match self.receiver.recv() {
+                    Ok(buf) => {
+                        self.buf = buf;
+                        self.pos = 0;
+                    }
+                    // Writer dropped
+                    Err(_) => return Ok(0),
+                }
+            }
+            let len = std::cmp::min(out.len(), self.buf.len() - self.pos);
+            out[..len].copy_from_slice(&self.buf[self.pos..self.pos + len]);
+            self.pos += len;
+
+            Ok(len)
+        }
+    }
+
+    impl Write for PipeWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.sender
+                .send(buf.to_vec())
+                .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "pipe closed"))?;
+
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+}